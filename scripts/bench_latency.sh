@@ -0,0 +1,43 @@
+#!/bin/bash
+
+# Tail latency of the running ring under rising concurrency.
+# Run it once per build to compare, e.g. the worker pool against spawn-per-connection:
+#   ./bench_latency.sh pool                                  (ring built from this revision)
+#   git checkout <revision before the pool> && ./killall.sh && ./init.sh
+#   ./bench_latency.sh spawn
+#   ./bench_latency.sh compare pool spawn
+
+RESULTS_DIR="../data/bench/"
+BOOTSTRAP_IP="10.0.24.44"
+BOOTSTRAP_PORT="8000"
+CONCURRENCY=(1 16 64 256)
+OPS=5000
+
+if [[ $# -lt 1 ]]; then
+    echo "Usage: $0 <label> | compare <label> <label>"
+    exit 1
+fi
+
+mkdir -p "$RESULTS_DIR"
+
+if [[ "$1" == "compare" ]]; then
+    for label in "$2" "$3"; do
+        echo "== $label"
+        # concurrency and the latency percentiles of every run
+        grep -o "concurrency=[0-9]*\|p[0-9]*_us=[0-9]*\|max_us=[0-9]*" "$RESULTS_DIR$label.txt" | paste -d ' ' - - - - -
+    done
+    exit 0
+fi
+
+out="$RESULTS_DIR$1.txt"
+: > "$out"
+
+echo Building the client...
+cargo build --release
+
+for c in "${CONCURRENCY[@]}"; do
+    echo "Running $OPS operations with concurrency $c"
+    cargo run --release cli $BOOTSTRAP_IP $BOOTSTRAP_PORT bench --ops $OPS --concurrency $c --seed 1 | tee -a "$out"
+done
+
+echo "Results written to $out"
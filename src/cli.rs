@@ -2,27 +2,95 @@
 
 use std::env;
 use std::io::{self, Write, Read};
-use std::net::{TcpStream, Ipv4Addr, TcpListener};
+use std::net::{TcpStream, Ipv4Addr, SocketAddr, TcpListener};
 use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use serde::Deserialize;
 use serde_json::Value;
+use chrono::{DateTime, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use hdrhistogram::Histogram;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-use crate::messages::{MsgType,MsgData,Message}; 
+use crate::messages::{MsgType,MsgData,Message,QueryKind,ReadPreference}; 
 use crate::node::NodeInfo;  
-use crate::utils::get_local_ip;
+use crate::utils::{get_local_ip, CompactStrategy, Consistency, HashIP, HashType, Item};
+use crate::network;
+
+// silences the per request connection logs while a progress bar is drawn
+static QUIET: AtomicBool = AtomicBool::new(false);
+// requests go out wrapped in a Proxy and the reply comes back on the same connection
+static SAME_CONNECTION: AtomicBool = AtomicBool::new(false);
+// response listener kept open by a repl session and reused by every request sent on its port
+static SESSION_LISTENER: OnceLock<std::net::TcpListener> = OnceLock::new();
+// reactor for threads outside the runtime (bench workers, lag and demo pollers) to bind their response port with
+static BIND_RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
 
 
 /// Sends a request to the node and reads a response.
 fn send_request(ip: Ipv4Addr, port: u16, request_msg: &Message) -> Result<String, String> {
-    let request = serde_json::json!(request_msg).to_string();
+    match send_request_data(ip, port, request_msg)? {
+        MsgData::Reply { reply } => Ok(reply),
+        _ => Err("Unexpected message data".to_string())
+    }
+}
+
+/// Sends a request to the node and returns the raw data of its response.
+/// A read answered by an offline node is sent once more to the node it names, writes are
+/// left to the caller since an insert that did land would be appended twice.
+fn send_request_data(ip: Ipv4Addr, port: u16, request_msg: &Message) -> Result<MsgData, String> {
+    let data = send_request_to(ip, port, request_msg)?;
+    let is_read = matches!(request_msg.extract_type(), MsgType::Query | MsgType::MultiGet | MsgType::MultiQuery | MsgType::QueryAll);
+    let retry_at = match &data {
+        MsgData::Reply { reply } if is_read => offline_retry_at(reply),
+        _ => None,
+    };
+    match retry_at {
+        Some(addr) => {
+            if !QUIET.load(Ordering::Relaxed) {
+                eprintln!("Node {}:{} is offline, retrying at {}", ip, port, addr);
+            }
+            send_request_to(*addr.ip(), addr.port(), request_msg)
+        }
+        None => Ok(data),
+    }
+}
+
+// the node an offline node's reply names for retries
+fn offline_retry_at(reply: &str) -> Option<std::net::SocketAddrV4> {
+    let rest = reply.split("Error: Offline:").nth(1)?;
+    rest.split("retry at ").nth(1)?.split_whitespace().next()?.parse().ok()
+}
+
+fn send_request_to(ip: Ipv4Addr, port: u16, request_msg: &Message) -> Result<MsgData, String> {
+    if SAME_CONNECTION.load(Ordering::Relaxed) {
+        return send_request_same_connection(ip, port, request_msg);
+    }
+    let request = request_msg.to_wire();
     let address = format!("{}:{}", ip, port);
     let response_ip = get_local_ip();
-    let response_port = port + (process::id() % 1000) as u16;
-    eprintln!("Sending request to {}: {}", address, request);
+    // the node replies to the client named in the request, default to the usual per process port
+    let response_port = request_msg.extract_client()
+        .map(|client| client.get_port())
+        .unwrap_or(port + (process::id() % 1000) as u16);
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("Sending request to {}: {}", address, request);
+        if let Some(trace_id) = request_msg.extract_trace_id() {
+            eprintln!("Trace id: {} (grep the node logs for it)", trace_id);
+        }
+    }
     let response_address = format!("{}:{}", response_ip, response_port);
 
     // 🚀 Step 1: Start a listening socket on response_port
-    let listener = TcpListener::bind(&response_address).map_err(|e| format!("Failed to bind response port: {}", e))?;
-    eprintln!("Listening for response on {}", response_address);
+    let listener = response_listener(response_port)
+        .map_err(|e| format!("Failed to bind response port: {}", e))?;
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("Listening for response on {}", response_address);
+    }
 
     // 🚀 Step 2: Send request to the node, including the response port
     let full_request = format!("{}", request);
@@ -31,183 +99,1845 @@ fn send_request(ip: Ipv4Addr, port: u16, request_msg: &Message) -> Result<String
             writeln!(stream, "{}", full_request).map_err(|e| format!("Failed to send request: {}", e))?;
             stream.flush().map_err(|e| format!("Failed to flush request: {}", e))?;
         }
-        Err(e) => return Err(format!("Could not connect to node at {}: {}", address, e)),
-    }
-
-    // 🚀 Step 3: Accept response connection and read response
-    match listener.accept() {
-        Ok((mut response_stream, _)) => {
-            let mut buffer = [0; 1024];
-            let mut response = Vec::new();
-
-            loop {
-                match response_stream.read(&mut buffer) {
-                    Ok(0) => break, // Connection closed
-                    Ok(n) => response.extend_from_slice(&buffer[..n]),
-                    Err(e) => return Err(format!("Failed to read response: {}", e)),
-                }
+        Err(e) => return Err(format!("Could not connect to node at {}: {}", address, e)),
+    }
+
+    // 🚀 Step 3: Accept response connection and read response
+    let response_stream = accept_within(&listener, reply_timeout(ip, port))?;
+    read_response(response_stream)
+}
+
+// ring size seen by the last overlay through the node at ip:port, kept across cli runs
+// one file per node, so rings sharing a host don't shorten each other's timeouts
+fn ring_size_cache(ip: Ipv4Addr, port: u16) -> std::path::PathBuf {
+    env::temp_dir().join(format!("chord-dht-ring-size-{}-{}", ip, port))
+}
+
+fn cached_ring_size(ip: Ipv4Addr, port: u16) -> usize {
+    std::fs::read_to_string(ring_size_cache(ip, port)).ok()
+        .and_then(|size| size.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+fn cache_ring_size(ip: Ipv4Addr, port: u16, size: usize) {
+    if let Err(e) = std::fs::write(ring_size_cache(ip, port), size.to_string()) {
+        eprintln!("Warning: could not cache the ring size: {}", e);
+    }
+}
+
+/// How long a request to the node at ip:port waits for its reply, longer for rings that
+/// took more nodes at the last `overlay` through it.
+fn reply_timeout(ip: Ipv4Addr, port: u16) -> Duration {
+    crate::utils::ring_timeout(crate::REPLY_TIMEOUT_MS, cached_ring_size(ip, port))
+}
+
+/// Accepts the node's reply connection, giving up after `limit`, which also bounds every read from it.
+fn accept_within(listener: &std::net::TcpListener, limit: Duration) -> Result<TcpStream, String> {
+    listener.set_nonblocking(true).map_err(|e| format!("Failed to poll response port: {}", e))?;
+    let deadline = Instant::now() + limit;
+    let accepted = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break Ok(stream),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock =>
+                break Err(format!("No reply within {:?}, run overlay if the ring has grown", limit)),
+            Err(e) => break Err(format!("Failed to accept response connection: {}", e)),
+        }
+    };
+    listener.set_nonblocking(false).map_err(|e| format!("Failed to reset response port: {}", e))?;
+    let stream = accepted?;
+    stream.set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(limit)))
+        .map_err(|e| format!("Failed to set up response connection: {}", e))?;
+    Ok(stream)
+}
+
+/// Sends a request wrapped in a Proxy and reads the reply from the same connection,
+/// so the node never has to connect back to the client.
+fn send_request_same_connection(ip: Ipv4Addr, port: u16, request_msg: &Message) -> Result<MsgData, String> {
+    let proxy_msg = Message::new(
+        MsgType::Proxy,
+        request_msg.extract_client(),
+        &MsgData::Proxy { msg_type: request_msg.extract_type(), data: Box::new(request_msg.extract_data()) }
+    );
+    let request = proxy_msg.to_wire();
+    let address = format!("{}:{}", ip, port);
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("Sending request to {} over one connection: {}", address, request);
+        if let Some(trace_id) = proxy_msg.extract_trace_id() {
+            eprintln!("Trace id: {} (grep the node logs for it)", trace_id);
+        }
+    }
+    let mut stream = TcpStream::connect(&address)
+        .map_err(|e| format!("Could not connect to node at {}: {}", address, e))?;
+    stream.write_all(format!("{}\n", request).as_bytes()).map_err(|e| format!("Failed to send request: {}", e))?;
+    stream.flush().map_err(|e| format!("Failed to flush request: {}", e))?;
+    stream.set_read_timeout(Some(reply_timeout(ip, port))).map_err(|e| format!("Failed to set reply timeout: {}", e))?;
+    read_response(stream)
+}
+
+/// Binds the response port of a request, or shares the listener a repl session keeps open on it.
+fn response_listener(port: u16) -> io::Result<std::net::TcpListener> {
+    let session = SESSION_LISTENER.get()
+        .filter(|listener| listener.local_addr().is_ok_and(|addr| addr.port() == port));
+    if let Some(listener) = session {
+        return listener.try_clone();
+    }
+    let _reactor = match tokio::runtime::Handle::try_current() {
+        Ok(_) => None,
+        Err(_) => Some(BIND_RUNTIME.get_or_init(|| {
+            tokio::runtime::Builder::new_current_thread().enable_io().build().expect("Failed to start the bind runtime")
+        }).enter())
+    };
+    network::bind_listener(SocketAddr::new(get_local_ip().into(), port), crate::LISTEN_BACKLOG)
+        .and_then(|listener| listener.into_std())
+        .and_then(|listener| listener.set_nonblocking(false).map(|_| listener))
+}
+
+/// Reads one message pushed by a node until it closes the connection and returns its data.
+fn read_response(mut response_stream: TcpStream) -> Result<MsgData, String> {
+    let mut buffer = [0; 1024];
+    let mut response = Vec::new();
+
+    loop {
+        match response_stream.read(&mut buffer) {
+            Ok(0) => break, // Connection closed
+            Ok(n) => response.extend_from_slice(&buffer[..n]),
+            Err(e) => return Err(format!("Failed to read response: {}", e)),
+        }
+    }
+
+    let response_str = String::from_utf8_lossy(&response).to_string();
+
+    // 🚀 Step 4: Deserialize and extract the data part
+
+    let json_value: Value = match serde_json::from_str(&response_str) {
+        Ok(value) => value,
+        Err(e) => return Err(format!("Failed to deserialize message: {}", e))
+    };
+
+    // Convert Value to Message
+    let msg: Message = match serde_json::from_value(json_value) {
+        Ok(msg) => msg,
+        Err(e) => return Err(format!("Failed to convert JSON value to Message: {}", e))
+    };
+    // extract only the data part
+    Ok(msg.extract_data())
+}
+
+/// Reads every key of a query file with a single MultiQuery and prints the values in file order.
+fn query_batch(ip: Ipv4Addr, port: u16, lines: &[&str]) {
+    let keys: Vec<String> = lines.iter().map(|line| line.trim().to_string()).filter(|key| !key.is_empty()).collect();
+    let request = Message::new(
+        MsgType::MultiQuery,
+        Some(&NodeInfo::new(get_local_ip(), port + (process::id() % 1000) as u16)),
+        &MsgData::MultiQuery { keys: keys.clone() }
+    );
+    match send_request_data(ip, port, &request) {
+        Ok(MsgData::MultiQueryResult { results }) => {
+            let values: std::collections::HashMap<String, Option<String>> = results.into_iter().collect();
+            let mut found = 0;
+            for key in keys.iter() {
+                match values.get(key).cloned().flatten() {
+                    Some(value) => {
+                        found += 1;
+                        println!("(🔑{} : 🔒{})", key, value);
+                    }
+                    None => println!("(🔑{} : doesn't exist)", key),
+                }
+            }
+            println!("{} of {} key(s) found", found, keys.len());
+        }
+        Ok(MsgData::Reply { reply }) => println!("{}", reply),
+        Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+/// Subscribes to a key and prints every change the primary pushes until the process is stopped.
+/// The response port stays bound, the node drops the subscription once it cannot reach it.
+fn subscribe(ip: Ipv4Addr, port: u16, key: &str) {
+    let client = NodeInfo::new(get_local_ip(), port + (process::id() % 1000) as u16);
+    let listener = match response_listener(client.get_port()) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: Failed to bind response port: {}", e);
+            return;
+        }
+    };
+    let request = Message::new(MsgType::Subscribe, Some(&client), &MsgData::Subscribe { key: key.to_string() });
+    let sent = TcpStream::connect((ip, port)).and_then(|mut stream| {
+        writeln!(stream, "{}", request.to_wire())?;
+        stream.flush()
+    });
+    if let Err(e) = sent {
+        eprintln!("Error: Could not connect to node at {}:{}: {}", ip, port, e);
+        return;
+    }
+
+    for stream in listener.incoming() {
+        let data = match stream {
+            Ok(stream) => read_response(stream),
+            Err(e) => Err(format!("Failed to accept connection: {}", e)),
+        };
+        match data {
+            Ok(MsgData::Reply { reply }) => println!("{}", reply),
+            Ok(MsgData::Notify { key, item: Some(item) }) =>
+                println!("[{}] 🔑{} changed: 🔒{} (🕰️ {})", Utc::now(), key, item.value, item.timestamp),
+            Ok(MsgData::Notify { key, item: None }) => println!("[{}] 🔑{} deleted", Utc::now(), key),
+            Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+}
+
+
+/// The value of `insert <key> <value>`: given inline, piped through `input` ('-') or read whole
+/// from a file. None when it is missing, an error when stdin or the file cannot be read.
+fn insert_value(args: &[String], mut input: impl Read) -> Result<Option<String>, String> {
+    match args.get(6).map(|arg| arg.as_str()) {
+        Some("-") => {
+            let mut buffer = String::new();
+            input.read_to_string(&mut buffer).map_err(|e| format!("Failed to read value from stdin: {}", e))?;
+            Ok(Some(buffer))
+        }
+        Some("--from-file") => {
+            let filename = args.get(7).ok_or("Missing <file> after --from-file")?;
+            std::fs::read_to_string(filename).map(Some).map_err(|e| format!("Failed to read file '{}': {}", filename, e))
+        }
+        Some(inline) => Ok(Some(inline.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Parses one positional argument, the error names what was expected.
+fn parse_arg<T: std::str::FromStr>(arg: Option<&String>, what: &str) -> Result<T, String>
+where T::Err: std::fmt::Display {
+    match arg {
+        Some(value) => value.parse().map_err(|e| format!("Invalid {} '{}': {}", what, value, e)),
+        None => Err(format!("Missing {}", what)),
+    }
+}
+
+/// Parses the value following the flag `name`, or returns `default` when the flag is absent.
+fn flag_value<T: std::str::FromStr>(args: &[String], name: &str, default: T) -> Result<T, String>
+where T::Err: std::fmt::Display {
+    match args.iter().position(|arg| arg == name) {
+        Some(pos) => parse_arg(args.get(pos + 1), &format!("value for {}", name)),
+        None => Ok(default),
+    }
+}
+
+/// Reports a malformed argument and ends the process with a non-zero exit code.
+fn exit_invalid(msg: String) -> ! {
+    eprintln!("Error: {}", msg);
+    process::exit(1);
+}
+
+/// Reads a request or seed file, the error names the file that could not be read.
+fn read_input_file(filename: &str) -> Result<String, String> {
+    std::fs::read_to_string(filename).map_err(|e| format!("Failed to read file '{}': {}", filename, e))
+}
+
+/// Parses the `<ip> <port>` of the node a cli invocation talks to.
+fn node_address(args: &[String]) -> Result<(Ipv4Addr, u16), String> {
+    Ok((parse_arg(args.get(2), "IP address")?, parse_arg(args.get(3), "port number")?))
+}
+
+/// Parses intervals such as "500ms", "2s" or a plain number of milliseconds.
+fn parse_interval(arg: &str) -> Option<Duration> {
+    if let Some(ms) = arg.strip_suffix("ms") {
+        ms.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = arg.strip_suffix('s') {
+        secs.parse().ok().map(Duration::from_secs)
+    } else {
+        arg.parse().ok().map(Duration::from_millis)
+    }
+}
+
+/// Renders the ring as a Graphviz digraph: one vertex per node labelled with its
+/// address, id and primary record count, and an edge from every node to its successor.
+fn format_ring_dot(peers: &[NodeInfo], counts: &[usize]) -> String {
+    let mut dot = String::from("digraph ring {\n    node [shape=box];\n");
+    for (i, peer) in peers.iter().enumerate() {
+        let records = counts.get(i).copied().unwrap_or(0);
+        let label = format!("{}\\n{}\\nrecords: {}",
+                            dot_escape(&format!("{}:{}", peer.get_ip(), peer.get_port())), dot_escape(&peer.get_id().to_string()), records);
+        dot.push_str(&format!("    \"{}\" [label=\"{}\"];\n", peer.get_id(), label));
+    }
+    // a single node is its own successor, which renders as a self loop
+    for (i, peer) in peers.iter().enumerate() {
+        let succ = &peers[(i + 1) % peers.len()];
+        dot.push_str(&format!("    \"{}\" -> \"{}\";\n", peer.get_id(), succ.get_id()));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Escapes text for a DOT string literal, which only needs quotes and backslashes escaped.
+fn dot_escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Renders the per node load of an overlay sweep: primary records and stored bytes
+/// (replicas included). Shares and the max/mean skew are taken over records, or over
+/// bytes with `by_bytes`, which also orders the nodes by bytes instead of ring order.
+fn format_load(peers: &[NodeInfo], counts: &[usize], bytes: &[u64], by_bytes: bool) -> String {
+    let load = |i: usize| if by_bytes { bytes.get(i).copied().unwrap_or(0) } else { counts.get(i).copied().unwrap_or(0) as u64 };
+    let mut order: Vec<usize> = (0..peers.len()).collect();
+    if by_bytes {
+        order.sort_by_key(|&i| std::cmp::Reverse(load(i)));
+    }
+    let total: u64 = (0..peers.len()).map(load).sum();
+    let mut out = format!("{:<22} {:>10} {:>14} {:>7}\n", "node", "records", "bytes", "share");
+    for &i in order.iter() {
+        let share = if total > 0 { 100.0 * load(i) as f64 / total as f64 } else { 0.0 };
+        out.push_str(&format!("{:<22} {:>10} {:>14} {:>6.1}%\n",
+                              format!("{}:{}", peers[i].get_ip(), peers[i].get_port()),
+                              counts.get(i).copied().unwrap_or(0), bytes.get(i).copied().unwrap_or(0), share));
+    }
+    let max = (0..peers.len()).map(load).max().unwrap_or(0);
+    let mean = total as f64 / peers.len().max(1) as f64;
+    let skew = if mean > 0.0 { max as f64 / mean } else { 1.0 };
+    out.push_str(&format!("{} skew (max/mean): {:.2}\n", if by_bytes { "byte" } else { "record" }, skew));
+    out
+}
+
+/// Reads `--consistency <mode>`, which overrides the ring's consistency for one operation.
+fn consistency_flag(args: &[String]) -> Result<Option<Consistency>, String> {
+    match args.iter().position(|arg| arg == "--consistency").and_then(|pos| args.get(pos + 1)) {
+        Some(mode) => mode.parse().map(Some).map_err(|e| format!("Invalid consistency: {} ({})", mode, e)),
+        None => Ok(None),
+    }
+}
+
+/// Reads `--colocate-with <parent>`, the key whose node should also store this one.
+fn colocate_flag(args: &[String]) -> Option<String> {
+    args.iter().position(|arg| arg == "--colocate-with").and_then(|pos| args.get(pos + 1)).cloned()
+}
+
+/// Reads `--max-value-len <n>`, the most bytes an appended value keeps, oldest dropped first.
+fn max_value_len_flag(args: &[String]) -> Result<Option<usize>, String> {
+    if !args.iter().any(|arg| arg == "--max-value-len") {
+        return Ok(None);
+    }
+    match flag_value(args, "--max-value-len", 0)? {
+        0 => Err("value for --max-value-len must be > 0".to_string()),
+        limit => Ok(Some(limit)),
+    }
+}
+
+/// Parses a node id given either as 40 hex digits or as the `<ip>:<port>` the node listens on.
+/// A weighted node is placed away from the id its address hashes to, so an address is looked up
+/// in the ring first and only hashed when no member listens on it.
+pub(crate) fn parse_node_id(node_ip: Ipv4Addr, node_port: u16, arg: &str) -> Option<HashType> {
+    HashType::from_hex(arg).ok().or_else(|| {
+        let addr = arg.parse::<std::net::SocketAddrV4>().ok()?;
+        Some(ring_member(node_ip, node_port, addr).map_or(HashIP(*addr.ip(), addr.port()), |member| member.get_id()))
+    })
+}
+
+/// The member of the ring listening on `addr`, as the overlay from the node asked lists it.
+fn ring_member(node_ip: Ipv4Addr, node_port: u16, addr: std::net::SocketAddrV4) -> Option<NodeInfo> {
+    let overlay = Message::new(
+        MsgType::Overlay,
+        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+        &MsgData::Overlay { structured: true }
+    );
+    match send_request_data(node_ip, node_port, &overlay) {
+        Ok(MsgData::Ring { peers, .. }) => peers.into_iter().find(|peer| (peer.get_ip(), peer.get_port()) == (*addr.ip(), addr.port())),
+        _ => None,
+    }
+}
+
+/// Drives a bulk file operation: a progress bar, per line output with `--verbose`,
+/// and a final summary. With `--dry-run` lines are counted but nothing is sent.
+struct BulkProgress {
+    bar: ProgressBar,
+    verbose: bool,
+    dry_run: bool,
+    succeeded: u64,
+    failed: u64,
+    started: Instant,
+}
+
+impl BulkProgress {
+    fn new(total: usize, args: &[String]) -> Self {
+        let verbose = args.iter().any(|arg| arg == "--verbose");
+        let bar = ProgressBar::new(total as u64);
+        bar.set_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} [{elapsed_precise}] {per_sec} ETA {eta}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+        );
+        QUIET.store(!verbose, Ordering::Relaxed);
+        BulkProgress {
+            bar,
+            verbose,
+            dry_run: args.iter().any(|arg| arg == "--dry-run"),
+            succeeded: 0,
+            failed: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Sends one request of the batch and returns the reply if one arrived.
+    fn send(&mut self, ip: Ipv4Addr, port: u16, request: &Message) -> Option<String> {
+        self.bar.inc(1);
+        if self.dry_run {
+            self.succeeded += 1;
+            return None;
+        }
+        match send_request(ip, port, request) {
+            Ok(response) => {
+                if response.starts_with("Error") { self.failed += 1; } else { self.succeeded += 1; }
+                if self.verbose { self.bar.println(&response); }
+                Some(response)
+            }
+            Err(e) => {
+                self.failed += 1;
+                if self.verbose { self.bar.println(format!("Error: {}", e)); }
+                None
+            }
+        }
+    }
+
+    /// Counts a line that could not be turned into a request.
+    fn reject(&mut self, reason: &str) {
+        self.bar.inc(1);
+        self.failed += 1;
+        if self.verbose { self.bar.println(reason); }
+    }
+
+    /// Prints the summary line and returns it.
+    fn finish(self) -> String {
+        self.bar.finish_and_clear();
+        QUIET.store(false, Ordering::Relaxed);
+        let summary = format!("{}{} request(s): {} succeeded, {} failed in {:.2?}",
+                              if self.dry_run { "[dry-run] " } else { "" },
+                              self.succeeded + self.failed, self.succeeded, self.failed, self.started.elapsed());
+        println!("{}", summary);
+        summary
+    }
+}
+
+/// Runs a mixed request file, one `insert, <key>, <value>` or `query, <key>` per line,
+/// and returns the summary line. Replies to queries go to `<file>_response.txt`.
+fn run_requests_file(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<String, String> {
+    let filename = args[5].as_str();
+    let file_content = read_input_file(filename)?;
+    let response_filename = format!("{}_response.txt", filename);
+    let mut response_file = std::fs::File::create(&response_filename)
+        .map_err(|e| format!("Failed to create response file '{}': {}", response_filename, e))?;
+    let lines: Vec<&str> = file_content.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut progress = BulkProgress::new(lines.len(), args);
+    for line in lines {
+        let request: Vec<&str> = line.split(", ").collect();
+        match request[0] {
+            "insert" => {
+                let request = Message::new(
+                    MsgType::Insert,
+                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                    &MsgData::Insert { key: request[1].to_string(), value: request[2].to_string(), wait_for_replication: false, consistency: None, colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None }
+                );
+                progress.send(node_ip, node_port, &request);
+            }
+            "query" => {
+                let request = Message::new(
+                    MsgType::Query,
+                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                    &MsgData::Query { key: request[1].to_string(), kind: QueryKind::Value, prefer: ReadPreference::Nearest, consistency: None, colocate_with: None }
+                );
+                if let Some(response) = progress.send(node_ip, node_port, &request) {
+                    writeln!(response_file, "Request: {} | Response: {}", line, response)
+                    .expect("Failed to write to response file");
+                }
+            }
+            _ => {
+                progress.reject(&format!("Invalid request type: {}", request[0]));
+            }
+        }
+    }
+    Ok(progress.finish())
+}
+
+/// Drives a synthetic workload against one node and prints latency percentiles.
+/// Each worker owns its own response port so requests can be in flight concurrently.
+/// The summary is a single `key=value` line so CI can diff runs.
+pub(crate) fn run_bench(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<String, String> {
+    let flag = |name: &str| args.iter().position(|arg| arg == name).and_then(|pos| args.get(pos + 1));
+    let ops: usize = flag_value(args, "--ops", 1000)?;
+    let concurrency: usize = flag_value(args, "--concurrency", 1)?;
+    let keys: usize = flag_value(args, "--keys", 1000)?;
+    // makes Eventual inserts reply only once every replica holds the value
+    let wait_for_replication = args.iter().any(|arg| arg == "--wait-replication");
+    let read_ratio: f64 = match flag("--mode").map(|mode| mode.as_str()) {
+        None | Some("mixed") => flag_value(args, "--read-ratio", 0.5)?,
+        Some("insert") => 0.0,
+        Some("query") => 1.0,
+        Some(mode) => return Err(format!("Invalid bench mode: {} [mixed | insert | query]", mode)),
+    };
+    if !(0.0..=1.0).contains(&read_ratio) {
+        return Err(format!("Invalid --read-ratio: {} [0.0 - 1.0]", read_ratio));
+    }
+    let concurrency = concurrency.clamp(1, ops.max(1));
+    let keys = keys.max(1);
+    let seed: Option<u64> = args.iter().position(|arg| arg == "--seed")
+        .map(|pos| parse_arg(args.get(pos + 1), "value for --seed")).transpose()?;
+
+    QUIET.store(true, Ordering::Relaxed);
+    let started = Instant::now();
+    let results: Vec<(Histogram<u64>, u64, u64)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency).map(|worker| {
+            let worker_ops = ops / concurrency + if worker < ops % concurrency { 1 } else { 0 };
+            scope.spawn(move || {
+                let client = NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16 + 1 + worker as u16);
+                let mut latencies = Histogram::<u64>::new_with_bounds(1, 60_000_000, 3).expect("Invalid histogram bounds");
+                let (mut ok, mut failed) = (0u64, 0u64);
+                // workers draw from their own stream of the seed so a seeded run repeats its workload
+                let mut rng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(worker as u64)),
+                    None => StdRng::from_entropy()
+                };
+                for _ in 0..worker_ops {
+                    let key = format!("bench_{}", rng.gen_range(0..keys));
+                    let data = if rng.gen_bool(read_ratio) {
+                        MsgData::Query { key, kind: QueryKind::Value, prefer: ReadPreference::Nearest, consistency: None, colocate_with: None }
+                    } else {
+                        MsgData::Insert { value: format!("{}", rng.gen::<u32>()), key, wait_for_replication, consistency: None, colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None }
+                    };
+                    let msg_type = match data {
+                        MsgData::Query { .. } => MsgType::Query,
+                        _ => MsgType::Insert
+                    };
+                    let request = Message::new(msg_type, Some(&client), &data);
+                    let sent = Instant::now();
+                    match send_request(node_ip, node_port, &request) {
+                        Ok(_) => {
+                            ok += 1;
+                            latencies.saturating_record(sent.elapsed().as_micros() as u64);
+                        }
+                        Err(_) => failed += 1,
+                    }
+                }
+                (latencies, ok, failed)
+            })
+        }).collect();
+        workers.into_iter().map(|worker| worker.join().expect("Bench worker panicked")).collect()
+    });
+    let elapsed = started.elapsed().as_secs_f64();
+    QUIET.store(false, Ordering::Relaxed);
+
+    let mut latencies = Histogram::<u64>::new_with_bounds(1, 60_000_000, 3).expect("Invalid histogram bounds");
+    let (mut ok, mut failed) = (0u64, 0u64);
+    for (worker_latencies, worker_ok, worker_failed) in results.iter() {
+        latencies.add(worker_latencies).expect("Failed to merge latencies");
+        ok += worker_ok;
+        failed += worker_failed;
+    }
+    Ok(format!("ops={} ok={} failed={} concurrency={} read_ratio={:.2} elapsed_s={:.3} throughput_ops={:.1} p50_us={} p90_us={} p99_us={} max_us={}",
+            ops, ok, failed, concurrency, read_ratio, elapsed,
+            if elapsed > 0.0 { ok as f64 / elapsed } else { 0.0 },
+            latencies.value_at_quantile(0.5), latencies.value_at_quantile(0.9),
+            latencies.value_at_quantile(0.99), latencies.max()))
+}
+
+/// Reads a key as a typed record so the demo can compare values, `None` when the key is absent.
+fn demo_read(ip: Ipv4Addr, port: u16, client: &NodeInfo, key: &str, prefer: ReadPreference) -> Result<Option<String>, String> {
+    read_record(ip, port, client, key, prefer, None).map(|record| record.map(|item| item.value))
+}
+
+/// Queries the stored item of a key, `None` when the key is absent.
+fn read_record(ip: Ipv4Addr, port: u16, client: &NodeInfo, key: &str, prefer: ReadPreference, consistency: Option<Consistency>) -> Result<Option<Item>, String> {
+    let request = Message::new(MsgType::Query, Some(client), &MsgData::Query { key: key.to_string(), kind: QueryKind::Record, prefer, consistency, colocate_with: None });
+    match send_request_data(ip, port, &request)? {
+        MsgData::Record { record, .. } => Ok(record),
+        MsgData::Reply { reply } => Err(reply),
+        other => Err(format!("Unexpected message data {:?}", other)),
+    }
+}
+
+// number of nodes listed in a replica-set reply
+fn replica_count(reply: &str) -> Option<usize> {
+    reply.split("replicas on ").nth(1)?.split_whitespace().next()?.parse().ok()
+}
+
+/// Writes a key, then polls every replica index until it holds the version the primary stored,
+/// and prints how long after the ack each one converged. Replica reads are sent as Eventual
+/// so they reach the indexed copy even on a Chain ring, where every copy is written before the ack.
+/// Returns the lag of every replica index, None if it did not converge, with the reads it took.
+pub(crate) fn run_lag(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<Vec<(Option<Duration>, usize)>, String> {
+    if args.len() < 2 {
+        println!("Usage:");
+        println!("cargo run cli <ip> <port> lag <key> <value> [--timeout <ms>]");
+        return Ok(Vec::new());
+    }
+    let (key, value) = (&args[0], &args[1]);
+    let timeout = Duration::from_millis(flag_value(args, "--timeout", 5000)?);
+    let base = node_port + (process::id() % 1000) as u16;
+    let client = NodeInfo::new(get_local_ip(), base);
+
+    QUIET.store(true, Ordering::Relaxed);
+    let request = Message::new(MsgType::ReplicaSet, Some(&client), &MsgData::ReplicaSet { key: key.clone() });
+    let replicas = send_request(node_ip, node_port, &request).map(|reply| replica_count(&reply))?
+        .ok_or_else(|| format!("could not read the replica set of 🔑 {}", key))?;
+
+    let insert = Message::new(MsgType::Insert, Some(&client), &MsgData::Insert { key: key.clone(), value: value.clone(), wait_for_replication: false,
+                                                                                 consistency: consistency_flag(args)?, colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None });
+    let sent = Instant::now();
+    send_request(node_ip, node_port, &insert).map_err(|e| format!("insert failed: {}", e))?;
+    let acked = Instant::now();
+    // the version to wait for, a key that already existed got the value appended
+    let written = read_record(node_ip, node_port, &client, key, ReadPreference::Primary, Some(Consistency::Eventual))?
+        .ok_or_else(|| format!("🔑 {} is missing at the primary after the insert", key))?
+        .version();
+
+    // one poller per replica index, each on its own response port
+    let lags: Vec<(Option<Duration>, usize)> = thread::scope(|scope| {
+        let pollers: Vec<_> = (0..replicas).map(|idx| {
+            let reader = NodeInfo::new(get_local_ip(), base + 1 + idx as u16);
+            scope.spawn(move || {
+                let mut reads = 0;
+                while acked.elapsed() < timeout {
+                    reads += 1;
+                    let read = read_record(node_ip, node_port, &reader, key, ReadPreference::Replica(idx as u8), Some(Consistency::Eventual));
+                    if matches!(read, Ok(Some(ref item)) if item.version() >= written) {
+                        return (Some(acked.elapsed()), reads);
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                (None, reads)
+            })
+        }).collect();
+        pollers.into_iter().map(|poller| poller.join().expect("Lag poller panicked")).collect()
+    });
+    QUIET.store(false, Ordering::Relaxed);
+
+    println!("🔑 {}: write acked after {:?}, lag measured from the ack", key, acked - sent);
+    for (idx, (lag, reads)) in lags.iter().enumerate() {
+        match lag {
+            Some(lag) => println!("  replica {}: converged after {:?} ({} read(s))", idx, lag, reads),
+            None => println!("  replica {}: not converged after {:?} ({} read(s))", idx, timeout, reads),
+        }
+    }
+    match lags.iter().map(|(lag, _)| *lag).collect::<Option<Vec<Duration>>>() {
+        Some(lags) => println!("max lag: {:?}", lags.into_iter().max().unwrap_or_default()),
+        None => println!("max lag: over {:?}, some replicas did not converge", timeout),
+    }
+    Ok(lags)
+}
+
+/// Executable check of one consistency mode's guarantee, printed as a pass/fail narrative.
+/// Keys and values come from `--seed` (default 42) and are deleted before the run,
+/// so repeating a seed replays the same scenario.
+fn run_demo(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<(), String> {
+    const ROUNDS: usize = 5;
+    let mode = args.first().map(|mode| mode.as_str()).unwrap_or("");
+    if mode != "chain" && mode != "eventual" && mode != "quorum" {
+        return Err(format!("Invalid demo mode: {} [chain | eventual | quorum]", mode));
+    }
+    let seed: u64 = flag_value(args, "--seed", 42)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let base = node_port + (process::id() % 1000) as u16;
+    let writer = NodeInfo::new(get_local_ip(), base + 1);
+    let reader = NodeInfo::new(get_local_ip(), base + 2);
+    let pairs: Vec<(String, String)> = (0..ROUNDS)
+        .map(|round| (format!("demo_{}_{}", seed, round), format!("v{}", rng.gen::<u32>())))
+        .collect();
+
+    QUIET.store(true, Ordering::Relaxed);
+    for (key, _) in pairs.iter() {
+        let _ = send_request(node_ip, node_port, &Message::new(MsgType::Delete, Some(&writer), &MsgData::Delete { key: key.clone(), consistency: None, colocate_with: None, if_version: None }));
+    }
+    println!("Demo {} with seed {}: {} fresh keys", mode, seed, ROUNDS);
+
+    let mut passed = 0;
+    for (key, value) in pairs.iter() {
+        let insert = Message::new(MsgType::Insert, Some(&writer), &MsgData::Insert { key: key.clone(), value: value.clone(), wait_for_replication: false, consistency: None, colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None });
+        let ok = if mode != "eventual" {
+            /* Chain promises that once the head acks a write every later read sees it, Quorum
+               does too as long as R + W > k. A reader keeps querying while the insert is in flight
+               and one more time after the ack */
+            let done = AtomicBool::new(false);
+            let (acked, reads) = thread::scope(|scope| {
+                let reads = scope.spawn(|| {
+                    let mut reads = Vec::new();
+                    loop {
+                        let after_ack = done.load(Ordering::SeqCst);
+                        reads.push((after_ack, demo_read(node_ip, node_port, &reader, key, ReadPreference::Nearest)));
+                        if after_ack {
+                            return reads;
+                        }
+                    }
+                });
+                let sent = Instant::now();
+                let acked = send_request(node_ip, node_port, &insert).map(|_| sent.elapsed());
+                done.store(true, Ordering::SeqCst);
+                (acked, reads.join().expect("Demo reader panicked"))
+            });
+            let during = reads.iter().filter(|(after_ack, _)| !after_ack).count();
+            let torn = reads.iter().filter(|(_, read)| matches!(read, Ok(Some(seen)) if seen != value)).count();
+            let after = reads.iter().filter(|(after_ack, _)| *after_ack).all(|(_, read)| matches!(read, Ok(Some(seen)) if seen == value));
+            match acked {
+                Ok(elapsed) => println!("  🔑{}: acked after {:?}, {} read(s) in flight, {} with a foreign value, read after ack {}",
+                                        key, elapsed, during, torn, if after { "saw the write" } else { "MISSED the write" }),
+                Err(ref e) => println!("  🔑{}: insert failed: {}", key, e),
+            }
+            acked.is_ok() && torn == 0 && after
+        } else {
+            /* Eventual only promises convergence. Reads right after the ack may hit a replica
+               the update hasn't reached yet, but every replica must agree within the timeout */
+            if let Err(e) = send_request(node_ip, node_port, &insert) {
+                println!("  🔑{}: insert failed: {}", key, e);
+                continue;
+            }
+            let stale = (0..10)
+                .filter(|_| !matches!(demo_read(node_ip, node_port, &reader, key, ReadPreference::Random), Ok(Some(ref seen)) if seen == value))
+                .count();
+            let started = Instant::now();
+            let mut converged = None;
+            while started.elapsed() < Duration::from_secs(2) {
+                let agree = (0..10).all(|_| matches!(demo_read(node_ip, node_port, &reader, key, ReadPreference::Random), Ok(Some(ref seen)) if seen == value));
+                if agree {
+                    converged = Some(started.elapsed());
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            match converged {
+                Some(elapsed) => println!("  🔑{}: {} of 10 random replica reads stale right after the ack (allowed), converged within {:?}",
+                                          key, stale, elapsed),
+                None => println!("  🔑{}: {} of 10 reads stale right after the ack, replicas still disagree after 2s", key, stale),
+            }
+            converged.is_some()
+        };
+        if ok {
+            passed += 1;
+        }
+    }
+    QUIET.store(false, Ordering::Relaxed);
+    println!("{} {}: {} of {} rounds met the {} guarantee",
+             if passed == ROUNDS { "PASS" } else { "FAIL" }, mode, passed, ROUNDS,
+             if mode == "eventual" { "convergence" } else { "read-after-ack" });
+    Ok(())
+}
+
+// problems counted in a verify-replicas reply
+fn verify_problem_count(reply: &str) -> Option<usize> {
+    reply.split(": ").nth(1)?.split_whitespace().next()?.parse().ok()
+}
+
+// whether id falls in the arc (lo, hi] of the ring, the whole ring when lo == hi
+fn in_arc(id: HashType, lo: HashType, hi: HashType) -> bool {
+    if lo < hi { lo < id && id <= hi } else { id > lo || id <= hi }
+}
+
+/// One machine-readable health report of the ring, meant to follow a churn scenario.
+/// It walks the overlay for ring invariants, runs verify-replicas on every node and reads
+/// every replica of up to `--sample` keys (default 50) to count routing errors, keys with
+/// missing copies and the largest number of copies of one key that differ from its primary.
+/// Colocated keys are left out of the sample since they cannot be looked up by name alone.
+/// Returns the printed report.
+pub(crate) fn run_health(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<serde_json::Value, String> {
+    let sample: usize = flag_value(args, "--sample", 50)?;
+    let client = NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16);
+    QUIET.store(true, Ordering::Relaxed);
+
+    let overlay = Message::new(MsgType::Overlay, Some(&client), &MsgData::Overlay { structured: true });
+    let peers = match send_request_data(node_ip, node_port, &overlay)? {
+        MsgData::Ring { peers, .. } => peers,
+        other => return Err(format!("Unexpected message data {:?}", other)),
+    };
+    cache_ring_size(node_ip, node_port, peers.len());
+
+    // the overlay follows successors, so ids must rise around the ring with a single wrap
+    let mut ring_violations = Vec::new();
+    let ids: std::collections::HashSet<HashType> = peers.iter().map(|peer| peer.get_id()).collect();
+    if ids.len() != peers.len() {
+        ring_violations.push(format!("{} duplicate id(s) in the overlay", peers.len() - ids.len()));
+    }
+    let wraps = (0..peers.len()).filter(|&i| peers[(i + 1) % peers.len()].get_id() <= peers[i].get_id()).count();
+    if peers.len() > 1 && wraps != 1 {
+        ring_violations.push(format!("successor order wraps {} times instead of once", wraps));
+    }
+
+    let (mut keys, mut replica_problems, mut routing_errors) = (Vec::new(), 0, 0);
+    for (i, peer) in peers.iter().enumerate() {
+        let prev = peers[(i + peers.len() - 1) % peers.len()];
+        let request = Message::new(MsgType::NodeRecords, Some(&client), &MsgData::NodeRecords { target: peer.get_id() });
+        match send_request_data(node_ip, node_port, &request) {
+            Ok(MsgData::Records { records, .. }) => {
+                let misplaced = records.iter().filter(|item| !in_arc(item.key_hash(), prev.get_id(), peer.get_id())).count();
+                if misplaced > 0 {
+                    ring_violations.push(format!("{}:{} is primary of {} key(s) outside its range", peer.get_ip(), peer.get_port(), misplaced));
+                }
+                keys.extend(records.into_iter().filter(|item| item.parent.is_none()).map(|item| item.title));
+            }
+            _ => routing_errors += 1,
+        }
+        let verify = Message::new(MsgType::VerifyReplicas, Some(&client), &MsgData::VerifyReplicas { repair: false, sample: None });
+        match send_request(peer.get_ip(), peer.get_port(), &verify).map(|reply| verify_problem_count(&reply)) {
+            Ok(Some(problems)) => replica_problems += problems,
+            _ => routing_errors += 1,
+        }
+    }
+
+    // every sampled key is read at each replica index, the walk length comes from its replica set
+    let total_keys = keys.len();
+    keys.truncate(sample);
+    let (mut wrong_replica_count, mut max_divergence) = (0, 0);
+    for key in keys.iter() {
+        let request = Message::new(MsgType::ReplicaSet, Some(&client), &MsgData::ReplicaSet { key: key.clone() });
+        let replicas = match send_request(node_ip, node_port, &request).map(|reply| replica_count(&reply)) {
+            Ok(Some(replicas)) => replicas,
+            _ => {
+                routing_errors += 1;
+                continue;
+            }
+        };
+        let mut copies = Vec::new();
+        for idx in 0..replicas {
+            match read_record(node_ip, node_port, &client, key, ReadPreference::Replica(idx as u8), Some(Consistency::Eventual)) {
+                Ok(copy) => copies.push(copy),
+                Err(_) => routing_errors += 1,
+            }
+        }
+        if copies.len() < replicas || copies.iter().any(|copy| copy.is_none()) {
+            wrong_replica_count += 1;
+        }
+        let primary = copies.first().cloned().flatten();
+        let diverging = copies.iter().skip(1).flatten()
+            .filter(|copy| primary.as_ref().is_none_or(|first| copy.value != first.value || copy.version() != first.version()))
+            .count();
+        max_divergence = max_divergence.max(diverging);
+    }
+    QUIET.store(false, Ordering::Relaxed);
+
+    let healthy = ring_violations.is_empty() && replica_problems == 0 && routing_errors == 0
+        && wrong_replica_count == 0 && max_divergence == 0;
+    let report = serde_json::json!({
+        "healthy": healthy,
+        "nodes": peers.len(),
+        "keys": total_keys,
+        "keys_sampled": keys.len(),
+        "wrong_replica_count": wrong_replica_count,
+        "max_replica_divergence": max_divergence,
+        "replica_problems": replica_problems,
+        "routing_errors": routing_errors,
+        "ring_violations": ring_violations,
+    });
+    println!("{}", report);
+    Ok(report)
+}
+
+/// Nodes of a cluster seed file, joined in the order they are listed:
+/// ```toml
+/// [[nodes]]
+/// ip = "10.0.24.45"
+/// port = 8001
+/// ```
+#[derive(Debug, Deserialize)]
+pub(crate) struct SeedFile {
+    nodes: Vec<Seed>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Seed {
+    ip: Ipv4Addr,
+    port: u16,
+}
+
+// delay asked for by a node refusing a join or depart during a topology change
+fn stabilizing_retry_ms(reply: &str) -> Option<u64> {
+    let rest = reply.split("Error: Stabilizing:").nth(1)?;
+    rest.split("retry in ").nth(1)?.split_whitespace().next()?.parse().ok()
+}
+
+pub fn bootstrap_cluster(path: &str) {
+    let content = read_input_file(path).unwrap_or_else(|e| exit_invalid(e));
+    let seeds: SeedFile = match toml::from_str(&content) {
+        Ok(seeds) => seeds,
+        Err(e) => {
+            eprintln!("Invalid seed file {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    match join_seeds(&seeds) {
+        Ok(summary) => println!("{}", summary),
+        Err(summary) => {
+            println!("{}", summary);
+            process::exit(1);
+        }
+    }
+}
+
+/// Asks every seed node, one at a time, to join the ring.
+/// A node already in the ring answers that it is part of the network and is skipped,
+/// so re-running after a partial failure only completes the missing joins.
+/// The summary is an error when any seed failed to join.
+pub(crate) fn join_seeds(seeds: &SeedFile) -> Result<String, String> {
+    let (mut joined, mut present, mut failed) = (0, 0, 0);
+    for seed in seeds.nodes.iter() {
+        let request = Message::new(
+            MsgType::Join,
+            Some(&NodeInfo::new(get_local_ip(), seed.port + (process::id() % 1000) as u16)),
+            &MsgData::Join { }
+        );
+        // a join refused while the ring stabilizes is retried once the window has passed
+        let mut response = send_request(seed.ip, seed.port, &request);
+        for _ in 1..crate::STABILIZE_RETRIES {
+            let wait_ms = match response.as_ref().ok().and_then(|reply| stabilizing_retry_ms(reply)) {
+                Some(wait_ms) => wait_ms,
+                None => break
+            };
+            println!("{}:{} waiting {} ms for the ring to stabilize", seed.ip, seed.port, wait_ms);
+            thread::sleep(Duration::from_millis(wait_ms));
+            response = send_request(seed.ip, seed.port, &request);
+        }
+        match response {
+            Ok(response) if response.contains("already part of the network") => {
+                present += 1;
+                println!("{}:{} already in the ring", seed.ip, seed.port);
+            }
+            Ok(response) if response.starts_with("Error") || response.contains("offline") => {
+                failed += 1;
+                eprintln!("{}:{} failed to join: {}", seed.ip, seed.port, response);
+            }
+            Ok(response) => {
+                joined += 1;
+                println!("{}", response);
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}:{} failed to join: {}", seed.ip, seed.port, e);
+            }
+        }
+    }
+    let summary = format!("{} seed(s): {} joined, {} already present, {} failed", seeds.nodes.len(), joined, present, failed);
+    if failed > 0 { Err(summary) } else { Ok(summary) }
+}
+
+/// CLI routine to send requests to the chord network.
+pub fn run_cli() {
+    // a global option, taken out so that commands never see it among their arguments
+    let same_connection = env::args().any(|arg| arg == "--same-connection");
+    SAME_CONNECTION.store(same_connection, Ordering::Relaxed);
+    let args: Vec<String> = env::args().filter(|arg| arg != "--same-connection").collect();
+    if args.len() < 5 {
+        eprintln!("Usage: cargo run cli [--same-connection] <ip> <port> <command> [args]");
+        process::exit(1);
+    }
+
+    let (node_ip, node_port) = node_address(&args).unwrap_or_else(|e| exit_invalid(e));
+    if args[4] == "repl" {
+        run_repl(node_ip, node_port, &args);
+        return;
+    }
+    if !run_command(node_ip, node_port, &args) {
+        process::exit(1);
+    }
+}
+
+/// Runs one cli command, `args` laid out as on the command line with the command at index 4.
+/// Returns false on a usage error, which ends the process outside of a repl session.
+pub(crate) fn run_command(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> bool {
+    match execute_command(node_ip, node_port, args) {
+        Ok(done) => done,
+        Err(e) => {
+            // a command that failed half way must not leave a repl session quiet
+            QUIET.store(false, Ordering::Relaxed);
+            eprintln!("Error: {}", e);
+            false
+        }
+    }
+}
+
+/// Body of `run_command`, a malformed argument or unreadable file comes back as the error.
+fn execute_command(node_ip: Ipv4Addr, node_port: u16, args: &[String]) -> Result<bool, String> {
+    let command = args[4].as_str();
+    match command {
+        "insert" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> insert [<key> <value> | <key> - | <key> --from-file <file> | -f <file>]");
+                return Ok(false);
+            }
+
+            if args[5] == "-f" {
+                let filename = args.get(6).ok_or("Missing <file> after -f")?;
+                let file_content = read_input_file(filename)?;
+                let lines: Vec<&str> = file_content.lines().filter(|line| !line.trim().is_empty()).collect();
+                let mut progress = BulkProgress::new(lines.len(), args);
+                for line in lines {
+                    let request = Message::new(
+                        MsgType::Insert,
+                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                        &MsgData::Insert { key: line.trim().to_string(), value: line.trim().to_string(), wait_for_replication: false, consistency: None, colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None }
+                    );
+                    progress.send(node_ip, node_port, &request);
+                }
+                progress.finish();
+                return Ok(true);
+            }
+
+            let value = match insert_value(args, io::stdin()) {
+                Ok(Some(value)) => value,
+                Err(e) => return Err(e),
+                Ok(None) => {
+                    println!("Usage:");
+                    println!("cargo run cli <ip> <port> insert [<key> <value> | <key> - | <key> --from-file <file> | -f <file>]");
+                    return Ok(false);
+                }
+            };
+
+            let request = Message::new(
+                MsgType::Insert,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Insert { key: args[5].to_string(), value, wait_for_replication: false, consistency: consistency_flag(args)?, colocate_with: colocate_flag(args),
+                                   max_value_len: max_value_len_flag(args)?, create_only: args.iter().any(|arg| arg == "--create-only"),
+                                   json: args.iter().any(|arg| arg == "--json"), copy_of: None }
+            );
+        
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "delete" => {
+            if args.len() < 5 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> delete <key>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::Delete,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Delete { key: args[5].to_string(), consistency: consistency_flag(args)?, colocate_with: colocate_flag(args), if_version: None }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "compact" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> compact <key> [--strategy last|dedup]");
+                return Ok(false);
+            }
+            let strategy = match args.iter().position(|arg| arg == "--strategy").and_then(|pos| args.get(pos + 1)) {
+                None => CompactStrategy::Last,
+                Some(mode) => match mode.as_str() {
+                    "last" => CompactStrategy::Last,
+                    "dedup" => CompactStrategy::Dedup,
+                    _ => {
+                        println!("Invalid compact strategy: {} [last | dedup]", mode);
+                        return Ok(false);
+                    }
+                }
+            };
+            let request = Message::new(
+                MsgType::Compact,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Compact { key: args[5].to_string(), strategy }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "pin" => {
+            if args.len() < 7 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> pin <key> <node ip>:<node port>");
+                return Ok(false);
+            }
+            let node = match args[6].parse::<std::net::SocketAddrV4>() {
+                Ok(addr) => ring_member(node_ip, node_port, addr).unwrap_or_else(|| NodeInfo::new(*addr.ip(), addr.port())),
+                Err(_) => {
+                    println!("Invalid node address: {} [<ip>:<port>]", args[6]);
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::Pin,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Pin { key: args[5].to_string(), node }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "subscribe" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> subscribe <key>");
+                return Ok(false);
+            }
+            subscribe(node_ip, node_port, &args[5]);
+        }
+        "append" => {
+            if args.len() < 7 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> append <key> <entry>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::Append,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Append { key: args[5].to_string(), entry: args[6..].join(" ") }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "readlog" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> readlog <key> [--since <rfc3339 timestamp>] [--limit <n>]");
+                return Ok(false);
+            }
+            let mut since = None;
+            let mut limit = None;
+            let mut rest = args[6..].iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--since" => {
+                        let at = match rest.next() {
+                            Some(at) => at,
+                            None => {
+                                eprintln!("Missing <timestamp> after --since");
+                                return Ok(false);
+                            }
+                        };
+                        match DateTime::parse_from_rfc3339(at) {
+                            Ok(ts) => since = Some(ts.with_timezone(&Utc)),
+                            Err(e) => {
+                                eprintln!("Invalid timestamp {}: {}", at, e);
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    "--limit" => {
+                        match parse_arg::<usize>(rest.next(), "<n> after --limit") {
+                            Ok(n) => limit = Some(n),
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    _ => {
+                        println!("Unknown option: {}", arg);
+                        return Ok(false);
+                    }
+                }
+            }
+            let request = Message::new(
+                MsgType::ReadLog,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::ReadLog { key: args[5].to_string(), since, limit }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "query-range" => {
+            // <after> [<before>], the window ends now without an upper bound
+            let mut bounds = Vec::new();
+            for at in args.iter().skip(5).take(2) {
+                match DateTime::parse_from_rfc3339(at) {
+                    Ok(ts) => bounds.push(ts.with_timezone(&Utc)),
+                    Err(e) => {
+                        eprintln!("Invalid timestamp {}: {}", at, e);
+                        return Ok(false);
+                    }
+                }
+            }
+            if bounds.is_empty() {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> query-range <after rfc3339 timestamp> [<before rfc3339 timestamp>]");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::TimeRangeQuery,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::TimeRangeQuery { after: bounds[0], before: bounds.get(1).copied().unwrap_or_else(Utc::now) }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "simulate-join" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> simulate-join [<id> | <node ip>:<node port>]");
+                return Ok(false);
+            }
+            let at_id = match parse_node_id(node_ip, node_port, &args[5]) {
+                Some(id) => id,
+                None => {
+                    println!("Invalid id: {} [40 hex digits | <ip>:<port>]", args[5]);
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::SimulateJoin,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::SimulateJoin { at_id }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "node-records" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> node-records [<id> | <node ip>:<node port>]");
+                return Ok(false);
+            }
+            let target = match parse_node_id(node_ip, node_port, &args[5]) {
+                Some(id) => id,
+                None => {
+                    println!("Invalid id: {} [40 hex digits | <ip>:<port>]", args[5]);
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::NodeRecords,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::NodeRecords { target }
+            );
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Records { node, records }) => {
+                    println!("📋Node: {} holds {} primary record(s)", node, records.len());
+                    for item in records.iter() {
+                        println!("(🔑{} : 🔒{}, 🕰️ {})", item.title, item.value, item.timestamp);
+                    }
+                }
+                Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        #[cfg(feature = "testing")]
+        "force-relocate" => {
+            let inc = match args.get(5).map(|arg| arg.as_str()) {
+                Some("join") => true,
+                Some("depart") => false,
+                _ => {
+                    println!("Usage:");
+                    println!("cargo run --features testing cli <ip> <port> force-relocate [join | depart] <k_remaining>");
+                    return Ok(false);
+                }
+            };
+            let k_remaining: u8 = match args.get(6).map_or(Ok(0), |v| parse_arg(Some(v), "<k_remaining>")) {
+                Ok(k_remaining) => k_remaining,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::ForceRelocate,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::ForceRelocate { inc, k_remaining }
+            );
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Records { node, records }) => {
+                    println!("📋Node: {} holds {} record(s) after the relocate", node, records.len());
+                    for item in records.iter() {
+                        println!("(🔑{} : replica {}, pending: {})", item.title, item.replica_idx, item.pending);
+                    }
+                }
+                Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "verify-replicas" => {
+            let sample = args.iter().position(|arg| arg == "--sample")
+                .map(|pos| parse_arg::<f64>(args.get(pos + 1), "fraction for --sample")).transpose()?;
+            let request = Message::new(
+                MsgType::VerifyReplicas,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::VerifyReplicas { repair: args.iter().any(|arg| arg == "--repair"), sample }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "inspect" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> inspect <key>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::InspectKey,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::InspectKey { key: args[5].to_string() }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "replica-set" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> replica-set <key>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::ReplicaSet,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::ReplicaSet { key: args[5].to_string() }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "synckey" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> synckey <key>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::SyncKey,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::SyncKey { key: args[5].to_string() }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "touch" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> touch <key>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::Touch,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Touch { key: args[5].to_string() }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "query" => {
+            if args.len() < 5 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> query [<key> [--prefer primary|nearest|random] [--verbose] [--field <name>] | * [--parallel] [--restart-on-churn]] ");
+                return Ok(false);
+            } 
+            let request:Message;
+            if args[5].as_str() == "*" {
+                request = Message::new(
+                    MsgType::QueryAll,
+                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                    &MsgData::QueryAll {
+                        parallel: args.iter().any(|arg| arg == "--parallel"),
+                        restart_on_churn: args.iter().any(|arg| arg == "--restart-on-churn")
+                    }
+                );
+            } else if args[5].as_str() == "-f" { 
+                let filename = args.get(6).ok_or("Missing <file> after -f")?;
+                let file_content = read_input_file(filename)?;
+                let lines: Vec<&str> = file_content.lines().filter(|line| !line.trim().is_empty()).collect();
+                if args.iter().any(|arg| arg == "--batch") {
+                    query_batch(node_ip, node_port, &lines);
+                    return Ok(true);
+                }
+                let mut progress = BulkProgress::new(lines.len(), args);
+                for line in lines {
+                    let request = Message::new(
+                        MsgType::Query,
+                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                        &MsgData::Query { key: line.trim().to_string(), kind: QueryKind::Value, prefer: ReadPreference::Nearest, consistency: None, colocate_with: None }
+                    );
+                    progress.send(node_ip, node_port, &request);
+                }
+                progress.finish();
+                return Ok(true);
+            } 
+            else {
+                let prefer = match args.iter().position(|arg| arg == "--prefer").and_then(|pos| args.get(pos + 1)) {
+                    None => ReadPreference::Nearest,
+                    Some(mode) => match mode.as_str() {
+                        "nearest" => ReadPreference::Nearest,
+                        "primary" => ReadPreference::Primary,
+                        "random" => ReadPreference::Random,
+                        _ => {
+                            println!("Invalid read preference: {} [primary | nearest | random]", mode);
+                            return Ok(false);
+                        }
+                    }
+                };
+                if args.iter().any(|arg| arg == "--check-replicas") {
+                    let request = Message::new(
+                        MsgType::CheckReplicas,
+                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                        &MsgData::CheckReplicas { key: args[5].to_string() }
+                    );
+                    match send_request(node_ip, node_port, &request) {
+                        Ok(response) => println!("{}", response),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    return Ok(true);
+                }
+                if args.iter().any(|arg| arg == "--verbose") {
+                    // the stored record tells which replica answered, 0 being the primary
+                    let request = Message::new(
+                        MsgType::Query,
+                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                        &MsgData::Query { key: args[5].to_string(), kind: QueryKind::Record, prefer, consistency: consistency_flag(args)?, colocate_with: colocate_flag(args) }
+                    );
+                    match send_request_data(node_ip, node_port, &request) {
+                        Ok(MsgData::Record { record: Some(found), .. }) => println!(
+                            "Found data: (🔑 {} : 🔒{}, 🕰️ {}) from_replica_idx: {}",
+                            found.title, found.value, found.timestamp, found.replica_idx),
+                        Ok(MsgData::Record { key, record: None }) => println!("Error: 🔑{} doesn't exist", key),
+                        Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                        Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    return Ok(true);
+                }
+                if let Some(pos) = args.iter().position(|arg| arg == "--field") {
+                    let path = match args.get(pos + 1) {
+                        Some(path) => path,
+                        None => {
+                            eprintln!("Missing <name> after --field");
+                            return Ok(false);
+                        }
+                    };
+                    // the node hands back the raw value, the projection happens here
+                    let request = Message::new(
+                        MsgType::Query,
+                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                        &MsgData::Query { key: args[5].to_string(), kind: QueryKind::Record, prefer, consistency: consistency_flag(args)?, colocate_with: colocate_flag(args) }
+                    );
+                    match send_request_data(node_ip, node_port, &request) {
+                        Ok(MsgData::Record { record: Some(found), .. }) => match found.json_field(path) {
+                            Ok(field) => println!("Found data: (🔑 {} . {} : {})", found.title, path, field),
+                            Err(e) => println!("Error: 🔑{}: {}", found.title, e),
+                        },
+                        Ok(MsgData::Record { key, record: None }) => println!("Error: 🔑{} doesn't exist", key),
+                        Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                        Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                        Err(e) => eprintln!("Error: {}", e),
+                    }
+                    return Ok(true);
+                }
+                request = Message::new(
+                    MsgType::Query,
+                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                    &MsgData::Query{key: args[5].to_string(), kind: QueryKind::Value, prefer, consistency: consistency_flag(args)?, colocate_with: colocate_flag(args) }
+                );
+            }
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "mget" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> mget <key> [<key> ...] [--at <rfc3339 timestamp>]");
+                return Ok(false);
+            }
+            let mut keys = Vec::new();
+            let mut snapshot_ts = None;
+            let mut rest = args[5..].iter();
+            while let Some(arg) = rest.next() {
+                if arg == "--at" {
+                    let at = match rest.next() {
+                        Some(at) => at,
+                        None => {
+                            eprintln!("Missing <timestamp> after --at");
+                            return Ok(false);
+                        }
+                    };
+                    match DateTime::parse_from_rfc3339(at) {
+                        Ok(ts) => snapshot_ts = Some(ts.with_timezone(&Utc)),
+                        Err(e) => {
+                            eprintln!("Invalid timestamp {}: {}", at, e);
+                            return Ok(false);
+                        }
+                    }
+                } else {
+                    keys.push(arg.to_string());
+                }
+            }
+            let request = Message::new(
+                MsgType::MultiGet,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::MultiGet { keys, snapshot_ts }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "demo" => {
+            run_demo(node_ip, node_port, &args[5..])?;
+        }
+        "bench" => {
+            println!("{}", run_bench(node_ip, node_port, &args[5..])?);
+        }
+        "lag" => {
+            run_lag(node_ip, node_port, &args[5..])?;
+        }
+        "health" => {
+            run_health(node_ip, node_port, &args[5..])?;
+        }
+        "exists" => {
+            if args.len() < 6 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> exists <key>");
+                return Ok(false);
             }
-
-            let response_str = String::from_utf8_lossy(&response).to_string();
-
-            // 🚀 Step 4: Deserialize and extract only Reply messages
-
-            let json_value: Value = match serde_json::from_str(&response_str) {
-                Ok(value) => value,
-                Err(e) => return Err(format!("Failed to deserialize message: {}", e))
-            };
-    
-            // Convert Value to Message
-            let msg: Message = match serde_json::from_value(json_value) {
-                Ok(msg) => msg,
-                Err(e) => return Err(format!("Failed to convert JSON value to Message: {}", e))
-            };
-            // extract only the data part
-            let msg_data = msg.extract_data();
-
-            match msg_data {
-                MsgData::Reply { reply } => {
-                    Ok(reply)
-                }
-                _ => Err(format!("Unexpected message data"))
+            let request = Message::new(
+                MsgType::Query,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Query { key: args[5].to_string(), kind: QueryKind::Exists, prefer: ReadPreference::Nearest, consistency: None, colocate_with: None }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
             }
         }
-        Err(e) => Err(format!("Failed to accept response connection: {}", e)),
-    }
-}
-
-
-/// CLI routine to send requests to the chord network.
-pub fn run_cli() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 5 {
-        eprintln!("Usage: cargo run cli <ip> <port> <command> [args]");
-        process::exit(1);
-    }
-
-    let node_ip = args[2].parse().expect("Invalid IP address");
-    let node_port = args[3].parse().expect("Invalid port number");
-    let command = args[4].as_str();
-    match command {
-        "insert" => {
+        "watch" => {
             if args.len() < 6 {
                 println!("Usage:");
-                println!("cargo run cli <ip> <port> insert [<key> <value> | -f <file>]");
-                process::exit(1);
+                println!("cargo run cli <ip> <port> watch <key> [--interval <500ms|2s>]");
+                return Ok(false);
             }
+            let interval = match args.iter().position(|arg| arg == "--interval") {
+                Some(pos) => match args.get(pos + 1).and_then(|arg| parse_interval(arg)) {
+                    Some(interval) => interval,
+                    None => {
+                        eprintln!("Invalid interval: expected <500ms|2s>");
+                        return Ok(false);
+                    }
+                },
+                None => Duration::from_millis(500)
+            };
+            let request = Message::new(
+                MsgType::Query,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Query { key: args[5].to_string(), kind: QueryKind::Record, prefer: ReadPreference::Nearest, consistency: None, colocate_with: None }
+            );
 
-            if args[5] == "-f" {
-                let filename = args[6].as_str();
-                let file_content = std::fs::read_to_string(filename).expect("Failed to read file");
-                let lines: Vec<&str> = file_content.lines().collect();
-                for line in lines {
-                    let request = Message::new(
-                        MsgType::Insert,
-                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                        &MsgData::Insert { key: line.trim().to_string(), value: line.trim().to_string() }
-                    );
-                    match send_request(node_ip, node_port, &request) {
-                        Ok(response) => println!("{}", response),
-                        Err(e) => eprintln!("Error: {}", e),
+            // poll until Ctrl-C, printing only when the stored value or its timestamp changes
+            let mut last_seen = None;
+            loop {
+                match send_request_data(node_ip, node_port, &request) {
+                    Ok(MsgData::Record { key, record }) => {
+                        let state = record.map(|item| (item.value, item.timestamp));
+                        if last_seen.as_ref() != Some(&state) {
+                            match &state {
+                                Some((value, timestamp)) => println!("🔑 {} : 🔒{} (🕰️ {})", key, value, timestamp),
+                                None => println!("🔑 {} : <not found>", key),
+                            }
+                            last_seen = Some(state);
+                        }
                     }
+                    Ok(MsgData::Reply { reply }) => eprintln!("{}", reply),
+                    Ok(_) => eprintln!("Error: Unexpected message data"),
+                    Err(e) => eprintln!("Error: {}", e),
                 }
-                return;
+                thread::sleep(interval);
             }
-
+        }
+        "pendings" => {
             let request = Message::new(
-                MsgType::Insert,
+                MsgType::Pendings,
                 Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                &MsgData::Insert { key: args[5].to_string(), value: args[6].to_string() }
+                &MsgData::Pendings {  }
             );
-        
             match send_request(node_ip, node_port, &request) {
                 Ok(response) => println!("{}", response),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
-        "delete" => {
-            if args.len() < 5 {
+        "cancel-pending" => {
+            if args.len() < 6 {
                 println!("Usage:");
-                println!("cargo run cli <ip> <port> delete <key>");
-                process::exit(1);
+                println!("cargo run cli <ip> <port> cancel-pending <key>");
+                return Ok(false);
             }
             let request = Message::new(
-                MsgType::Delete,
+                MsgType::CancelPending,
                 Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                &MsgData::Delete { key: args[5].to_string() }
+                &MsgData::CancelPending { key: args[5].to_string() }
             );
             match send_request(node_ip, node_port, &request) {
                 Ok(response) => println!("{}", response),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
-        "query" => {
-            if args.len() < 5 {
-                println!("Usage:");
-                println!("cargo run cli <ip> <port> query [<key> | *] ");
-                process::exit(1);
-            } 
-            let request:Message;
-            if args[5].as_str() == "*" {
-                request = Message::new(
-                    MsgType::QueryAll,
-                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                    &MsgData::QueryAll {  }
-                );
-            } else if args[5].as_str() == "-f" { 
-                let filename = args[6].as_str();
-                let file_content = std::fs::read_to_string(filename).expect("Failed to read file");
-                let lines: Vec<&str> = file_content.lines().collect();
-                for line in lines {
-                    let request = Message::new(
-                        MsgType::Query,
-                        Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                        &MsgData::Query { key: line.trim().to_string() }
-                    );
-                    match send_request(node_ip, node_port, &request) {
-                        Ok(response) => println!("{}", response),
-                        Err(e) => eprintln!("Error: {}", e),
+        "trace" => {
+            let trace_id = match args.get(5).map(|arg| uuid::Uuid::parse_str(arg)) {
+                Some(Ok(trace_id)) => trace_id,
+                _ => {
+                    println!("Usage:");
+                    println!("cargo run cli <ip> <port> trace <trace_id>");
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::Trace,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Trace { trace_id }
+            );
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Spans { spans, .. }) if spans.is_empty() => {
+                    println!("No spans recorded for {} (are the nodes running with --trace?)", trace_id);
+                }
+                // spans are ordered by the clocks of the nodes that sent them, see clockcheck
+                Ok(MsgData::Spans { spans, .. }) => {
+                    let start = spans[0].timestamp;
+                    for (i, span) in spans.iter().enumerate() {
+                        println!("{:>3}. +{:>5}ms {} --{}--> {}",
+                            i + 1,
+                            (span.timestamp - start).num_milliseconds(),
+                            span.node, span.msg_type, span.next_hop);
                     }
                 }
-                return;
-            } 
-            else {
-                request = Message::new(
-                    MsgType::Query,
-                    Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                    &MsgData::Query{key: args[5].to_string() }
-                );
+                Ok(_) => eprintln!("Error: Unexpected message data"),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "rename" => {
+            if args.len() < 7 {
+                println!("Usage:");
+                println!("cargo run cli <ip> <port> rename <from> <to>");
+                return Ok(false);
+            }
+            let request = Message::new(
+                MsgType::Rename,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Rename { from: args[5].to_string(), to: args[6].to_string() }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "readonly" => {
+            let enabled = match args.get(5).map(|arg| arg.as_str()) {
+                Some("on") => true,
+                Some("off") => false,
+                _ => {
+                    println!("Usage:");
+                    println!("cargo run cli <ip> <port> readonly on|off");
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::SetReadOnly,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::SetReadOnly { enabled }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "reconfigure" => {
+            let (read_quorum, write_quorum): (u8, u8) = match (parse_arg(args.get(5), "read quorum <r>"), parse_arg(args.get(6), "write quorum <w>")) {
+                (Ok(r), Ok(w)) => (r, w),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
+                    println!("Usage:");
+                    println!("cargo run cli <ip> <port> reconfigure <r> <w>");
+                    return Ok(false);
+                }
+            };
+            let request = Message::new(
+                MsgType::Reconfigure,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Reconfigure { read_quorum, write_quorum, header: None, checked: false }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
             }
+        }
+        "keycount" => {
+            let request = Message::new(
+                MsgType::KeyCount,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::KeyCount { }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "deadletters" => {
+            let request = Message::new(
+                MsgType::DeadLetters,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::DeadLetters { }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "reassembly" => {
+            let request = Message::new(
+                MsgType::Reassembly,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Reassembly { }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "readstats" => {
+            let request = Message::new(
+                MsgType::ReadStats,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::ReadStats {  }
+            );
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "members" => {
+            let request = Message::new(
+                MsgType::Members,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Members {  }
+            );
             match send_request(node_ip, node_port, &request) {
                 Ok(response) => println!("{}", response),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
+        "stats" => {
+            let by_bytes = args.iter().skip(5).any(|arg| arg == "--by-bytes");
+            let request = Message::new(
+                MsgType::Overlay,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Overlay { structured: true }
+            );
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Ring { peers, counts, bytes }) => print!("{}", format_load(&peers, &counts, &bytes, by_bytes)),
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
         "overlay" => {
+            let dot = args.iter().skip(5).any(|arg| arg == "--dot");
+            // always structured, the ring size it shows sets the reply timeout of later runs
             let request = Message::new(
                 MsgType::Overlay,
                 Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                &MsgData::Overlay {  }
+                &MsgData::Overlay { structured: true }
             );
             
-            match send_request(node_ip, node_port, &request) {
-                Ok(response) => {
-                    println!("{}", response);
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Ring { peers, counts, .. }) => {
+                    cache_ring_size(node_ip, node_port, peers.len());
+                    if dot {
+                        print!("{}", format_ring_dot(&peers, &counts));
+                    } else {
+                        println!("{}", crate::utils::format_overlay_msg(&peers));
+                    }
+                }
+                Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "ping-ring" => {
+            let request = Message::new(
+                MsgType::Overlay,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Overlay { structured: true }
+            );
+            let started = Instant::now();
+            // a sweep that stalls is reported by the node it stalled at, the timeout covers lost reports
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Ring { peers, .. }) => {
+                    cache_ring_size(node_ip, node_port, peers.len());
+                    println!("✅ ring complete: {} node(s) reached in {:?}", peers.len(), started.elapsed());
                 }
+                Ok(MsgData::Reply { reply }) => println!("❌ {} ({:?})", reply, started.elapsed()),
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
+                Err(e) => println!("❌ no report within {:?}: {}", started.elapsed(), e),
+            }
+        }
+        "clockcheck" => {
+            let request = Message::new(
+                MsgType::ClockCheck,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::ClockCheck {  }
+            );
+
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+        "fixneighbors" => {
+            let request = Message::new(
+                MsgType::WhoIsMyNeighbor,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::WhoIsMyNeighbor { asker: NodeInfo::new(node_ip, node_port), forward: true, header: None, previous: None }
+            );
+
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
         "depart" => {
+            let drain_file = match args.iter().position(|arg| arg == "--drain") {
+                Some(pos) => match args.get(pos + 1) {
+                    Some(file) => Some(file.clone()),
+                    None => return Err("Missing <file> after --drain".to_string())
+                },
+                None => None
+            };
             let request = Message::new(
                 MsgType::Quit,
                 Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                &MsgData::Quit { id: format!("") } // TODO! 
+                &MsgData::Quit { warm: args.iter().any(|arg| arg == "--warm"), drain: drain_file.is_some() }
             );
             
-            match send_request(node_ip, node_port, &request) {
-                Ok(response) => {
-                    println!("{}", response);
+            match send_request_data(node_ip, node_port, &request) {
+                Ok(MsgData::Reply { reply }) => println!("{}", reply),
+                // only a bootstrap that is alone drains, in the line format 'requests' replays
+                Ok(MsgData::LocalRecords { records }) => {
+                    let file = drain_file.unwrap_or_default();
+                    let lines: String = records.iter()
+                        .map(|item| format!("insert, {}, {}\n", item.title, item.value))
+                        .collect();
+                    match std::fs::write(&file, lines) {
+                        Ok(()) => println!("Bootstrap node has left the network, {} keys drained to {}", records.len(), file),
+                        Err(e) => eprintln!("Error: bootstrap node has left the network but writing {} failed: {}", file, e),
+                    }
                 }
+                Ok(other) => eprintln!("Error: Unexpected message data {:?}", other),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
@@ -215,7 +1945,7 @@ pub fn run_cli() {
             let request = Message::new(
                 MsgType::Join,
                 Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                &MsgData::Join { id: format!("") }   // TODO!
+                &MsgData::Join { }
             );
             
             match send_request(node_ip, node_port, &request) {
@@ -223,53 +1953,26 @@ pub fn run_cli() {
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
+        "shutdown-ring" => {
+            let request = Message::new(
+                MsgType::Shutdown,
+                Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
+                &MsgData::Shutdown {  }
+            );
+
+            match send_request(node_ip, node_port, &request) {
+                Ok(response) => println!("{}", response),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
         "requests" => {
             if args.len() < 5 {
                 println!("Usage:");
-                println!("cargo run cli <ip> <port> requests <file>");
-                process::exit(1);
-            }
-
-            let filename = args[5].as_str();
-            let file_content = std::fs::read_to_string(filename).expect("Failed to read file");
-            let response_filename = format!("{}_response.txt", filename);
-            let mut response_file = std::fs::File::create(response_filename).expect("Failed to create response file");
-            let lines: Vec<&str> = file_content.lines().collect();
-            for line in lines {
-                let request: Vec<&str> = line.split(", ").collect();
-                match request[0] {
-                    "insert" => {
-                        let request = Message::new(
-                            MsgType::Insert,
-                            Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                            &MsgData::Insert { key: request[1].to_string(), value: request[2].to_string() }
-                        );
-                        match send_request(node_ip, node_port, &request) {
-                            Ok(response) => println!("{}", response),
-                            Err(e) => eprintln!("Error: {}", e),
-                        }
-                    }
-                    "query" => {
-                        let request = Message::new(
-                            MsgType::Query,
-                            Some(&NodeInfo::new(get_local_ip(), node_port + (process::id() % 1000) as u16)),
-                            &MsgData::Query { key: request[1].to_string() }
-                        );
-                        match send_request(node_ip, node_port, &request) {
-                            Ok(response) => { 
-                                println!("{}", response);
-                                writeln!(response_file, "Request: {} | Response: {}", line, response)
-                                .expect("Failed to write to response file");
-                            }
-                            ,
-                            Err(e) => eprintln!("Error: {}", e),
-                        }
-                    }
-                    _ => {
-                        eprintln!("Invalid request type: {}", request[0]);
-                    }
-                }
+                println!("cargo run cli <ip> <port> requests <file> [--verbose] [--dry-run]");
+                return Ok(false);
             }
+
+            run_requests_file(node_ip, node_port, args)?;
         }
         "help" => {
             println!("Options:");
@@ -277,15 +1980,223 @@ pub fn run_cli() {
             println!("  <port>                => Port of the node to connect to");
             println!("Available commands:");
             println!("  insert <key> <value>  => Insert a (key,value) in the DHT");
+            println!("  insert <key> -        => Insert a value read from stdin until EOF");
+            println!("  insert <key> --from-file <file> => Insert the whole file content as a single value");
             println!("  delete <key>          => Delete the given key from the DHT");
+            println!("  rename <from> <to>    => Move the value of <from> to the new key <to>, <from> is deleted once <to> is written");
             println!("  query <key>           => Query the DHT for a specific key or '*' for all");
+            println!("  query <key> --prefer <primary|nearest|random> => Choose which replica answers (Eventual)");
+            println!("  query <key> --verbose => Also print the replica index of the answering copy (0 = primary)");
+            println!("  query <key> --check-replicas => Also count the copies held along the replica chain, to spot under-replicated keys");
+            println!("  query * --parallel    => Fetch all nodes' records concurrently instead of one circulating message");
+            println!("  query * --restart-on-churn => Start the sweep over when a node joins or departs during it");
+            println!("  insert|query -f <file> [--verbose] [--dry-run] => Run one request per line with a progress bar and summary");
+            println!("  query -f <file> --batch => Read all keys of the file with a single request");
+            println!("  insert|query|delete <key> ... --consistency <eventual|chain> => Override the ring consistency for this operation");
+            println!("  insert <key> <value> --create-only => Fail with an error instead of appending when the key already exists");
+            println!("  insert <key> <value> --json => Reject the value unless it parses as JSON");
+            println!("  query <key> --field <name> => Print one field of a JSON value, dots reach nested fields and array indices");
+            println!("  insert <key> <value> --max-value-len <n> => Keep only the newest n bytes of the value as appends grow it");
+            println!("  insert|query|delete <key> ... --colocate-with <parent> => Store the key on the node of <parent>, later queries and deletes must name the same parent");
+            println!("  requests <file> [--verbose] [--dry-run] => Run a mixed request file with a progress bar and summary");
+            println!("  touch <key>           => Refresh the timestamp of a key without changing its value");
+            println!("  verify-replicas [--repair] => Check that each successor holds the node's primary keys at the right replica_idx, optionally fixing them");
+            println!("  verify-replicas --sample <fraction> => Check only that random fraction of the keys and estimate how many miss a copy");
+            println!("  inspect <key>         => Show the value, timestamp and state of the copy at every replica position");
+            println!("  replica-set <key>     => List the nodes holding replicas 0..k of a key, primary first");
+            println!("  synckey <key>         => Overwrite every replica of a key with the primary's copy");
+            println!("  compact <key> [--strategy last|dedup] => Collapse a value built from appends and update its replicas");
+            println!("  exists <key>          => Check whether a key is stored without fetching its value");
+            println!("  mget <key>... [--at <timestamp>] => Read several keys as of one point in time");
+            println!("  demo chain|eventual|quorum [--seed <n>] => Check the guarantee of a consistency mode against the ring");
+            println!("  health [--sample <n>] => Print a JSON report of ring invariants, replica problems, routing errors and divergence");
+            println!("  lag <key> <value> [--timeout <ms>] => Write a key and report how long each replica took to show it");
+            println!("  bench [--ops <n>] [--mode mixed|insert|query] [--read-ratio <0.8>] [--concurrency <c>] [--keys <n>] [--wait-replication] [--seed <n>] => Report latency percentiles and throughput");
+            println!("  watch <key> [--interval <500ms>] => Poll a key and print every change until Ctrl-C");
             println!("  overlay               => Print the chord ring topology");
-            println!("  join                  => Join the ring");
-            println!("  depart                => Gracefully remove this node from the ring");
+            println!("  overlay --dot         => Print the ring as a Graphviz digraph");
+            println!("  stats [--by-bytes]    => Print records and stored bytes per node, shares and skew by records or bytes");
+            println!("  members               => Print the ring members this node learnt through gossip");
+            println!("  pendings              => List the keys pending on this node and how many readers wait on each");
+            println!("  cancel-pending <key>  => Clear a stuck pending on this node, its waiting readers get an error");
+            println!("  trace <trace_id>      => Print the forwards of one request, recorded by nodes started with --trace");
+            println!("  readonly on|off       => Make the node refuse the writes it would apply itself, reads are still served");
+            println!("  reconfigure <r> <w>   => Set the Quorum read and write sizes on every node, each between 1 and k copies");
+            println!("  keycount              => Estimate the number of distinct keys in the ring without fetching them");
+            println!("  ping-ring             => Time one overlay sweep and name the node it stalls at, if any");
+            println!("  reassembly            => Show the memory held by requests being read against the node's budget");
+            println!("  deadletters           => Show the replication and topology messages this node is still trying to deliver");
+            println!("  readstats             => Count the Eventual reads this node answered itself and the ones it forwarded");
+            println!("  clockcheck            => Report the clock skew between all nodes");
+            println!("  fixneighbors          => Ask the bootstrap to recompute this node's neighbours");
+            println!("  join                  => Make the node at <ip> <port> join the ring");
+            println!("  simulate-join [<id> | <ip>:<port>] => Preview which keys a node joining there would take over");
+            println!("  node-records [<id> | <ip>:<port>] => List the primary records of one node");
+            #[cfg(feature = "testing")]
+            println!("  force-relocate [join | depart] <k_remaining> => Run one relocate hop on the node and list its records (testing builds)");
+            println!("  pin <key> <ip>:<port> => Place key on the given node regardless of its hash");
+            println!("  subscribe <key>      => Print every change of key until Ctrl-C");
+            println!("  append <key> <entry> => Append a timestamped entry to the log stored at key");
+            println!("  readlog <key> [--since <ts>] [--limit <n>] => Read the entries of a log key in order");
+            println!("  query-range <after> [<before>] => List the records whose latest version was written in the window (rfc3339 timestamps)");
+            println!("  depart                => Gracefully remove the node at <ip> <port> from the ring");
+            println!("  depart --warm         => Hand the data over to the successor first, then leave in a short cutover");
+            println!("  depart --drain <file> => Let a bootstrap that is alone leave, its keys are saved to <file> for 'requests'");
+            println!("  shutdown-ring         => Stop every node in the ring, bootstrap last");
+            println!("  repl                  => Run commands against the node interactively, one per line, until Ctrl-D or 'exit'");
             println!("  help                  => Show this help message");
+            println!("  --same-connection     => (before <ip>) Read replies on the request's own connection, for clients the nodes cannot connect back to");
         }
         _ => {
             println!("Unknown command. Type 'help' to see available commands.")
         }
     }
+    Ok(true)
+}
+
+/// Keeps one response listener open and runs a command per input line against the same
+/// node until Ctrl-D or `exit`. Words are split on whitespace, so values can't contain spaces.
+fn run_repl(node_ip: Ipv4Addr, node_port: u16, args: &[String]) {
+    let response_port = node_port + (process::id() % 1000) as u16;
+    match response_listener(response_port) {
+        Ok(listener) => { let _ = SESSION_LISTENER.set(listener); }
+        Err(e) => {
+            eprintln!("Failed to bind response port {}: {}", response_port, e);
+            process::exit(1);
+        }
+    }
+    repl_loop(node_ip, node_port, args, io::stdin().lock());
+}
+
+/// Reads and runs the commands of a repl session, a failing one only fails that line.
+/// Returns the number of commands run once the input ends or asks to exit.
+fn repl_loop(node_ip: Ipv4Addr, node_port: u16, args: &[String], mut input: impl io::BufRead) -> usize {
+    let mut commands = 0;
+    loop {
+        print!("{}:{}> ", node_ip, node_port);
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => {
+                // Ctrl-D
+                println!();
+                break;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                break;
+            }
+        }
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        match words.first().map(|word| word.as_str()) {
+            None => continue,
+            Some("exit") | Some("quit") => break,
+            Some("repl") => {
+                println!("Already in a repl session");
+                continue;
+            }
+            Some(_) => ()
+        }
+        let mut line_args = args[..4].to_vec();
+        line_args.extend(words);
+        run_command(node_ip, node_port, &line_args);
+        commands += 1;
+    }
+    io::stdout().flush().ok();
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli_args(rest: &[&str]) -> Vec<String> {
+        ["dht", "cli", "127.0.0.1", "8000"].iter().chain(rest).map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn insert_value_reads_all_of_stdin() {
+        let value: String = (0..64 * 1024).map(|i| char::from(b'a' + (i % 26) as u8)).chain("\nlast line\n".chars()).collect();
+        let read = insert_value(&cli_args(&["insert", "big", "-"]), io::Cursor::new(value.clone()));
+        assert_eq!(read.unwrap().as_deref(), Some(value.as_str()));
+    }
+
+    #[test]
+    fn insert_value_without_value_is_a_usage_error() {
+        assert_eq!(insert_value(&cli_args(&["insert", "key"]), io::empty()), Ok(None));
+        assert_eq!(insert_value(&cli_args(&["insert", "key", "inline"]), io::empty()).unwrap().as_deref(), Some("inline"));
+    }
+
+    #[test]
+    fn insert_value_reports_an_unreadable_file() {
+        let missing = insert_value(&cli_args(&["insert", "key", "--from-file"]), io::empty());
+        assert_eq!(missing, Err("Missing <file> after --from-file".to_string()));
+        let unreadable = insert_value(&cli_args(&["insert", "key", "--from-file", "/nonexistent/value.txt"]), io::empty());
+        assert!(unreadable.unwrap_err().starts_with("Failed to read file '/nonexistent/value.txt'"));
+    }
+
+    #[test]
+    fn bad_port_is_a_clean_error() {
+        assert_eq!(node_address(&cli_args(&["insert", "key", "value"])), Ok((Ipv4Addr::LOCALHOST, 8000)));
+        let args: Vec<String> = ["dht", "cli", "127.0.0.1", "99999", "insert", "key", "value"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(node_address(&args), Err("Invalid port number '99999': number too large to fit in target type".to_string()));
+        assert_eq!(node_address(&args[..3]), Err("Missing port number".to_string()));
+    }
+
+    #[test]
+    fn a_bad_argument_fails_the_command_without_ending_the_process() {
+        assert!(!run_command(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["insert", "-f", "/nonexistent/requests.txt"])));
+        assert!(!run_command(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["insert", "key", "value", "--consistency", "sometimes"])));
+        assert!(!run_command(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["bench", "--ops", "many"])));
+    }
+
+    #[test]
+    fn repl_keeps_going_after_a_failing_command_and_ends_on_eof() {
+        let session = "insert\n\ninsert -f /nonexistent/requests.txt\nrepl\ninsert key value --consistency sometimes\n";
+        assert_eq!(repl_loop(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["repl"]), io::Cursor::new(session)), 3);
+        assert_eq!(repl_loop(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["repl"]), io::Cursor::new("insert\nexit\ninsert\n")), 1);
+    }
+
+    #[test]
+    fn ring_dot_of_a_single_node_is_a_self_loop() {
+        let peer = NodeInfo::new(Ipv4Addr::LOCALHOST, 8000);
+        let id = peer.get_id();
+        let dot = format_ring_dot(&[peer], &[7]);
+        assert_eq!(dot, format!("digraph ring {{\n    node [shape=box];\n    \"{id}\" [label=\"127.0.0.1:8000\\n{id}\\nrecords: 7\"];\n    \"{id}\" -> \"{id}\";\n}}\n", id = id));
+    }
+
+    #[test]
+    fn ring_dot_escapes_labels_and_keeps_them_on_their_node() {
+        assert_eq!(dot_escape(r#"a "quoted" \path"#), r#"a \"quoted\" \\path"#);
+        // a count missing at the end only blanks the last label, the others keep theirs
+        let peers = [NodeInfo::new(Ipv4Addr::LOCALHOST, 8000), NodeInfo::new(Ipv4Addr::LOCALHOST, 8001)];
+        let dot = format_ring_dot(&peers, &[3]);
+        assert!(dot.contains(&format!("\"{}\" [label=\"127.0.0.1:8000\\n{}\\nrecords: 3\"]", peers[0].get_id(), peers[0].get_id())), "{}", dot);
+        assert!(dot.contains(&format!("\"{}\" [label=\"127.0.0.1:8001\\n{}\\nrecords: 0\"]", peers[1].get_id(), peers[1].get_id())), "{}", dot);
+    }
+
+    #[test]
+    fn ring_size_is_cached_per_node_address() {
+        let (small, large) = (40000 + (process::id() % 10000) as u16, 50000 + (process::id() % 10000) as u16);
+        cache_ring_size(Ipv4Addr::LOCALHOST, small, 2);
+        cache_ring_size(Ipv4Addr::LOCALHOST, large, 100);
+        assert_eq!(cached_ring_size(Ipv4Addr::LOCALHOST, small), 2);
+        assert!(reply_timeout(Ipv4Addr::LOCALHOST, large) > reply_timeout(Ipv4Addr::LOCALHOST, small));
+        std::fs::remove_file(ring_size_cache(Ipv4Addr::LOCALHOST, small)).ok();
+        std::fs::remove_file(ring_size_cache(Ipv4Addr::LOCALHOST, large)).ok();
+    }
+
+    #[test]
+    fn dry_run_summary_counts_every_line_of_the_file() {
+        let path = std::env::temp_dir().join(format!("dht_requests_{}.txt", process::id()));
+        let lines = ["insert, a, 1", "", "query, a", "insert, b, 2", "   ", "remove, a", "query, b"];
+        std::fs::write(&path, lines.join("\n")).expect("write request file");
+
+        let summary = run_requests_file(Ipv4Addr::LOCALHOST, 8000, &cli_args(&["requests", path.to_str().unwrap(), "--dry-run"])).expect("dry run");
+        // blank lines are skipped, the unknown request type counts as failed
+        assert!(summary.starts_with("[dry-run] 5 request(s): 4 succeeded, 1 failed"), "{}", summary);
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(format!("{}_response.txt", path.display())).ok();
+    }
 }
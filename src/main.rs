@@ -4,6 +4,9 @@ use std::net::Ipv4Addr;
 use std::env;
 
 use utils::Consistency;
+use utils::KeyNorm;
+use utils::{EmptyRing, OfflineRequests};
+use utils::RelocateReads;
 use utils::get_local_ip;
 
 
@@ -12,15 +15,304 @@ mod node;
 mod network;
 mod cli;
 mod messages;
+mod partition;
 
 // Bootsrap node info are globally known 
 //const BOOT_ADDR: Ipv4Addr = Ipv4Addr::new(0,0,0,0);  //localhost 
 const BOOT_ADDR: Ipv4Addr = Ipv4Addr::new(10,0,24,44);  
 const API_PORT: u16 = 8000; 
 const NUM_THREADS: usize = 8;
+const LISTEN_BACKLOG: u32 = 1024;      // pending connections the OS queues per listener
+const MAX_CLOCK_SKEW_MS: i64 = 500;     // warn on clockcheck above this
+const GATHER_TIMEOUT_MS: u64 = 2000;    // per node limit for parallel queryall
+const TRANSFER_CHUNK: usize = 1000;     // max items per join/relocate transfer message
+const GOSSIP_INTERVAL_MS: u64 = 1000;   // period of membership exchanges
+const GOSSIP_FANOUT: usize = 8;         // peers shared per exchange
+const CHAIN_WINDOW: usize = 64;         // chain inserts a head keeps in flight before waiting for acks
+const CHAIN_WINDOW_WAIT_MS: u64 = 5000; // a chain write still without a slot after this long is refused
+const MIN_JOIN_FORWARDS: usize = 64;    // join hops always allowed, even before gossip has sized the ring
+const RANGE_CHECK_INTERVAL_MS: u64 = 5000; // period of the replica range checkpoint
+const RANGE_AUTO_CORRECT: bool = false;     // repair drift that survives two checkpoints instead of only logging it
+const JOIN_GRACE_MS: u64 = 2000;        // a joined node leaves reads to established replicas for this long
+const READ_CACHE_TTL_MS: u64 = 1000;    // lifetime of a cached remote query result
+const DEPART_RETRIES: u32 = 3;          // attempts per depart message before the depart is aborted
+const DEPART_RETRY_MS: u64 = 500;       // backoff step between those attempts
+const TRACE_CAPACITY: usize = 1024;     // traces the bootstrap keeps spans for
+const REQUEST_READ_TIMEOUT_MS: u64 = 10000; // a request must arrive in full within this, counted from accept
+const PROXY_REPLY_TIMEOUT_MS: u64 = 30000; // longest a node holds a same connection request open for its reply
+const REPLY_TIMEOUT_MS: u64 = 10000;    // what a cli request waits for its reply, before HOP_TIMEOUT_MS per node
+const HOP_TIMEOUT_MS: u64 = 200;        // added to reply deadlines for every node of the ring, requests may cross all of them
+const REASSEMBLY_STEP_KB: u32 = 64;     // a request being read takes --reassembly-budget-mb permits in steps of this
+const MAX_MESSAGE_BYTES: usize = 64 * 1024 * 1024; // larger requests are rejected unread, unless --max-message-mb is given
+const MAX_HEADER_BYTES: usize = 64 * 1024; // the fields ahead of the data are looked for in this much of a request
+const PENDINGS_LIMIT: usize = 4096;     // keys readers may wait on per node, the longest idle one is expired beyond
+const PENDING_EXPIRY_MS: u64 = 30000;   // readers still blocked this long after the last one arrived get an error
+const MEM_CHECK_INTERVAL_MS: u64 = 1000; // period of the stored bytes check against --mem-high-mb
+const QUERY_ALL_RESTARTS: u8 = 2;       // sweeps a query * --restart-on-churn starts over before settling for a stale one
+const DEAD_LETTER_LIMIT: usize = 1024;  // undelivered messages kept per node, the oldest is dropped beyond
+const DEAD_LETTER_RETRY_MS: u64 = 250;  // first redelivery delay, doubled after every failed attempt
+const DEAD_LETTER_MAX_AGE_MS: u64 = 60000; // undelivered messages older than this are dropped
+const RELOCATE_WAIT_MS: u64 = 500;      // longest a read waits for a relocation running on the node
+const STABILIZE_RETRIES: u32 = 5;       // attempts bootstrap-cluster makes per seed while the ring is stabilizing
 
 // for testing locally only
 
+// flags a bootstrap and a peer node both accept
+fn node_options(args: &[String]) -> node::NodeOptions {
+    node::NodeOptions {
+        read_cache: read_cache_size(args),
+        tracing: args.iter().any(|arg| arg == "--trace"),
+        mem_limits: mem_limits(args),
+        rate_limit: rate_limit(args),
+        audit_log: audit_log(args),
+        stabilize_ms: stabilize_ms(args),
+        relocate_reads: relocate_reads(args),
+        max_message_bytes: max_message_bytes(args),
+        offline_requests: offline_requests(args),
+        seed: seed(args),
+        reassembly_budget_kb: reassembly_budget_kb(args),
+        workers: workers(args),
+        weight: weight(args),
+        range_check_ms: range_check_ms(args),
+        range_auto_correct: args.iter().any(|arg| arg == "--range-auto-correct") || RANGE_AUTO_CORRECT,
+        pendings_limit: pendings_limit(args),
+        pending_expiry_ms: pending_expiry_ms(args),
+        ..node::NodeOptions::default()
+    }
+}
+
+// '--read-cache-size <n>' anywhere after the positional arguments, the cache is off without it
+fn read_cache_size(args: &[String]) -> Option<usize> {
+    let pos = args.iter().position(|arg| arg == "--read-cache-size")?;
+    match args.get(pos + 1).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Some(n),
+        _ => panic!("Invalid parameter for --read-cache-size: expected a number of entries\n")
+    }
+}
+
+// '--max-nodes <n>' caps the ring size at the bootstrap, unbounded without it
+fn max_nodes(args: &[String]) -> Option<usize> {
+    let pos = args.iter().position(|arg| arg == "--max-nodes")?;
+    match args.get(pos + 1).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => Some(n),
+        _ => panic!("Invalid parameter for --max-nodes: expected a number of nodes > 0\n")
+    }
+}
+
+// '--rate-limit <n>' caps client requests per second and address, unlimited without it
+fn rate_limit(args: &[String]) -> Option<u32> {
+    let pos = args.iter().position(|arg| arg == "--rate-limit")?;
+    match args.get(pos + 1).map(|n| n.parse::<u32>()) {
+        Some(Ok(n)) if n > 0 => Some(n),
+        _ => panic!("Invalid parameter for --rate-limit: expected a number of requests per second > 0\n")
+    }
+}
+
+// '--normalize-keys <trim,nfc,case>' sets how the ring rewrites client keys before hashing
+fn key_norm(args: &[String]) -> KeyNorm {
+    let pos = match args.iter().position(|arg| arg == "--normalize-keys") {
+        Some(pos) => pos,
+        None => return KeyNorm::default()
+    };
+    match args.get(pos + 1).map(|list| list.parse::<KeyNorm>()) {
+        Some(Ok(norm)) => norm,
+        Some(Err(e)) => panic!("Invalid parameter for --normalize-keys: {}\n", e),
+        None => panic!("Invalid parameter for --normalize-keys: expected a list of trim|nfc|case\n")
+    }
+}
+
+// '--relocate-reads wait|primary' picks what reads do while a relocation runs on the node
+fn relocate_reads(args: &[String]) -> RelocateReads {
+    let pos = match args.iter().position(|arg| arg == "--relocate-reads") {
+        Some(pos) => pos,
+        None => return RelocateReads::default()
+    };
+    match args.get(pos + 1).map(|policy| policy.parse::<RelocateReads>()) {
+        Some(Ok(policy)) => policy,
+        Some(Err(e)) => panic!("Invalid parameter for --relocate-reads: {}\n", e),
+        None => panic!("Invalid parameter for --relocate-reads: expected wait|primary\n")
+    }
+}
+
+// '--offline-requests redirect|reject' is what clients hear from the node once it is offline
+fn offline_requests(args: &[String]) -> OfflineRequests {
+    let pos = match args.iter().position(|arg| arg == "--offline-requests") {
+        Some(pos) => pos,
+        None => return OfflineRequests::default()
+    };
+    match args.get(pos + 1).map(|policy| policy.parse::<OfflineRequests>()) {
+        Some(Ok(policy)) => policy,
+        Some(Err(e)) => panic!("Invalid parameter for --offline-requests: {}\n", e),
+        None => panic!("Invalid parameter for --offline-requests: expected redirect|reject\n")
+    }
+}
+
+// '--empty-ring fail|start' is what a node does when no ring answers its join
+fn empty_ring(args: &[String]) -> EmptyRing {
+    let pos = match args.iter().position(|arg| arg == "--empty-ring") {
+        Some(pos) => pos,
+        None => return EmptyRing::default()
+    };
+    match args.get(pos + 1).map(|policy| policy.parse::<EmptyRing>()) {
+        Some(Ok(policy)) => policy,
+        Some(Err(e)) => panic!("Invalid parameter for --empty-ring: {}\n", e),
+        None => panic!("Invalid parameter for --empty-ring: expected fail|start\n")
+    }
+}
+
+// '--reassembly-budget-mb <n>' caps what all requests being read may buffer together, in KB for the node
+fn reassembly_budget_kb(args: &[String]) -> Option<u32> {
+    let pos = args.iter().position(|arg| arg == "--reassembly-budget-mb")?;
+    match args.get(pos + 1).map(|n| n.parse::<u32>()) {
+        Some(Ok(n)) if n > 0 && n <= u32::MAX / 1024 => Some(n * 1024),
+        _ => panic!("Invalid parameter for --reassembly-budget-mb: expected a number of MB > 0\n")
+    }
+}
+
+// '--seed <n>' makes the node's random choices, e.g. Random reads and gossip peers, repeat across runs
+fn seed(args: &[String]) -> Option<u64> {
+    let pos = args.iter().position(|arg| arg == "--seed")?;
+    match args.get(pos + 1).map(|n| n.parse::<u64>()) {
+        Some(Ok(n)) => Some(n),
+        _ => panic!("Invalid parameter for --seed: expected a number\n")
+    }
+}
+
+// '--join-via <ip:port>' sends joins to that ring member instead of the bootstrap
+fn join_via(args: &[String]) -> Option<node::NodeInfo> {
+    let pos = args.iter().position(|arg| arg == "--join-via")?;
+    match args.get(pos + 1).map(|addr| addr.parse::<std::net::SocketAddrV4>()) {
+        Some(Ok(addr)) => Some(node::NodeInfo::new(*addr.ip(), addr.port())),
+        _ => panic!("Invalid parameter for --join-via: expected <ip:port>\n")
+    }
+}
+
+// '--workers <n>' sizes the pool handling requests, NUM_THREADS without it
+fn workers(args: &[String]) -> usize {
+    let pos = match args.iter().position(|arg| arg == "--workers") {
+        Some(pos) => pos,
+        None => return NUM_THREADS
+    };
+    match args.get(pos + 1).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        _ => panic!("Invalid parameter for --workers: expected a number of workers > 0\n")
+    }
+}
+
+// '--weight <w>' gives the node a w times larger share of the keyspace than a node of weight 1
+fn weight(args: &[String]) -> u32 {
+    let pos = match args.iter().position(|arg| arg == "--weight") {
+        Some(pos) => pos,
+        None => return 1
+    };
+    match args.get(pos + 1).map(|w| w.parse::<u32>()) {
+        Some(Ok(w)) if w > 0 => w,
+        _ => panic!("Invalid parameter for --weight: expected a weight > 0\n")
+    }
+}
+
+// '--max-message-mb <n>' rejects requests announcing more than n MB, instead of MAX_MESSAGE_BYTES
+fn max_message_bytes(args: &[String]) -> usize {
+    let pos = match args.iter().position(|arg| arg == "--max-message-mb") {
+        Some(pos) => pos,
+        None => return MAX_MESSAGE_BYTES
+    };
+    match args.get(pos + 1).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n * 1024 * 1024,
+        _ => panic!("Invalid parameter for --max-message-mb: expected a number of MB > 0\n")
+    }
+}
+
+// '--stabilize-ms <n>' refuses joins and departs for n ms after a topology change
+fn stabilize_ms(args: &[String]) -> Option<u64> {
+    let pos = args.iter().position(|arg| arg == "--stabilize-ms")?;
+    match args.get(pos + 1).map(|n| n.parse::<u64>()) {
+        Some(Ok(n)) if n > 0 => Some(n),
+        _ => panic!("Invalid parameter for --stabilize-ms: expected a number of milliseconds > 0\n")
+    }
+}
+
+// '--range-check-ms <n>' sets the period of the replica range checkpoint, RANGE_CHECK_INTERVAL_MS without it
+fn range_check_ms(args: &[String]) -> u64 {
+    let pos = match args.iter().position(|arg| arg == "--range-check-ms") {
+        Some(pos) => pos,
+        None => return RANGE_CHECK_INTERVAL_MS
+    };
+    match args.get(pos + 1).map(|n| n.parse::<u64>()) {
+        Some(Ok(n)) if n > 0 => n,
+        _ => panic!("Invalid parameter for --range-check-ms: expected a number of milliseconds > 0\n")
+    }
+}
+
+// '--pendings-limit <n>' caps the keys readers may wait on per node, PENDINGS_LIMIT without it
+fn pendings_limit(args: &[String]) -> usize {
+    let pos = match args.iter().position(|arg| arg == "--pendings-limit") {
+        Some(pos) => pos,
+        None => return PENDINGS_LIMIT
+    };
+    match args.get(pos + 1).map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => n,
+        _ => panic!("Invalid parameter for --pendings-limit: expected a number of keys > 0\n")
+    }
+}
+
+// '--pending-expiry-ms <n>' fails readers blocked n ms after the last one arrived, PENDING_EXPIRY_MS without it
+fn pending_expiry_ms(args: &[String]) -> u64 {
+    let pos = match args.iter().position(|arg| arg == "--pending-expiry-ms") {
+        Some(pos) => pos,
+        None => return PENDING_EXPIRY_MS
+    };
+    match args.get(pos + 1).map(|n| n.parse::<u64>()) {
+        Some(Ok(n)) if n > 0 => n,
+        _ => panic!("Invalid parameter for --pending-expiry-ms: expected a number of milliseconds > 0\n")
+    }
+}
+
+// '--audit-log <path>' appends topology and replica range changes to the file
+fn audit_log(args: &[String]) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == "--audit-log")?;
+    match args.get(pos + 1) {
+        Some(path) if !path.starts_with("--") => Some(path.clone()),
+        _ => panic!("Invalid parameter for --audit-log: expected a file path\n")
+    }
+}
+
+// '--mem-high-mb <n> [--mem-low-mb <n>]' sheds primary keys past n MB of values, low defaults to 80% of high
+fn mem_limits(args: &[String]) -> Option<(u64, u64)> {
+    let mb = |flag: &str| -> Option<u64> {
+        let pos = args.iter().position(|arg| arg == flag)?;
+        match args.get(pos + 1).map(|n| n.parse::<u64>()) {
+            Some(Ok(n)) if n > 0 => Some(n * 1024 * 1024),
+            _ => panic!("Invalid parameter for {}: expected a number of MB > 0\n", flag)
+        }
+    };
+    let high = mb("--mem-high-mb")?;
+    let low = mb("--mem-low-mb").unwrap_or(high / 10 * 8);
+    if low >= high {
+        panic!("Invalid memory marks: --mem-low-mb must be below --mem-high-mb\n");
+    }
+    Some((high, low))
+}
+
+// optional quorum sizes after <k> <m>, both counted in copies out of k and given together
+fn quorum_sizes(args: &[String], k: u8) -> Result<Option<(u8, u8)>, String> {
+    let given = |i: usize| args.get(i).filter(|arg| !arg.starts_with("--"));
+    let (r, w) = match (given(4), given(5)) {
+        (None, _) => return Ok(None),
+        (Some(r), Some(w)) => (r, w),
+        (Some(_), None) => return Err("both r and w must be given".to_string()),
+    };
+    let r: u8 = r.parse().map_err(|_| format!("Invalid parameter for read quorum: r '{}'", r))?;
+    let w: u8 = w.parse().map_err(|_| format!("Invalid parameter for write quorum: w '{}'", w))?;
+    if r < 1 || r > k || w < 1 || w > k {
+        return Err("Invalid quorum. Must be 0 < r,w <= k.".to_string());
+    }
+    if r as u16 + w as u16 <= k as u16 {
+        eprintln!("Warning: r + w <= k, quorum reads may miss the latest write");
+    }
+    Ok(Some((r, w)))
+}
+
 #[tokio::main]
 async fn main() {
     println!("Entering Chord-DHT Network...");
@@ -28,7 +320,7 @@ async fn main() {
     let args: Vec<String> = env::args().collect();
     
     if args.len() < 2 {
-        eprintln!("Usage: {} [bootstrap <k> <m> |node <n> | cli <command> [args]]", args[0]);
+        eprintln!("Usage: {} [bootstrap <k> <m> [<r> <w>] [--max-nodes <n>] [--reply-from-head] [--normalize-keys <trim,nfc,case>] [--trace] [--mem-high-mb <n> [--mem-low-mb <n>]] [--rate-limit <n>] [--audit-log <path>] [--stabilize-ms <n>] [--relocate-reads wait|primary] [--max-message-mb <n>] [--offline-requests redirect|reject] [--seed <n>] [--reassembly-budget-mb <n>] [--workers <n>] [--weight <w>] [--range-check-ms <n>] [--range-auto-correct] [--pendings-limit <n>] [--pending-expiry-ms <n>] |node <n> [--trace] [--mem-high-mb <n> [--mem-low-mb <n>]] [--rate-limit <n>] [--audit-log <path>] [--stabilize-ms <n>] [--relocate-reads wait|primary] [--join-via <ip:port>] [--max-message-mb <n>] [--offline-requests redirect|reject] [--seed <n>] [--reassembly-budget-mb <n>] [--empty-ring fail|start] [--workers <n>] [--weight <w>] [--range-check-ms <n>] [--range-auto-correct] [--pendings-limit <n>] [--pending-expiry-ms <n>] | cli <command> [args] | bootstrap-cluster <seeds.toml>]", args[0]);
         return;
     }
 
@@ -40,31 +332,38 @@ async fn main() {
     match args[1].as_str() {
         "bootstrap" => {
             if args.len() < 4 {
-                panic!("Usage: {} bootstrap <k> <m>", args[0]);
+                panic!("Usage: {} bootstrap <k> <m> [<r> <w>] [--max-nodes <n>] [--reply-from-head] [--normalize-keys <trim,nfc,case>] [--trace] [--mem-high-mb <n> [--mem-low-mb <n>]] [--rate-limit <n>] [--audit-log <path>] [--stabilize-ms <n>] [--relocate-reads wait|primary] [--max-message-mb <n>] [--offline-requests redirect|reject] [--seed <n>] [--reassembly-budget-mb <n>] [--workers <n>] [--weight <w>] [--range-check-ms <n>] [--range-auto-correct] [--pendings-limit <n>] [--pending-expiry-ms <n>]", args[0]);
             } else {
                 let k: u8 = match args[2].parse(){
                     Ok(val) => val,
                     Err(_) => panic!("Invalid parameter for replication factor: k\n")
                 };
                 if k < 1 { panic!("Invalid k. Must be > 0.\n"); }
-                let m_code: usize = match args[3].parse() {
+                let m: Consistency = match args[3].parse() {
                     Ok(val) => val,
-                    Err(_) => panic!("Invalid parameter for replication mode: m\n 
-                                        <m> = \t\t [0 -> Eventual | 1 -> Chain | 2 -> Quorum]")
+                    Err(e) => panic!("Invalid parameter for replication mode: m\n{}\n", e)
                 };
-                let m = match m_code {
-                    0 => Consistency::Eventual,
-                    1 => Consistency::Chain,
-                    2 => Consistency::Quorum,
-                    _ => panic!("Invalid parameter for replication mode: m\n 
-                                <m> = \t\t [0 -> Eventual | 1 -> Chain | 2 -> Quorum]")
+                let quorum = match quorum_sizes(&args, k) {
+                    Ok(quorum) => quorum,
+                    Err(e) => {
+                        eprintln!("Error: {}\nUsage: {} bootstrap <k> <m> [<r> <w>] [--flags]", e, args[0]);
+                        std::process::exit(1);
+                    }
                 };
                 let boot_node = node::Node::new(
                     &BOOT_ADDR,
                     Some(API_PORT),
                     Some(k-1),
                     Some(m),
-                    None            // denotes ptr to itself
+                    None,           // denotes ptr to itself
+                    node::NodeOptions {
+                        quorum,
+                        max_nodes: max_nodes(&args),
+                        reply_from_head: args.iter().any(|arg| arg == "--reply-from-head"),
+                        key_norm: key_norm(&args),
+                        empty_ring: EmptyRing::Start,   // the bootstrap always starts the ring
+                        ..node_options(&args)
+                    }
                 );
                 boot_node.init().await;
             }
@@ -72,19 +371,26 @@ async fn main() {
         }
         "node" => {
             if args.len() < 3 {
-                panic!("Usage: {} node <n>", args[0]);
+                panic!("Usage: {} node <n> [--trace] [--mem-high-mb <n> [--mem-low-mb <n>]] [--rate-limit <n>] [--audit-log <path>] [--stabilize-ms <n>] [--relocate-reads wait|primary] [--join-via <ip:port>] [--max-message-mb <n>] [--offline-requests redirect|reject] [--seed <n>] [--reassembly-budget-mb <n>] [--empty-ring fail|start] [--workers <n>] [--weight <w>] [--range-check-ms <n>] [--range-auto-correct] [--pendings-limit <n>] [--pending-expiry-ms <n>]", args[0]);
             } else {
                 let n: u16 = match args[2].parse(){
                     Ok(val) => val,
                     Err(_) => panic!("Invalid parameter for n.\n")
                 };
                 
+                // reply_from_head and key normalization are taken from the ring on join
                 let node_instance = node::Node::new(
                     &get_local_ip(), 
                     Some(API_PORT+n),     // offset 
                     None, 
                     None,
-                    Some(bootstrap_info));
+                    Some(bootstrap_info),
+                    node::NodeOptions {
+                        join_via: join_via(&args),
+                        empty_ring: empty_ring(&args),
+                        ..node_options(&args)
+                    }
+                );
             
 
                 node_instance.init().await;
@@ -94,6 +400,12 @@ async fn main() {
         "cli" => {
             cli::run_cli();
         }
+        "bootstrap-cluster" => {
+            if args.len() < 3 {
+                panic!("Usage: {} bootstrap-cluster <seeds.toml>", args[0]);
+            }
+            cli::bootstrap_cluster(&args[2]);
+        }
         _ => {
             eprintln!("Usage: {} [bootstrap <k> <m> |node| cli <command> [args]]", args[0]);
         }
@@ -1,7 +1,9 @@
 use std::fmt;
-use crate::{node::{NodeInfo,ReplicationConfig}, utils::HashType, utils::Item, utils::Range};
+use crate::{node::{NodeInfo,ReplicationConfig}, utils::HashType, utils::Item, utils::Range, utils::ClockSample, utils::CompactStrategy, utils::Consistency, utils::KeyNorm, utils::HyperLogLog};
 
 use serde::{Deserialize,Serialize};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MsgType {
@@ -23,14 +25,157 @@ pub enum MsgType {
     Overlay,
     FwOverlay,
     Reply,
-    Relocate
+    Relocate,
+    ClockCheck,
+    FwClockCheck,
+    WhoIsMyNeighbor,
+    Touch,
+    FwTouch,
+    Shutdown,
+    FwShutdown,
+    Repair,
+    AckRepair,
+    LocalRecords,
+    SyncKey,
+    FwSyncKey,
+    Transfer,
+    MultiGet,
+    FwMultiGet,
+    Gossip,
+    Members,
+    Compact,
+    SimulateJoin,
+    NodeRecords,
+    Append,
+    ReadLog,
+    Pin,
+    FwPin,
+    Subscribe,
+    Unsubscribe,
+    Notify,
+    MultiQuery,
+    Pendings,
+    CancelPending,
+    SetReadOnly,
+    CheckReplicas,
+    FwCheckReplicas,
+    Span,
+    Trace,
+    ReadStats,
+    TimeRangeQuery,
+    FwTimeRangeQuery,
+    Rename,
+    InspectKey,
+    FwInspectKey,
+    ReplicaSet,
+    FwReplicaSet,
+    DeadLetters,
+    Reassembly,
+    KeyCount,
+    FwKeyCount,
+    VerifyReplicas,
+    FwVerifyReplicas,
+    Proxy,
+    FwQuorumRead,
+    Reconfigure,
+    #[cfg(feature = "testing")]
+    ForceRelocate
 } 
 
+// wire protocol version: major in the high byte, minor in the low byte
+pub const PROTO_VERSION: u16 = 0x0100;
+
+pub fn proto_major(version: u16) -> u8 {
+    (version >> 8) as u8
+}
+
+tokio::task_local! {
+    // trace id of the request being handled, every message sent while handling it inherits the id
+    pub static TRACE_ID: Uuid;
+    // ring member sending messages from this task, they carry it as their sender
+    pub static SENDER: NodeInfo;
+}
+
+pub fn current_trace() -> Option<Uuid> {
+    TRACE_ID.try_with(|id| *id).ok()
+}
+
+pub fn current_sender() -> Option<NodeInfo> {
+    SENDER.try_with(|info| *info).ok()
+}
+
+// one forward of a traced request: who sent which message where and when
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Span {
+    pub node: NodeInfo,
+    pub msg_type: MsgType,
+    pub timestamp: DateTime<Utc>,
+    pub next_hop: NodeInfo,
+}
+
+/* Spans collected by the bootstrap, grouped by trace id.
+   Only the most recent 'capacity' traces are kept, the oldest is dropped first */
+#[derive(Debug)]
+pub struct TraceSink {
+    capacity: usize,
+    traces: std::collections::HashMap<Uuid, Vec<Span>>,
+    order: std::collections::VecDeque<Uuid>,
+}
+
+impl TraceSink {
+    pub fn new(capacity: usize) -> Self {
+        TraceSink { capacity, traces: std::collections::HashMap::new(), order: std::collections::VecDeque::new() }
+    }
+
+    pub fn record(&mut self, trace_id: Uuid, span: Span) {
+        if !self.traces.contains_key(&trace_id) {
+            if self.order.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.traces.remove(&oldest);
+                }
+            }
+            self.order.push_back(trace_id);
+        }
+        self.traces.entry(trace_id).or_default().push(span);
+    }
+
+    // spans in the order the sending nodes stamped them
+    pub fn get(&self, trace_id: &Uuid) -> Vec<Span> {
+        let mut spans = self.traces.get(trace_id).cloned().unwrap_or_default();
+        spans.sort_by_key(|span| span.timestamp);
+        spans
+    }
+}
+
+// what a query returns once it reaches a node allowed to answer
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum QueryKind {
+    #[default]
+    Value,
+    Exists,         // only whether the key is stored, never the value
+    Record,         // the stored item itself, answered with MsgData::Record
+}
+
+// which replica manager answers a read under Eventual consistency
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ReadPreference {
+    #[default]
+    Nearest,        // first replica manager the request reaches
+    Primary,        // always the node responsible for the key
+    Random,         // any replica manager, spreads read load
+    Replica(u8),    // a fixed replica index, what 'Random' resolves into
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     size: usize,                                // used to create stroing buffer of appropriate size
+    proto_version: u16,                         // checked before decoding, a message without it is refused
+    #[serde(default)]
+    trace_id: Option<Uuid>,                     // same for every hop of one request
     r#type:MsgType,
     client: Option<NodeInfo>,
+    #[serde(default)]
+    sender: Option<NodeInfo>,                   // ring member that sent this hop, None for clients
     data: MsgData
 }
 
@@ -38,33 +183,145 @@ pub struct Message {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]  // Enables JSON with type-discriminated serialization
 pub enum MsgData {
-    Join { id: String },
-    FwJoin { new_node: NodeInfo },
-    AckJoin { prev_info: Option<NodeInfo>, succ_info : Option<NodeInfo>, new_items:Vec<Item>, replica_config: ReplicationConfig  },
-    Quit { id: String },
+    // the addressed node joins or leaves itself, so neither carries an id
+    Join { },
+    // a join without a weight is of weight 1
+    FwJoin { new_node: NodeInfo, #[serde(default)] hops: usize, #[serde(default)] weight: Option<u32> },
+    // transfer names the chunks staged before this message, new_items is the last of them
+    // placed_at is the position a weighted join gave the new node, its hashed id stays without
+    AckJoin { prev_info: Option<NodeInfo>, succ_info : Option<NodeInfo>, new_items:Vec<Item>, replica_config: ReplicationConfig,
+              #[serde(default)] pins: Vec<(HashType, NodeInfo)>, #[serde(default)] transfer: Uuid, #[serde(default)] placed_at: Option<HashType> },
+    // warm streams the handover to the successor before the neighbours are relinked
+    // drain lets a bootstrap that is alone leave with keys, they are replied as LocalRecords
+    Quit { #[serde(default)] warm: bool, #[serde(default)] drain: bool },
     Update { prev_info: Option<NodeInfo>, succ_info: Option<NodeInfo> },
-    Insert { key: String, value: String },
-    FwInsert { key: String, value: String, replica:i16, forward_back:bool },
+    // 'consistency' overrides the ring's mode for this one operation and its forwards
+    // colocate_with places the key on the node of that parent key, see utils::HashColocated
+    // max_value_len keeps only the newest bytes of an appended value, see Item::trim_front
+    // create_only fails the insert at the primary when the key is already stored there
+    // json rejects the insert at the first node unless the value parses as JSON, see Item::json_field
+    // copy_of stores the value as a copy of that item, keeping its version and append offsets (rename)
+    Insert { key: String, value: String, #[serde(default)] wait_for_replication: bool, #[serde(default)] consistency: Option<Consistency>,
+             #[serde(default)] colocate_with: Option<String>, #[serde(default)] max_value_len: Option<usize>, #[serde(default)] create_only: bool,
+             #[serde(default)] json: bool, #[serde(default)] copy_of: Option<Item> },
+    // timestamp and writer are the version the first copy was stamped with, the receiver stamps its own without
+    FwInsert { key: String, value: String, replica:i16, forward_back:bool, #[serde(default)] consistency: Option<Consistency>,
+               #[serde(default)] colocate_with: Option<String>, #[serde(default)] max_value_len: Option<usize>,
+               #[serde(default)] timestamp: Option<DateTime<Utc>>, #[serde(default)] writer: Option<HashType> },
     AckInsert {key : HashType },
-    Delete {key : String },
-    FwDelete { key: HashType, forward_back:bool },
+    // if_version deletes only while the primary copy still has that version, see Item::version
+    Delete {key : String, #[serde(default)] consistency: Option<Consistency>, #[serde(default)] colocate_with: Option<String>,
+            #[serde(default)] if_version: Option<(DateTime<Utc>, Option<HashType>)> },
+    FwDelete { key: HashType, forward_back:bool, #[serde(default)] consistency: Option<Consistency> },
     AckDelete { key: HashType },
-    Query { key: String },
-    FwQuery {key : HashType },
-    QueryAll { },
-    FwQueryAll { record_list: Vec<Item>, header: HashType },
-    Overlay { },
-    FwOverlay { peers: Vec<NodeInfo> },
+    Query { key: String, #[serde(default)] kind: QueryKind, #[serde(default)] prefer: ReadPreference, #[serde(default)] consistency: Option<Consistency>,
+            #[serde(default)] colocate_with: Option<String> },
+    FwQuery {key : HashType, #[serde(default)] kind: QueryKind, #[serde(default)] prefer: ReadPreference, #[serde(default)] consistency: Option<Consistency> },
+    QueryAll { #[serde(default)] parallel: bool, #[serde(default)] restart_on_churn: bool },
+    // visited guards the sweep against departures, churned marks a join or depart seen on the way
+    FwQueryAll { record_list: Vec<Item>, header: HashType, #[serde(default)] visited: Vec<HashType>,
+                 #[serde(default)] churned: bool, #[serde(default)] restart_on_churn: bool, #[serde(default)] restarts: u8 },
+    Overlay { #[serde(default)] structured: bool },
+    FwOverlay { peers: Vec<NodeInfo>, #[serde(default)] gather: bool,
+                #[serde(default)] counts: Vec<usize>, #[serde(default)] structured: bool,
+                #[serde(default)] bytes: Vec<u64> },
     Reply { reply: String },
-    Relocate { k_remaining:u8, inc: bool, new_copies: Option<Vec<Item>>, range: Option<Range<HashType>> } 
+    Record { key: String, record: Option<Item> },
+    // keep lists the prefetched keys that are still current after a warm handoff
+    // widen marks the hop past the last replica of a departed node, see handle_relocate
+    Relocate { k_remaining:u8, inc: bool, new_copies: Option<Vec<Item>>, range: Option<Range<HashType>>,
+               #[serde(default)] keep: Option<Vec<HashType>>, #[serde(default)] transfer: Uuid, #[serde(default)] widen: bool },
+    ClockCheck { },
+    FwClockCheck { samples: Vec<ClockSample> },
+    // previous carries what the forward walk found on to the backward walk, which replies
+    WhoIsMyNeighbor { asker: NodeInfo, forward: bool, header: Option<HashType>, #[serde(default)] previous: Option<NodeInfo> },
+    Touch { key: String },
+    FwTouch { key: HashType, timestamp: DateTime<Utc>, #[serde(default)] writer: Option<HashType> },
+    Shutdown { },
+    FwShutdown { header: HashType, stopped: u32 },
+    Repair { key: HashType, origin: NodeInfo, kind: QueryKind, hops_left: u8, forward_back: bool },
+    AckRepair { item: Item },
+    LocalRecords { records: Vec<Item> },
+    SyncKey { key: String },
+    FwSyncKey { item: Item, updated: u8 },
+    Transfer { items: Vec<Item>, #[serde(default)] prefetch: bool, #[serde(default)] transfer: Uuid },
+    MultiGet { keys: Vec<String>, #[serde(default)] snapshot_ts: Option<DateTime<Utc>> },
+    FwMultiGet { pending: Vec<String>, found: Vec<(String, Option<Item>)>, snapshot_ts: DateTime<Utc>, header: HashType,
+                 #[serde(default)] typed: bool },
+    Gossip { from: NodeInfo, peers: Vec<NodeInfo>, reply: bool },
+    Members { },
+    Compact { key: String, #[serde(default)] strategy: CompactStrategy },
+    SimulateJoin { at_id: HashType },
+    NodeRecords { target: HashType },
+    Records { node: NodeInfo, records: Vec<Item> },
+    Append { key: String, entry: String },
+    ReadLog { key: String, #[serde(default)] since: Option<DateTime<Utc>>, #[serde(default)] limit: Option<usize> },
+    // ring in successor order with the primary record count and stored bytes of each node
+    Ring { peers: Vec<NodeInfo>, counts: Vec<usize>, #[serde(default)] bytes: Vec<u64> },
+    Pin { key: String, node: NodeInfo },
+    FwPin { key: String, node: NodeInfo, header: HashType },
+    Subscribe { key: String },
+    Unsubscribe { key: String },
+    // new state of a subscribed key, None once it has been deleted
+    Notify { key: String, item: Option<Item> },
+    MultiQuery { keys: Vec<String> },
+    // (key, value) in the order the keys were answered, None for missing keys
+    MultiQueryResult { results: Vec<(String, Option<String>)> },
+    Pendings { },
+    CancelPending { key: String },
+    SetReadOnly { enabled: bool },
+    CheckReplicas { key: String },
+    // walks the replica chain of key, counting the copies found and those equal to the primary's
+    FwCheckReplicas { key: String, primary: Option<Item>, copies: u8, matching: u8, hops_left: u8 },
+    // a span reported to the bootstrap, and the spans it collected for one trace
+    Span { trace_id: Uuid, span: Span },
+    Trace { trace_id: Uuid },
+    Spans { trace_id: Uuid, spans: Vec<Span> },
+    ReadStats { },
+    // primary records whose latest version was written in [after, before], collected in one ring sweep
+    TimeRangeQuery { after: DateTime<Utc>, before: DateTime<Utc> },
+    FwTimeRangeQuery { after: DateTime<Utc>, before: DateTime<Utc>, found: Vec<Item>, header: HashType },
+    // copy the value of 'from' to the new key 'to', then delete 'from'
+    Rename { from: String, to: String },
+    InspectKey { key: String },
+    // the nodes holding replicas 0..k of key, in chain order starting at the primary
+    ReplicaSet { key: String },
+    FwReplicaSet { key: String, nodes: Vec<NodeInfo>, hops_left: u8 },
+    DeadLetters { },
+    // memory held by requests being read on the addressed node, against --reassembly-budget-mb
+    Reassembly { },
+    // approximate ring wide key count, the sketches of all nodes merged in one ring sweep
+    KeyCount { },
+    FwKeyCount { sketch: HyperLogLog, header: HashType, nodes: usize },
+    // checks that the n-th successor of the addressed node holds each of its primary keys at replica_idx n
+    // sample checks only that random fraction of the keys and extrapolates the missing copies found
+    VerifyReplicas { #[serde(default)] repair: bool, #[serde(default)] sample: Option<f64> },
+    // a client request whose reply the node writes back on the same connection instead of dialing the client
+    Proxy { msg_type: MsgType, data: Box<MsgData> },
+    // population is the number of primary keys a sample was drawn from, missing the sampled keys lacking a copy
+    FwVerifyReplicas { items: Vec<Item>, replica_idx: u8, hops_left: u8, repair: bool, problems: Vec<String>, repaired: usize,
+                       #[serde(default)] population: Option<usize>, #[serde(default)] missing: Vec<HashType> },
+    // walks the replica chain of key, every node adds the copy it holds, None when it has none
+    FwInspectKey { key: String, copies: Vec<(NodeInfo, Option<Item>)>, hops_left: u8 },
+    // walks the replica chain of key keeping the newest copy, 'left' counts the copies still to read for R
+    FwQuorumRead { key: HashType, title: String, kind: QueryKind, left: u8, newest: Option<Item> },
+    // sets R and W on every node, header is the node that started the walk
+    // the first walk only checks R and W at every node, the second one applies them
+    Reconfigure { read_quorum: u8, write_quorum: u8, #[serde(default)] header: Option<HashType>, #[serde(default)] checked: bool },
+    // runs one relocate hop on the addressed node, replied with all of its records
+    #[cfg(feature = "testing")]
+    ForceRelocate { inc: bool, k_remaining: u8 }
 }
 
 impl Message {
     pub fn new(r#type:MsgType, client:Option<&NodeInfo>, data:&MsgData) -> Self {
         let msg = Message {
                             size: 0,                // stub fix later
+                            proto_version: PROTO_VERSION,
+                            trace_id: Some(current_trace().unwrap_or_else(Uuid::new_v4)),
                             r#type,
                             client: client.cloned(),
+                            sender: current_sender(),
                             data: data.clone()
                         };
         let actual_size = serde_json::to_string(&msg)
@@ -75,14 +332,32 @@ impl Message {
         Message { size:actual_size, ..msg }
     }
 
+    /* The text sent for the message. Fields keep the order they are declared in, so the size a
+        message announces comes first and a node can refuse one too large before its data arrives */
+    pub fn to_wire(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
     pub fn extract_client(&self) -> Option<&NodeInfo> {
         self.client.as_ref()
     }
 
+    pub fn extract_sender(&self) -> Option<&NodeInfo> {
+        self.sender.as_ref()
+    }
+
     pub fn extract_type(&self) -> MsgType {
         self.r#type
     }
 
+    pub fn extract_version(&self) -> u16 {
+        self.proto_version
+    }
+
+    pub fn extract_trace_id(&self) -> Option<Uuid> {
+        self.trace_id
+    }
+
     pub fn extract_size(&self) -> usize {
         self.size
     }
@@ -93,6 +368,110 @@ impl Message {
 
 }
 
+/* Reads the fields ahead of "data" from the start of a message still being received, so that
+    the announced size and the client can be checked before the body arrives. Also tells whether
+    all of them are there, a value the prefix may cut short is left out */
+pub fn header_fields(prefix: &[u8]) -> (serde_json::Map<String, serde_json::Value>, bool) {
+    let mut fields = serde_json::Map::new();
+    let skip_ws = |pos: usize| pos + prefix[pos..].iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+    let next_value = |pos: usize| {
+        let mut values = serde_json::Deserializer::from_slice(&prefix[pos..]).into_iter::<serde_json::Value>();
+        match values.next() {
+            Some(Ok(value)) => Some((value, pos + values.byte_offset())),
+            _ => None
+        }
+    };
+
+    let mut pos = skip_ws(0);
+    if prefix.get(pos) != Some(&b'{') {
+        return (fields, false);
+    }
+    pos += 1;
+    loop {
+        let (key, after_key) = match next_value(skip_ws(pos)) {
+            Some((serde_json::Value::String(key), after_key)) => (key, after_key),
+            _ => return (fields, false)
+        };
+        pos = skip_ws(after_key);
+        if prefix.get(pos) != Some(&b':') {
+            return (fields, false);
+        }
+        if key == "data" {
+            return (fields, true);
+        }
+        let (value, after_value) = match next_value(skip_ws(pos + 1)) {
+            Some(found) => found,
+            None => return (fields, false)
+        };
+        // a number is only whole once something follows it
+        pos = skip_ws(after_value);
+        match prefix.get(pos) {
+            Some(b',') => {
+                fields.insert(key, value);
+                pos += 1;
+            }
+            Some(b'}') => {
+                fields.insert(key, value);
+                return (fields, true);
+            }
+            _ => return (fields, false)
+        }
+    }
+}
+
+impl MsgData {
+    /* Rewrites the keys of a client request with the ring's normalization. Forwards between
+        nodes carry keys that were normalized where the request entered, so they are left as is */
+    pub fn normalize_keys(&mut self, norm: &KeyNorm) {
+        match self {
+            MsgData::Insert { key, colocate_with, .. }
+            | MsgData::Delete { key, colocate_with, .. }
+            | MsgData::Query { key, colocate_with, .. } => {
+                *key = norm.apply(key);
+                if let Some(parent) = colocate_with.as_mut() {
+                    *parent = norm.apply(parent);
+                }
+            }
+            MsgData::Touch { key }
+            | MsgData::SyncKey { key }
+            | MsgData::Compact { key, .. }
+            | MsgData::Append { key, .. }
+            | MsgData::ReadLog { key, .. }
+            | MsgData::Pin { key, .. }
+            | MsgData::Subscribe { key }
+            | MsgData::Unsubscribe { key }
+            | MsgData::CancelPending { key }
+            | MsgData::CheckReplicas { key }
+            | MsgData::InspectKey { key }
+            | MsgData::ReplicaSet { key } => *key = norm.apply(key),
+            MsgData::MultiGet { keys, .. } | MsgData::MultiQuery { keys } => {
+                for key in keys.iter_mut() {
+                    *key = norm.apply(key);
+                }
+            }
+            MsgData::Rename { from, to } => {
+                *from = norm.apply(from);
+                *to = norm.apply(to);
+            }
+            _ => ()
+        }
+    }
+}
+
+impl MsgType {
+    // sent only by nodes to one another, never by the cli on behalf of a client
+    pub fn is_peer_only(&self) -> bool {
+        matches!(self,
+            MsgType::FwJoin | MsgType::AckJoin | MsgType::Update | MsgType::FwInsert | MsgType::AckInsert
+            | MsgType::FwDelete | MsgType::AckDelete | MsgType::FwQuery | MsgType::FwQueryAll | MsgType::FwOverlay
+            | MsgType::Reply | MsgType::Relocate | MsgType::FwClockCheck | MsgType::FwTouch | MsgType::FwShutdown
+            | MsgType::Repair | MsgType::AckRepair | MsgType::LocalRecords | MsgType::FwSyncKey | MsgType::Transfer
+            | MsgType::FwMultiGet | MsgType::Gossip | MsgType::FwPin | MsgType::Notify | MsgType::FwCheckReplicas
+            | MsgType::Span | MsgType::FwTimeRangeQuery | MsgType::FwInspectKey | MsgType::FwReplicaSet
+            | MsgType::FwKeyCount | MsgType::FwVerifyReplicas | MsgType::FwQuorumRead)
+    }
+}
+
 impl fmt::Display for MsgType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self) // Print the enum variant name
@@ -103,6 +482,10 @@ impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Message [ Type: {}", self.r#type)?;
 
+        if let Some(trace_id) = &self.trace_id {
+            write!(f, ", Trace: {}", trace_id)?;
+        }
+
         if let Some(client) = &self.client {
             write!(f, ", Client: {}", client)?;
         }
@@ -1,9 +1,57 @@
+use std::future::Future;
 use std::sync::Arc;
 use async_trait::async_trait;
-use tokio::net::{TcpListener, TcpStream};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::task;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};  
 use tokio::runtime::Builder;  // For multi-threaded runtime
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, Duration};
+
+// pending connections allowed per worker before accept() is slowed down
+const QUEUE_PER_WORKER: usize = 16;
+// a handler still running after this long gives its worker back to the pool
+const DETACH_AFTER_MS: u64 = 200;
+// handlers running per worker, detached ones included, before the workers stop taking connections
+pub const IN_FLIGHT_PER_WORKER: usize = 16;
+
+/* Binds with SO_REUSEADDR so a node or cli restarted right after a crash
+   does not fail while the old socket lingers in TIME_WAIT */
+pub fn bind_listener(addr: SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+tokio::task_local! {
+    // the pool and the permit of the handler running on this task
+    static IN_FLIGHT: (Arc<Semaphore>, std::sync::Mutex<Option<OwnedSemaphorePermit>>);
+}
+
+/* Runs a wait that only another request can end, e.g. a chain read waiting for its ack.
+   The handler's permit is given back meanwhile, otherwise enough parked handlers would hold
+   every permit and the request they wait for would never be taken. It is taken again before
+   the handler goes on. Outside a handler of the pool the wait just runs */
+pub async fn parked<F: Future>(wait: F) -> F::Output {
+    let pool = IN_FLIGHT.try_with(|(pool, permit)| {
+        permit.lock().unwrap().take().map(|permit| {
+            drop(permit);
+            Arc::clone(pool)
+        })
+    }).ok().flatten();
+    let output = wait.await;
+    if let Some(pool) = pool {
+        if let Ok(permit) = pool.acquire_owned().await {
+            IN_FLIGHT.with(|(_, held)| *held.lock().unwrap() = Some(permit));
+        }
+    }
+    output
+}
 
 #[async_trait]
 pub trait ConnectionHandler: Send + Sync {
@@ -23,14 +71,51 @@ impl<T: ConnectionHandler + 'static> Server<T> {
         }
     }
 
-    pub async fn wait_for_requests(&self, listener: TcpListener) {
+    /* A fixed number of workers pull accepted connections from a bounded queue.
+       When the queue is full accept() waits, which pushes back on senders.
+       Some handlers sleep until another message arrives (e.g. chain reads waiting for an ack),
+       so a handler that outlives DETACH_AFTER_MS keeps running on its own and frees its worker.
+       Every handler holds a permit until it returns, which bounds the detached ones as well,
+       except while it is parked waiting for another request (see 'parked') */
+    pub async fn wait_for_requests(&self, listener: TcpListener, num_workers: usize) {
+        let num_workers = num_workers.max(1);
+        let (sender, receiver) = mpsc::channel::<TcpStream>(num_workers * QUEUE_PER_WORKER);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let in_flight = Arc::new(Semaphore::new(num_workers * IN_FLIGHT_PER_WORKER));
+
+        for _ in 0..num_workers {
+            let handler = Arc::clone(&self.handler);
+            let receiver = Arc::clone(&receiver);
+            let in_flight = Arc::clone(&in_flight);
+            tokio::spawn(async move {
+                loop {
+                    let permit = match Arc::clone(&in_flight).acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => break,
+                    };
+                    let next = receiver.lock().await.recv().await;
+                    match next {
+                        Some(stream) => {
+                            let handler = Arc::clone(&handler);
+                            let held = (Arc::clone(&in_flight), std::sync::Mutex::new(Some(permit)));
+                            // the permit goes with the scope when the handler returns
+                            let job = tokio::spawn(IN_FLIGHT.scope(held, async move {
+                                handler.handle_request(stream).await;
+                            }));
+                            let _ = timeout(Duration::from_millis(DETACH_AFTER_MS), job).await;
+                        }
+                        None => break,   // server dropped the queue
+                    }
+                }
+            });
+        }
+
         loop {
             match listener.accept().await {
                 Ok((stream, _)) => {
-                    let handler = Arc::clone(&self.handler);
-                    tokio::spawn(async move {
-                        handler.handle_request(stream).await;
-                    });
+                    if sender.send(stream).await.is_err() {
+                        eprintln!("Worker pool is closed, dropping connection");
+                    }
                 }
                 Err(e) => {
                     eprintln!("Connection error: {}", e);
@@ -39,3 +124,134 @@ impl<T: ConnectionHandler + 'static> Server<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::time::{sleep, Instant};
+
+    // holds every connection until released, like reads waiting for an ack that does not come
+    #[derive(Default)]
+    struct StuckHandler {
+        running: AtomicUsize,
+        done: AtomicUsize,
+        released: AtomicBool,
+    }
+
+    #[async_trait]
+    impl ConnectionHandler for Arc<StuckHandler> {
+        async fn handle_request(&self, _stream: TcpStream) {
+            self.running.fetch_add(1, Ordering::SeqCst);
+            while !self.released.load(Ordering::SeqCst) {
+                sleep(Duration::from_millis(10)).await;
+            }
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            self.done.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detached_handlers_stay_bounded() {
+        let workers = 4;
+        let limit = workers * IN_FLIGHT_PER_WORKER;
+        let connections = limit + 10;
+        let handler = Arc::new(StuckHandler::default());
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), 1024).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(Arc::clone(&handler));
+        tokio::spawn(async move { server.wait_for_requests(listener, workers).await });
+
+        let mut streams = Vec::new();
+        for _ in 0..connections {
+            streams.push(TcpStream::connect(addr).await.unwrap());
+        }
+        // every worker detaches a handler per DETACH_AFTER_MS until the permits run out
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while handler.running.load(Ordering::SeqCst) < limit {
+            assert!(Instant::now() < deadline, "only {} handlers started", handler.running.load(Ordering::SeqCst));
+            sleep(Duration::from_millis(20)).await;
+        }
+        sleep(Duration::from_millis(DETACH_AFTER_MS * 3)).await;
+        assert_eq!(handler.running.load(Ordering::SeqCst), limit);
+
+        handler.released.store(true, Ordering::SeqCst);
+        while handler.done.load(Ordering::SeqCst) < connections {
+            assert!(Instant::now() < deadline, "only {} of {} handled", handler.done.load(Ordering::SeqCst), connections);
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    // parks every connection until one more arrives, like chain reads waiting for their ack
+    #[derive(Default)]
+    struct ParkedHandler {
+        parked: AtomicUsize,
+        done: AtomicUsize,
+        ack: tokio::sync::Notify,
+    }
+
+    #[async_trait]
+    impl ConnectionHandler for Arc<ParkedHandler> {
+        async fn handle_request(&self, mut stream: TcpStream) {
+            let mut kind = [0u8; 1];
+            stream.read_exact(&mut kind).await.unwrap();
+            if &kind == b"a" {
+                self.ack.notify_waiters();
+                return;
+            }
+            let acked = self.ack.notified();
+            tokio::pin!(acked);
+            acked.as_mut().enable();
+            self.parked.fetch_add(1, Ordering::SeqCst);
+            parked(acked).await;
+            self.done.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn parked_handlers_leave_room_for_the_ack() {
+        let workers = 2;
+        let readers = workers * IN_FLIGHT_PER_WORKER + 8;
+        let handler = Arc::new(ParkedHandler::default());
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), 1024).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(Arc::clone(&handler));
+        tokio::spawn(async move { server.wait_for_requests(listener, workers).await });
+
+        let mut streams = Vec::new();
+        for _ in 0..readers {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"r").await.unwrap();
+            streams.push(stream);
+        }
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while handler.parked.load(Ordering::SeqCst) < readers {
+            assert!(Instant::now() < deadline, "only {} of {} readers parked", handler.parked.load(Ordering::SeqCst), readers);
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        // more readers are parked than there are permits, the ack must still be taken
+        let mut ack = TcpStream::connect(addr).await.unwrap();
+        ack.write_all(b"a").await.unwrap();
+        while handler.done.load(Ordering::SeqCst) < readers {
+            assert!(Instant::now() < deadline, "only {} of {} readers woken", handler.done.load(Ordering::SeqCst), readers);
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn rebinds_right_after_drop() {
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the side closing first keeps the port in TIME_WAIT, as a crashed node does
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (mut accepted, _) = listener.accept().await.unwrap();
+        accepted.shutdown().await.unwrap();
+        drop(accepted);
+        drop(client);
+        drop(listener);
+
+        let rebound = bind_listener(addr, 16).expect("port taken again right away");
+        assert_eq!(rebound.local_addr().unwrap(), addr);
+    }
+}
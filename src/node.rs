@@ -2,28 +2,35 @@
 #![allow(dead_code, non_snake_case, unused_imports)]
 
 use tokio::net::{TcpListener, TcpStream};
-use std::net::{Ipv4Addr,SocketAddrV4};
+use std::net::{Ipv4Addr,SocketAddr,SocketAddrV4};
 use std::collections::BTreeMap;
 use tokio::sync::RwLock;
 use std::sync::Arc;
 use num_traits::Bounded;
 use serde::{Serialize, Deserialize};
-use std::sync::atomic::{AtomicBool, Ordering};
-use tokio::time::{sleep, Duration};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use tokio::time::{sleep, Duration, Instant};
 use serde_json::Value;
 use std::{thread, vec};
 use async_trait::async_trait;
 use tokio::io::{AsyncReadExt,BufReader,AsyncWriteExt};
 use std::fmt;
-use std::collections::HashMap;
-use tokio::sync::Notify;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::sync::{oneshot, Notify, Semaphore};
 use chrono::{DateTime, Utc};
-
-use crate::messages::{Message, MsgType, MsgData};
-use crate::utils::{Consistency, DebugMsg, HashFunc, HashIP, HashType, Item, Range, UnionRange};
-use crate::network::{ConnectionHandler, Server};
+use rand::Rng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use uuid::Uuid;
+
+use crate::messages::{Message, MsgType, MsgData, QueryKind, ReadPreference, Span, TraceSink};
+use crate::utils::{CompactStrategy, Consistency, DebugMsg, HashFunc, HashIP, HashType, HashWeighted, HyperLogLog, Item, KeyNorm, EmptyRing, OfflineRequests, RateLimiter, RelocateReads, Range, ReadCache, TimeIndex, UnionRange};
+use crate::network::{self, ConnectionHandler, Server};
+use crate::partition::{ChordPartitioner, Partitioner};
 use crate::NUM_THREADS; 
 use crate::utils;
+use crate::messages;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct NodeInfo {
@@ -33,25 +40,325 @@ pub struct NodeInfo {
 }
 
 
+/* Everything a node can be started with besides its address, k, m and bootstrap.
+    Default is a plain Chord node with every optional feature off */
+#[derive(Clone)]
+pub struct NodeOptions {
+    pub quorum: Option<(u8, u8)>,                           // R and W, a majority of k copies without
+    pub partitioner: Option<Arc<dyn Partitioner>>,          // key placement, Chord ring without
+    pub read_cache: Option<usize>,                          // entries of the remote read cache, off without
+    pub max_nodes: Option<usize>,                           // ring size cap, taken from the bootstrap by joining nodes
+    pub reply_from_head: bool,
+    pub key_norm: KeyNorm,
+    pub tracing: bool,
+    pub mem_limits: Option<(u64, u64)>,                     // (high, low) marks in stored bytes
+    pub rate_limit: Option<u32>,                            // client requests per second and client
+    pub audit_log: Option<String>,                          // path of the audit file
+    pub stabilize_ms: Option<u64>,
+    pub relocate_reads: RelocateReads,
+    pub join_via: Option<NodeInfo>,
+    pub max_message_bytes: usize,
+    pub offline_requests: OfflineRequests,
+    pub seed: Option<u64>,
+    pub reassembly_budget_kb: Option<u32>,
+    pub empty_ring: EmptyRing,
+    pub workers: usize,                                     // connections handled at once before accept() waits
+    pub weight: u32,                                        // share of the keyspace relative to other nodes
+    pub range_check_ms: u64,                                // period of the replica range checkpoint, --range-check-ms
+    pub range_auto_correct: bool,                           // fix drifted ranges instead of logging them, --range-auto-correct
+    pub pendings_limit: usize,                              // keys readers may wait on at once, --pendings-limit
+    pub pending_expiry_ms: u64,                             // idle time before blocked readers fail, --pending-expiry-ms
+}
+
+impl Default for NodeOptions {
+    fn default() -> Self {
+        NodeOptions {
+            quorum: None,
+            partitioner: None,
+            read_cache: None,
+            max_nodes: None,
+            reply_from_head: false,
+            key_norm: KeyNorm::default(),
+            tracing: false,
+            mem_limits: None,
+            rate_limit: None,
+            audit_log: None,
+            stabilize_ms: None,
+            relocate_reads: RelocateReads::default(),
+            join_via: None,
+            max_message_bytes: crate::MAX_MESSAGE_BYTES,
+            offline_requests: OfflineRequests::default(),
+            seed: None,
+            reassembly_budget_kb: None,
+            empty_ring: EmptyRing::default(),
+            workers: NUM_THREADS,
+            weight: 1,
+            range_check_ms: crate::RANGE_CHECK_INTERVAL_MS,
+            range_auto_correct: crate::RANGE_AUTO_CORRECT,
+            pendings_limit: crate::PENDINGS_LIMIT,
+            pending_expiry_ms: crate::PENDING_EXPIRY_MS,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReplicationConfig {
     replication_factor: u8,
     replication_mode: Consistency,
     replica_ranges: UnionRange<HashType>,
+    read_quorum: u8,                                        // R and W for Quorum, counted in copies
+    write_quorum: u8,
+    #[serde(default)]
+    reply_from_head: bool,                                  // Chain replies to clients once the acks reach the head, not at the tail
+    #[serde(default)]
+    key_norm: KeyNorm,                                      // applied to client keys before hashing, the same on every node
+    #[serde(default)]
+    max_nodes: Option<usize>,                               // ring size cap, checked by whichever node a join enters through
+}
+
+// readers blocked on a pending key, woken by its ack, by an operator cancelling it or by expiry
+#[derive(Debug)]
+struct PendingWait {
+    notify: Notify,
+    waiters: AtomicUsize,
+    cancelled: AtomicBool,
+    last_waiter: std::sync::Mutex<Instant>,     // when the latest reader started waiting
+}
+
+/* Relocations running on this node, the same wait and wake as PendingWait but for the
+   whole node: replica indices and ranges change for many keys at once */
+#[derive(Debug, Default)]
+struct RelocationGuard {
+    active: AtomicUsize,
+    done: Notify,
+}
+
+// held while a relocation runs, the last one dropped wakes the waiting reads
+struct RelocationPass(Arc<RelocationGuard>);
+
+impl RelocationGuard {
+    fn enter(self: &Arc<Self>) -> RelocationPass {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        RelocationPass(Arc::clone(self))
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst) > 0
+    }
+
+    // false if relocations were still running when the wait gave up
+    async fn wait(&self, limit: Duration) -> bool {
+        let done = self.done.notified();
+        tokio::pin!(done);
+        done.as_mut().enable();
+        if !self.is_active() {
+            return true;
+        }
+        tokio::time::timeout(limit, done).await.is_ok()
+    }
+}
+
+impl Drop for RelocationPass {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.done.notify_waiters();
+        }
+    }
+}
+
+/* Memory all requests being read may buffer together, in KB. A request holds permits for
+   what it has buffered so far and gives them back once it has been handled */
+#[derive(Debug)]
+struct ReassemblyBudget {
+    permits: Semaphore,
+    total_kb: u32,
+    peak_kb: AtomicUsize,       // most ever held at once
+    waiting_kb: AtomicUsize,    // held by requests that are themselves waiting for more
+    rejected: AtomicUsize,      // requests dropped because the budget stayed exhausted
+}
+
+// the permits of one request, returned when it is dropped
+struct ReassemblyHold {
+    budget: Arc<ReassemblyBudget>,
+    held_kb: u32,
+}
+
+impl ReassemblyBudget {
+    fn new(total_kb: u32) -> Self {
+        ReassemblyBudget { permits: Semaphore::new(total_kb as usize), total_kb, peak_kb: AtomicUsize::new(0),
+                          waiting_kb: AtomicUsize::new(0), rejected: AtomicUsize::new(0) }
+    }
+
+    fn hold(self: &Arc<Self>) -> ReassemblyHold {
+        ReassemblyHold { budget: Arc::clone(self), held_kb: 0 }
+    }
+
+    fn in_flight_kb(&self) -> usize {
+        self.total_kb as usize - self.permits.available_permits()
+    }
+}
+
+impl ReassemblyHold {
+    /* Grows the hold to cover len buffered bytes, REASSEMBLY_STEP_KB at a time. Waits for other
+        requests to give permits back until the deadline, false if that wait fails or the
+        request alone is larger than the whole budget. Once every request holding permits is
+        waiting for more, none of them can finish: the one whose wait would close that circle
+        gives up at once instead, and its permits let the others go on */
+    async fn cover(&mut self, len: usize, deadline: Instant) -> bool {
+        let needed = (len / 1024 + 1).min(u32::MAX as usize) as u32;
+        if needed <= self.held_kb {
+            return true;
+        }
+        let budget = &self.budget;
+        if needed > budget.total_kb {
+            budget.rejected.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+        let more = (needed - self.held_kb).max(crate::REASSEMBLY_STEP_KB).min(budget.total_kb - self.held_kb);
+        let acquired = match budget.permits.try_acquire_many(more) {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                let held = self.held_kb as usize;
+                let waiting = budget.waiting_kb.fetch_add(held, Ordering::SeqCst) + held;
+                let acquired = if held > 0 && waiting >= budget.in_flight_kb() {
+                    None
+                } else {
+                    tokio::time::timeout_at(deadline, budget.permits.acquire_many(more)).await.ok().and_then(|permit| permit.ok())
+                };
+                budget.waiting_kb.fetch_sub(held, Ordering::SeqCst);
+                acquired
+            }
+        };
+        match acquired {
+            Some(permit) => {
+                permit.forget();
+                self.held_kb += more;
+                budget.peak_kb.fetch_max(budget.in_flight_kb(), Ordering::SeqCst);
+                true
+            }
+            None => {
+                budget.rejected.fetch_add(1, Ordering::SeqCst);
+                false
+            }
+        }
+    }
+}
+
+impl Drop for ReassemblyHold {
+    fn drop(&mut self) {
+        self.budget.permits.add_permits(self.held_kb as usize);
+    }
+}
+
+// a replication or topology message that could not be delivered, retried with backoff
+#[derive(Debug)]
+struct DeadLetter {
+    dest: NodeInfo,
+    msg: Message,
+    failed_at: Instant,         // first failed delivery
+    attempts: u32,
+    retry_at: Instant,
+}
+
+// undelivered messages waiting for redelivery, with what became of the earlier ones
+#[derive(Debug, Default)]
+struct DeadLetters {
+    queue: VecDeque<DeadLetter>,
+    redelivered: u64,
+    dropped: u64,
+}
+
+/* Messages the ring depends on to converge. A lost one leaves a replica behind for good,
+   while client requests and replies are better answered by a timeout. Topology messages
+   (Update, Relocate, AckJoin) are not retried: they carry no version, so a pointer update
+   replayed late could overwrite a newer one */
+fn redeliverable(msg_type: MsgType) -> bool {
+    matches!(msg_type, MsgType::FwInsert | MsgType::AckInsert | MsgType::FwDelete | MsgType::AckDelete
+                     | MsgType::FwSyncKey | MsgType::FwTouch | MsgType::AckRepair | MsgType::FwPin)
+}
+
+// Eventual reads reaching this node, answered from the local copy or passed on
+#[derive(Debug, Default)]
+struct ReadCounters {
+    local: AtomicU64,
+    forwarded: AtomicU64,
+}
+
+impl PendingWait {
+    fn new() -> Self {
+        PendingWait {
+            notify: Notify::new(),
+            waiters: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            last_waiter: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_waiter.lock().unwrap().elapsed()
+    }
+
+    // the readers still blocked get an error, as for cancel-pending
+    fn expire(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
 }
 
 
 #[derive(Debug, Clone)]
 pub struct Node {
-    info: NodeInfo,                                         /* wraps ip, port, id
-                                                            no lock needed - is immutable */                              
+    info: Arc<std::sync::RwLock<NodeInfo>>,                 /* wraps ip, port, id. Only the id changes, once,
+                                                            when a weighted join places the node. Its id then
+                                                            no longer follows from ip and port, so lookups by
+                                                            address go through the members, never a hash */
+    weight : u32,                                           // share of the keyspace relative to other nodes
     previous : Arc<RwLock<Option<NodeInfo>>>,                  
     successor : Arc<RwLock<Option<NodeInfo>>>, 
     bootstrap : Option<NodeInfo>,                           // no lock because it is read only
+    join_via : Option<NodeInfo>,                            // ring member joins are sent to instead of the bootstrap
+    max_message_bytes : usize,                              // requests announcing more, or growing past it, are rejected
+    offline_requests : OfflineRequests,                     // whether an offline node names a live one to retry at
+    empty_ring : EmptyRing,                                 // whether a join no ring answers starts a ring of its own
+    workers : usize,                                        // size of the request worker pool
+    rng : Arc<std::sync::Mutex<StdRng>>,                    // every random choice of the node, seeded by --seed for reproducible runs
+    reassembly : Option<Arc<ReassemblyBudget>>,             // memory shared by requests being read, unbounded without
+    tracing : bool,                                         // report a span to the bootstrap for every forward
+    traces : Arc<RwLock<TraceSink>>,                        // spans reported by the ring, only filled at the bootstrap
     replication: Arc<RwLock<ReplicationConfig>>,             // wraps k, m, ids             
     records : Arc<RwLock<BTreeMap<HashType, Item>>>,        // list of hashed records per node
-    pendings : Arc<RwLock<HashMap<HashType, Arc<Notify>>>>,    // keeps track of blocked queries at head
-    status: Arc<AtomicBool>                                 // denotes if server is alive
+    pendings : Arc<RwLock<HashMap<HashType, Arc<PendingWait>>>>,    // keeps track of blocked queries at head
+    incoming : Arc<RwLock<HashMap<Uuid, Vec<Item>>>>,       // transfer chunks staged per transfer until it completes
+    prefetched : Arc<RwLock<Vec<Item>>>,                    // warm handoff copies of the predecessor, applied at its cutover
+    members : Arc<RwLock<BTreeMap<HashType, NodeInfo>>>,    // peers learnt through gossip, bootstrap is only a seed
+    chain_window : Arc<Semaphore>,                          // free slots for chain inserts awaiting their ack
+    pins : Arc<RwLock<HashMap<HashType, NodeInfo>>>,        // keys placed on a designated node instead of by hash
+    range_drift : Arc<AtomicU64>,                           // replica range mismatches found by the checkpoint
+    range_check : (Duration, bool),                         // checkpoint period and whether lasting drift is repaired
+    pendings_bound : (usize, Duration),                     // keys readers may wait on and how long they stay idle before expiry
+    stored_bytes : Arc<AtomicU64>,                          // value bytes of all local records, primaries and replicas
+    subscribers : Arc<RwLock<HashMap<HashType, Vec<NodeInfo>>>>,  // clients pushed on changes of keys this node is primary for
+    serving_from : Arc<RwLock<Option<Instant>>>,            // end of the post join grace period, reads are not served locally before it
+    stabilize_window : Option<Duration>,                    // joins and departs refused this long after a topology change, none without
+    relocating : Arc<RelocationGuard>,                      // relocations shifting replica indices on this node right now
+    creating : Arc<tokio::sync::Mutex<()>>,                 // held by a create-only insert from its existence check until it is stored
+    relocate_reads : RelocateReads,                         // what reads arriving meanwhile do
+    stable_from : Arc<RwLock<Option<Instant>>>,             // end of the current stabilization window
+    read_cache : Arc<RwLock<ReadCache>>,                    // recent Eventual results for keys held elsewhere, empty when disabled
+    awaiting : Arc<RwLock<HashMap<Uuid, oneshot::Sender<MsgData>>>>,  // replies this node asked for itself, by trace id
+    status: Arc<AtomicBool>,                                // denotes if server is alive
+    departing: Arc<AtomicBool>,                             // a depart is in progress, the node still serves until the cutover
+    read_only: Arc<AtomicBool>,                             // refuse client writes this node would apply
+    reads: Arc<ReadCounters>,                               // Eventual reads answered here vs forwarded
+    mem_limits: Option<(u64, u64)>,                         // (high, low) marks in stored bytes, no shedding without
+    accessed: Arc<RwLock<HashMap<HashType, Instant>>>,      // last local read per key, only kept with mem_limits
+    rate_limiter: Arc<RwLock<RateLimiter>>,                 // requests per second and client address, ring peers exempt
+    by_time: Arc<RwLock<TimeIndex>>,                        // records by the timestamp of their latest version
+    key_sketch: Arc<RwLock<HyperLogLog>>,                   // every key inserted here, deletes are not subtracted
+    audit_log: Option<Arc<std::sync::Mutex<std::fs::File>>>, // topology and replica range changes, appended in order
+    dead_letters: Arc<RwLock<DeadLetters>>,                 // replication messages awaiting redelivery
+    stopped: Arc<Notify>,                                   // woken by a ring shutdown, ends init and with it the process
+    partitioner: Arc<dyn Partitioner>                       // key placement, Chord ring unless replaced
 }
 
 impl NodeInfo {
@@ -77,7 +384,7 @@ impl NodeInfo {
 
     async fn send_msg(&self, msg: &Message) -> Option<TcpStream> { 
         let sock_addr = std::net::SocketAddrV4::new(self.ip_addr, self.port);
-        let jsonify = serde_json::json!(msg).to_string();
+        let jsonify = msg.to_wire();
         let msg_bytes = jsonify.as_bytes();
         
         match TcpStream::connect(sock_addr).await {
@@ -113,6 +420,40 @@ impl NodeInfo {
         }
     }
 
+    // asks the node for its primary records and waits for them on the same connection
+    async fn fetch_records(&self) -> Option<Vec<Item>> {
+        let request = Message::new(
+            MsgType::LocalRecords,
+            None,
+            &MsgData::LocalRecords { records: Vec::new() }
+        );
+        let mut stream = self.send_msg(&request).await?;
+        let mut response = Vec::new();
+        let read = tokio::time::timeout(
+            Duration::from_millis(crate::GATHER_TIMEOUT_MS),
+            stream.read_to_end(&mut response)
+        ).await;
+        if !matches!(read, Ok(Ok(_))) {
+            eprintln!("❌ No records received from node {}:{}", self.ip_addr, self.port);
+            return None;
+        }
+        match serde_json::from_slice::<Message>(&response).ok()?.extract_data() {
+            MsgData::LocalRecords { records } => Some(records),
+            _ => None
+        }
+    }
+
+    // the receiver closes the connection once it has handled the message, which acts as the ack
+    async fn send_acked(&self, msg: &Message) -> bool {
+        match self.send_msg(msg).await {
+            Some(mut stream) => {
+                let mut ack = Vec::new();
+                stream.read_to_end(&mut ack).await.is_ok()
+            }
+            None => false
+        }
+    }
+
 }
 
 impl Node  {
@@ -120,7 +461,7 @@ impl Node  {
     // fileds startin with _ can be initilaised to None
     pub fn new( ip:&Ipv4Addr, _port: Option<u16>, 
                 _k_repl: Option<u8>, _m_repl: Option<Consistency>, 
-                _boot_ref: Option<NodeInfo>) -> Self {
+                _boot_ref: Option<NodeInfo>, opts: NodeOptions) -> Self {
 
         let init_info = NodeInfo {
             ip_addr: *ip,
@@ -128,48 +469,148 @@ impl Node  {
             id : HashIP(*ip, _port.unwrap_or(0)),                                     
         };
 
+        // nodes started with one seed still differ from each other, by their port
+        let rng = match opts.seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(init_info.port as u64)),
+            None => StdRng::from_entropy()
+        };
+
+        // default to a majority of all copies for both reads and writes
+        let copies = _k_repl.unwrap_or(0) + 1;
+        let majority = copies / 2 + 1;
+        let (read_quorum, write_quorum) = opts.quorum.unwrap_or((majority, majority));
+
         let init_replication = ReplicationConfig {
             replication_factor: _k_repl.unwrap_or(0),
             replica_ranges: UnionRange::new(),           
             replication_mode: _m_repl.unwrap_or(Consistency::Eventual),
+            read_quorum,
+            write_quorum,
+            reply_from_head: opts.reply_from_head,
+            key_norm: opts.key_norm,
+            max_nodes: opts.max_nodes,
         };
         
 
         Node {
-            info: init_info,                
+            info: Arc::new(std::sync::RwLock::new(init_info)),
+            weight: opts.weight,
             successor: Arc::new(RwLock::new(None)),
             previous: Arc::new(RwLock::new(None)),
             bootstrap: _boot_ref,
+            join_via: opts.join_via,
+            max_message_bytes: opts.max_message_bytes,
+            offline_requests: opts.offline_requests,
+            empty_ring: opts.empty_ring,
+            workers: opts.workers,
+            rng: Arc::new(std::sync::Mutex::new(rng)),
+            reassembly: opts.reassembly_budget_kb.map(|total_kb| Arc::new(ReassemblyBudget::new(total_kb))),
+            tracing: opts.tracing,
+            traces: Arc::new(RwLock::new(TraceSink::new(crate::TRACE_CAPACITY))),
             replication: Arc::new(RwLock::new(init_replication)),
             records: Arc::new(RwLock::new(BTreeMap::new())),
             pendings: Arc::new(RwLock::new(HashMap::new())),
-            status: Arc::new(AtomicBool::new(false)) 
+            incoming: Arc::new(RwLock::new(HashMap::new())),
+            prefetched: Arc::new(RwLock::new(Vec::new())),
+            members: Arc::new(RwLock::new(BTreeMap::new())),
+            chain_window: Arc::new(Semaphore::new(crate::CHAIN_WINDOW)),
+            pins: Arc::new(RwLock::new(HashMap::new())),
+            range_drift: Arc::new(AtomicU64::new(0)),
+            range_check: (Duration::from_millis(opts.range_check_ms), opts.range_auto_correct),
+            pendings_bound: (opts.pendings_limit, Duration::from_millis(opts.pending_expiry_ms)),
+            stored_bytes: Arc::new(AtomicU64::new(0)),
+            subscribers: Arc::new(RwLock::new(HashMap::new())),
+            serving_from: Arc::new(RwLock::new(None)),
+            stabilize_window: opts.stabilize_ms.map(Duration::from_millis),
+            relocating: Arc::new(RelocationGuard::default()),
+            creating: Arc::new(tokio::sync::Mutex::new(())),
+            relocate_reads: opts.relocate_reads,
+            stable_from: Arc::new(RwLock::new(None)),
+            read_cache: Arc::new(RwLock::new(ReadCache::new(opts.read_cache.unwrap_or(0), Duration::from_millis(crate::READ_CACHE_TTL_MS)))),
+            awaiting: Arc::new(RwLock::new(HashMap::new())),
+            status: Arc::new(AtomicBool::new(false)),
+            departing: Arc::new(AtomicBool::new(false)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            reads: Arc::new(ReadCounters::default()),
+            mem_limits: opts.mem_limits,
+            accessed: Arc::new(RwLock::new(HashMap::new())),
+            rate_limiter: Arc::new(RwLock::new(RateLimiter::new(opts.rate_limit.unwrap_or(0)))),
+            by_time: Arc::new(RwLock::new(TimeIndex::default())),
+            key_sketch: Arc::new(RwLock::new(HyperLogLog::new())),
+            audit_log: opts.audit_log.map(|path| {
+                let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)
+                    .unwrap_or_else(|e| panic!("Cannot open audit log {}: {}", path, e));
+                Arc::new(std::sync::Mutex::new(file))
+            }),
+            dead_letters: Arc::new(RwLock::new(DeadLetters::default())),
+            stopped: Arc::new(Notify::new()),
+            partitioner: opts.partitioner.unwrap_or_else(|| Arc::new(ChordPartitioner))
         }
     }
 
     pub fn clone (&self) -> Self {
         Node {
-            info: self.info,
+            info: Arc::clone(&self.info),
+            weight: self.weight,
             previous: Arc::clone(&self.previous),
             successor: Arc::clone(&self.successor),
             bootstrap: self.bootstrap,
+            join_via: self.join_via,
+            max_message_bytes: self.max_message_bytes,
+            offline_requests: self.offline_requests,
+            empty_ring: self.empty_ring,
+            workers: self.workers,
+            rng: Arc::clone(&self.rng),
+            reassembly: self.reassembly.clone(),
+            tracing: self.tracing,
+            traces: Arc::clone(&self.traces),
             replication: self.replication.clone(),
             records: Arc::clone(&self.records),
             pendings: Arc::clone(&self.pendings),
-            status: Arc::clone(&self.status)
+            incoming: Arc::clone(&self.incoming),
+            prefetched: Arc::clone(&self.prefetched),
+            members: Arc::clone(&self.members),
+            chain_window: Arc::clone(&self.chain_window),
+            pins: Arc::clone(&self.pins),
+            range_drift: Arc::clone(&self.range_drift),
+            range_check: self.range_check,
+            pendings_bound: self.pendings_bound,
+            stored_bytes: Arc::clone(&self.stored_bytes),
+            subscribers: Arc::clone(&self.subscribers),
+            serving_from: Arc::clone(&self.serving_from),
+            stabilize_window: self.stabilize_window,
+            relocating: Arc::clone(&self.relocating),
+            creating: Arc::clone(&self.creating),
+            relocate_reads: self.relocate_reads,
+            stable_from: Arc::clone(&self.stable_from),
+            read_cache: Arc::clone(&self.read_cache),
+            awaiting: Arc::clone(&self.awaiting),
+            status: Arc::clone(&self.status),
+            departing: Arc::clone(&self.departing),
+            read_only: Arc::clone(&self.read_only),
+            reads: Arc::clone(&self.reads),
+            mem_limits: self.mem_limits,
+            accessed: Arc::clone(&self.accessed),
+            rate_limiter: Arc::clone(&self.rate_limiter),
+            by_time: Arc::clone(&self.by_time),
+            key_sketch: Arc::clone(&self.key_sketch),
+            audit_log: self.audit_log.clone(),
+            dead_letters: Arc::clone(&self.dead_letters),
+            stopped: Arc::clone(&self.stopped),
+            partitioner: Arc::clone(&self.partitioner)
         }
     }
 
     fn get_id(&self) -> HashType {
-        self.info.id
+        self.get_info().id
     }
 
     fn get_ip(&self) -> Ipv4Addr {
-        self.info.ip_addr
+        self.get_info().ip_addr
     }
 
     fn get_port(&self) -> u16 {
-        self.info.port
+        self.get_info().port
     }
 
     fn get_status(&self) -> bool {
@@ -196,18 +637,40 @@ impl Node  {
 
     async fn set_prev(&self, new_node:Option<NodeInfo>) {
     self.print_debug_msg("Acquiring write lock on previous...");
-        *self.previous.write().await = new_node;
+        let old = std::mem::replace(&mut *self.previous.write().await, new_node);
     self.print_debug_msg("Write lock released on previous.");
+        if old.map(|node| node.id) != new_node.map(|node| node.id) {
+            self.audit(&format!("prev {} -> {}", Self::audit_id(&old), Self::audit_id(&new_node)));
+        }
     }
 
     async fn set_succ(&self, new_node:Option<NodeInfo>) {
     self.print_debug_msg("Acquiring write lock on successor...");
-        *self.successor.write().await = new_node;
+        let old = std::mem::replace(&mut *self.successor.write().await, new_node);
     self.print_debug_msg("Write lock released on successor.");
+        if old.map(|node| node.id) != new_node.map(|node| node.id) {
+            self.audit(&format!("succ {} -> {}", Self::audit_id(&old), Self::audit_id(&new_node)));
+        }
+    }
+
+    /* One line per change of durable state: "<timestamp> <node id> <event>". Writes are
+        synchronous and unbuffered so the file is complete up to a crash. Never takes async locks,
+        so it can be called while holding the records or replication locks */
+    fn audit(&self, event:&str) {
+        if let Some(log) = &self.audit_log {
+            let line = format!("{} {} {}\n", Utc::now().to_rfc3339(), self.get_id(), event);
+            if let Err(e) = std::io::Write::write_all(&mut *log.lock().unwrap(), line.as_bytes()) {
+                eprintln!("Failed to write the audit log: {}", e);
+            }
+        }
+    }
+
+    fn audit_id(node:&Option<NodeInfo>) -> String {
+        node.map_or("none".to_string(), |node| node.id.to_string())
     }
 
     fn get_info(&self) -> NodeInfo {
-        self.info
+        *self.info.read().unwrap()
     }
 
     async fn get_replica_ranges(&self) -> UnionRange<HashType> {
@@ -231,6 +694,35 @@ impl Node  {
         return ret;
     }
 
+    async fn key_norm(&self) -> KeyNorm {
+        self.print_debug_msg("Acquiring read lock on replication...");
+        let ret = self.replication.read().await.key_norm;
+        self.print_debug_msg("Read lock acquired on replication.");
+        ret
+    }
+
+    async fn reply_from_head(&self) -> bool {
+        self.print_debug_msg("Acquiring read lock on replication...");
+        let ret = self.replication.read().await.reply_from_head;
+        self.print_debug_msg("Read lock acquired on replication.");
+        ret
+    }
+
+    // returns (R, W) used by Quorum reads and writes
+    async fn get_quorum(&self) -> (u8, u8) {
+        self.print_debug_msg("Acquiring read lock on replication...");
+        let replication_reader = self.replication.read().await;
+        self.print_debug_msg("Read lock acquired on replication.");
+        (replication_reader.read_quorum, replication_reader.write_quorum)
+    }
+
+    // R and W capped by the copies the ring holds at the moment
+    async fn quorum_copies(&self) -> (u8, u8) {
+        let (read_quorum, write_quorum) = self.get_quorum().await;
+        let copies = self.get_current_k().await + 1;
+        (read_quorum.min(copies), write_quorum.min(copies))
+    }
+
     // dynamically adjusts replication factor when online nodes are less than k
     async fn get_current_k(&self) -> u8 {
         self.print_debug_msg("Acquiring read lock on replication...");
@@ -239,110 +731,623 @@ impl Node  {
         std::cmp::min(self.get_replica_ranges().await.get_size() as u8 , k) 
     }
 
+    // read-only snapshots of the topology state, for embedding the node without messages
+    pub async fn snapshot_ranges(&self) -> UnionRange<HashType> {
+        self.get_replica_ranges().await
+    }
+
+    pub async fn neighbors(&self) -> (Option<NodeInfo>, Option<NodeInfo>) {
+        (self.get_prev().await, self.get_succ().await)
+    }
+
+    pub fn range_drift(&self) -> u64 {
+        self.range_drift.load(Ordering::SeqCst)
+    }
+
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes.load(Ordering::SeqCst)
+    }
+
+    // kept next to every change of a record's value so reports never rescan the records
+    fn track_bytes(&self, removed: usize, added: usize) {
+        self.stored_bytes.fetch_add(added as u64, Ordering::SeqCst);
+        self.stored_bytes.fetch_sub(removed as u64, Ordering::SeqCst);
+    }
+
+    // (key, replica index, pending) for every record held locally
+    pub async fn record_keys(&self) -> Vec<(HashType, u8, bool)> {
+        self.print_debug_msg("Acquiring read lock on records...");
+        let records_reader = self.records.read().await;
+        self.print_debug_msg("Read lock acquired on records.");
+        records_reader.iter()
+            .map(|(key, item)| (*key, item.replica_idx, item.pending))
+            .collect()
+    }
+
+    /* Join and relocate transfers are split into TRANSFER_CHUNK sized batches so that no
+        single message carries a whole node's records. All batches but the last are staged
+        by the receiver and each one is acked before the next is sent, so a slow receiver
+        slows the sender down. The remaining batch is returned with the id the chunks were
+        staged under, both travel inside the message that completes the transfer (AckJoin or
+        Relocate). None when a chunk is still unacknowledged after the retries, the caller
+        must then not complete the transfer */
+    async fn send_chunks(&self, dest: Option<NodeInfo>, mut items: Vec<Item>) -> Option<(Uuid, Vec<Item>)> {
+        let transfer = Uuid::new_v4();
+        while items.len() > crate::TRANSFER_CHUNK {
+            let rest = items.split_off(crate::TRANSFER_CHUNK);
+            if let Some(node) = dest {
+                let chunk_msg = Message::new(
+                    MsgType::Transfer,
+                    None,
+                    &MsgData::Transfer { items, prefetch: false, transfer }
+                );
+                if !self.send_confirmed(node, &chunk_msg).await {
+                    eprintln!("❌ Transfer {} to {} aborted, a chunk was not acknowledged", transfer, node);
+                    return None;
+                }
+            }
+            items = rest;
+        }
+        Some((transfer, items))
+    }
+
+    async fn take_staged(&self, transfer: Uuid) -> Vec<Item> {
+        self.print_debug_msg("Acquiring write lock on incoming...");
+        let mut incoming_writer = self.incoming.write().await;
+        self.print_debug_msg("Write lock acquired on incoming.");
+        incoming_writer.remove(&transfer).unwrap_or_default()
+    }
+
+    // prefetched copies the departing predecessor still holds unchanged, the rest is dropped
+    async fn take_prefetched(&self, keep: &[HashType]) -> Vec<Item> {
+        let keep: HashSet<&HashType> = keep.iter().collect();
+        self.print_debug_msg("Acquiring write lock on prefetched...");
+        let mut prefetched_writer = self.prefetched.write().await;
+        self.print_debug_msg("Write lock acquired on prefetched.");
+        std::mem::take(&mut *prefetched_writer)
+            .into_iter()
+            .filter(|item| keep.contains(&item.key_hash()))
+            .collect()
+    }
+
    
     async fn insert_aux(&self, key: HashType, new_record: &mut Item) {
+        // copies received from peers keep the writer that stamped them
+        new_record.writer.get_or_insert(self.get_id());
         self.print_debug_msg("Acquiring write lock on records...");
     
         let exists = {
             let record_reader = self.records.read().await;
             record_reader.get(&key).is_some()
         };
+        let mut trimmed = 0;
     
         if exists {
             self.sleep_on_updates(key).await;
     
             let mut record_writer = self.records.write().await;
             let exist = record_writer.get_mut(&key).unwrap();
+            exist.appends.push(exist.value.len());
             exist.value = format!("{}{}", exist.value, new_record.value);
+            // a limit stays with the key until an insert brings another one
+            exist.max_value_len = new_record.max_value_len.or(exist.max_value_len);
+            trimmed = exist.max_value_len.map_or(0, |limit| exist.trim_front(limit));
+            exist.seal();
             exist.pending |= new_record.pending;  // Perform 'OR' on 'pending'
             exist.timestamp = new_record.timestamp;
+            exist.writer = new_record.writer;
         } else {
+            if let Some(limit) = new_record.max_value_len {
+                new_record.trim_front(limit);
+                new_record.seal();
+            }
             let mut record_writer = self.records.write().await;
             record_writer.insert(key, new_record.clone());
         }
+        self.track_bytes(trimmed, new_record.value.len());
+        self.index_time(key, new_record.timestamp).await;
+        self.key_sketch.write().await.add(&key);
     
         self.print_debug_msg("Write lock released on records.");
+        if new_record.replica_idx == 0 {
+            let stored = self.records.read().await.get(&key).cloned();
+            self.notify_subscribers(key, &new_record.title, stored).await;
+        }
+    }
+
+    /* Pushes the new state of a key to its subscribers. Only the primary copy notifies,
+        so every change is pushed once. Subscribers that can no longer be reached are dropped */
+    async fn notify_subscribers(&self, key: HashType, title: &str, item: Option<Item>) {
+        let subscribers = match self.subscribers.read().await.get(&key) {
+            Some(subscribers) => subscribers.clone(),
+            None => return
+        };
+        let notify_msg = Message::new(
+            MsgType::Notify,
+            None,
+            &MsgData::Notify { key: title.to_string(), item }
+        );
+        let mut gone = Vec::new();
+        for subscriber in subscribers.iter() {
+            if subscriber.send_msg(&notify_msg).await.is_none() {
+                gone.push(subscriber.id);
+            }
+        }
+        if !gone.is_empty() {
+            let mut subscribers_writer = self.subscribers.write().await;
+            if let Some(list) = subscribers_writer.get_mut(&key) {
+                list.retain(|subscriber| !gone.contains(&subscriber.id));
+                if list.is_empty() {
+                    subscribers_writer.remove(&key);
+                }
+            }
+        }
     }
     
 
     async fn send_msg(&self, dest_node: Option<NodeInfo>, msg: &Message) -> Option<TcpStream> {
         if let Some(dest) = dest_node {
-            dest.send_msg(&msg).await
+            if self.tracing {
+                self.report_span(msg, dest).await;
+            }
+            let stream = dest.send_msg(msg).await;
+            if stream.is_none() && redeliverable(msg.extract_type()) {
+                self.push_dead_letter(DeadLetter { dest, msg: msg.clone(), failed_at: Instant::now(), attempts: 0,
+                                                   retry_at: Instant::now() + Duration::from_millis(crate::DEAD_LETTER_RETRY_MS) }).await;
+            }
+            stream
         } else {
             eprintln!("Failed to send message: destination node not found");
             None
         }
     }
 
+    // the oldest undelivered message makes room once the queue is full
+    async fn push_dead_letter(&self, letter: DeadLetter) {
+        let mut letters = self.dead_letters.write().await;
+        if letters.queue.len() >= crate::DEAD_LETTER_LIMIT {
+            if let Some(oldest) = letters.queue.pop_front() {
+                eprintln!("❌ Dead letter queue full, dropping {:?} for {}", oldest.msg.extract_type(), oldest.dest);
+                letters.dropped += 1;
+            }
+        }
+        letters.queue.push_back(letter);
+    }
+
+    /* Retries undelivered replication messages, doubling the delay after
+        every failed attempt. Messages still undelivered after DEAD_LETTER_MAX_AGE_MS are dropped */
+    async fn redeliver_periodically(&self) {
+        let retry = Duration::from_millis(crate::DEAD_LETTER_RETRY_MS);
+        let max_age = Duration::from_millis(crate::DEAD_LETTER_MAX_AGE_MS);
+        loop {
+            sleep(retry).await;
+            // due letters are taken out so that nothing is locked while sending
+            let due: VecDeque<DeadLetter> = {
+                let mut letters = self.dead_letters.write().await;
+                let before = letters.queue.len();
+                letters.queue.retain(|letter| letter.failed_at.elapsed() < max_age);
+                letters.dropped += (before - letters.queue.len()) as u64;
+                if before != letters.queue.len() {
+                    eprintln!("❌ Dropped {} dead letters older than {:?}", before - letters.queue.len(), max_age);
+                }
+                let now = Instant::now();
+                let (due, waiting): (VecDeque<DeadLetter>, VecDeque<DeadLetter>) = std::mem::take(&mut letters.queue).into_iter().partition(|letter| letter.retry_at <= now);
+                letters.queue = waiting;
+                due
+            };
+            for mut letter in due {
+                if letter.dest.send_msg(&letter.msg).await.is_some() {
+                    self.print_debug_msg(&format!("Redelivered {:?} to {} after {} attempts", letter.msg.extract_type(), letter.dest, letter.attempts + 1));
+                    self.dead_letters.write().await.redelivered += 1;
+                    continue;
+                }
+                letter.attempts += 1;
+                letter.retry_at = Instant::now() + retry.saturating_mul(1 << letter.attempts.min(16));
+                self.push_dead_letter(letter).await;
+            }
+        }
+    }
+
+    async fn handle_dead_letters(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::DeadLetters { } => {
+                let reply = {
+                    let letters = self.dead_letters.read().await;
+                    let oldest = letters.queue.iter().map(|letter| letter.failed_at.elapsed()).max().unwrap_or_default();
+                    format!("Node {} dead letters: {} queued (oldest {:.1}s), {} redelivered, {} dropped",
+                            self.get_info(), letters.queue.len(), oldest.as_secs_f64(), letters.redelivered, letters.dropped)
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_reassembly(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::Reassembly { } => {
+                let reply = match &self.reassembly {
+                    Some(budget) => format!("Node {} reassembly: {} KB of {} KB in flight, peak {} KB, {} request(s) dropped",
+                                            self.get_info(), budget.in_flight_kb(), budget.total_kb,
+                                            budget.peak_kb.load(Ordering::SeqCst), budget.rejected.load(Ordering::SeqCst)),
+                    None => format!("Node {} reassembly: no budget, start the node with --reassembly-budget-mb", self.get_info())
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    // forwards between nodes are what a trace shows, replies to clients are left out
+    async fn report_span(&self, msg: &Message, next_hop: NodeInfo) {
+        let trace_id = match msg.extract_trace_id() {
+            Some(trace_id) => trace_id,
+            None => return
+        };
+        let span = Span { node: self.get_info(), msg_type: msg.extract_type(), timestamp: Utc::now(), next_hop };
+        match self.bootstrap {
+            None => self.traces.write().await.record(trace_id, span),
+            Some(collector) => {
+                // the report must not delay the forward itself
+                let report = Message::new(MsgType::Span, None, &MsgData::Span { trace_id, span });
+                tokio::spawn(async move { collector.send_msg(&report).await; });
+            }
+        }
+    }
+
+    async fn handle_span(&self, data:&MsgData) {
+        match data {
+            MsgData::Span { trace_id, span } => {
+                if self.bootstrap.is_none() {
+                    self.traces.write().await.record(*trace_id, span.clone());
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_trace(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::Trace { trace_id } => {
+                // only the bootstrap collects spans
+                if let Some(collector) = self.bootstrap {
+                    let fw_trace = Message::new(MsgType::Trace, client, &MsgData::Trace { trace_id: *trace_id });
+                    collector.send_msg(&fw_trace).await;
+                    return;
+                }
+                let spans = self.traces.read().await.get(trace_id);
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Spans { trace_id: *trace_id, spans }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
     async fn is_responsible(&self, key: &HashType) -> bool {
         // get read locks first 
         let prev_rd = self.get_prev().await;
         let succ_rd = self.get_succ().await;
+        /* a node alone in the ring points to itself, unset pointers mean
+            the join has not completed yet so it must not claim any key */
         if prev_rd.is_none() || succ_rd.is_none() {
-            return true;
+            return false;
         }
-        let prev_id = prev_rd.unwrap().id;
-         // Check if this node is responsible for the key
-        if prev_id < self.get_id() {
-            // Normal case: key falls within (prev, self]
-            *key > prev_id && *key <= self.get_id()
-        } else {
-            // Wrapped case: previous is greater due to ring wrap-around
-            *key > prev_id || *key <= self.get_id()
+        self.is_responsible_after(key, &prev_rd.unwrap().id).await
+    }
+
+    // nodes are placed by their id on the Chord ring whatever partitioner places the keys
+    async fn owns_position(&self, position: &HashType) -> bool {
+        match (self.get_prev().await, self.get_succ().await) {
+            (Some(prev), Some(_)) => ChordPartitioner.owns(position, &prev.id, &self.get_id()),
+            _ => false
+        }
+    }
+
+    // whether the key would be this node's with 'prev' as its previous, as when a node joins before it
+    async fn is_responsible_after(&self, key: &HashType, prev: &HashType) -> bool {
+        if let Some(pinned) = self.pinned_to(key).await {
+            return pinned == self.get_id();
         }
+        self.partitioner.owns(key, prev, &self.get_id())
     }
 
+    /* A pinned key is placed as if it hashed to the id of its designated node,
+        so routing and replica ranges follow that node like any key it owns */
+    async fn pinned_to(&self, key: &HashType) -> Option<HashType> {
+        self.pins.read().await.get(key).map(|node| node.id)
+    }
+
+    /* A departed or stopped node keeps its last successor, which took over its keys.
+        With OfflineRequests::Redirect clients are told to retry there, or at any other
+        node this one knows. Retries go to a single node so two offline nodes never bounce a request */
+    async fn offline_reply(&self) -> String {
+        let retry_at = match self.get_succ().await.filter(|succ| succ.id != self.get_id()) {
+            Some(succ) => Some(succ),
+            None => self.members.read().await.values().next().copied().or(self.bootstrap)
+        };
+        match (self.offline_requests, retry_at) {
+            (OfflineRequests::Redirect, Some(node)) =>
+                format!("Error: Offline: node {} is offline, retry at {}:{}", self.get_info(), node.ip_addr, node.port),
+            _ => format!("Node {} is offline", self.get_info())
+        }
+    }
+
+    // false until both neighbours are known, i.e. the node has not finished joining
+    async fn is_linked(&self) -> bool {
+        self.get_prev().await.is_some() && self.get_succ().await.is_some()
+    }
 
     // returns -1 if not a replica manager, otherwise the replica_idx of key in this node
     async fn is_replica_manager(&self, key:&HashType) -> i16 {
         if self.is_responsible(key).await { return 0; }
         let replica_reader = self.get_replica_ranges().await;
         self.print_debug_msg(&format!("Ranges: {:?} for key {}", replica_reader, key));
-        return replica_reader.is_subset(*key);
+        if let Some(pinned) = self.pinned_to(key).await {
+            return ChordPartitioner.replica_index(&pinned, &replica_reader);
+        }
+        self.partitioner.replica_index(key, &replica_reader)
+    }
+
+    /* Hands a request for a key this node does not own to the neighbour towards its primary.
+        When that neighbour is this node itself, e.g. pointers that loop back here on a node
+        that still does not own the key, the client gets a routing error instead of the
+        request circling forever */
+    async fn forward_to_primary(&self, client:Option<&NodeInfo>, key:&HashType, msg:&Message) {
+        let next = if self.maybe_next_responsible(key).await { self.get_succ().await } else { self.get_prev().await };
+        if self.routes_to_self(&next) {
+            self.reject_unroutable(client, key).await;
+            return;
+        }
+        self.send_msg(next, msg).await;
+    }
+
+    fn routes_to_self(&self, next:&Option<NodeInfo>) -> bool {
+        next.is_none_or(|next| next.id == self.get_id())
+    }
+
+    async fn reject_unroutable(&self, client:Option<&NodeInfo>, key:&HashType) {
+        eprintln!("Key {} is not owned by node {} and routes back to it", key, self.get_info());
+        if let Some(client) = client {
+            let error_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: format!("Error: RoutingError: node {} does not own key {} and has no other node to route it to", self.get_info(), key) },
+            );
+            client.send_msg(&error_msg).await;
+        }
     }
 
     /* used to check whether a key should be passed to successor or predecessor node
         taking into account wrapping around on last node 
         to avoid traversing the whole ring backwards */
     async fn maybe_next_responsible(&self, key: &HashType) -> bool {
-        let succ_rd = self.get_succ();
-        let succ_id = succ_rd.await.unwrap().id;
-        if self.get_id() < succ_id {
-            // Normal case: key falls within (self, any forward successor]
-            *key > self.get_id() 
-        } else {
-            // Wrapped case
-            *key > self.get_id() || *key <= succ_id
+        let succ_id = match self.get_succ().await {
+            Some(succ) => succ.id,
+            None => return true
+        };
+        if let Some(pinned) = self.pinned_to(key).await {
+            return ChordPartitioner.forward(&pinned, &self.get_id(), &succ_id);
         }
+        self.partitioner.forward(key, &self.get_id(), &succ_id)
     }
 
-    async fn relocate_replicas(&self) {
-        let k = self.get_current_k().await;
-    self.print_debug_msg("Acquiring write lock on records...");
-        let mut records_writer = self.records.write().await;
-    self.print_debug_msg("Write lock released on records.");
-        let mut to_remove: Vec<HashType> = Vec::new();
-        for (key, item) in records_writer.iter_mut(){
-            if item.replica_idx == k {
-                to_remove.push(*key);
-            } else if (item.replica_idx > 0 && item.replica_idx < k) || 
-                      (item.replica_idx == 0 && !self.is_responsible(key).await) {
-                item.replica_idx += 1;
-            } 
-        }
-
-        for key in to_remove.iter(){
-            records_writer.remove(key);
+    // a copy of a key already held locally only replaces it when it is the newer version
+    async fn reconcile_copy(&self, key: HashType, copy: &mut Item) {
+        self.print_debug_msg("Acquiring read lock on records...");
+        let local = self.records.read().await.get(&key).cloned();
+        self.print_debug_msg("Read lock acquired on records.");
+        match local {
+            None => self.insert_aux(key, copy).await,
+            Some(local) if copy.newer_than(&local) => {
+                let mut record_writer = self.records.write().await;
+                if let Some(exist) = record_writer.get_mut(&key) {
+                    self.track_bytes(exist.value.len(), copy.value.len());
+                    *exist = Item { replica_idx: exist.replica_idx, pending: exist.pending, ..copy.clone() };
+                }
+                drop(record_writer);
+                self.index_time(key, copy.timestamp).await;
+            }
+            Some(_) => ()
         }
     }
 
-    async fn spinlock_on_updates(&self, key_hash: HashType) {
-        let mut delay = Duration::from_millis(1); // Start with a small delay
-        let max_delay = Duration::from_millis(300); // Cap delay to avoid waiting too long
+    #[cfg(feature = "testing")]
+    async fn handle_force_relocate(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Drives the same hop a Relocate from a joining (inc) or departing neighbour would,
+        without ranges or copies, so the index shifts and the forwards to the successor
+        can be observed alone. The reply carries every local record, replicas included */
+        match data {
+            MsgData::ForceRelocate { inc, k_remaining } => {
+                // the join path computes k - k_remaining - 1
+                if *inc && *k_remaining >= self.get_current_k().await {
+                    self.reject_request(client, "k_remaining must be below the current replication factor").await;
+                    return;
+                }
+                let relocate = MsgData::Relocate { k_remaining: *k_remaining, inc: *inc, new_copies: None, range: None, keep: None, transfer: Uuid::nil(), widen: false };
+                self.handle_relocate(&relocate).await;
 
-        loop {
-            let is_pending = {
-                self.print_debug_msg("Acquiring read lock on records...");
+                let records = self.records.read().await.values().cloned().collect();
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Records { node: self.get_info(), records }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_pendings(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::Pendings { } => {
+                let pending: Vec<(HashType, String)> = self.records.read().await.iter()
+                    .filter(|(_, item)| item.pending)
+                    .map(|(key, item)| (*key, item.title.clone()))
+                    .collect();
+                let waiting = self.pendings.read().await;
+                let mut reply = format!("Node {} has {} pending key(s)", self, pending.len());
+                for (key, title) in pending.iter() {
+                    let readers = waiting.get(key).map_or(0, |wait| wait.waiters.load(Ordering::SeqCst));
+                    reply.push_str(&format!("\n⏳ 🔑 {} ({} waiting reader(s))", title, readers));
+                }
+                drop(waiting);
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_cancel_pending(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Operational recovery for a pending whose ack was lost. Only this node's copy is
+        cleared, the blocked readers get an error instead of the uncommitted value and
+        an ack that still arrives later is ignored. A head also gives back its chain slot */
+        match data {
+            MsgData::CancelPending { key } => {
+                let key_hash = HashFunc(key);
+                let cancelled = {
+                    self.print_debug_msg("Acquiring write lock on records...");
+                    let mut record_writer = self.records.write().await;
+                    self.print_debug_msg("Write lock acquired on records.");
+                    match record_writer.get_mut(&key_hash) {
+                        Some(exist) if exist.pending => {
+                            exist.pending = false;
+                            Some(exist.replica_idx)
+                        }
+                        _ => None
+                    }
+                };
+                let reply = match cancelled {
+                    Some(replica_idx) => {
+                        if replica_idx == 0 {
+                            self.release_chain_slot();
+                        }
+                        let readers = match self.pendings.write().await.remove(&key_hash) {
+                            Some(wait) => {
+                                wait.cancelled.store(true, Ordering::SeqCst);
+                                wait.notify.notify_waiters();
+                                wait.waiters.load(Ordering::SeqCst)
+                            }
+                            None => 0
+                        };
+                        format!("Cancelled pending 🔑 {} on node {}, {} waiting reader(s) released", key, self, readers)
+                    }
+                    None => format!("Error: 🔑 {} is not pending on node {}", key, self)
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    fn count_read(&self, local:bool) {
+        let counter = if local { &self.reads.local } else { &self.reads.forwarded };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn handle_read_stats(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::ReadStats { } => {
+                let local = self.reads.local.load(Ordering::Relaxed);
+                let forwarded = self.reads.forwarded.load(Ordering::Relaxed);
+                let share = if local + forwarded > 0 { 100.0 * local as f64 / (local + forwarded) as f64 } else { 0.0 };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply: format!("Node {} Eventual reads: {} answered locally, {} forwarded ({:.1}% local)", self.get_info(), local, forwarded, share) }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_set_read_only(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::SetReadOnly { enabled } => {
+                self.read_only.store(*enabled, Ordering::SeqCst);
+                let reply = format!("Node {} is now {}", self.get_info(), if *enabled { "read-only" } else { "writable" });
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    /* Called where a client write would be applied here. Writes only passing through
+        are still routed, and replica forwards are still applied so that chains and
+        eventual propagation started elsewhere complete */
+    async fn refuse_if_read_only(&self, client:Option<&NodeInfo>) -> bool {
+        if !self.read_only.load(Ordering::SeqCst) {
+            return false;
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: ReadOnly: node {} refuses writes for now, retry later or through another node", self.get_info()) }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+        true
+    }
+
+    async fn relocate_replicas(&self) {
+        let k = self.get_current_k().await;
+    self.print_debug_msg("Acquiring write lock on records...");
+        let mut records_writer = self.records.write().await;
+    self.print_debug_msg("Write lock released on records.");
+        let mut to_remove: Vec<HashType> = Vec::new();
+        for (key, item) in records_writer.iter_mut(){
+            // without replicas the tail is the primary itself, keys it still owns must stay
+            if item.replica_idx == k && (k > 0 || !self.is_responsible(key).await) {
+                to_remove.push(*key);
+            } else if (item.replica_idx > 0 && item.replica_idx < k) || 
+                      (item.replica_idx == 0 && !self.is_responsible(key).await) {
+                item.replica_idx += 1;
+            } 
+        }
+
+        for key in to_remove.iter(){
+            if let Some(gone) = records_writer.remove(key) {
+                self.track_bytes(gone.value.len(), 0);
+            }
+        }
+    }
+
+    async fn spinlock_on_updates(&self, key_hash: HashType) {
+        let mut delay = Duration::from_millis(1); // Start with a small delay
+        let max_delay = Duration::from_millis(300); // Cap delay to avoid waiting too long
+
+        loop {
+            let is_pending = {
+                self.print_debug_msg("Acquiring read lock on records...");
                 let record_reader = self.records.read().await;
                 self.print_debug_msg("Read lock acquired on records.");
                 record_reader.get(&key_hash).map(|r| r.pending).unwrap_or(false)
@@ -359,7 +1364,8 @@ impl Node  {
         }
 }
 
-async fn sleep_on_updates(&self, key_hash: HashType) {
+// returns false if the pending was cancelled instead of resolved by its ack
+async fn sleep_on_updates(&self, key_hash: HashType) -> bool {
     loop {
         let is_pending = {
             let record_reader = self.records.read().await;
@@ -369,82 +1375,187 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
         };
 
         if !is_pending {
-            return; 
+            return true; 
         }
 
-        let notify = {
+        let wait = {
             let pending_reader = self.pendings.read().await;
-            pending_reader.get(&key_hash).cloned()  // Clone Arc<Notify> to avoid holding lock
+            pending_reader.get(&key_hash).cloned()  // Clone Arc<PendingWait> to avoid holding lock
         };
 
-        match notify {
-            Some(n) => n.notified().await, // ✅ Await outside lock
+        let wait = match wait {
+            Some(w) => w,
             None => {
-                let notify = Arc::new(Notify::new());
+                let wait = Arc::new(PendingWait::new());
                 let mut pending_writer = self.pendings.write().await;
-                pending_writer.insert(key_hash, notify.clone());
+                if pending_writer.len() >= self.pendings_bound.0 {
+                    // make room by giving up on the key whose readers have waited unjoined the longest
+                    let oldest = pending_writer.iter()
+                        .max_by_key(|(_, wait)| wait.idle_for())
+                        .map(|(key, _)| *key);
+                    if let Some(evicted) = oldest.and_then(|key| pending_writer.remove(&key)) {
+                        self.print_debug_msg(&format!("Pendings full, expired the longest idle key ({} reader(s))", evicted.waiters.load(Ordering::SeqCst)));
+                        evicted.expire();
+                    }
+                }
+                pending_writer.insert(key_hash, wait.clone());
                 drop(pending_writer); // ✅ Drop before awaiting
-                notify.notified().await;
+                wait
             }
+        };
+        *wait.last_waiter.lock().unwrap() = Instant::now();
+        wait.waiters.fetch_add(1, Ordering::SeqCst);
+        // ✅ Await outside lock, and without a permit of the pool since the ack needs one
+        network::parked(wait.notify.notified()).await;
+        wait.waiters.fetch_sub(1, Ordering::SeqCst);
+        if wait.cancelled.load(Ordering::SeqCst) {
+            return false;
         }
     }
 }
 
 
+    /* Bounds the chain inserts this head has in flight. A slot is taken before forwarding
+        and given back when the ack returns to the head, so a slow tail makes new writes
+        wait here instead of piling up along the chain. Waiting gives up after
+        CHAIN_WINDOW_WAIT_MS and the write is refused, never forwarded without a slot.
+        Slots leaked by lost acks come back when the stuck write is cancelled */
+    async fn acquire_chain_slot(&self, client:Option<&NodeInfo>) -> bool {
+        let slot = tokio::time::timeout(Duration::from_millis(crate::CHAIN_WINDOW_WAIT_MS), self.chain_window.acquire());
+        match network::parked(slot).await {
+            Ok(Ok(permit)) => {
+                permit.forget();
+                true
+            }
+            _ => {
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply: format!("Error: ChainFull: {} chain writes are awaiting their ack at node {}, retry later or cancel-pending the stuck ones", crate::CHAIN_WINDOW, self.get_info()) }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+                false
+            }
+        }
+    }
+
+    fn release_chain_slot(&self) {
+        // a cancelled write and its late ack both give back the slot, never grow past the window
+        if self.chain_window.available_permits() < crate::CHAIN_WINDOW {
+            self.chain_window.add_permits(1);
+        }
+    }
+
     pub async fn init(&self) { 
         let sock_addr = SocketAddrV4::new(self.get_ip(), self.get_port());
-        match TcpListener::bind(sock_addr).await {
+        match network::bind_listener(SocketAddr::V4(sock_addr), crate::LISTEN_BACKLOG) {
             Ok(listener) => {
                 if self.bootstrap.is_none() {
-                    self.set_prev(Some(self.get_info())).await;
-                    self.set_succ(Some(self.get_info())).await;
+                    self.start_alone().await;
                 }
                 let node_server = Server::new(self.clone());
                 self.set_status(true);
+                // the background tasks talk to the ring as this node
+                let gossiper = self.clone();
+                tokio::spawn(messages::SENDER.scope(self.get_info(), async move { gossiper.gossip_periodically().await }));
+                let checker = self.clone();
+                tokio::spawn(messages::SENDER.scope(self.get_info(), async move { checker.check_ranges_periodically().await }));
+                let reaper = self.clone();
+                tokio::spawn(messages::SENDER.scope(self.get_info(), async move { reaper.reap_pendings_periodically().await }));
+                let redeliverer = self.clone();
+                tokio::spawn(messages::SENDER.scope(self.get_info(), async move { redeliverer.redeliver_periodically().await }));
+                if self.mem_limits.is_some() {
+                    let shedder = self.clone();
+                    tokio::spawn(messages::SENDER.scope(self.get_info(), async move { shedder.shed_periodically().await }));
+                }
                 match self.bootstrap {
                     Some(_) => self.print_debug_msg(&format!("Node with id: {} is listening on {}", self.get_id(), sock_addr)),
                     _ => self.print_debug_msg(&format!("Bootstrap has id:{} and is listening on {}", self.get_id(), sock_addr))
                 }
-                //node_server.wait_for_requests(listener, NUM_THREADS);
-                node_server.wait_for_requests(listener).await; 
+                // the listener closes as soon as a shutdown stops the node
+                tokio::select! {
+                    _ = node_server.wait_for_requests(listener, self.workers) => (),
+                    _ = self.stopped.notified() => ()
+                }
             }
             Err(e) => panic!("Failed to bind to {}: {}", sock_addr, e)    
         }
     }
 
     pub async fn join_ring(&self, client:Option<&NodeInfo>) {
-        // forward the Join Request to bootsrap
+        // forward the Join Request to bootsrap, or to the member given with --join-via
         self.print_debug_msg("Preparing 'Join' Request...");
         if let Some(bootstrap_node) = self.bootstrap {
             let join_msg = Message::new(
                 MsgType::FwJoin,
                 client,
-                &MsgData::FwJoin { new_node: self.get_info() } 
+                &MsgData::FwJoin { new_node: self.get_info(), hops: 0, weight: Some(self.weight) } 
+            );
+            /* Any member places a join the way the bootstrap does, forwarding it to the
+                responsible node, and checks the --max-nodes cap it took from the ring */
+            let entry = self.join_via.unwrap_or(bootstrap_node);
+            if entry.send_msg(&join_msg).await.is_some() {
+                return;
+            }
+            // entry is down, the bootstrap or any member known from an earlier membership can take the join
+            let mut seeds: Vec<NodeInfo> = self.members.read().await.values().cloned().collect();
+            seeds.insert(0, bootstrap_node);
+            for seed in seeds.iter().filter(|seed| seed.id != entry.id) {
+                if seed.send_msg(&join_msg).await.is_some() {
+                    return;
+                }
+            }
+            // nothing answered, so there is no ring to join
+            let reply = match self.empty_ring {
+                EmptyRing::Start => {
+                    self.start_alone().await;
+                    format!("Node {} found no ring at {} and started one on its own", self.get_info(), entry)
+                }
+                EmptyRing::Fail => format!("Error: EmptyRing: no ring answered at {}, start the bootstrap first or use --empty-ring start", entry)
+            };
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply }
             );
-            bootstrap_node.send_msg(&join_msg).await;
+            client.unwrap().send_msg(&user_msg).await;
         } 
         else {
-            // bootstrap node just changes its status
-            self.set_status(true);
+            // bootstrap node just changes its status, a serving one is already in the ring like a joined peer
+            let reply = if self.get_status() {
+                format!("Node {} is already part of the network", self.get_info())
+            } else {
+                self.set_status(true);
+                "Bootstrap node joined the ring successfully!".to_string()
+            };
 
             let user_msg = Message::new(
                 MsgType::Reply,
                 None,
-                &MsgData::Reply { reply: format!("Bootstrap node joined the ring successfully!") }
+                &MsgData::Reply { reply }
             );
 
             client.unwrap().send_msg(&user_msg).await;
         } 
     }
 
+    /* The very first node of a ring is its own previous and successor, which is what makes it
+        responsible for every key and lets the next join close a 2-cycle with it */
+    async fn start_alone(&self) {
+        self.set_prev(Some(self.get_info())).await;
+        self.set_succ(Some(self.get_info())).await;
+        self.set_status(true);
+        self.audit("started a ring alone");
+    }
+
     async fn handle_join(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::FwJoin { new_node } => {
+            MsgData::FwJoin { new_node, hops, weight } => {
                 self.print_debug_msg(&format!("Handling Join Request - {} ", new_node));
                 let id = new_node.id;
                 let peer_port = new_node.port;
                 let peer_ip = new_node.ip_addr;
-                if id == self.get_id() {
+                if id == self.get_id() && (peer_ip, peer_port) == (self.get_ip(), self.get_port()) {
                     let user_msg = Message::new(
                         MsgType::Reply,
                         None,
@@ -453,21 +1564,118 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                     client.unwrap().send_msg(&user_msg).await;
                     return;
                 } 
+                /* Two addresses hashing to one id would share a ring position and one of them
+                    would silently own nothing. Such a join is refused, never salted: a join is routed
+                    by the id derived from ip and port, a weight only moves it within the range found */
+                let holder = if id == self.get_id() {
+                    Some(self.get_info())
+                } else {
+                    self.members.read().await.get(&id).copied()
+                };
+                if let Some(holder) = holder.filter(|holder| (holder.ip_addr, holder.port) != (peer_ip, peer_port)) {
+                    let user_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::Reply { reply: format!("Error: IdCollision: node {} has the same id {} as member {}, start it on another port", new_node, id, holder) }
+                    );
+                    client.unwrap().send_msg(&user_msg).await;
+                    return;
+                }
+                // the node a join enters through, the bootstrap or any member, admits it while the ring has room
+                if *hops == 0 {
+                    let max_nodes = self.replication.read().await.max_nodes;
+                    if let Some(max_nodes) = max_nodes {
+                        if let Some(reply) = self.check_ring_room(new_node, max_nodes).await {
+                            let user_msg = Message::new(
+                                MsgType::Reply,
+                                None,
+                                &MsgData::Reply { reply }
+                            );
+                            client.unwrap().send_msg(&user_msg).await;
+                            return;
+                        }
+                    }
+                }
                 // get a read lock on neighbors and k
                 let prev_rd = self.get_prev().await;
                 let succ_rd = self.get_succ().await;
                 let max_k = self.max_replication().await;
-                // create the new node
-                let new_node = Some(NodeInfo::new(peer_ip, peer_port));
+                /* a node whose own join has not completed has no place to offer,
+                    and forwarding to its unset successor would drop the join silently */
+                if prev_rd.is_none() || succ_rd.is_none() {
+                    let user_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::Reply { reply: format!("Error: NotJoined: node {} is not part of a ring yet, join via the bootstrap or another member", self.get_info()) }
+                    );
+                    client.unwrap().send_msg(&user_msg).await;
+                    return;
+                }
+                // the node keeps the id it announced, so every hop checks the same id for collisions
+                let joining = *new_node;
                 
                 //self.print_debug_msg(&format!("My ranges: {:?}", self.get_replica_ranges()));
 
-                if self.is_responsible(&id).await { 
+                if self.owns_position(&id).await { 
+                    // concurrent joins and departs around the same nodes are what cross the pointers
+                    if let Err(left) = self.claim_stable().await {
+                        self.reject_unstable(client, left).await;
+                        return;
+                    }
+                    /* Unweighted rings keep every node at its hashed id. Otherwise the new node is
+                        placed inside this node's range, splitting it by the weights of the two */
+                    let weight = weight.unwrap_or(1);
+                    let placed_at = if weight == 1 && self.weight == 1 {
+                        None
+                    } else {
+                        Some(HashWeighted(prev_rd.unwrap().id, self.get_id(), id, weight, self.weight))
+                    };
+                    let id = placed_at.unwrap_or(id);
+                    let new_node = Some(NodeInfo { id, ..joining });
                     self.print_debug_msg(&format!("Preparing 'AckJoin' for new node {}", new_node.unwrap()));
+                    let relocating = self.relocating.enter();
 
                     // define replica ranges for current and new node 
                     let mut transferred_ranges = self.get_replica_ranges().await;
-                    let mut wrap = false;
+                    // until the ring is larger than max_k the new node also gets this node's range
+                    let wrap = transferred_ranges.get_size() != max_k as usize;
+                    let furthest_idx = transferred_ranges.get_size() as u8 + 1;
+                    // find records to share with the new node according to new managers and previous
+                    let mut vec_items: Vec<Item> = Vec::new();
+                    {
+                        self.print_debug_msg("Acquiring read lock on records...");
+                        let records_read = self.records.read().await;
+                        self.print_debug_msg("Read lock acquired on records.");
+                        for (key, item) in records_read.iter() {
+                            let stays = self.is_responsible_after(key, &id).await;
+                            if item.replica_idx > 0 || (item.replica_idx == 0 && !stays) {
+                                vec_items.push(item.clone());
+                                continue;
+                            } 
+                            // the ring is still small enough for the new node to hold every key, these at the furthest index
+                            if wrap && item.replica_idx == 0 && stays {
+                                vec_items.push(Item {
+                                    replica_idx: furthest_idx,
+                                    ..item.clone() // Keep other fields unchanged
+                                });
+                            }
+                        }
+                    } // drop locks here
+                    // nothing has changed yet, so a transfer that is not acknowledged simply fails the join
+                    let (transfer, vec_items) = match self.send_chunks(new_node, vec_items).await {
+                        Some(sent) => sent,
+                        None => {
+                            drop(relocating);
+                            let user_msg = Message::new(
+                                MsgType::Reply,
+                                None,
+                                &MsgData::Reply { reply: format!("Error: TransferFailed: new node {}:{} did not acknowledge its records, join aborted", peer_ip, peer_port) }
+                            );
+                            client.unwrap().send_msg(&user_msg).await;
+                            return;
+                        }
+                    };
+
                     let new_range = Range::new(
                         prev_rd.unwrap().id,
                         id, 
@@ -480,64 +1688,51 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                         self.print_debug_msg("Write lock released on replication.");
                         let my_replica_ranges = &mut replication_writer.replica_ranges;
                         my_replica_ranges.insert(new_range);   // add new node's key range
-                        if my_replica_ranges.get_size() == (max_k + 1) as usize { 
+                        if !wrap { 
                             my_replica_ranges.pop_head(); 
                         } else {
-                            wrap = true;
                             let wrap_range = Range::new(
                                 id,
                                 self.get_id(),
                                 false,
                                 true
                             );
-                            transferred_ranges.insert(wrap_range); // wrap around
+                            transferred_ranges.insert_head(wrap_range); // wrap around, this node is the furthest back
                         }
+                        self.audit(&format!("ranges {:?} after join of {}", my_replica_ranges, id));
                     } // release replica locks here 
 
+                    let (read_quorum, write_quorum) = self.get_quorum().await;
                     let replica_config = ReplicationConfig {
                         replication_factor : max_k,
                         replication_mode : self.get_consistency().await,
-                        replica_ranges : transferred_ranges
+                        replica_ranges : transferred_ranges,
+                        read_quorum,
+                        write_quorum,
+                        reply_from_head : self.reply_from_head().await,
+                        key_norm : self.key_norm().await,
+                        max_nodes : self.replication.read().await.max_nodes
                     };
 
                     // update always locally 
                     self.print_debug_msg(&format!("Updating previous locally to {}", new_node.unwrap()));
                     self.set_prev(new_node).await;
 
-                    // find records to share with the new node according to new managers and previous
-                    let mut vec_items: Vec<Item> = Vec::new();
-                    {
-                        self.print_debug_msg("Acquiring read lock on records...");
-                        let records_read = self.records.read().await;
-                        self.print_debug_msg("Read lock acquired on records.");
-                        for (key, item) in records_read.iter() {
-                            if item.replica_idx > 0 || (item.replica_idx == 0 && !self.is_responsible(key).await) {
-                                vec_items.push(item.clone());
-                                continue;
-                            } 
-                            // TODO! check this 
-                            if wrap && item.replica_idx == 0 && self.is_responsible(key).await {
-                                vec_items.push(Item {
-                                    replica_idx: 1, // Increment only replica_idx
-                                    ..item.clone() // Keep other fields unchanged
-                                });
-                            }
-                        }
-                    } // drop locks here
-
-
-                    // send a compact message with new neighbours, all new records and replica managers
+                    // send a compact message with new neighbours, the last records and replica managers
                     let ack_msg = Message::new(
                         MsgType::AckJoin,
                         client,
                         &MsgData::AckJoin {  prev_info: prev_rd, succ_info: Some(self.get_info()), 
-                                                  new_items: vec_items, replica_config: replica_config}
+                                                  new_items: vec_items, replica_config: replica_config,
+                                                  pins: self.pins.read().await.iter().map(|(key, node)| (*key, *node)).collect(), transfer, placed_at }
                     );
 
                     self.send_msg(new_node, &ack_msg).await;
 
-                    // inform previous about the new node join
-                    if !prev_rd.is_none() && self.get_id() != prev_rd.unwrap().id {
+                    /* inform previous about the new node join. A node alone in the ring is its own
+                        previous, so it takes the new node as successor too, and the AckJoin above
+                        named it as both neighbours of the new node: the two form a 2-cycle */
+                    if self.get_id() != prev_rd.unwrap().id {
                         self.print_debug_msg(&format!("Sending 'Update' to previous node {}", prev_rd.unwrap()));
                         let prev_msg = Message::new(
                             MsgType::Update,
@@ -555,15 +1750,19 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
 
                     // update my replica indices
                     self.relocate_replicas().await;
+                    drop(relocating);
 
-                    // forward replica relocation to successors
+                    /* forward replica relocation to successors. Once the ring is larger than max_k
+                        the range cut from this node is also held by the k nodes after it, until then
+                        by every other node */
                     let k = self.get_current_k().await;
+                    let hops = if wrap { k } else { k + 1 };
 
-                    if k > 1 && succ_rd.unwrap().id != self.get_id() {
+                    if hops > 1 && succ_rd.unwrap().id != self.get_id() {
                         let rel_msg = Message::new(
                             MsgType::Relocate,
                             None,
-                            &MsgData::Relocate { k_remaining: k-2, inc: true, new_copies: None, range: Some(new_range) }
+                            &MsgData::Relocate { k_remaining: hops-2, inc: true, new_copies: None, range: Some(new_range), keep: None, transfer: Uuid::nil(), widen: false }
                         );
 
                         self.send_msg(succ_rd, &rel_msg).await;
@@ -572,11 +1771,23 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                 }
                 
                 else {
+                    // a join that went round the whole ring without finding its place never will
+                    let ring_size = self.members.read().await.len() + 1;
+                    if *hops >= ring_size.max(crate::MIN_JOIN_FORWARDS) {
+                        self.print_debug_msg(&format!("Dropping 'Join' Request of {} after {} hops", joining, hops));
+                        let user_msg = Message::new(
+                            MsgType::Reply,
+                            None,
+                            &MsgData::Reply { reply: format!("Error: ring appears inconsistent, cannot place node {}", joining) }
+                        );
+                        client.unwrap().send_msg(&user_msg).await;
+                        return;
+                    }
                     self.print_debug_msg(&format!("Forwarding 'Join' Request to successor {}", succ_rd.unwrap()));
                     let fw_msg = Message::new(
                         MsgType::FwJoin,
                         client,
-                        &MsgData::FwJoin { new_node: new_node.unwrap() }
+                        &MsgData::FwJoin { new_node: joining, hops: hops + 1, weight: *weight }
                     );
                     self.send_msg(succ_rd, &fw_msg).await;
                 } 
@@ -586,15 +1797,72 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
         }
     }
 
+    async fn handle_simulate_join(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Read-only preview of a join at 'at_id'. It is routed like a join to the node that
+        would become the new node's successor, which repeats the record selection of
+        handle_join against a hypothetical previous without changing any state */
+        match data {
+            MsgData::SimulateJoin { at_id } => {
+                let reply = if *at_id == self.get_id() {
+                    format!("Error: node {} already sits at id {}", self.get_info(), at_id)
+                } else if !self.owns_position(at_id).await {
+                    let fw_msg = Message::new(
+                        MsgType::SimulateJoin,
+                        client,
+                        &MsgData::SimulateJoin { at_id: *at_id }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                    return;
+                } else {
+                    // same cases as handle_join, with 'at_id' standing in for the new previous
+                    let wrap = self.get_replica_ranges().await.get_size() != self.max_replication().await as usize;
+                    let mut moving = Vec::new();
+                    let mut transferred = 0;
+                    {
+                        self.print_debug_msg("Acquiring read lock on records...");
+                        let records_read = self.records.read().await;
+                        self.print_debug_msg("Read lock acquired on records.");
+                        for (key, item) in records_read.iter() {
+                            let still_mine = self.partitioner.owns(key, at_id, &self.get_id());
+                            if item.replica_idx == 0 && !still_mine {
+                                moving.push(item.title.clone());
+                                transferred += 1;
+                            } else if item.replica_idx > 0 || wrap {
+                                transferred += 1;
+                            }
+                        }
+                    }
+                    format!("A node at id {} would join before {}: {} key(s) would change primary, {} record(s) would be transferred. Sample: {:?}",
+                            at_id, self.get_info(), moving.len(), transferred, moving.iter().take(10).collect::<Vec<_>>())
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
     async fn handle_ack_join(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
             MsgData::AckJoin { prev_info, succ_info, 
-                               new_items, replica_config } => {
+                               new_items, replica_config, pins, transfer, placed_at } => {
+                // the new node took part in the join too, a join landing in its range waits for the window
+                self.extend_unstable().await;
+                if let Some(position) = placed_at {
+                    self.info.write().unwrap().id = *position;
+                }
                 self.set_prev(*prev_info).await;
                 self.set_succ(*succ_info).await;
-                // insert new_items
-                for item in new_items.clone().iter_mut() {
-                    let new_key = HashFunc(&item.title);
+                self.pins.write().await.extend(pins.iter().cloned());
+                // insert the staged chunks and the last one carried here
+                let mut all_items = self.take_staged(*transfer).await;
+                all_items.extend(new_items.iter().cloned());
+                for item in all_items.iter_mut() {
+                    let new_key = item.key_hash();
                     self.insert_aux(new_key, item).await;
                 }
                 
@@ -604,15 +1872,22 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                     self.print_debug_msg("Write lock released on replication.");
                     replication_writer.replication_factor = replica_config.replication_factor;
                     replication_writer.replication_mode = replica_config.replication_mode;
+                    replication_writer.read_quorum = replica_config.read_quorum;
+                    replication_writer.write_quorum = replica_config.write_quorum;
+                    replication_writer.reply_from_head = replica_config.reply_from_head;
+                    replication_writer.key_norm = replica_config.key_norm;
+                    replication_writer.max_nodes = replica_config.max_nodes;
                     // get replica managers assert vector is empty in this point
                     let ranges_writer = &mut replication_writer.replica_ranges;
                     for range in replica_config.replica_ranges.iter() {
                         ranges_writer.insert(*range);
                     }
+                    self.audit(&format!("joined the ring, ranges {:?}", ranges_writer));
                 } // release replica locks here
 
                 // change status 
                 self.set_status(true);
+                *self.serving_from.write().await = Some(Instant::now() + Duration::from_millis(crate::JOIN_GRACE_MS));
                 //inform user
                 let user_msg = Message::new(
                     MsgType::Reply,
@@ -629,6 +1904,7 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
     async fn handle_update(&self, data:&MsgData) {
         match data {
             MsgData::Update { prev_info, succ_info} => {
+                self.extend_unstable().await;
                 if !prev_info.is_none() {
                     self.set_prev(*prev_info).await;
                     self.print_debug_msg(&format!("Updated 'previous' to {}", prev_info.unwrap()));
@@ -645,25 +1921,42 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
 
     async fn handle_relocate(&self, data:&MsgData) {
         match data {
-            MsgData::Relocate { k_remaining, inc, new_copies, range} => {
+            MsgData::Relocate { k_remaining, inc, new_copies, range, keep, transfer, widen } => {
+                if *widen {
+                    self.widen_furthest_range(range, new_copies, *transfer).await;
+                    return;
+                }
+                let relocating = self.relocating.enter();
                 let k = self.get_current_k().await;
                 let max_k = self.max_replication().await;
 
                 if *inc { // case 'join'
-                    {
+                /* The joined range was cut from the range held here at index 'split_idx'. Copies
+                    of ranges further back move one index up and drop out past max_k, of the cut
+                    range only the keys that now belong to the new node do */
+                    let split_idx = match range {
+                        Some(joined) => self.get_replica_ranges().await.is_subset(joined.get_bounds().1),
+                        None => k as i16 - *k_remaining as i16 - 1
+                    };
+                    if split_idx >= 0 {
                         self.print_debug_msg("Acquiring write lock on records...");
                         let mut records_writer = self.records.write().await;
                         self.print_debug_msg("Write lock released on records.");
                         let mut to_remove: Vec<HashType> = Vec::new();
                         for (key, item) in records_writer.iter_mut(){
-                            if item.replica_idx == k {
-                                to_remove.push(*key);
-                            } else if item.replica_idx > (k - k_remaining - 1) && item.replica_idx < k {
-                                item.replica_idx += 1;
-                            } 
+                            let idx = item.replica_idx as i16;
+                            if idx > split_idx || (idx == split_idx && range.is_some_and(|joined| joined.contains(*key))) {
+                                if item.replica_idx >= max_k {
+                                    to_remove.push(*key);
+                                } else {
+                                    item.replica_idx += 1;
+                                }
+                            }
                         }
                         for key in to_remove.iter(){
-                            records_writer.remove(key);
+                            if let Some(gone) = records_writer.remove(key) {
+                                self.track_bytes(gone.value.len(), 0);
+                            }
                         }
                     } // release locks
 
@@ -679,21 +1972,24 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                         if ranges.get_size() > max_k as usize {
                             ranges.pop_head();
                         }
+                        self.audit(&format!("relocate join (k_remaining {}), ranges {:?}", k_remaining, ranges));
                     }
+                    drop(relocating);
 
                     if *k_remaining > 0 {
                         // inform next one 
                         let rel_msg = Message::new(
                             MsgType::Relocate,
                             None,
-                            &MsgData::Relocate { k_remaining: *k_remaining-1, inc: true, new_copies: None, range: *range }
+                            &MsgData::Relocate { k_remaining: *k_remaining-1, inc: true, new_copies: None, range: *range, keep: None, transfer: Uuid::nil(), widen: false }
                         );
 
                         self.send_msg(self.get_succ().await, &rel_msg).await;
                     }
                 } 
                 else { // case 'depart'
-                if *k_remaining > 0 {
+                /* the last hop (k_remaining == 0) must also shift its indices,
+                    otherwise with k == 1 the successor never becomes primary */
                 let mut to_transfer: Vec<Item> = Vec::new();
                 {
                     self.print_debug_msg("Acquiring write lock on records...");
@@ -703,15 +1999,18 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                         if item.replica_idx == k {
                             to_transfer.push(item.clone());
                         }
-                        if item.replica_idx > (k - k_remaining -1) {
+                        if item.replica_idx > 0 && item.replica_idx as i16 > k as i16 - *k_remaining as i16 - 1 {
                             item.replica_idx -= 1;
                         } 
                     }
                 } // release write locks here
                     let ranges_tmp = self.get_replica_ranges().await;
-                    let mut range_to_transfer = ranges_tmp.get_head();
+                    let furthest = if ranges_tmp.get_size() > 0 { Some(ranges_tmp.get_head()) } else { None };
+                    let mut range_to_transfer = if ranges_tmp.get_size() > 0 { Some(ranges_tmp.get_head()) } else { None };
                     if ranges_tmp.get_size() == 1 {
-                        range_to_transfer.set_upper(self.get_succ().await.unwrap().id);
+                        if let Some(head) = range_to_transfer.as_mut() {
+                            head.set_upper(self.get_succ().await.unwrap().id);
+                        }
                     }
                     if let Some(range) = range {
                         self.print_debug_msg("Acquiring write lock on replication...");
@@ -720,77 +2019,163 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                         let ranges = &mut replica_writer.replica_ranges;
                         ranges.merge_at(*k_remaining as usize);
                         ranges.insert_head(range.clone());
-                        
+                        self.audit(&format!("relocate depart (k_remaining {}), ranges {:?}", k_remaining, ranges));
                     }
                     // create one more replica manager for last copies
-                    if let Some(copies) = new_copies { 
-                        for copy in copies.clone().iter_mut(){
-                            let key_copy = HashFunc(&copy.title);
-                            self.print_debug_msg("Acquiring read lock on records...");
-                            if self.records.read().await.get(&key_copy).is_none() {
-                                self.print_debug_msg("Read lock acquired on records.");
-                                self.insert_aux(key_copy, copy).await;
-                            }
+                    if let Some(last_copies) = new_copies { 
+                        let mut copies = self.take_staged(*transfer).await;
+                        if let Some(keep) = keep {
+                            copies.extend(self.take_prefetched(keep).await);
+                        }
+                        copies.extend(last_copies.iter().cloned());
+                        for copy in copies.iter_mut(){
+                            let key_copy = copy.key_hash();
+                            self.reconcile_copy(key_copy, copy).await;
                         }
                     }
+                    drop(relocating);
 
                     if *k_remaining > 0 {
-                        // inform next one 
+                        /* inform next one. The copies go as a whole or not at all, the indices are
+                            shifted either way and copies that could not be delivered are left to anti-entropy */
+                        let (transfer, new_copies) = match self.send_chunks(self.get_succ().await, to_transfer).await {
+                            Some((transfer, to_transfer)) => (transfer, Some(to_transfer)),
+                            None => (Uuid::nil(), None)
+                        };
                         let rel_msg = Message::new(
                             MsgType::Relocate,
                             None,
-                            &MsgData::Relocate { k_remaining: *k_remaining-1, inc: false, new_copies: Some(to_transfer), range: Some(range_to_transfer) }
+                            &MsgData::Relocate { k_remaining: *k_remaining-1, inc: false, new_copies, range: range_to_transfer, keep: None, transfer, widen: false }
                         );
 
                         self.send_msg(self.get_succ().await, &rel_msg).await;
                         self.print_debug_msg(&format!("Ranges after relocation: {:?}", self.get_replica_ranges().await));
                         return;
                     } 
-                }
-                self.print_debug_msg(&format!("Ranges after relocation: {:?}", self.get_replica_ranges().await));
-            }
-            }
+                    /* The copies at index k here were the departed node's primary keys. Its range now
+                        belongs to the first successor, so the next node, which kept that successor's
+                        range furthest back, takes them and widens the range down to them */
+                    if k > 0 && range.is_some() {
+                        if let Some(departed) = furthest {
+                            let (transfer, new_copies) = match self.send_chunks(self.get_succ().await, to_transfer).await {
+                                Some((transfer, to_transfer)) => (transfer, Some(to_transfer)),
+                                None => (Uuid::nil(), None)
+                            };
+                            let widen_msg = Message::new(
+                                MsgType::Relocate,
+                                None,
+                                &MsgData::Relocate { k_remaining: 0, inc: false, new_copies, range: Some(departed), keep: None, transfer, widen: true }
+                            );
+                            self.send_msg(self.get_succ().await, &widen_msg).await;
+                        }
+                    }
+                self.print_debug_msg(&format!("Ranges after relocation: {:?}", self.get_replica_ranges().await));
+            }
+            }
 
             _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data)),
         }
     }
 
-    async fn handle_quit(&self, client:Option<&NodeInfo>, _data:&MsgData) {
+    // the last step of a depart, only a node whose furthest range starts where the departed range ends takes part
+    async fn widen_furthest_range(&self, departed:&Option<Range<HashType>>, new_copies:&Option<Vec<Item>>, transfer:Uuid) {
+        let mut copies = self.take_staged(transfer).await;
+        let departed = match departed {
+            Some(departed) => departed,
+            None => return
+        };
+        {
+            self.print_debug_msg("Acquiring write lock on replication...");
+            let mut replica_writer = self.replication.write().await;
+            self.print_debug_msg("Write lock released on replication.");
+            let ranges = &mut replica_writer.replica_ranges;
+            if ranges.get_size() == 0 || ranges.get_head().get_bounds().0 != departed.get_bounds().1 {
+                return;
+            }
+            let furthest = ranges.get_head();
+            ranges.pop_head();
+            ranges.insert_head(Range::new(departed.get_bounds().0, furthest.get_bounds().1, false, true));
+            self.audit(&format!("relocate depart widened the furthest range, ranges {:?}", ranges));
+        }
+        copies.extend(new_copies.iter().flatten().cloned());
+        for copy in copies.iter_mut() {
+            let key_copy = copy.key_hash();
+            self.reconcile_copy(key_copy, copy).await;
+        }
+    }
+
+    async fn handle_quit(&self, client:Option<&NodeInfo>, data:&MsgData) {
         self.print_debug_msg("Preparing to Quit...");
         // grab read locks here 
         let prev = self.get_prev().await;
         let succ = self.get_succ().await;
 
+        /* The bootstrap is the entry point of every join, so it only leaves last. The keys of
+            departing nodes end up on it, and once alone it has no successor to hand them to:
+            it refuses to leave while it holds any, unless the client drains them first */
         if self.bootstrap.is_none() {
-            let reply:&str;
+            let reply:String;
             if prev.is_none() || succ.is_none() || prev.unwrap().id == self.get_id() || succ.unwrap().id == self.get_id() {
                 self.print_debug_msg("Bootstrap node is alone in the network");
+                let drain = matches!(data, MsgData::Quit { drain: true, .. });
+                let records: Vec<Item> = self.records.read().await.values().cloned().collect();
+                if !records.is_empty() && !drain {
+                    self.reject_request(client, &format!("bootstrap node still holds {} keys that would be lost, depart with --drain to receive them first", records.len())).await;
+                    return;
+                }
                 self.set_status(false);
-                reply = "Bootstrap node has left the network";
+                self.records.write().await.clear();
+                self.stored_bytes.store(0, Ordering::SeqCst);
+                self.audit(&format!("bootstrap left the ring, {} keys drained", records.len()));
+                if drain {
+                    let drain_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::LocalRecords { records }
+                    );
+                    client.unwrap().send_msg(&drain_msg).await;
+                    return;
+                }
+                reply = "Bootstrap node has left the network".to_string();
             } else {
-                reply = "Bootstrap node cannot leave the network, depart the other nodes first";
+                reply = format!("Bootstrap node cannot leave the network, depart the other nodes first. Its {} keys stay in place",
+                                self.records.read().await.len());
             }
             let user_msg = Message::new(
                 MsgType::Reply, 
                 None,
-                &MsgData::Reply { reply: reply.to_string() }
+                &MsgData::Reply { reply }
             );
             client.unwrap().send_msg(&user_msg).await;
             return;
         }
-        let k = self.get_current_k().await;
-        let max_k = self.max_replication().await;
-        if k <= max_k {
-            let user_msg = Message::new(
-                MsgType::Reply, 
-                None,
-                &MsgData::Reply { reply: format!("Network configuration must have at least {} nodes", max_k+1) }
-            );
-            client.unwrap().send_msg(&user_msg).await;
+        if self.departing.swap(true, Ordering::SeqCst) {
+            self.reject_request(client, &format!("node {} is already leaving the ring", self)).await;
             return;
         }
-        /* construct an Update Message for previous
-            only neighbours change ? */ 
+        if let Err(left) = self.claim_stable().await {
+            self.departing.store(false, Ordering::SeqCst);
+            self.reject_unstable(client, left).await;
+            return;
+        }
+        /* A warm depart first streams the copies the successor takes over while this node
+            keeps serving. The cutover below then only ships what changed in the meantime */
+        let warm = matches!(data, MsgData::Quit { warm: true, .. });
+        let mut prefetched = HashMap::new();
+        if let Some(succ_node) = succ.filter(|node| warm && node.id != self.get_id()) {
+            match self.prefetch_handover(succ_node).await {
+                Some(versions) => prefetched = versions,
+                None => {
+                    self.abort_depart(client, succ_node, None, None).await;
+                    return;
+                }
+            }
+        }
+        /* No minimum ring size is enforced: get_current_k already shrinks with the ring,
+            so once fewer than k nodes remain every live node simply keeps a copy of every key.
+            With k == 0 nobody else holds our keys and they are handed over to the successor below. */
+        /* Every step must be confirmed before local state is dropped. If a neighbour can't
+            confirm, the ones already told are pointed back at this node and it stays in the ring */
         if let Some(prev_node) = prev {
             if prev_node.id != self.get_id() {
                 let quit_msg_prev = Message::new(
@@ -798,7 +2183,10 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                     None,
                     &MsgData::Update { prev_info: None, succ_info: succ } 
                 );
-                prev_node.send_msg(&quit_msg_prev).await;
+                if !self.send_confirmed(prev_node, &quit_msg_prev).await {
+                    self.abort_depart(client, prev_node, None, None).await;
+                    return;
+                }
                 self.print_debug_msg(&format!("Sent Quit Message to {} succesfully ", prev_node));
             }
         }
@@ -811,7 +2199,10 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                     None,
                     &MsgData::Update { prev_info: prev, succ_info: None }
                 );
-                succ_node.send_msg(&quit_msg_succ).await;
+                if !self.send_confirmed(succ_node, &quit_msg_succ).await {
+                    self.abort_depart(client, succ_node, prev, None).await;
+                    return;
+                }
                 self.print_debug_msg(&format!("Sent Quit Message to {} succesfully ", succ_node));
             }
 
@@ -830,18 +2221,41 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
             // TODO! Test this
             let succ = self.get_succ().await;
             let ranges = self.get_replica_ranges().await;
-            let range = if ranges.get_size() > 0 {Some(ranges.get_head())} else { None };
+            let mut range = if ranges.get_size() > 0 {Some(ranges.get_head())} else { None };
             if ranges.get_size() == 1 {
-                range.unwrap().set_upper(succ.unwrap().id);
+                if let Some(head) = range.as_mut() {
+                    head.set_upper(succ.unwrap().id);
+                }
+            }
+            drop(record_reader);
+
+            // copies prefetched unchanged stay with the successor, only the delta is shipped
+            let mut keep = None;
+            if warm {
+                let (unchanged, changed): (Vec<Item>, Vec<Item>) = last_replicas.into_iter()
+                    .partition(|item| prefetched.get(&item.key_hash()) == Some(&item.version()));
+                keep = Some(unchanged.iter().map(Item::key_hash).collect());
+                last_replicas = changed;
             }
-            let rel_msg = Message::new(
-                MsgType::Relocate,
-                None,
-                &MsgData::Relocate { k_remaining: k - 1 , inc: false, new_copies: Some(last_replicas), range: range }
-            );
             
             if succ.unwrap().id != self.get_id() {
-                self.send_msg(succ, &rel_msg).await;
+                // the relocate itself has to be confirmed too before the records are dropped
+                let sent = self.send_chunks(Some(succ_node), last_replicas).await;
+                let confirmed = match sent {
+                    Some((transfer, last_replicas)) => {
+                        let rel_msg = Message::new(
+                            MsgType::Relocate,
+                            None,
+                            &MsgData::Relocate { k_remaining: k.saturating_sub(1), inc: false, new_copies: Some(last_replicas), range: range, keep, transfer, widen: false }
+                        );
+                        self.send_confirmed(succ_node, &rel_msg).await
+                    }
+                    None => false
+                };
+                if !confirmed {
+                    self.abort_depart(client, succ_node, prev, succ).await;
+                    return;
+                }
             }
         }
         // delete all records 
@@ -849,15 +2263,18 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
         let mut map = self.records.write().await;
         self.print_debug_msg("Write lock released on records.");
         map.clear();
+        self.stored_bytes.store(0, Ordering::SeqCst);
         
 
         self.print_debug_msg("Acquiring write lock on replication...");
         let mut replica = self.replication.write().await;
         self.print_debug_msg("Write lock released on replication.");
         replica.replica_ranges.clear();
+        self.audit("departed the ring, records and ranges cleared");
         
         // change status and inform user
         self.set_status(false);
+        self.departing.store(false, Ordering::SeqCst);
         let user_msg = Message::new(
             MsgType::Reply, 
             None,
@@ -867,32 +2284,132 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
 
     }
 
+    // retries a message until the receiver confirms it has handled it
+    async fn send_confirmed(&self, dest:NodeInfo, msg:&Message) -> bool {
+        for attempt in 0..crate::DEPART_RETRIES {
+            if attempt > 0 {
+                sleep(Duration::from_millis(crate::DEPART_RETRY_MS * attempt as u64)).await;
+            }
+            if dest.send_acked(msg).await {
+                return true;
+            }
+            eprintln!("❌ {} did not confirm {:?} (attempt {} of {})", dest, msg.extract_type(), attempt + 1, crate::DEPART_RETRIES);
+        }
+        false
+    }
+
+    /* Phase one of a warm depart: every copy the successor will take over is sent as a
+        confirmed prefetch chunk. Returns the versions sent so the cutover can tell which changed */
+    async fn prefetch_handover(&self, succ_node:NodeInfo) -> Option<HashMap<HashType, (DateTime<Utc>, Option<HashType>)>> {
+        let k = self.get_current_k().await;
+        self.print_debug_msg("Acquiring read lock on records...");
+        let mut items: Vec<Item> = self.records.read().await.values()
+            .filter(|item| item.replica_idx == k)
+            .cloned()
+            .collect();
+        self.print_debug_msg("Read lock released on records.");
+        let versions: HashMap<_, _> = items.iter().map(|item| (item.key_hash(), item.version())).collect();
+        while !items.is_empty() {
+            let rest = items.split_off(items.len().min(crate::TRANSFER_CHUNK));
+            let chunk_msg = Message::new(
+                MsgType::Transfer,
+                None,
+                &MsgData::Transfer { items, prefetch: true, transfer: Uuid::nil() }
+            );
+            if !self.send_confirmed(succ_node, &chunk_msg).await {
+                return None;
+            }
+            items = rest;
+        }
+        self.print_debug_msg(&format!("Prefetched {} copies to {}", versions.len(), succ_node));
+        Some(versions)
+    }
+
+    // points the neighbours that were already told back at this node and keeps its data
+    async fn abort_depart(&self, client:Option<&NodeInfo>, unconfirmed:NodeInfo, told_prev:Option<NodeInfo>, told_succ:Option<NodeInfo>) {
+        self.departing.store(false, Ordering::SeqCst);
+        if let Some(prev_node) = told_prev.filter(|node| node.id != self.get_id()) {
+            let relink = Message::new(
+                MsgType::Update,
+                None,
+                &MsgData::Update { prev_info: None, succ_info: Some(self.get_info()) }
+            );
+            prev_node.send_msg(&relink).await;
+        }
+        if let Some(succ_node) = told_succ.filter(|node| node.id != self.get_id()) {
+            let relink = Message::new(
+                MsgType::Update,
+                None,
+                &MsgData::Update { prev_info: Some(self.get_info()), succ_info: None }
+            );
+            succ_node.send_msg(&relink).await;
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: depart aborted, {} did not confirm the handover. Node {} stays in the ring", unconfirmed, self) }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
     async fn handle_insert(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::Insert { key, value } => {
-                let key_hash = HashFunc(key);
+            MsgData::Insert { key, value, wait_for_replication, consistency, colocate_with, max_value_len, create_only, json, copy_of } => {
+                // blank titles would be stored under the hash of "" and show up in every QueryAll
+                if key.trim().is_empty() {
+                    self.reject_request(client, "key must not be empty").await;
+                    return;
+                }
+                if *json {
+                    if let Err(e) = serde_json::from_str::<serde_json::Value>(value) {
+                        self.reject_request(client, &format!("value is not valid JSON: {}", e)).await;
+                        return;
+                    }
+                }
+                let key_hash = utils::HashKey(key, colocate_with);
+                // writes seen on the way drop the cached read, the TTL covers the ones routed elsewhere
+                self.read_cache.write().await.invalidate(&key_hash);
                 let prev = self.get_prev().await;
                 let succ = self.get_succ().await;
-                let cons = self.get_consistency().await;
+                // a per operation override travels with every forward, the ring default applies otherwise
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
                 match cons {
                     Consistency::Eventual => {
                         /* every replica manager can save the new item loally 
                             and reply to client immediately. */
                         let replica= self.is_replica_manager(&key_hash).await;
                         if replica == 0 {
+                            if self.refuse_if_read_only(client).await {
+                                return;
+                            }
+                            let _creating = if *create_only { Some(self.creating.lock().await) } else { None };
+                            if *create_only && self.refuse_if_exists(client, &key_hash, key).await {
+                                return;
+                            }
                             let mut new_item = Item::new( 
                                 key, 
                                 value, 
                                 replica as u8, 
                                 false );
+                            new_item.parent = colocate_with.clone();
+                            new_item.max_value_len = *max_value_len;
+                            if let Some(source) = copy_of {
+                                new_item.adopt(source);
+                            }
                             self.insert_aux(key_hash, &mut new_item).await;
 
-                            let user_msg = Message::new(
-                                MsgType::Reply,
-                                None,
-                                &MsgData::Reply { reply: format!("Inserted (🔑 {} : 🔒{}) at 🕰️ {} successfully!", key, value, new_item.timestamp) }
-                            );
-                            client.unwrap().send_msg(&user_msg).await;
+                            /* When the client asked to wait for replication, the reply travels with
+                                the forwards and the last replica sends it, like the chain tail does */
+                            let k = self.get_current_k().await;
+                            let reply_at_tail = *wait_for_replication && k > 0;
+                            if !reply_at_tail {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: utils::format_insert_msg(key, value, &new_item.timestamp, 1, k == 0) }
+                                );
+                                client.unwrap().send_msg(&user_msg).await;
+                            }
 
                             // propagate insert to other replica managers
                             if replica > 0 {
@@ -901,35 +2418,34 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                     MsgType::FwInsert,
                                     None,
                                     &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                               replica:(replica - 1), forward_back:true }
+                                                               replica:(replica - 1), forward_back:true, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                                 );
 
                                 self.send_msg(prev, &fw_back).await;
                             }
 
-                            if (replica as u8) < self.get_current_k().await {
+                            if (replica as u8) < k {
                                 // successor's replica_idx += 1
                                 let fw_next = Message::new(
                                     MsgType::FwInsert,
-                                    None,
+                                    if reply_at_tail { client } else { None },
                                     &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                               replica: (replica + 1), forward_back:false }
+                                                               replica: (replica + 1), forward_back:false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                                 );
 
-                                self.send_msg(prev, &fw_next).await;
+                                self.send_msg(succ, &fw_next).await;
                             }
                         } else {
                             // forward same message to another node in the primary direction 
                             let fw_ins = Message::new(
                                 MsgType::Insert,
                                 client,
-                                &MsgData::Insert { key: key.clone(), value: value.clone() }
+                                &MsgData::Insert { key: key.clone(), value: value.clone(), wait_for_replication: *wait_for_replication, consistency: *consistency, colocate_with: colocate_with.clone(),
+                                                   max_value_len: *max_value_len, create_only: *create_only, json: *json, copy_of: copy_of.clone() }
                             );
-                            if self.maybe_next_responsible(&key_hash).await {
-                                self.send_msg(succ, &fw_ins).await;
-                            } else {
-                                self.send_msg(prev, &fw_ins).await;
-                            }
+                            self.forward_to_primary(client, &key_hash, &fw_ins).await;
                         }
                     }
 
@@ -938,10 +2454,26 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                            It forwards the insert request to all other replica managers without replying to client.
                            Meanwhile the 'pending' field remains true until an ack is received. */
                         if self.is_responsible(&key_hash).await {
+                            if self.refuse_if_read_only(client).await {
+                                return;
+                            }
                             // sleep if it's currently updating
                             //self.sleep_on_updates(key_hash).await;
 
+                            /* The slot is taken before the creating lock, so a create-only insert
+                                waiting for a slot never holds up the checks of the others */
                             let k = self.get_current_k().await;
+                            if k > 0 && !self.acquire_chain_slot(client).await {
+                                return;
+                            }
+                            // a pending head copy counts as existing, so the check holds before propagation
+                            let _creating = if *create_only { Some(self.creating.lock().await) } else { None };
+                            if *create_only && self.refuse_if_exists(client, &key_hash, key).await {
+                                if k > 0 {
+                                    self.release_chain_slot();
+                                }
+                                return;
+                            }
                             let is_pending =  k > 0 ; // no need for pending head == tail
                             self.print_debug_msg(&format!("Inserting key: {} with pending: {}", key, is_pending));
                             let mut new_item = Item:: new(
@@ -950,6 +2482,11 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                 0,
                                 is_pending
                             );
+                            new_item.parent = colocate_with.clone();
+                            new_item.max_value_len = *max_value_len;
+                            if let Some(source) = copy_of {
+                                new_item.adopt(source);
+                            }
                             self.insert_aux(key_hash, &mut new_item).await;
 
                             if k > 0 {
@@ -957,14 +2494,15 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                     MsgType::FwInsert,
                                     client,
                                     &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                                replica: 1, forward_back: false }
+                                                                replica: 1, forward_back: false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                                 );
                                 self.send_msg(succ, &fw_ins).await;
                             } else if k == 0 {
                                 let user_msg = Message::new(
                                     MsgType::Reply,
                                     None,
-                                    &MsgData::Reply { reply: format!("Inserted (🔑 {} : 🔒{}) at 🕰️ {} successfully!", key, value, new_item.timestamp) }
+                                    &MsgData::Reply { reply: utils::format_insert_msg(key, value, &new_item.timestamp, 1, true) }
                                 );
                                 client.unwrap().send_msg(&user_msg).await;
                             }
@@ -972,18 +2510,69 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             let fw_ins = Message::new(
                                 MsgType::Insert,
                                 client,
-                                &MsgData::Insert { key: key.clone(), value: value.clone() }
+                                &MsgData::Insert { key: key.clone(), value: value.clone(), wait_for_replication: *wait_for_replication, consistency: *consistency, colocate_with: colocate_with.clone(),
+                                                   max_value_len: *max_value_len, create_only: *create_only, json: *json, copy_of: copy_of.clone() }
                             );
 
-                            if self.maybe_next_responsible(&key_hash).await {
+                            self.forward_to_primary(client, &key_hash, &fw_ins).await;
+                        }
+                    }
+
+                    Consistency::Quorum => {
+                        /* The primary stores the item and forwards it down the replica chain.
+                           The client travels with the forward until W copies hold the item,
+                           the replica storing the W-th copy replies and the rest only store it */
+                        if self.is_responsible(&key_hash).await {
+                            if self.refuse_if_read_only(client).await {
+                                return;
+                            }
+                            let _creating = if *create_only { Some(self.creating.lock().await) } else { None };
+                            if *create_only && self.refuse_if_exists(client, &key_hash, key).await {
+                                return;
+                            }
+                            let mut new_item = Item::new(
+                                key,
+                                value,
+                                0,
+                                false );
+                            new_item.parent = colocate_with.clone();
+                            new_item.max_value_len = *max_value_len;
+                            if let Some(source) = copy_of {
+                                new_item.adopt(source);
+                            }
+                            self.insert_aux(key_hash, &mut new_item).await;
+
+                            let k = self.get_current_k().await;
+                            let (_, w) = self.quorum_copies().await;
+                            if w == 1 {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: utils::format_insert_msg(key, value, &new_item.timestamp, 1, k == 0) }
+                                );
+                                client.unwrap().send_msg(&user_msg).await;
+                            }
+
+                            if k > 0 {
+                                let fw_ins = Message::new(
+                                    MsgType::FwInsert,
+                                    if w > 1 { client } else { None },
+                                    &MsgData::FwInsert { key: key.clone(), value: value.clone(),
+                                                               replica: 1, forward_back: false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
+                                );
                                 self.send_msg(succ, &fw_ins).await;
-                            } else {
-                                self.send_msg(prev, &fw_ins).await;
                             }
+                        } else {
+                            let fw_ins = Message::new(
+                                MsgType::Insert,
+                                client,
+                                &MsgData::Insert { key: key.clone(), value: value.clone(), wait_for_replication: *wait_for_replication, consistency: *consistency, colocate_with: colocate_with.clone(),
+                                                   max_value_len: *max_value_len, create_only: *create_only, json: *json, copy_of: copy_of.clone() }
+                            );
+                            self.forward_to_primary(client, &key_hash, &fw_ins).await;
                         }
                     }
-
-                    _ => self.print_debug_msg(&format!("Unsupported Consistency model - {:?}", cons))
                 }
             }
 
@@ -991,29 +2580,50 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
         } 
     }
 
+    // create-only inserts of a key that the primary already stores are answered with an error
+    async fn refuse_if_exists(&self, client:Option<&NodeInfo>, key_hash:&HashType, key:&str) -> bool {
+        if !self.records.read().await.contains_key(key_hash) {
+            return false;
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: AlreadyExists: 🔑 {} already exists", key) }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+        true
+    }
+
     async fn handle_fw_insert(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::FwInsert { key, value, replica, forward_back } => {
+            MsgData::FwInsert { key, value, replica, forward_back, consistency, colocate_with, max_value_len, timestamp, writer } => {
                 // forward_back is used to avoid ping-pong messages
-                let key_hash = HashFunc(key);
+                let key_hash = utils::HashKey(key, colocate_with);
                 let prev = self.get_prev().await;
                 let succ = self.get_succ().await;
-                let cons = self.get_consistency().await;
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
                 match cons {
                     Consistency::Eventual => {
                         if *replica >= 0 {
-                            self.insert_aux(key_hash, &mut Item::new ( 
+                            let mut new_item = Item::new ( 
                                 key, 
                                 value, 
                                 *replica as u8, 
-                                false )).await;
+                                false );
+                            new_item.parent = colocate_with.clone();
+                            new_item.max_value_len = *max_value_len;
+                            // every copy keeps the version stamped by the node that stored the write first
+                            new_item.timestamp = timestamp.unwrap_or(new_item.timestamp);
+                            new_item.writer = *writer;
+                            self.insert_aux(key_hash, &mut new_item).await;
 
                             if *replica > 0 && *forward_back == true {
                                 let fw_ins = Message::new(
                                     MsgType::FwInsert,
                                     None,
                                     &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                               replica: (replica - 1), forward_back: true }
+                                                               replica: (replica - 1), forward_back: true, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                                 );
                                 self.send_msg(prev, &fw_ins).await;
                                 return;
@@ -1022,14 +2632,25 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             if (*replica as u8) < self.get_current_k().await && *forward_back == false {
                                 let fw_ins = Message::new(
                                     MsgType::FwInsert,
-                                    None,
+                                    client,
                                     &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                               replica: (replica + 1), forward_back: false }
+                                                               replica: (replica + 1), forward_back: false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                                 );
                                 self.send_msg(succ, &fw_ins).await;
                                 return;
                             }
 
+                            // only inserts waiting for replication carry the client this far
+                            if let Some(client) = client {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: utils::format_insert_msg(key, value, &new_item.timestamp, *replica as u8 + 1, true) }
+                                );
+                                client.send_msg(&user_msg).await;
+                            }
+
                         } else {
                             self.print_debug_msg(&format!("Invalid replica_idx provided: {}", replica));
                         }
@@ -1044,6 +2665,12 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             k > 0 && (*replica as u8) < k
                         );
 
+                        new_item.parent = colocate_with.clone();
+                        new_item.max_value_len = *max_value_len;
+                        // every copy keeps the version stamped by the node that stored the write first
+                        new_item.timestamp = timestamp.unwrap_or(new_item.timestamp);
+                        new_item.writer = *writer;
+
                         self.insert_aux(key_hash, &mut new_item).await;
                         self.print_debug_msg("Here 1");
                         self.print_debug_msg(&format!("Replica: {}, k: {}", replica, k));
@@ -1053,27 +2680,32 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                 MsgType::FwInsert,
                                 client,
                                 &MsgData::FwInsert { key: key.clone(), value: value.clone(), 
-                                                          replica: *replica + 1, forward_back: false }
+                                                          replica: *replica + 1, forward_back: false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
                             );
 
                             self.send_msg(succ, &fw_msg).await;
                             return;
                         } 
                         else if (*replica as u8) == k {
-                            /* If reached tail reply to client and send an ack to previous node */
+                            /* If reached tail reply to client and send an ack to previous node.
+                               With reply_from_head the client travels with the acks instead */
                             self.print_debug_msg("Here 3");
-                            let user_msg = Message::new(
-                                MsgType::Reply,
-                                None,
-                                &MsgData::Reply {reply: format!("Inserted (🔑 {} : 🔒{}) at 🕰️ {} successfully!", new_item.title, new_item.value, new_item.timestamp)}
-                            );
-                            
-                            client.unwrap().send_msg(&user_msg).await;
+                            let head_replies = k > 0 && self.reply_from_head().await;
+                            if !head_replies {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply {reply: utils::format_insert_msg(&new_item.title, &new_item.value, &new_item.timestamp, *replica as u8 + 1, true)}
+                                );
+                                
+                                client.unwrap().send_msg(&user_msg).await;
+                            }
 
                             if k > 0 {      // init acks only if tail != head
                                 let ack_msg = Message::new(
                                     MsgType::AckInsert,
-                                    None,
+                                    if head_replies { client } else { None },
                                     &MsgData::AckInsert { key: key_hash }
                                 );
 
@@ -1083,7 +2715,45 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                         }
                     }
 
-                    _ => self.print_debug_msg(&format!("Unsupported Consistency model - {:?}", cons))
+                    Consistency::Quorum => {
+                        let k = self.get_current_k().await;
+                        let mut new_item = Item::new(
+                            key,
+                            value,
+                            *replica as u8,
+                            false
+                        );
+                        new_item.parent = colocate_with.clone();
+                        new_item.max_value_len = *max_value_len;
+                        // every copy keeps the version stamped by the node that stored the write first
+                        new_item.timestamp = timestamp.unwrap_or(new_item.timestamp);
+                        new_item.writer = *writer;
+                        self.insert_aux(key_hash, &mut new_item).await;
+
+                        // the client is only carried until the W-th copy is stored
+                        let (_, w) = self.quorum_copies().await;
+                        let mut client = client;
+                        if let Some(user) = client.filter(|_| *replica as u8 + 1 >= w) {
+                            let user_msg = Message::new(
+                                MsgType::Reply,
+                                None,
+                                &MsgData::Reply { reply: utils::format_insert_msg(key, value, &new_item.timestamp, *replica as u8 + 1, *replica as u8 == k) }
+                            );
+                            user.send_msg(&user_msg).await;
+                            client = None;
+                        }
+
+                        if (*replica as u8) < k {
+                            let fw_ins = Message::new(
+                                MsgType::FwInsert,
+                                client,
+                                &MsgData::FwInsert { key: key.clone(), value: value.clone(),
+                                                          replica: *replica + 1, forward_back: false, consistency: *consistency, colocate_with: colocate_with.clone(), max_value_len: *max_value_len,
+                                                               timestamp: Some(new_item.timestamp), writer: new_item.writer }
+                            );
+                            self.send_msg(succ, &fw_ins).await;
+                        }
+                    }
                 }
 
             }
@@ -1092,7 +2762,7 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
     }
 
 
-    async fn handle_ack_insert(&self, data:&MsgData) {
+    async fn handle_ack_insert(&self, client:Option<&NodeInfo>, data:&MsgData) {
         /* used for linearizability only
             change 'pending' to false and inform previous */
             match data {
@@ -1101,15 +2771,19 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                     let mut record_writer = self.records.write().await;
                     self.print_debug_msg("Write lock released on records.");
                     if let Some(record) = record_writer.get_mut(&key) {
-                        assert!(record.pending);
+                        // an operator may have cancelled the pending before the ack made it back
+                        if !record.pending {
+                            self.print_debug_msg(&format!("Ack for {} arrived after its pending was cancelled", key));
+                        }
                         record.pending = false;
                         let curr_idx = record.replica_idx;
+                        let acked = record.clone();
 
                         drop(record_writer);
                         if curr_idx > 0 {
                             let fw_ack = Message::new(
                                 MsgType::AckInsert,
-                                None,
+                                client,
                                 &MsgData::AckInsert { key: *key }
                             );
 
@@ -1117,12 +2791,22 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             return;
                         } 
                         else if curr_idx == 0  {
+                            self.release_chain_slot();
+                            // acks only carry the client when the head replies
+                            if let Some(client) = client {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: utils::format_insert_msg(&acked.title, &acked.value, &acked.timestamp, self.get_current_k().await + 1, true) }
+                                );
+                                client.send_msg(&user_msg).await;
+                            }
                             // notify waiting readers on this key
                             let waiting_list = self.pendings.write().await;
 
-                            if let Some(notify) = waiting_list.get(&key) {
+                            if let Some(wait) = waiting_list.get(key) {
                                 self.print_debug_msg("Sent a notification to ");
-                                notify.notify_waiters();  
+                                wait.notify.notify_waiters();  
                                 // remove this from queue
                                 //waiting_list.remove(&key);
                             }
@@ -1141,43 +2825,62 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
 
     async fn handle_query(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::Query { key } => {
-                let key_hash = HashFunc(key);
-                let cons = self.get_consistency().await;
-                let prev = self.get_prev().await;
+            MsgData::Query { key, kind, prefer, consistency, colocate_with } => {
+                let key_hash = utils::HashKey(key, colocate_with);
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
+                let prefer = &self.settle_relocation(*prefer, cons).await;
                 let succ = self.get_succ().await;
                 match cons {
                     Consistency::Eventual => {
-                        // whoever has a replica can reply
-                        if self.is_replica_manager(&key_hash).await >= 0 {
-                            self.print_debug_msg("Acquiring read lock on records...");
-                            let records_reader = self.records.read().await;
-                            self.print_debug_msg("Read lock acquired on records.");
-                            let res = records_reader.get(&key_hash);
-                            let reply: &str = match res {
-                                Some(found) => &format!("Found data: (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
-                                _ => &format!("Error: 🔑{} doesn't exist", key)
-                            };
+                        // the read preference decides which replica manager replies
+                        let route = self.route_eventual_read(&key_hash, *prefer).await;
+                        self.count_read(route.is_none());
+                        match route {
+                            None => {
+                                self.note_access(key_hash).await;
+                                self.print_debug_msg("Acquiring read lock on records...");
+                                let records_reader = self.records.read().await;
+                                self.print_debug_msg("Read lock acquired on records.");
+                                let res = records_reader.get(&key_hash);
+                                if let Some(corrupted) = res.filter(|item| !item.verify()) {
+                                    self.start_repair(client, key_hash, corrupted.replica_idx, *kind).await;
+                                    return;
+                                }
+                                if *kind != QueryKind::Value {
+                                    self.answer_query(client, key, res, *kind).await;
+                                    return;
+                                }
+                                let reply: &str = match res {
+                                    Some(found) => &format!("Found data: (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
+                                    _ => &format!("Error: 🔑{} doesn't exist", key)
+                                };
                             
-                            let user_msg = Message::new(
-                                MsgType::Reply,
-                                None,
-                                &MsgData::Reply { reply: reply.to_string() }
-                            );
-                            // send to user
-                            client.unwrap().send_msg(&user_msg).await;
-                            return;
-                        } else {
-                            // jsut forward Query to the direction of the primary node
-                            let fw_query = Message::new(
-                                MsgType::FwQuery,
-                                client,
-                                &MsgData::FwQuery { key: key_hash}
-                            );
-                            if self.maybe_next_responsible(&key_hash).await {
-                                self.send_msg(succ, &fw_query).await;
-                            } else {
-                                self.send_msg(prev, &fw_query).await;
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: reply.to_string() }
+                                );
+                                // send to user
+                                client.unwrap().send_msg(&user_msg).await;
+                                return;
+                            }
+                            Some((next_node, next_prefer)) => {
+                                // forward Query to the direction of the primary or the chosen replica
+                                if self.routes_to_self(&next_node) {
+                                    self.reject_unroutable(client, &key_hash).await;
+                                    return;
+                                }
+                                if *kind == QueryKind::Value && *prefer == ReadPreference::Nearest
+                                    && self.read_cache.read().await.is_enabled() {
+                                    self.cached_query(client, key, key_hash, next_node).await;
+                                    return;
+                                }
+                                let fw_query = Message::new(
+                                    MsgType::FwQuery,
+                                    client,
+                                    &MsgData::FwQuery { key: key_hash, kind: *kind, prefer: next_prefer, consistency: *consistency }
+                                );
+                                self.send_msg(next_node, &fw_query).await;
                             }
                         }
                     }
@@ -1192,13 +2895,21 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             //self.spinlock_on_updates(key_hash).await;
 
                             //create a sleeping process
-                            self.sleep_on_updates(key_hash).await;
+                            if !self.sleep_on_updates(key_hash).await {
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: format!("Error: pending update on 🔑 {} was cancelled, retry the read", key) }
+                                );
+                                client.unwrap().send_msg(&user_msg).await;
+                                return;
+                            }
                             
                             if  self.get_current_k().await > 0 {
                                 let fw_msg = Message::new(
                                     MsgType::FwQuery,
                                     client,
-                                    &MsgData::FwQuery { key: key_hash }
+                                    &MsgData::FwQuery { key: key_hash, kind: *kind, prefer: *prefer, consistency: *consistency }
                                 );
                                 self.send_msg(succ, &fw_msg).await;
                                 return;
@@ -1207,7 +2918,16 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                 self.print_debug_msg("Acquiring read lock on records...");
                                 let record_reader = self.records.read().await;
                                 self.print_debug_msg("Read lock acquired on records.");
+                                self.note_access(key_hash).await;
                                 let record = record_reader.get(&key_hash);
+                                if let Some(corrupted) = record.filter(|item| !item.verify()) {
+                                    self.start_repair(client, key_hash, corrupted.replica_idx, *kind).await;
+                                    return;
+                                }
+                                if *kind != QueryKind::Value {
+                                    self.answer_query(client, key, record, *kind).await;
+                                    return;
+                                }
                                 match record {
                                     Some(exist) => {
                                         let user_msg = Message::new(
@@ -1237,59 +2957,96 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             let fw_query = Message::new(
                                 MsgType::Query,
                                 client,
-                                &MsgData::Query { key: key.clone() }
+                                &MsgData::Query { key: key.clone(), kind: *kind, prefer: *prefer, consistency: *consistency, colocate_with: colocate_with.clone() }
                             ); 
 
-                            if self.maybe_next_responsible(&key_hash).await {
-                                self.send_msg(succ, &fw_query).await;
-                            } else {
-                                self.send_msg(prev, &fw_query).await;
-                            }
+                            self.forward_to_primary(client, &key_hash, &fw_query).await;
                         }
                     }
-            _ => self.print_debug_msg(&format!("Unsupported Consistency model - {:?}", cons))
-        }
-    }
-        _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+                    Consistency::Quorum => {
+                        /* The primary reads its own copy and, unless R is 1, sends it down the
+                           replica chain. Every replica keeps the newer of the copy carried and
+                           its own, the one reading the R-th copy answers with the newest seen */
+                        if self.is_responsible(&key_hash).await {
+                            self.note_access(key_hash).await;
+                            let own = self.records.read().await.get(&key_hash).filter(|item| item.verify()).cloned();
+                            let (r, _) = self.quorum_copies().await;
+                            if r > 1 {
+                                let fw_read = Message::new(
+                                    MsgType::FwQuorumRead,
+                                    client,
+                                    &MsgData::FwQuorumRead { key: key_hash, title: key.clone(), kind: *kind, left: r - 1, newest: own }
+                                );
+                                self.send_msg(succ, &fw_read).await;
+                                return;
+                            }
+                            self.answer_read(client, key, own.as_ref(), *kind).await;
+                        } else {
+                            let fw_query = Message::new(
+                                MsgType::Query,
+                                client,
+                                &MsgData::Query { key: key.clone(), kind: *kind, prefer: *prefer, consistency: *consistency, colocate_with: colocate_with.clone() }
+                            );
+                            self.forward_to_primary(client, &key_hash, &fw_query).await;
+                        }
+                    }
+        }
+    }
+        _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
     }
 }
 
     async fn handle_fw_query(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::FwQuery { key } => {
-                let cons = self.get_consistency().await;
+            MsgData::FwQuery { key, kind, prefer, consistency } => {
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
+                let prefer = &self.settle_relocation(*prefer, cons).await;
                 match cons {
                     Consistency::Eventual => {
                         // same as Query but hash is pre-computed
-                        if self.is_replica_manager(&key).await >= 0 {
-                            self.print_debug_msg("Acquiring read lock on records...");
-                            let records_reader = self.records.read().await;
-                            self.print_debug_msg("Read lock acquired on records.");
-                            let res = records_reader.get(&key);
-                            let reply: &str = match res {
-                                Some(found) => &format!("Found (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
-                                _ => &format!("Error: {} doesn't exist", key)
-                            };
+                        let route = self.route_eventual_read(key, *prefer).await;
+                        self.count_read(route.is_none());
+                        match route {
+                            None => {
+                                self.note_access(*key).await;
+                                self.print_debug_msg("Acquiring read lock on records...");
+                                let records_reader = self.records.read().await;
+                                self.print_debug_msg("Read lock acquired on records.");
+                                let res = records_reader.get(&key);
+                                if let Some(corrupted) = res.filter(|item| !item.verify()) {
+                                    self.start_repair(client, *key, corrupted.replica_idx, *kind).await;
+                                    return;
+                                }
+                                if *kind != QueryKind::Value {
+                                    self.answer_query(client, &key.to_string(), res, *kind).await;
+                                    return;
+                                }
+                                let reply: &str = match res {
+                                    Some(found) => &format!("Found (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
+                                    _ => &format!("Error: {} doesn't exist", key)
+                                };
                             
-                            let user_msg = Message::new(
-                                MsgType::Reply,
-                                None,
-                                &MsgData::Reply { reply: reply.to_string() }
-                            );
-                            // send to user
-                            client.unwrap().send_msg(&user_msg).await;
-                            return;
-                        } else {
-                            // jsut forward Query to the direction of the primary node
-                            let fw_query = Message::new(
-                                MsgType::FwQuery,
-                                client,
-                                &MsgData::FwQuery { key: *key }
-                            );
-                            if self.maybe_next_responsible(key).await {
-                                self.send_msg(self.get_succ().await, &fw_query).await;
-                            } else {
-                                self.send_msg(self.get_prev().await, &fw_query).await;
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: reply.to_string() }
+                                );
+                                // send to user
+                                client.unwrap().send_msg(&user_msg).await;
+                                return;
+                            }
+                            Some((next_node, next_prefer)) => {
+                                // forward Query to the direction of the primary or the chosen replica
+                                if self.routes_to_self(&next_node) {
+                                    self.reject_unroutable(client, key).await;
+                                    return;
+                                }
+                                let fw_query = Message::new(
+                                    MsgType::FwQuery,
+                                    client,
+                                    &MsgData::FwQuery { key: *key, kind: *kind, prefer: next_prefer, consistency: *consistency }
+                                );
+                                self.send_msg(next_node, &fw_query).await;
                             }
                         }
                     }
@@ -1305,7 +3062,7 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                                 let fw_tail = Message::new(
                                     MsgType::FwQuery,
                                     client,
-                                    &MsgData::FwQuery { key: *key }
+                                    &MsgData::FwQuery { key: *key, kind: *kind, prefer: *prefer, consistency: *consistency }
                                 );
 
                                 self.send_msg(self.get_succ().await, &fw_tail).await;
@@ -1313,10 +3070,23 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
                             } 
                             // reached tail so we can reply
                             else if (idx as u8) == k {
+                            // only the tail may answer, so a freshly joined tail waits out its grace period
+                            if let Some(left) = self.grace_left().await {
+                                sleep(left).await;
+                            }
                             self.print_debug_msg("Acquiring read lock on records...");
                             let record_reader = self.records.read().await;
                             self.print_debug_msg("Read lock acquired on records.");
+                                self.note_access(*key).await;
                                 let record = record_reader.get(key);
+                                if let Some(corrupted) = record.filter(|item| !item.verify()) {
+                                    self.start_repair(client, *key, corrupted.replica_idx, *kind).await;
+                                    return;
+                                }
+                                if *kind != QueryKind::Value {
+                                    self.answer_query(client, &key.to_string(), record, *kind).await;
+                                    return;
+                                }
                                 match record {
                                     Some(exist) => {
                                         let user_msg = Message::new(
@@ -1350,405 +3120,2585 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
         }
     }
 
-    async fn handle_query_all(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    async fn handle_fw_quorum_read(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::QueryAll {  } => {
-    self.print_debug_msg("Acquiring read lock on records...");
-                let records_reader = self.records.read().await;
-    self.print_debug_msg("Read lock acquired on records.");
-                self.print_debug_msg(&format!("All records: {:?}", records_reader));
-                let mut res = Vec::new();
-                // works as barrier for printing items per node
-                let node_item = Item::new (
-                    &format!("__nodeID__"),
-                    &self.get_id().to_string(),
-                    0,
-                    false
-                );
-                res.push(node_item);
-                for (_key, item) in records_reader.iter() {
-                    if item.replica_idx == 0 && item.pending == false {
-                        res.push(item.clone());
-                    }
+            MsgData::FwQuorumRead { key, title, kind, left, newest } => {
+                self.note_access(*key).await;
+                let own = self.records.read().await.get(key).filter(|item| item.verify()).cloned();
+                let newest = match (newest.clone(), own) {
+                    // equal timestamps are settled by the writer, the way replicas converge
+                    (Some(seen), Some(own)) if own.newer_than(&seen) => Some(own),
+                    (None, own) => own,
+                    (seen, _) => seen
+                };
+
+                // left never exceeds the copies after the primary, so the walk ends at the tail at the latest
+                if *left > 1 {
+                    let fw_read = Message::new(
+                        MsgType::FwQuorumRead,
+                        client,
+                        &MsgData::FwQuorumRead { key: *key, title: title.clone(), kind: *kind, left: left - 1, newest }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_read).await;
+                    return;
                 }
+                self.answer_read(client, title, newest.as_ref(), *kind).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
 
-                let succ_node = self.get_succ().await;
-                if succ_node.unwrap().id == self.get_id() {
-                    // node is alone 
-                    let user_msg = Message::new(
-                        MsgType::Reply,
-                        None,
-                        &MsgData::Reply { reply: utils::format_queryall_msg(&res) }
-                    );
-                    client.unwrap().send_msg(&user_msg).await;
+    // answers a read with the record found, value reads with the usual text reply
+    async fn answer_read(&self, client:Option<&NodeInfo>, key:&str, record:Option<&Item>, kind:QueryKind) {
+        if kind != QueryKind::Value {
+            self.answer_query(client, key, record, kind).await;
+            return;
+        }
+        let reply = match record {
+            Some(found) => format!("Found (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
+            None => format!("Error: 🔑 {} doesn't exist", key)
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_reconfigure(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Sets R and W ring wide in two walks over the successors. The first one checks them
+        against the replication factor of every node and is refused by the first node they
+        do not fit, before any node applied them. Back at the node asked, the second walk
+        applies them at every node it passes */
+        match data {
+            MsgData::Reconfigure { read_quorum, write_quorum, header, checked } => {
+                let walk_done = *header == Some(self.get_id());
+                if *checked && walk_done {
+                    self.reply_reconfigured(client, *read_quorum, *write_quorum).await;
                     return;
                 }
+                if !*checked {
+                    let copies = self.max_replication().await + 1;
+                    if !(1..=copies).contains(read_quorum) || !(1..=copies).contains(write_quorum) {
+                        self.reject_request(client, &format!("quorum sizes must be between 1 and {} copies at node {}", copies, self.get_info())).await;
+                        return;
+                    }
+                }
 
+                let succ = self.get_succ().await;
+                let alone = succ.is_none_or(|succ| succ.id == self.get_id());
+                // the checking walk applies nothing, the applying one starts where it ended
+                let applying = *checked || walk_done || alone;
+                if applying {
+                    self.print_debug_msg("Acquiring write lock on replication...");
+                    let mut replication_writer = self.replication.write().await;
+                    self.print_debug_msg("Write lock released on replication.");
+                    replication_writer.read_quorum = *read_quorum;
+                    replication_writer.write_quorum = *write_quorum;
+                }
+                if alone {
+                    self.reply_reconfigured(client, *read_quorum, *write_quorum).await;
+                    return;
+                }
                 let fw_msg = Message::new(
-                    MsgType::FwQueryAll,
+                    MsgType::Reconfigure,
                     client,
-                    &MsgData::FwQueryAll { record_list: res, header:self.get_id() }
+                    &MsgData::Reconfigure { read_quorum: *read_quorum, write_quorum: *write_quorum,
+                                            header: Some(header.unwrap_or(self.get_id())), checked: applying }
                 );
+                self.send_msg(succ, &fw_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn reply_reconfigured(&self, client:Option<&NodeInfo>, read_quorum:u8, write_quorum:u8) {
+        let copies = self.max_replication().await + 1;
+        let mut reply = format!("Quorum set to R={} W={} on every node", read_quorum, write_quorum);
+        if read_quorum + write_quorum <= copies {
+            reply.push_str(&format!(". Warning: R + W <= {}, quorum reads may miss the latest write", copies));
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    /* Opens a stabilization window for a topology change this node takes part in.
+        Fails with the time left while the previous one is still open */
+    async fn claim_stable(&self) -> Result<(), Duration> {
+        let window = match self.stabilize_window {
+            Some(window) => window,
+            None => return Ok(())
+        };
+        let mut stable_from = self.stable_from.write().await;
+        let now = Instant::now();
+        if let Some(left) = stable_from.and_then(|until| until.checked_duration_since(now)) {
+            return Err(left);
+        }
+        *stable_from = Some(now + window);
+        Ok(())
+    }
+
+    // a neighbour change made elsewhere also keeps this node busy for a window
+    async fn extend_unstable(&self) {
+        if let Some(window) = self.stabilize_window {
+            *self.stable_from.write().await = Some(Instant::now() + window);
+        }
+    }
+
+    async fn reject_unstable(&self, client:Option<&NodeInfo>, left:Duration) {
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: Stabilizing: node {} is in a topology change, retry in {} ms", self, left.as_millis().max(1)) }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    // time left before a freshly joined node serves reads from its own copies
+    async fn grace_left(&self) -> Option<Duration> {
+        let serving_from = (*self.serving_from.read().await)?;
+        serving_from.checked_duration_since(Instant::now())
+    }
+
+    /* A read reaching this node while a relocation shifts its replica indices could see a
+        copy at the wrong depth or miss one in transit. Depending on --relocate-reads it waits
+        for the relocation, at most RELOCATE_WAIT_MS, or an Eventual read goes on to the primary */
+    async fn settle_relocation(&self, prefer:ReadPreference, cons:Consistency) -> ReadPreference {
+        if !self.relocating.is_active() {
+            return prefer;
+        }
+        if self.relocate_reads == RelocateReads::Primary && matches!(cons, Consistency::Eventual) {
+            return ReadPreference::Primary;
+        }
+        if !self.relocating.wait(Duration::from_millis(crate::RELOCATE_WAIT_MS)).await {
+            self.print_debug_msg("Relocation still running, reading anyway");
+        }
+        prefer
+    }
 
-                self.send_msg(succ_node, &fw_msg).await; 
+    /* Under Eventual decides whether this node answers a read given the preference.
+        None means reply here, otherwise the next hop and the preference it carries on.
+        'Random' is resolved once, at the first replica manager reached, into a fixed replica index.
+        Query and FwQuery share it, so a replica manager answers a Nearest read from its own copy
+        whichever entry point the read came through */
+    async fn route_eventual_read(&self, key:&HashType, prefer:ReadPreference) -> Option<(Option<NodeInfo>, ReadPreference)> {
+        let replica = self.is_replica_manager(key).await;
+        let towards_primary = if self.maybe_next_responsible(key).await { self.get_succ().await } else { self.get_prev().await };
+        if replica < 0 {
+            return Some((towards_primary, prefer));
+        }
+
+        /* a freshly joined node hands the read to the neighbouring copy, which was already in place,
+            unless the primary was asked for: writes reach it first, so its copy is never behind.
+            A read naming its replica is served by that replica, so it tells which copy answered */
+        let k = self.get_current_k().await;
+        let local_idx = self.records.read().await.get(key).map(|item| item.replica_idx);
+        if let (Some(_), Some(idx)) = (self.grace_left().await, local_idx) {
+            if k > 0 && !matches!(prefer, ReadPreference::Primary | ReadPreference::Replica(_)) {
+                return if idx < k {
+                    Some((self.get_succ().await, ReadPreference::Replica(idx + 1)))
+                } else {
+                    Some((self.get_prev().await, ReadPreference::Replica(idx - 1)))
+                };
             }
-            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data)),
+        }
+
+        let target = match prefer {
+            ReadPreference::Nearest => return None,
+            ReadPreference::Primary if replica == 0 => return None,
+            ReadPreference::Primary => return Some((towards_primary, prefer)),
+            ReadPreference::Random => self.rng.lock().unwrap().gen_range(0..=k),
+            ReadPreference::Replica(idx) => idx
+        };
+
+        // walk along the replica chain using the index of the local copy
+        match local_idx {
+            Some(idx) if idx < target => Some((self.get_succ().await, ReadPreference::Replica(target))),
+            Some(idx) if idx > target => Some((self.get_prev().await, ReadPreference::Replica(target))),
+            _ => None
         }
     }
 
-    async fn handle_fw_query_all(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Called when the local copy read for a query fails its checksum.
+        The query is handed to the neighbouring replica along the chain which answers
+        the client if its copy is valid and sends the good value back to this node */
+    async fn start_repair(&self, client:Option<&NodeInfo>, key:HashType, local_idx:u8, kind:QueryKind) {
+        eprintln!("Checksum mismatch for key {} at replica {}", key, local_idx);
+        let k = self.get_current_k().await;
+        if k == 0 {
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: format!("Error: 🔑 {} is corrupted and has no other replica", key) }
+            );
+            client.unwrap().send_msg(&user_msg).await;
+            return;
+        }
+
+        let forward_back = local_idx >= k;
+        let repair_msg = Message::new(
+            MsgType::Repair,
+            client,
+            &MsgData::Repair { key, origin: self.get_info(), kind, hops_left: k - 1, forward_back }
+        );
+        let next = if forward_back { self.get_prev().await } else { self.get_succ().await };
+        self.send_msg(next, &repair_msg).await;
+    }
+
+    async fn handle_repair(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::FwQueryAll { record_list, header } => {
-    self.print_debug_msg("Acquiring read lock on records...");
-                let records_reader = self.records.read().await;
-    self.print_debug_msg("Read lock acquired on records.");
-    self.print_debug_msg("Acquiring read lock on records...");
-                self.print_debug_msg(&format!("All records: {:?}", self.records.read().await));
-    self.print_debug_msg("Read lock acquired on records.");
-                let mut record_clone = record_list.clone();
-                // works as barrier for printing items per node
-                let node_item = Item::new(
-                    &format!("__nodeID__"),
-                    &self.get_id().to_string(),
-                    0,
-                    false
-                );
-                record_clone.push(node_item);
-                // Append current node's relevant records
-                for (_, item) in records_reader.iter() {
-                    if item.replica_idx == 0 && item.pending == false {
-                        record_clone.push(item.clone());
-                    }
-                }
-            
-                let succ_node = self.get_succ().await;
-                if !succ_node.is_none(){
-                    if succ_node.unwrap().id == *header{
-                        // If this is the original sender, reply with the accumulated data
+            MsgData::Repair { key, origin, kind, hops_left, forward_back } => {
+                let record = self.records.read().await.get(key).cloned();
+                let k = self.get_current_k().await;
+                match record {
+                    Some(found) if found.verify() => {
+                        let ack_msg = Message::new(
+                            MsgType::AckRepair,
+                            None,
+                            &MsgData::AckRepair { item: found.clone() }
+                        );
+                        origin.send_msg(&ack_msg).await;
+
+                        if *kind != QueryKind::Value {
+                            self.answer_query(client, &found.title, Some(&found), *kind).await;
+                            return;
+                        }
                         let user_msg = Message::new(
                             MsgType::Reply,
                             None,
-                            &MsgData::Reply { reply: utils::format_queryall_msg(&record_clone)}
+                            &MsgData::Reply { reply: format!("Found (🔑 {} : 🔒{}, 🕰️ {}) [repaired]", found.title, found.value, found.timestamp) }
                         );
                         client.unwrap().send_msg(&user_msg).await;
                     }
-                    else {
-                        // Otherwise, forward the query along the ring
-                        let fw_msg = Message::new(
-                            MsgType::FwQueryAll,
+                    Some(found) if *hops_left > 0 && ((*forward_back && found.replica_idx > 0) ||
+                                                      (!*forward_back && found.replica_idx < k)) => {
+                        // this copy is bad too, keep walking the chain
+                        let repair_msg = Message::new(
+                            MsgType::Repair,
                             client,
-                            &MsgData::FwQueryAll { record_list: record_clone, header: *header }
+                            &MsgData::Repair { key: *key, origin: *origin, kind: *kind, hops_left: hops_left - 1, forward_back: *forward_back }
                         );
-            
-                        self.send_msg(succ_node, &fw_msg).await;
+                        let next = if *forward_back { self.get_prev().await } else { self.get_succ().await };
+                        self.send_msg(next, &repair_msg).await;
+                    }
+                    _ => {
+                        let user_msg = Message::new(
+                            MsgType::Reply,
+                            None,
+                            &MsgData::Reply { reply: format!("Error: 🔑 {} is corrupted and no valid replica was found", key) }
+                        );
+                        client.unwrap().send_msg(&user_msg).await;
                     }
                 }
-
             }
-
-            _ => self.print_debug_msg(&format!("unexpected data - {:?}", data))
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
         }
     }
-    
 
-    async fn handle_delete(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    async fn handle_ack_repair(&self, data:&MsgData) {
         match data {
-            MsgData::Delete {key} => {
-                let key_hash = HashFunc(key);
-                let cons = self.get_consistency().await;
-                match cons {
-                    Consistency::Eventual => {
-                        /* Any replica manager can delete and inform client immediately.
-                           If delete initiated from an intermediate node, the propagation must 
-                           be delivered to both directions. To avoid ping-pong messaged each 
-                           forwarded message will then follow only one direction, denoted by the 
-                           special field 'forward_back' */
-                        if self.is_replica_manager(&key_hash).await >= 0 {
+            MsgData::AckRepair { item } => {
+                let key = item.key_hash();
     self.print_debug_msg("Acquiring write lock on records...");
-                            let res = self.records.write().await.remove(&key_hash);
+                let mut record_writer = self.records.write().await;
     self.print_debug_msg("Write lock released on records.");
-                            match res {
-                                Some(found) => {
-                                    let user_msg = Message::new(
-                                        MsgType::Reply,
-                                        None,
-                                        &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} sucessfully!", found.title, found.value, found.timestamp) }
-                                    );
-                                    client.unwrap().send_msg(&user_msg).await;
-
-                                    // propagate to other replica managers if needed (async)
-                                    if found.replica_idx < self.get_current_k().await {
-                                        let fw_next = Message::new(
-                                            MsgType::FwDelete,
-                                            None,
-                                            &MsgData::FwDelete { key: key_hash, forward_back: false }
-                                        );
-                                        self.send_msg(self.get_succ().await, &fw_next).await;
-                                    }
-                                    if found.replica_idx > 0 {
-                                        let fw_back = Message::new(
-                                            MsgType::FwDelete,
-                                            None,
-                                            &MsgData::FwDelete { key: key_hash, forward_back: true }
-                                        );
-                                        self.send_msg(self.get_prev().await, &fw_back).await;
-                                    }
-                                }
-
-                                _ => {
-                                    let user_msg =Message::new(
-                                        MsgType::Reply,
-                                        None,
-                                        &MsgData::Reply { reply: format!("Error: Title 🔑 {} doesn't exist!", key) }
-                                    );
-                                    client.unwrap().send_msg(&user_msg).await;
-                                }
-                            }
+                // keep the local replica index, only the payload is replaced
+                if let Some(exist) = record_writer.get_mut(&key) {
+                    self.track_bytes(exist.value.len(), item.value.len());
+                    exist.value = item.value.clone();
+                    exist.timestamp = item.timestamp;
+                    exist.writer = item.writer;
+                    exist.seal();
+                    self.print_debug_msg(&format!("Repaired key {} from a valid replica", key));
+                    drop(record_writer);
+                    self.index_time(key, item.timestamp).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
 
-                        } else {
-                            // just forward to the primary node direction
-                            let fw_del = Message::new(
-                                MsgType::Delete,
-                                client,
-                                &MsgData::Delete { key: key.clone() }
-                            );
-                            if self.maybe_next_responsible(&key_hash).await {
-                                self.send_msg(self.get_succ().await, &fw_del).await;
-                            } else {
-                                self.send_msg(self.get_prev().await, &fw_del).await;
-                            }
-                        }
+    // replies to the non-value query kinds, value replies keep their own wording per model
+    /* Read-through for Eventual reads this node would only forward. On a miss the query is
+        sent on with this node as the client, the answer comes back as a separate request
+        carrying the same trace id, is cached and then relayed to the real client.
+        Without an answer in time the query is forwarded as usual */
+    async fn cached_query(&self, client:Option<&NodeInfo>, key:&str, key_hash:HashType, next_node:Option<NodeInfo>) {
+        let cached = self.read_cache.write().await.get(&key_hash);
+        let record = match cached {
+            Some(record) => {
+                self.print_debug_msg(&format!("Read cache hit for {}", key));
+                record
+            }
+            None => {
+                let trace_id = messages::current_trace().unwrap_or_else(Uuid::new_v4);
+                let (sender, receiver) = oneshot::channel();
+                self.awaiting.write().await.insert(trace_id, sender);
+                let fw_query = Message::new(
+                    MsgType::FwQuery,
+                    Some(&self.get_info()),
+                    &MsgData::FwQuery { key: key_hash, kind: QueryKind::Record, prefer: ReadPreference::Nearest, consistency: Some(Consistency::Eventual) }
+                );
+                self.send_msg(next_node, &fw_query).await;
+                let answer = tokio::time::timeout(self.ring_timeout(crate::GATHER_TIMEOUT_MS).await, receiver).await;
+                self.awaiting.write().await.remove(&trace_id);
+                match answer {
+                    Ok(Ok(MsgData::Record { record, .. })) => {
+                        self.read_cache.write().await.put(key_hash, record.clone());
+                        record
+                    }
+                    _ => {
+                        let fw_query = Message::new(
+                            MsgType::FwQuery,
+                            client,
+                            &MsgData::FwQuery { key: key_hash, kind: QueryKind::Value, prefer: ReadPreference::Nearest, consistency: Some(Consistency::Eventual) }
+                        );
+                        self.send_msg(next_node, &fw_query).await;
+                        return;
                     }
+                }
+            }
+        };
+        let reply = match record {
+            Some(found) => format!("Found data: (🔑 {} : 🔒{}, 🕰️ {})", found.title, found.value, found.timestamp),
+            None => format!("Error: 🔑{} doesn't exist", key)
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
 
-                    Consistency::Chain => {
+    /* There is no authoritative member count (gossip only converges), so the ring is
+        counted by an overlay sweep answered to this node. Returns the reply for a
+        rejected join, None when the node may join. Joins admitted concurrently may
+        still overshoot the cap by the number of joins in flight. */
+    async fn check_ring_room(&self, new_node:&NodeInfo, max_nodes:usize) -> Option<String> {
+        let succ = self.get_succ().await;
+        let peers = if succ.is_none_or(|node| node.id == self.get_id()) {
+            vec![self.get_info()]
+        } else {
+            let trace_id = messages::current_trace().unwrap_or_else(Uuid::new_v4);
+            let (sender, receiver) = oneshot::channel();
+            self.awaiting.write().await.insert(trace_id, sender);
+            let sweep = Message::new(
+                MsgType::FwOverlay,
+                Some(&self.get_info()),
+                &MsgData::FwOverlay { peers: vec![self.get_info()], gather: false, counts: Vec::new(), structured: true, bytes: Vec::new() }
+            );
+            self.send_msg(succ, &sweep).await;
+            let answer = tokio::time::timeout(self.ring_timeout(crate::GATHER_TIMEOUT_MS).await, receiver).await;
+            self.awaiting.write().await.remove(&trace_id);
+            match answer {
+                Ok(Ok(MsgData::Ring { peers, .. })) => peers,
+                _ => return Some(format!("Error: could not count the ring to admit node {}, try again", new_node))
+            }
+        };
+        // a node already in the ring is rejoining and takes no extra place
+        if peers.len() >= max_nodes && !peers.iter().any(|peer| peer.id == new_node.id) {
+            return Some(format!("Error: ring full, {} of {} nodes joined, node {} rejected", peers.len(), max_nodes, new_node));
+        }
+        None
+    }
+
+    // hands a reply addressed to this node to the request that is waiting for it
+    /* Sends a client request to this node itself, as the client, and waits for the reply
+        the ring sends back. Used to run a sequence of ordinary operations one after another */
+    async fn request_own(&self, msg_type:MsgType, data:&MsgData) -> Option<MsgData> {
+        self.request_own_within(msg_type, data, self.ring_timeout(crate::GATHER_TIMEOUT_MS).await).await
+    }
+
+    // base_ms scaled by the ring size known from gossip
+    async fn ring_timeout(&self, base_ms:u64) -> Duration {
+        utils::ring_timeout(base_ms, self.members.read().await.len() + 1)
+    }
+
+    async fn request_own_within(&self, msg_type:MsgType, data:&MsgData, limit:Duration) -> Option<MsgData> {
+        let trace_id = messages::current_trace().unwrap_or_else(Uuid::new_v4);
+        let (sender, receiver) = oneshot::channel();
+        self.awaiting.write().await.insert(trace_id, sender);
+        let request = Message::new(msg_type, Some(&self.get_info()), data);
+        self.get_info().send_msg(&request).await;
+        let answer = tokio::time::timeout(limit, receiver).await;
+        self.awaiting.write().await.remove(&trace_id);
+        match answer {
+            Ok(Ok(data)) => Some(data),
+            _ => None
+        }
+    }
+
+    async fn handle_rename(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* The keys may live on different nodes, so the rename is a sequence of ordinary
+        operations under the ring consistency, coordinated here: both keys are read, the
+        value is inserted at 'to' waiting for its replicas, and only then is 'from' deleted.
+        A reader sees the old key, the new one or briefly both, never neither. A failure
+        before the delete leaves 'from' untouched, a failed delete leaves both keys holding
+        the value, which the reply reports so the delete can be retried. The delete only
+        goes through while 'from' holds the version copied, a write in between backs the
+        rename out by removing the copy again */
+        match data {
+            MsgData::Rename { from, to } => {
+                let reply = self.rename(from, to).await;
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn rename(&self, from:&str, to:&str) -> String {
+        if from == to || to.trim().is_empty() {
+            return format!("Error: BadRequest: cannot rename 🔑 {} to 🔑 {}", from, to);
+        }
+        let read = |key:&str| MsgData::Query { key: key.to_string(), kind: QueryKind::Record, prefer: ReadPreference::Primary,
+                                              consistency: None, colocate_with: None };
+        let item = match self.request_own(MsgType::Query, &read(from)).await {
+            Some(MsgData::Record { record: Some(item), .. }) => item,
+            Some(MsgData::Record { record: None, .. }) => return format!("Error: 🔑 {} doesn't exist", from),
+            _ => return format!("Error: rename aborted, 🔑 {} could not be read", from)
+        };
+        // an insert on an existing key appends to it, so the new key must be free
+        match self.request_own(MsgType::Query, &read(to)).await {
+            Some(MsgData::Record { record: None, .. }) => (),
+            Some(MsgData::Record { record: Some(_), .. }) => return format!("Error: 🔑 {} already exists", to),
+            _ => return format!("Error: rename aborted, 🔑 {} could not be read", to)
+        }
+
+        // the copy keeps the version of the value read, so both deletes below can be made conditional on it
+        let insert = MsgData::Insert { key: to.to_string(), value: item.value.clone(), wait_for_replication: true,
+                                       consistency: None, colocate_with: None, max_value_len: item.max_value_len, create_only: true, json: false,
+                                       copy_of: Some(item.clone()) };
+        match self.request_own(MsgType::Insert, &insert).await {
+            Some(MsgData::Reply { reply }) if !reply.starts_with("Error") => (),
+            Some(MsgData::Reply { reply }) => return format!("Error: rename aborted, 🔑 {} is unchanged: {}", from, reply),
+            // the insert may still land, both keys then hold the value
+            _ => return format!("Error: rename of 🔑 {} timed out while writing 🔑 {}, check both keys", from, to)
+        }
+
+        /* A write to 'from' between the read and here would be lost with it,
+           so 'from' is only deleted while it still holds the version that was copied */
+        let delete = |key:&str| MsgData::Delete { key: key.to_string(), consistency: None, colocate_with: None, if_version: Some(item.version()) };
+        match self.request_own(MsgType::Delete, &delete(from)).await {
+            Some(MsgData::Reply { reply }) if !reply.starts_with("Error") =>
+                format!("Renamed 🔑 {} to 🔑 {} (🔒{})", from, to, item.value),
+            Some(MsgData::Reply { reply }) if reply.starts_with("Error: Conflict") => {
+                match self.request_own(MsgType::Delete, &delete(to)).await {
+                    Some(MsgData::Reply { reply }) if !reply.starts_with("Error") =>
+                        format!("Error: Conflict: rename aborted, 🔑 {} changed while it was copied and is unchanged", from),
+                    _ => format!("Error: Conflict: 🔑 {} changed while it was copied and the copy at 🔑 {} could not be removed, check both keys", from, to)
+                }
+            }
+            _ => format!("Error: 🔑 {} was copied to 🔑 {} but could not be deleted, both hold the value; retry the delete", from, to)
+        }
+    }
+
+    async fn handle_reply(&self, data:&MsgData) {
+        let waiting = match messages::current_trace() {
+            Some(trace_id) => self.awaiting.write().await.remove(&trace_id),
+            None => None
+        };
+        match waiting {
+            Some(sender) => { let _ = sender.send(data.clone()); }
+            None => self.print_debug_msg(&format!("Dropping unexpected reply - {:?}", data))
+        }
+    }
+
+    async fn answer_query(&self, client:Option<&NodeInfo>, key:&str, record:Option<&Item>, kind:QueryKind) {
+        let data = match kind {
+            QueryKind::Exists => MsgData::Reply { reply: utils::format_exists_msg(key, record) },
+            _ => MsgData::Record { key: key.to_string(), record: record.cloned() }
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &data
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_query_all(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::QueryAll { parallel, restart_on_churn } => {
+                let succ_node = self.get_succ().await;
+                if succ_node.unwrap().id == self.get_id() {
+                    // node is alone 
+                    let user_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::Reply { reply: utils::format_queryall_msg(&self.query_all_items(&[]).await) }
+                    );
+                    client.unwrap().send_msg(&user_msg).await;
+                    return;
+                }
+
+                if *parallel {
+                    // collect the members first, their records are fetched at once when the walk returns
+                    let fw_msg = Message::new(
+                        MsgType::FwOverlay,
+                        client,
+                        &MsgData::FwOverlay { peers: vec![self.get_info()], gather: true, counts: Vec::new(), structured: false, bytes: Vec::new() }
+                    );
+                    self.send_msg(succ_node, &fw_msg).await;
+                    return;
+                }
+
+                self.start_query_all_sweep(client, *restart_on_churn, 0).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data)),
+        }
+    }
+
+    // the sweep starts at this node, which is also where it is expected to end
+    async fn start_query_all_sweep(&self, client:Option<&NodeInfo>, restart_on_churn:bool, restarts:u8) {
+        let fw_msg = Message::new(
+            MsgType::FwQueryAll,
+            client,
+            &MsgData::FwQueryAll {
+                record_list: self.query_all_items(&[]).await,
+                header: self.get_id(),
+                visited: vec![self.get_id()],
+                churned: false,
+                restart_on_churn,
+                restarts
+            }
+        );
+        self.send_msg(self.get_succ().await, &fw_msg).await;
+    }
+
+    /* This node's barrier item followed by its committed primary copies. A key that moved
+        during the sweep may already have been collected from its old holder, it is kept once */
+    async fn query_all_items(&self, collected:&[Item]) -> Vec<Item> {
+        let seen: HashSet<&String> = collected.iter()
+            .filter(|item| item.title != "__nodeID__")
+            .map(|item| &item.title)
+            .collect();
+    self.print_debug_msg("Acquiring read lock on records...");
+        let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+        self.print_debug_msg(&format!("All records: {:?}", records_reader));
+        let mut res = Vec::new();
+        // works as barrier for printing items per node
+        let node_item = Item::new (
+            "__nodeID__",
+            &self.get_id().to_string(),
+            0,
+            false
+        );
+        res.push(node_item);
+        for (_key, item) in records_reader.iter() {
+            if item.replica_idx == 0 && !item.pending && !seen.contains(&item.title) {
+                res.push(item.clone());
+            }
+        }
+        res
+    }
+
+    async fn handle_fw_query_all(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* The sweep follows successor pointers, so a join or depart while it runs can make it
+        skip nodes or miss the header. Each hop checks that its predecessor is the node the
+        sweep came from, and the sweep ends at the header or at the first node it visited before.
+        A changed ring either restarts the sweep here or is flagged in the reply */
+        match data {
+            MsgData::FwQueryAll { record_list, header, visited, churned, restart_on_churn, restarts } => {
+                if visited.contains(&self.get_id()) {
+                    // the header departed and the ring closed behind it
+                    self.reply_query_all(client, record_list, true).await;
+                    return;
+                }
+                let came_from = visited.last().copied();
+                let churned = *churned || self.get_prev().await.map(|prev| prev.id) != came_from;
+                if churned && *restart_on_churn && *restarts < crate::QUERY_ALL_RESTARTS {
+                    self.print_debug_msg(&format!("Ring changed during query * sweep, restarting from {}", self.get_id()));
+                    self.start_query_all_sweep(client, true, restarts + 1).await;
+                    return;
+                }
+
+                let mut record_clone = record_list.clone();
+                record_clone.extend(self.query_all_items(record_list).await);
+                let mut visited = visited.clone();
+                visited.push(self.get_id());
+            
+                let succ_node = self.get_succ().await;
+                if !succ_node.is_none(){
+                    let succ_id = succ_node.unwrap().id;
+                    if succ_id == *header || visited.contains(&succ_id) {
+                        // If this is the original sender, reply with the accumulated data
+                        self.reply_query_all(client, &record_clone, churned || succ_id != *header).await;
+                    }
+                    else {
+                        // Otherwise, forward the query along the ring
+                        let fw_msg = Message::new(
+                            MsgType::FwQueryAll,
+                            client,
+                            &MsgData::FwQueryAll { record_list: record_clone, header: *header, visited,
+                                                   churned, restart_on_churn: *restart_on_churn, restarts: *restarts }
+                        );
+            
+                        self.send_msg(succ_node, &fw_msg).await;
+                    }
+                }
+
+            }
+
+            _ => self.print_debug_msg(&format!("unexpected data - {:?}", data))
+        }
+    }
+
+    async fn reply_query_all(&self, client:Option<&NodeInfo>, records:&Vec<Item>, churned:bool) {
+        let mut reply = utils::format_queryall_msg(records);
+        if churned {
+            reply.push_str("\n⚠️ The ring changed during the sweep, the result may be slightly stale\n");
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+    
+
+    // committed primary copies held here
+    async fn primary_records(&self) -> Vec<Item> {
+    self.print_debug_msg("Acquiring read lock on records...");
+        let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+        records_reader.values()
+            .filter(|item| item.replica_idx == 0 && !item.pending)
+            .cloned()
+            .collect()
+    }
+
+    async fn primary_count(&self) -> usize {
+    self.print_debug_msg("Acquiring read lock on records...");
+        let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+        records_reader.values().filter(|item| item.replica_idx == 0 && !item.pending).count()
+    }
+
+    // primary copies headed by the barrier item used when printing per node
+    async fn local_primary_records(&self) -> Vec<Item> {
+        let mut res = vec![Item::new(
+            "__nodeID__",
+            &self.get_id().to_string(),
+            0,
+            false
+        )];
+        res.extend(self.primary_records().await);
+        res
+    }
+
+    async fn handle_node_records(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Walks the successors until the node with the target id, which replies with its
+        primary records as a typed list. Passing the target without meeting it means
+        no node has that id */
+        match data {
+            MsgData::NodeRecords { target } => {
+                let reply = if *target == self.get_id() {
+                    MsgData::Records { node: self.get_info(), records: self.primary_records().await }
+                } else {
+                    let succ_node = self.get_succ().await;
+                    match succ_node {
+                        Some(succ) if succ.id != self.get_id()
+                            && (succ.id == *target || utils::in_arc(*target, succ.id, self.get_id())) => {
+                            let fw_msg = Message::new(
+                                MsgType::NodeRecords,
+                                client,
+                                &MsgData::NodeRecords { target: *target }
+                            );
+                            self.send_msg(succ_node, &fw_msg).await;
+                            return;
+                        }
+                        _ => MsgData::Reply { reply: format!("Error: no node with id {} in the ring", target) }
+                    }
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &reply
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn gather_records(&self, client:Option<&NodeInfo>, peers:&[NodeInfo]) {
+    /* Scatter-gather variant of QueryAll. The members were collected by an overlay walk,
+        so every other node is asked for its primary records concurrently and the replies
+        are merged here in ring order. Nodes that fail to answer are listed after the records */
+        let mut fetches = Vec::new();
+        for peer in peers.iter().skip(1) {
+            let peer = *peer;
+            fetches.push((peer, tokio::spawn(messages::SENDER.scope(self.get_info(), async move { peer.fetch_records().await }))));
+        }
+
+        let mut res = self.local_primary_records().await;
+        let mut missing = Vec::new();
+        for (peer, fetch) in fetches {
+            match fetch.await {
+                Ok(Some(records)) => res.extend(records),
+                _ => missing.push(peer)
+            }
+        }
+
+        let mut reply = utils::format_queryall_msg(&res);
+        for peer in missing.iter() {
+            reply.push_str(&format!("\n⚠️ Node {} did not return its records", peer));
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_transfer(&self, data:&MsgData) {
+        match data {
+            MsgData::Transfer { items, prefetch: false, transfer } => {
+                self.print_debug_msg("Acquiring write lock on incoming...");
+                self.incoming.write().await.entry(*transfer).or_default().extend(items.iter().cloned());
+                self.print_debug_msg("Write lock acquired on incoming.");
+            }
+            MsgData::Transfer { items, prefetch: true, .. } => {
+                self.print_debug_msg("Acquiring write lock on prefetched...");
+                self.prefetched.write().await.extend(items.iter().cloned());
+                self.print_debug_msg("Write lock acquired on prefetched.");
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_local_records(&self, stream:&mut TcpStream) {
+        let records = self.local_primary_records().await;
+        let reply = Message::new(
+            MsgType::LocalRecords,
+            None,
+            &MsgData::LocalRecords { records }
+        );
+        let jsonify = reply.to_wire();
+        if let Err(e) = stream.write_all(jsonify.as_bytes()).await {
+            eprintln!("❌ Failed to return local records - {}", e);
+        }
+    }
+
+    /* Clients that cannot accept connections, e.g. behind NAT, wrap their request in a Proxy.
+        The node sends the request to itself as the client, like request_own, and writes the
+        reply back on the connection the proxy arrived on. Only requests answered by a single
+        reply can be proxied */
+    async fn handle_proxy(&self, stream:&mut TcpStream, data:&MsgData) {
+        let answer = match data {
+            MsgData::Proxy { msg_type, data } => {
+                let limit = self.ring_timeout(crate::PROXY_REPLY_TIMEOUT_MS).await;
+                self.request_own_within(*msg_type, data, limit).await.unwrap_or_else(|| MsgData::Reply {
+                    reply: format!("Error: Timeout: no reply to the {:?} request within {:?}", msg_type, limit)
+                })
+            }
+            _ => {
+                self.print_debug_msg(&format!("Unexpected data - {:?}", data));
+                return;
+            }
+        };
+        let reply = Message::new(
+            MsgType::Reply,
+            None,
+            &answer
+        );
+        let jsonify = reply.to_wire();
+        if let Err(e) = stream.write_all(jsonify.as_bytes()).await {
+            eprintln!("❌ Failed to return the proxied reply - {}", e);
+            return;
+        }
+        /* Bytes the client sent after the JSON, such as its trailing newline, may still be unread.
+           Dropping the stream with them pending resets the connection and the client loses the
+           reply, so the write half is closed first and the rest is drained until the client hangs up */
+        if let Err(e) = stream.shutdown().await {
+            self.print_debug_msg(&format!("Failed to close the proxied reply - {}", e));
+            return;
+        }
+        let deadline = Instant::now() + Duration::from_millis(crate::REQUEST_READ_TIMEOUT_MS);
+        let mut buffer = [0; 64];
+        while let Ok(Ok(n)) = tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    async fn handle_multi_get(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::MultiGet { keys, snapshot_ts } => {
+                // without an explicit snapshot the read is taken as of now
+                let snapshot_ts = snapshot_ts.unwrap_or_else(Utc::now);
+                self.collect_multi_get(client, keys, &[], snapshot_ts, self.get_id(), false).await;
+            }
+            // same ring walk, answered with plain values instead of a snapshot
+            MsgData::MultiQuery { keys } => {
+                self.collect_multi_get(client, keys, &[], Utc::now(), self.get_id(), true).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_fw_multi_get(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::FwMultiGet { pending, found, snapshot_ts, header, typed } => {
+                self.collect_multi_get(client, pending, found, *snapshot_ts, *header, *typed).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn collect_multi_get(&self, client:Option<&NodeInfo>, pending:&[String],
+                               found:&[(String, Option<Item>)], snapshot_ts:DateTime<Utc>, header:HashType, typed:bool) {
+    /* A single message walks the ring once starting from the header. Every node answers
+        the keys it may read under the current model: the tail for Chain, after waiting
+        for pending updates, and any replica manager otherwise. The walk stops early once
+        every key is answered and keys still unanswered at the end don't exist */
+        let cons = self.get_consistency().await;
+        let k = self.get_current_k().await;
+        let mut found = found.to_vec();
+        let mut remaining = Vec::new();
+        for key in pending.iter() {
+            let key_hash = HashFunc(key);
+            let replica_idx = self.is_replica_manager(&key_hash).await;
+            let answers_here = match cons {
+                Consistency::Chain => replica_idx == k as i16,
+                _ => replica_idx >= 0
+            };
+            if matches!(cons, Consistency::Eventual) {
+                self.count_read(answers_here);
+            }
+            if !answers_here {
+                remaining.push(key.clone());
+                continue;
+            }
+            if let Consistency::Chain = cons {
+                self.sleep_on_updates(key_hash).await;
+            }
+    self.print_debug_msg("Acquiring read lock on records...");
+            let record = self.records.read().await.get(&key_hash).cloned();
+    self.print_debug_msg("Read lock acquired on records.");
+            found.push((key.clone(), record));
+        }
+
+        let succ_node = self.get_succ().await;
+        if remaining.is_empty() || succ_node.is_none() || succ_node.unwrap().id == header {
+            found.extend(remaining.into_iter().map(|key| (key, None)));
+            let reply = if typed {
+                MsgData::MultiQueryResult {
+                    results: found.into_iter().map(|(key, record)| (key, record.map(|item| item.value))).collect()
+                }
+            } else {
+                MsgData::Reply { reply: utils::format_multiget_msg(&found, &snapshot_ts) }
+            };
+            let user_msg = Message::new(MsgType::Reply, None, &reply);
+            client.unwrap().send_msg(&user_msg).await;
+            return;
+        }
+
+        let fw_msg = Message::new(
+            MsgType::FwMultiGet,
+            client,
+            &MsgData::FwMultiGet { pending: remaining, found, snapshot_ts, header, typed }
+        );
+        self.send_msg(succ_node, &fw_msg).await;
+    }
+
+    /* Membership is spread epidemically: every GOSSIP_INTERVAL_MS a linked node swaps a random
+        sample of its view with one random member, which answers with a sample of its own.
+        Views converge on the full ring without the bootstrap being reachable.
+        Members that cannot be contacted are dropped, the next exchange may teach them again */
+    async fn gossip_periodically(&self) {
+        loop {
+            sleep(Duration::from_millis(crate::GOSSIP_INTERVAL_MS)).await;
+            if !self.get_status() || !self.is_linked().await {
+                continue;
+            }
+            // neighbours are always known members
+            let neighbours = vec![self.get_prev().await.unwrap(), self.get_succ().await.unwrap()];
+            self.learn_members(&neighbours).await;
+
+            let target = {
+                let members_reader = self.members.read().await;
+                members_reader.values().cloned().collect::<Vec<NodeInfo>>().choose(&mut *self.rng.lock().unwrap()).cloned()
+            };
+            if let Some(peer) = target {
+                let gossip_msg = Message::new(
+                    MsgType::Gossip,
+                    None,
+                    &MsgData::Gossip { from: self.get_info(), peers: self.sample_members().await, reply: true }
+                );
+                if peer.send_msg(&gossip_msg).await.is_none() {
+                    self.print_debug_msg(&format!("Dropping unreachable member {}", peer));
+                    self.members.write().await.remove(&peer.id);
+                }
+            }
+        }
+    }
+
+    /* Entries of resolved keys stay in the pendings map after their ack, and a write whose
+        ack never arrives keeps its readers blocked. Both are dropped here: resolved entries
+        nobody waits on silently, entries idle for the expiry by waking their readers with an error */
+    async fn reap_pendings_periodically(&self) {
+        let (_, expiry) = self.pendings_bound;
+        loop {
+            sleep(expiry / 2).await;
+            // collected first, records are never locked while holding the pendings lock
+            let still_pending: HashSet<HashType> = self.records.read().await.iter()
+                .filter(|(_, item)| item.pending)
+                .map(|(key, _)| *key)
+                .collect();
+            let mut pending_writer = self.pendings.write().await;
+            let before = pending_writer.len();
+            let mut expired = 0;
+            pending_writer.retain(|key, wait| {
+                if wait.idle_for() >= expiry {
+                    if wait.waiters.load(Ordering::SeqCst) > 0 {
+                        wait.expire();
+                        expired += 1;
+                    }
+                    return false;
+                }
+                still_pending.contains(key) || wait.waiters.load(Ordering::SeqCst) > 0
+            });
+            if before != pending_writer.len() {
+                self.print_debug_msg(&format!("Reaped {} pendings entries, {} with readers still blocked", before - pending_writer.len(), expired));
+            }
+        }
+    }
+
+    /* Only the requests a client can send count against the limit. Forwards, acks and
+        repairs travel between nodes alone and the ring depends on them, so they always pass,
+        as do client requests a known ring member passes on. The member is the sender the
+        message names, taken only when it matches the address the connection came from.
+        Nothing proves that name, so a client on the host of a member can claim to be it */
+    async fn admit_request(&self, ip:std::net::IpAddr, msg_type:MsgType, sender:Option<&NodeInfo>) -> bool {
+        if msg_type.is_peer_only() || !self.rate_limiter.read().await.is_enabled() {
+            return true;
+        }
+        let sender = sender.filter(|sender| std::net::IpAddr::V4(sender.get_ip()) == ip);
+        let is_peer = |info:&NodeInfo| sender.is_some_and(|sender| sender.get_ip() == info.get_ip() && sender.get_port() == info.get_port());
+        let neighbours = [self.get_prev().await, self.get_succ().await, self.bootstrap, Some(self.get_info())];
+        if neighbours.iter().flatten().any(is_peer) || self.members.read().await.values().any(is_peer) {
+            return true;
+        }
+        self.rate_limiter.write().await.allow(ip)
+    }
+
+    // records are never locked here, callers may still hold them
+    async fn index_time(&self, key:HashType, timestamp:DateTime<Utc>) {
+        self.by_time.write().await.add(timestamp, key);
+    }
+
+    /* Answers from the time index. A hit only counts while the record still exists
+        with that timestamp, other hits are stale and dropped on the way. When stale
+        entries outnumber the records the index is rebuilt from them */
+    async fn records_written_between(&self, after:DateTime<Utc>, before:DateTime<Utc>) -> Vec<Item> {
+        let candidates = self.by_time.read().await.range(after, before);
+        let mut found = Vec::new();
+        let mut stale = Vec::new();
+        let held = {
+    self.print_debug_msg("Acquiring read lock on records...");
+            let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+            for (timestamp, key) in candidates {
+                match records_reader.get(&key) {
+                    Some(item) if item.timestamp == timestamp => {
+                        if item.replica_idx == 0 && !item.pending {
+                            found.push(item.clone());
+                        }
+                    }
+                    _ => stale.push((timestamp, key))
+                }
+            }
+            records_reader.len()
+        };
+        let needs_rebuild = {
+            let mut index_writer = self.by_time.write().await;
+            for (timestamp, key) in stale.iter() {
+                index_writer.remove(timestamp, key);
+            }
+            index_writer.len() > 2 * held + 1024
+        };
+        if needs_rebuild {
+            // the records are locked first, as everywhere else
+            let records_reader = self.records.read().await;
+            self.by_time.write().await.rebuild(records_reader.iter());
+        }
+        found
+    }
+
+    async fn handle_time_range_query(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* One message walks the ring from the node the client asked, like a sequential
+        query *, but every node only adds its primary records written inside the window */
+        let (after, before, mut found, header) = match data {
+            MsgData::TimeRangeQuery { after, before } => (*after, *before, Vec::new(), self.get_id()),
+            MsgData::FwTimeRangeQuery { after, before, found, header } => (*after, *before, found.clone(), *header),
+            _ => {
+                self.print_debug_msg(&format!("Unexpected data - {:?}", data));
+                return;
+            }
+        };
+        found.extend(self.records_written_between(after, before).await);
+
+        let succ_node = self.get_succ().await;
+        if succ_node.is_none() || succ_node.unwrap().id == header {
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: utils::format_time_range_msg(&found, &after, &before) }
+            );
+            client.unwrap().send_msg(&user_msg).await;
+            return;
+        }
+        let fw_msg = Message::new(
+            MsgType::FwTimeRangeQuery,
+            client,
+            &MsgData::FwTimeRangeQuery { after, before, found, header }
+        );
+        self.send_msg(succ_node, &fw_msg).await;
+    }
+
+    async fn handle_key_count(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* A cheap alternative to counting the records of a query * sweep: the message only
+        carries one sketch around the ring, every node merges its own into it */
+        let (mut sketch, header, nodes) = match data {
+            MsgData::KeyCount { } => (HyperLogLog::new(), self.get_id(), 0),
+            MsgData::FwKeyCount { sketch, header, nodes } => (sketch.clone(), *header, *nodes),
+            _ => {
+                self.print_debug_msg(&format!("Unexpected data - {:?}", data));
+                return;
+            }
+        };
+        sketch.merge(&*self.key_sketch.read().await);
+        let nodes = nodes + 1;
+
+        let succ_node = self.get_succ().await;
+        if succ_node.is_none() || succ_node.unwrap().id == header {
+            let reply = format!("About {:.0} distinct keys inserted across {} node(s) (HyperLogLog, ±{:.1}%, deleted keys still counted)",
+                                sketch.estimate(), nodes, 100.0 * HyperLogLog::std_error());
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply }
+            );
+            client.unwrap().send_msg(&user_msg).await;
+            return;
+        }
+        let fw_msg = Message::new(
+            MsgType::FwKeyCount,
+            client,
+            &MsgData::FwKeyCount { sketch, header, nodes }
+        );
+        self.send_msg(succ_node, &fw_msg).await;
+    }
+
+    async fn note_access(&self, key:HashType) {
+        if self.mem_limits.is_some() {
+            self.accessed.write().await.insert(key, Instant::now());
+        }
+    }
+
+    /* Once the stored values pass the high mark, primary keys are deleted until the low mark
+        is reached, least recently read first and never read ones before them, oldest write first.
+        Only keys this node is primary for are chosen, so every delete starts at the head of
+        its replica chain and reaches all copies like a client delete. Copies held for other
+        primaries count towards the bytes but are left to their primaries, the low mark may then
+        not be reached. Reads answered by replicas are not seen here */
+    async fn shed_periodically(&self) {
+        let (high, low) = self.mem_limits.unwrap();
+        loop {
+            sleep(Duration::from_millis(crate::MEM_CHECK_INTERVAL_MS)).await;
+            let stored = self.stored_bytes();
+            if !self.get_status() || !self.is_linked().await || stored <= high {
+                continue;
+            }
+            let mut candidates: Vec<(HashType, Option<Instant>, DateTime<Utc>, u64)> = {
+                let accessed = self.accessed.read().await;
+    self.print_debug_msg("Acquiring read lock on records...");
+                let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+                records_reader.iter()
+                    .filter(|(_, item)| item.replica_idx == 0 && !item.pending)
+                    .map(|(key, item)| (*key, accessed.get(key).copied(), item.timestamp, item.value.len() as u64))
+                    .collect()
+            };
+            candidates.sort_by_key(|(_, last_read, written, _)| (*last_read, *written));
+            let mut freed = 0;
+            let mut victims = Vec::new();
+            for (key, _, _, bytes) in candidates {
+                if stored - freed <= low {
+                    break;
+                }
+                freed += bytes;
+                victims.push(key);
+            }
+            eprintln!("Stored bytes {} above the high mark {}, shedding {} key(s) for {} bytes", stored, high, victims.len(), freed);
+            for key in victims {
+                self.evict_primary(key).await;
+            }
+            // forget the reads of keys that are gone, whatever removed them
+            let still_held: HashSet<HashType> = self.records.read().await.keys().copied().collect();
+            self.accessed.write().await.retain(|key, _| still_held.contains(key));
+        }
+    }
+
+    // deletes a primary key as a client delete entering here would, without anyone to reply to
+    async fn evict_primary(&self, key:HashType) {
+        let k = self.get_current_k().await;
+        match self.get_consistency().await {
+            Consistency::Chain if k > 0 => {
+                // logical delete here, the tail removes its copy first and the acks clear the rest
+                match self.records.write().await.get_mut(&key) {
+                    Some(exist) => exist.pending = true,
+                    None => return
+                }
+                let fw_del = Message::new(
+                    MsgType::FwDelete,
+                    None,
+                    &MsgData::FwDelete { key, forward_back: false, consistency: None }
+                );
+                self.send_msg(self.get_succ().await, &fw_del).await;
+            }
+            _ => {
+                let gone = self.records.write().await.remove(&key);
+                if let Some(gone) = gone {
+                    self.track_bytes(gone.value.len(), 0);
+                    self.notify_subscribers(key, &gone.title, None).await;
+                    if k > 0 {
+                        let fw_del = Message::new(
+                            MsgType::FwDelete,
+                            None,
+                            &MsgData::FwDelete { key, forward_back: false, consistency: Some(Consistency::Eventual) }
+                        );
+                        self.send_msg(self.get_succ().await, &fw_del).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /* Checkpoint of the replica ranges. A node can only verify what its own neighbourhood
+        implies: at most k ranges, each starting where the one before it ends, and the
+        newest one ending at the previous node. Rings of at most k + 1 nodes keep wrap-around
+        ranges that break these rules, so they are skipped. Joins and departs briefly break them
+        too while relocations are in flight, so only drift seen on two checkpoints in a row
+        is corrected, and only the newest range and the size, gaps further back are logged */
+    async fn check_ranges_periodically(&self) {
+        let mut drifted = false;
+        loop {
+            let (interval, auto_correct) = self.range_check;
+            sleep(interval).await;
+            if !self.get_status() || !self.is_linked().await {
+                continue;
+            }
+            let prev_id = self.get_prev().await.unwrap().id;
+            let max_k = self.max_replication().await as usize;
+            if self.members.read().await.len() <= max_k {
+                continue;
+            }
+            let ranges = self.get_replica_ranges().await;
+            let bounds: Vec<(HashType, HashType)> = ranges.iter().map(|range| range.get_bounds()).collect();
+
+            let mut problems = Vec::new();
+            if bounds.len() > max_k {
+                problems.push(format!("{} ranges for k = {}", bounds.len(), max_k));
+            }
+            if let Some((_, upper)) = bounds.last() {
+                if *upper != prev_id {
+                    problems.push(format!("newest range ends at {} instead of previous {}", upper, prev_id));
+                }
+            }
+            for pair in bounds.windows(2) {
+                if pair[0].1 != pair[1].0 {
+                    problems.push(format!("gap between {} and {}", pair[0].1, pair[1].0));
+                }
+            }
+
+            if problems.is_empty() {
+                drifted = false;
+                continue;
+            }
+            self.range_drift.fetch_add(1, Ordering::SeqCst);
+            eprintln!("Replica range drift on {}: {}", self.get_info(), problems.join(", "));
+            if drifted && auto_correct {
+                self.print_debug_msg("Acquiring write lock on replication...");
+                let mut replication_writer = self.replication.write().await;
+                self.print_debug_msg("Write lock acquired on replication.");
+                let ranges = &mut replication_writer.replica_ranges;
+                while ranges.get_size() > max_k {
+                    ranges.pop_head();
+                }
+                if ranges.get_size() > 0 {
+                    let (lower, _) = ranges.get_tail().get_bounds();
+                    ranges.pop_tail();
+                    ranges.insert(Range::new(lower, prev_id, false, true));
+                }
+                self.audit(&format!("ranges {:?} after range check correction", ranges));
+                drifted = false;
+            } else {
+                drifted = true;
+            }
+        }
+    }
+
+    async fn learn_members(&self, peers:&[NodeInfo]) {
+        let mut members_writer = self.members.write().await;
+        for peer in peers.iter().filter(|peer| peer.id != self.get_id()) {
+            members_writer.insert(peer.id, *peer);
+        }
+    }
+
+    // random part of the view, myself included
+    async fn sample_members(&self) -> Vec<NodeInfo> {
+        let mut sample: Vec<NodeInfo> = self.members.read().await.values().cloned().collect();
+        sample.shuffle(&mut *self.rng.lock().unwrap());
+        sample.truncate(crate::GOSSIP_FANOUT.saturating_sub(1));
+        sample.push(self.get_info());
+        sample
+    }
+
+    async fn handle_gossip(&self, data:&MsgData) {
+        match data {
+            MsgData::Gossip { from, peers, reply } => {
+                let mut learnt = peers.clone();
+                learnt.push(*from);
+                self.learn_members(&learnt).await;
+                if *reply {
+                    let gossip_msg = Message::new(
+                        MsgType::Gossip,
+                        None,
+                        &MsgData::Gossip { from: self.get_info(), peers: self.sample_members().await, reply: false }
+                    );
+                    from.send_msg(&gossip_msg).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_members(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::Members {  } => {
+                let mut members: Vec<NodeInfo> = self.members.read().await.values().cloned().collect();
+                members.push(self.get_info());
+                members.sort_by_key(|peer| peer.get_id());
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply: utils::format_members_msg(&members) }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_delete(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::Delete { key, consistency, colocate_with, if_version } => {
+                let key_hash = utils::HashKey(key, colocate_with);
+                self.read_cache.write().await.invalidate(&key_hash);
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
+                match cons {
+                    Consistency::Eventual => {
+                        /* Any replica manager can delete and inform client immediately.
+                           If delete initiated from an intermediate node, the propagation must 
+                           be delivered to both directions. To avoid ping-pong messaged each 
+                           forwarded message will then follow only one direction, denoted by the 
+                           special field 'forward_back' */
+                        // a conditional delete is checked against the primary copy only
+                        let replica = self.is_replica_manager(&key_hash).await;
+                        if replica == 0 || (replica > 0 && if_version.is_none()) {
+                            if self.refuse_if_read_only(client).await {
+                                return;
+                            }
+    self.print_debug_msg("Acquiring write lock on records...");
+                            let res = match self.remove_if_version(&key_hash, if_version).await {
+                                Ok(res) => res,
+                                Err(_) => {
+                                    self.refuse_changed(client, key).await;
+                                    return;
+                                }
+                            };
+    self.print_debug_msg("Write lock released on records.");
+                            match res {
+                                Some(found) => {
+                                    self.track_bytes(found.value.len(), 0);
+                                    if found.replica_idx == 0 {
+                                        self.notify_subscribers(key_hash, &found.title, None).await;
+                                    }
+                                    // the other copies are removed after the reply
+                                    let user_msg = Message::new(
+                                        MsgType::Reply,
+                                        None,
+                                        &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} sucessfully! [replicas_removed: 1]", found.title, found.value, found.timestamp) }
+                                    );
+                                    client.unwrap().send_msg(&user_msg).await;
+
+                                    // propagate to other replica managers if needed (async)
+                                    if found.replica_idx < self.get_current_k().await {
+                                        let fw_next = Message::new(
+                                            MsgType::FwDelete,
+                                            None,
+                                            &MsgData::FwDelete { key: key_hash, forward_back: false, consistency: *consistency }
+                                        );
+                                        self.send_msg(self.get_succ().await, &fw_next).await;
+                                    }
+                                    if found.replica_idx > 0 {
+                                        let fw_back = Message::new(
+                                            MsgType::FwDelete,
+                                            None,
+                                            &MsgData::FwDelete { key: key_hash, forward_back: true, consistency: *consistency }
+                                        );
+                                        self.send_msg(self.get_prev().await, &fw_back).await;
+                                    }
+                                }
+
+                                _ => {
+                                    let user_msg =Message::new(
+                                        MsgType::Reply,
+                                        None,
+                                        &MsgData::Reply { reply: format!("Error: Title 🔑 {} doesn't exist!", key) }
+                                    );
+                                    client.unwrap().send_msg(&user_msg).await;
+                                }
+                            }
+
+                        } else {
+                            // just forward to the primary node direction
+                            let fw_del = Message::new(
+                                MsgType::Delete,
+                                client,
+                                &MsgData::Delete { key: key.clone(), consistency: *consistency, colocate_with: colocate_with.clone(), if_version: *if_version }
+                            );
+                            self.forward_to_primary(client, &key_hash, &fw_del).await;
+                        }
+                    }
+
+                    Consistency::Chain => {
                         /* Only the primary node can perform the first 'logical' delete request.
                             by setting 'pending' to true. Forwarding happens as in insert. */
                             if self.is_responsible(&key_hash).await {
+                                if self.refuse_if_read_only(client).await {
+                                    return;
+                                }
+    self.print_debug_msg("Acquiring write lock on records...");
+                                let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock released on records.");
+                                let record = record_writer.get_mut(&key_hash);
+                                match record {
+                                    Some(exist) if if_version.is_some_and(|version| exist.version() != version) => {
+                                        drop(record_writer);
+                                        self.refuse_changed(client, key).await;
+                                    }
+                                    Some(exist) => {
+                                        exist.pending = true;
+                                        if exist.replica_idx < self.get_current_k().await {
+                                            let fw_del = Message::new(
+                                                MsgType::FwDelete,
+                                                client,
+                                                &MsgData::FwDelete { key: key_hash, forward_back: false, consistency: *consistency }
+                                            );
+                                            self.send_msg(self.get_succ().await, &fw_del).await;
+                                            return;
+                                        }
+                                    }
+
+                                    _ => {
+                                        let user_msg = Message::new(
+                                            MsgType::Reply,
+                                            None,
+                                            &MsgData::Reply { reply: format!("Error: 🔑 {} doesn't exist!", key) }
+                                        );
+
+                                        client.unwrap().send_msg(&user_msg).await;
+                                        return;
+                                    }
+                                }
+                            }
+                            else {
+                                // just forward to the primary direction
+                                let fw_del = Message::new(
+                                    MsgType::Delete,
+                                    client,
+                                    &MsgData::Delete { key: key.clone(), consistency: *consistency, colocate_with: colocate_with.clone(), if_version: *if_version }
+                                );
+                                self.forward_to_primary(client, &key_hash, &fw_del).await;
+                            }
+                    }
+
+                    Consistency::Quorum => {
+                        /* Removed at the primary first and then down the replica chain,
+                           as for inserts the replica removing the W-th copy replies */
+                        if self.is_responsible(&key_hash).await {
+                            if self.refuse_if_read_only(client).await {
+                                return;
+                            }
+    self.print_debug_msg("Acquiring write lock on records...");
+                            let res = match self.remove_if_version(&key_hash, if_version).await {
+                                Ok(res) => res,
+                                Err(_) => {
+                                    self.refuse_changed(client, key).await;
+                                    return;
+                                }
+                            };
+    self.print_debug_msg("Write lock released on records.");
+                            match res {
+                                Some(found) => {
+                                    self.track_bytes(found.value.len(), 0);
+                                    self.notify_subscribers(key_hash, &found.title, None).await;
+                                    let (_, w) = self.quorum_copies().await;
+                                    if w == 1 {
+                                        let user_msg = Message::new(
+                                            MsgType::Reply,
+                                            None,
+                                            &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} successfully! [replicas_removed: 1]", found.title, found.value, found.timestamp) }
+                                        );
+                                        client.unwrap().send_msg(&user_msg).await;
+                                    }
+                                    if self.get_current_k().await > 0 {
+                                        let fw_del = Message::new(
+                                            MsgType::FwDelete,
+                                            if w > 1 { client } else { None },
+                                            &MsgData::FwDelete { key: key_hash, forward_back: false, consistency: *consistency }
+                                        );
+                                        self.send_msg(self.get_succ().await, &fw_del).await;
+                                    }
+                                }
+                                _ => {
+                                    let user_msg = Message::new(
+                                        MsgType::Reply,
+                                        None,
+                                        &MsgData::Reply { reply: format!("Error: 🔑 {} doesn't exist!", key) }
+                                    );
+                                    client.unwrap().send_msg(&user_msg).await;
+                                }
+                            }
+                        } else {
+                            let fw_del = Message::new(
+                                MsgType::Delete,
+                                client,
+                                &MsgData::Delete { key: key.clone(), consistency: *consistency, colocate_with: colocate_with.clone(), if_version: *if_version }
+                            );
+                            self.forward_to_primary(client, &key_hash, &fw_del).await;
+                        }
+                    }
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+             
+    }
+
+    /* Removes the copy a delete names. With 'if_version' a copy whose version has moved on,
+        an insert or touch that landed after the caller read it, is kept and returned instead */
+    async fn remove_if_version(&self, key_hash:&HashType, if_version:&Option<(DateTime<Utc>, Option<HashType>)>) -> Result<Option<Item>, Item> {
+        let mut records = self.records.write().await;
+        match (records.get(key_hash), if_version) {
+            (Some(found), Some(version)) if found.version() != *version => Err(found.clone()),
+            _ => Ok(records.remove(key_hash)),
+        }
+    }
+
+    // conditional deletes of a key that changed since it was read are answered with an error
+    async fn refuse_changed(&self, client:Option<&NodeInfo>, key:&str) {
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: Conflict: 🔑 {} changed since it was read", key) }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_fw_delete(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::FwDelete { key, forward_back, consistency } => {
+                // forward back is used to avoid ping-pong messages between nodes...
+                let cons = match consistency { Some(cons) => *cons, None => self.get_consistency().await };
+                match cons {
+                    Consistency::Eventual => {
+                        if self.is_replica_manager(key).await >= 0 {
+    self.print_debug_msg("Acquiring write lock on records...");
+                            let res = self.records.write().await.remove(key);
+    self.print_debug_msg("Write lock released on records.");
+                            match res {
+                                Some(found) => {
+                                    self.track_bytes(found.value.len(), 0);
+                                    if found.replica_idx == 0 {
+                                        self.notify_subscribers(*key, &found.title, None).await;
+                                    }
+                                    let fw_del = Message::new(
+                                        MsgType::FwDelete,
+                                        None,
+                                        &MsgData::FwDelete { key: key.clone(), forward_back: *forward_back, consistency: *consistency }
+                                    );
+                                    if found.replica_idx > 0 && *forward_back == true {
+                                        self.send_msg(self.get_prev().await, &fw_del).await;
+                                        return;
+                                    } 
+                                    if found.replica_idx < self.get_current_k().await && *forward_back == false {
+                                        self.send_msg(self.get_succ().await, &fw_del).await;
+                                        return;
+                                    }
+                                }
+                                _ => self.print_debug_msg("Error: Wrong delete forwarding"),
+                            }
+                        }
+                    }
+
+                    Consistency::Chain => {
     self.print_debug_msg("Acquiring write lock on records...");
-                                let mut record_writer = self.records.write().await;
+                        let record_reader = self.records.read().await;
     self.print_debug_msg("Write lock released on records.");
-                                let record = record_writer.get_mut(&key_hash);
-                                match record {
-                                    Some(exist) => {
-                                        exist.pending = true;
-                                        if exist.replica_idx < self.get_current_k().await {
-                                            let fw_del = Message::new(
-                                                MsgType::FwDelete,
-                                                client,
-                                                &MsgData::FwDelete { key: key_hash, forward_back: false }
-                                            );
-                                            self.send_msg(self.get_succ().await, &fw_del).await;
-                                            return;
-                                        }
+                        let record = record_reader.get(&key).cloned();
+                        drop(record_reader);
+                        match record {
+                            Some(exist) => {
+                                if exist.replica_idx < self.get_current_k().await {
+                                    //drop(record_reader);
+                                    let mut new_lock = self.records.write().await;
+                                    let rec = new_lock.get_mut(&key);
+                                    rec.unwrap().pending = true;
+                                    drop(new_lock);
+                                    let fw_del = Message::new(
+                                        MsgType::FwDelete,
+                                        client,
+                                        &MsgData::FwDelete { key: *key, forward_back: false, consistency: *consistency }
+                                    );
+
+                                    self.send_msg(self.get_succ().await, &fw_del).await;
+                                    return;
+                                }
+                                else if exist.replica_idx == self.get_current_k().await {
+                                /* When reach tail: perform first 'physical' delete, reply to client
+                                   and initiate acks to previous nodes */
+    self.print_debug_msg("Acquiring write lock on records...");
+                                   let mut record_writer = self.records.write().await;
+                                   if let Some(gone) = record_writer.remove(key) {
+                                       self.track_bytes(gone.value.len(), 0);
+                                   }
+    self.print_debug_msg("Write lock released on records.");
+                                    drop(record_writer);
+                                    if exist.replica_idx == 0 {
+                                        self.notify_subscribers(*key, &exist.title, None).await;
+                                    }
+
+                                   let head_replies = exist.replica_idx > 0 && self.reply_from_head().await;
+                                   // deletes started by eviction have no client
+                                   if let (false, Some(client)) = (head_replies, client) {
+                                    /* the copies up the chain are still pending removal, but no
+                                        Chain read returns them any more, so all count as removed */
+                                    let user_msg = Message::new(
+                                        MsgType::Reply,
+                                        None,
+                                        &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} successfully! [replicas_removed: {}]",
+                                                                         exist.title, exist.value, exist.timestamp, exist.replica_idx + 1) }
+                                    );
+
+                                    client.send_msg(&user_msg).await;
+                                   }
+
+                                   if exist.replica_idx > 0 {
+                                    let ack_del = Message::new(
+                                        MsgType::AckDelete,
+                                        if head_replies { client } else { None },
+                                        &MsgData::AckDelete { key: *key }
+                                    );
+                                    self.send_msg(self.get_prev().await, &ack_del).await;
                                     }
 
-                                    _ => {
-                                        let user_msg = Message::new(
-                                            MsgType::Reply,
-                                            None,
-                                            &MsgData::Reply { reply: format!("Error: 🔑 {} doesn't exist!", key) }
-                                        );
+                                }
+                            }
+
+                            _ => self.print_debug_msg("Wrong delete forwarding")
+                        }
+                        
+                    }
+
+                    Consistency::Quorum => {
+    self.print_debug_msg("Acquiring write lock on records...");
+                        let res = self.records.write().await.remove(key);
+    self.print_debug_msg("Write lock released on records.");
+                        let idx = match res.as_ref() {
+                            Some(found) => {
+                                self.track_bytes(found.value.len(), 0);
+                                found.replica_idx as i16
+                            }
+                            None => self.is_replica_manager(key).await
+                        };
+                        if idx < 0 {
+                            self.print_debug_msg("Error: Wrong delete forwarding");
+                            return;
+                        }
+                        let idx = idx as u8;
+
+                        // a copy already missing here still counts, the key is gone from this node either way
+                        let (_, w) = self.quorum_copies().await;
+                        let mut client = client;
+                        if let Some(user) = client.filter(|_| idx + 1 >= w) {
+                            let reply = match res {
+                                Some(found) => format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} successfully! [replicas_removed: {}]", found.title, found.value, found.timestamp, idx + 1),
+                                None => format!("Deleted 🔑 {} successfully! [replicas_removed: {}]", key, idx + 1)
+                            };
+                            let user_msg = Message::new(
+                                MsgType::Reply,
+                                None,
+                                &MsgData::Reply { reply }
+                            );
+                            user.send_msg(&user_msg).await;
+                            client = None;
+                        }
+
+                        if idx < self.get_current_k().await {
+                            let fw_del = Message::new(
+                                MsgType::FwDelete,
+                                client,
+                                &MsgData::FwDelete { key: *key, forward_back: false, consistency: *consistency }
+                            );
+                            self.send_msg(self.get_succ().await, &fw_del).await;
+                        }
+                    }
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_ack_delete(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        /* used for linearizability only
+            implement the physical delete here */
+        match data {
+            MsgData::AckDelete { key } => {
+    self.print_debug_msg("Acquiring read lock on records...");
+            let record_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+            let record = record_reader.get(key).cloned();
+            drop(record_reader);
+                match record {
+                    Some(exist) => {
+                        let idx = exist.replica_idx.clone();
+                        if exist.pending {
+                            //drop(record_reader); 
+    self.print_debug_msg("Acquiring write lock on records...");
+                            if let Some(gone) = self.records.write().await.remove(key) {
+                                self.track_bytes(gone.value.len(), 0);
+                            }
+    self.print_debug_msg("Write lock released on records.");
+                            if idx == 0 {
+                                self.notify_subscribers(*key, &exist.title, None).await;
+                            }
+                        } else {
+                            self.print_debug_msg("Error: 'logical' delete must occur first");
+                            return;
+                        }
+                        if  idx > 0 {
+                            let ack_del = Message::new(
+                                MsgType::AckDelete,
+                                client,
+                                &MsgData::AckDelete { key: *key }
+                            );
+                            
+                            self.send_msg(self.get_prev().await, &ack_del).await;
+                            return;
+                        }
+                        else if idx == 0  {
+                            if let Some(client) = client {
+                                // the acks reached the head, every copy is gone
+                                let user_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} successfully! [replicas_removed: {}]",
+                                                                     exist.title, exist.value, exist.timestamp, self.get_current_k().await + 1) }
+                                );
+                                client.send_msg(&user_msg).await;
+                            }
+                            // notify waiting readers on this key
+                            let waiting_list = self.pendings.read().await;
+
+                            if let Some(wait) = waiting_list.get(key) {
+                                self.print_debug_msg("Sent a notification to ");
+                                wait.notify.notify_waiters();  
+                                // remove this from queue
+                                //waiting_list.remove(&key);
+                            }
+                        }
+                    }
+                    _ => self.print_debug_msg("Wrong delete ack received"),
+                }
+            }
+
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data)),
+        }
+    }
+
+
+    async fn handle_touch(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Refreshes the timestamp of an existing record without resending its value.
+        The primary stamps the new time and pushes that exact timestamp down the replica chain */
+        match data {
+            MsgData::Touch { key } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_touch = Message::new(
+                        MsgType::Touch,
+                        client,
+                        &MsgData::Touch { key: key.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_touch).await;
+                    return;
+                }
+
+                self.sleep_on_updates(key_hash).await;
+                let now = Utc::now();
+                let touched = {
+    self.print_debug_msg("Acquiring write lock on records...");
+                    let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock released on records.");
+                    match record_writer.get_mut(&key_hash) {
+                        Some(exist) => {
+                            exist.timestamp = now;
+                            exist.writer = Some(self.get_id());
+                            true
+                        }
+                        None => false
+                    }
+                };
+                if touched {
+                    self.index_time(key_hash, now).await;
+                }
+
+                let reply = if touched {
+                    format!("Touched 🔑 {} at 🕰️ {} successfully!", key, now)
+                } else {
+                    format!("Error: 🔑 {} doesn't exist!", key)
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+
+                if touched && self.get_current_k().await > 0 {
+                    let fw_msg = Message::new(
+                        MsgType::FwTouch,
+                        None,
+                        &MsgData::FwTouch { key: key_hash, timestamp: now, writer: Some(self.get_id()) }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_fw_touch(&self, data:&MsgData) {
+        match data {
+            MsgData::FwTouch { key, timestamp, writer } => {
+                let replica_idx = {
+    self.print_debug_msg("Acquiring write lock on records...");
+                    let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock released on records.");
+                    match record_writer.get_mut(key) {
+                        Some(exist) => {
+                            exist.timestamp = *timestamp;
+                            exist.writer = *writer;
+                            exist.replica_idx
+                        }
+                        None => {
+                            self.print_debug_msg("Wrong touch forwarding");
+                            return;
+                        }
+                    }
+                };
+                self.index_time(*key, *timestamp).await;
+
+                if replica_idx < self.get_current_k().await {
+                    let fw_msg = Message::new(
+                        MsgType::FwTouch,
+                        None,
+                        &MsgData::FwTouch { key: *key, timestamp: *timestamp, writer: *writer }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_sync_key(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Targeted repair of a single key. The primary pushes its own copy down the replica
+        chain and every replica manager overwrites whatever it holds. The last replica
+        replies with how many copies actually differed from the primary */
+        match data {
+            MsgData::SyncKey { key } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_sync = Message::new(
+                        MsgType::SyncKey,
+                        client,
+                        &MsgData::SyncKey { key: key.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_sync).await;
+                    return;
+                }
+
+                self.sleep_on_updates(key_hash).await;
+                let primary_copy = {
+    self.print_debug_msg("Acquiring read lock on records...");
+                    let records_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+                    records_reader.get(&key_hash).cloned()
+                };
+
+                let reply = match primary_copy {
+                    None => format!("Error: 🔑 {} doesn't exist!", key),
+                    Some(_) if self.get_current_k().await == 0 => format!("🔑 {} has no replicas to sync", key),
+                    Some(item) => {
+                        let fw_msg = Message::new(
+                            MsgType::FwSyncKey,
+                            client,
+                            &MsgData::FwSyncKey { item, updated: 0 }
+                        );
+                        self.send_msg(self.get_succ().await, &fw_msg).await;
+                        return;
+                    }
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_fw_sync_key(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::FwSyncKey { item, updated } => {
+                let key_hash = item.key_hash();
+                let mut copy = item.clone();
+                copy.replica_idx += 1;
+                copy.pending = false;
+                let stale = {
+    self.print_debug_msg("Acquiring write lock on records...");
+                    let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock acquired on records.");
+                    let stale = match record_writer.get(&key_hash) {
+                        Some(exist) => exist.value != copy.value || exist.version() != copy.version() || !exist.verify(),
+                        None => true
+                    };
+                    if let Some(replaced) = record_writer.insert(key_hash, copy.clone()) {
+                        self.track_bytes(replaced.value.len(), 0);
+                    }
+                    self.track_bytes(0, copy.value.len());
+                    stale
+                };
+                self.index_time(key_hash, copy.timestamp).await;
+                let updated = if stale { updated + 1 } else { *updated };
+
+                if copy.replica_idx < self.get_current_k().await {
+                    let fw_msg = Message::new(
+                        MsgType::FwSyncKey,
+                        client,
+                        &MsgData::FwSyncKey { item: copy, updated }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                    return;
+                }
+
+                // pushes started by other operations (e.g. compact) have already answered
+                if let Some(user) = client {
+                    let user_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::Reply {
+                            reply: format!("Synced 🔑 {}: {} of {} replica(s) updated", copy.title, updated, copy.replica_idx)
+                        }
+                    );
+                    user.send_msg(&user_msg).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_check_replicas(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Detects under-replicated keys. The primary starts a walk over its k replica managers,
+        each counts whether it holds the key and whether its copy is intact and equal to the
+        primary's version. The last one replies with the value and both counts */
+        match data {
+            MsgData::CheckReplicas { key } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_check = Message::new(
+                        MsgType::CheckReplicas,
+                        client,
+                        &MsgData::CheckReplicas { key: key.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_check).await;
+                    return;
+                }
+
+                let primary = self.records.read().await.get(&key_hash).cloned();
+                let intact = primary.as_ref().is_some_and(|item| item.verify());
+                let k = self.get_current_k().await;
+                let (copies, matching) = if primary.is_some() { (1, intact as u8) } else { (0, 0) };
+                if k == 0 {
+                    self.reply_replica_check(client, key, primary.as_ref(), copies, matching).await;
+                    return;
+                }
+                let fw_check = Message::new(
+                    MsgType::FwCheckReplicas,
+                    client,
+                    &MsgData::FwCheckReplicas { key: key.clone(), primary, copies, matching, hops_left: k }
+                );
+                self.send_msg(self.get_succ().await, &fw_check).await;
+            }
+            MsgData::FwCheckReplicas { key, primary, copies, matching, hops_left } => {
+                let local = self.records.read().await.get(&HashFunc(key)).cloned();
+                let copies = copies + local.is_some() as u8;
+                let equal = match (&local, primary) {
+                    (Some(copy), Some(first)) => copy.verify() && copy.value == first.value,
+                    _ => false
+                };
+                let matching = matching + equal as u8;
+                if *hops_left > 1 {
+                    let fw_check = Message::new(
+                        MsgType::FwCheckReplicas,
+                        client,
+                        &MsgData::FwCheckReplicas { key: key.clone(), primary: primary.clone(), copies, matching, hops_left: hops_left - 1 }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_check).await;
+                    return;
+                }
+                self.reply_replica_check(client, key, primary.as_ref(), copies, matching).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
 
-                                        client.unwrap().send_msg(&user_msg).await;
-                                        return;
-                                    }
-                                }
-                            }
-                            else {
-                                // just forward to the primary direction
-                                let fw_del = Message::new(
-                                    MsgType::Delete,
-                                    client,
-                                    &MsgData::Delete { key: key.clone() }
-                                );
-                                if self.maybe_next_responsible(&key_hash).await {
-                                    self.send_msg(self.get_succ().await, &fw_del).await;
-                                } else {
-                                    self.send_msg(self.get_prev().await, &fw_del).await;
-                                }
-                            }
-                    }
+    async fn reply_replica_check(&self, client:Option<&NodeInfo>, key:&str, primary:Option<&Item>, copies:u8, matching:u8) {
+        let expected = self.max_replication().await + 1;
+        let reply = match primary {
+            Some(found) => format!("Found (🔑 {} : 🔒{}, 🕰️ {}) [copies: {} of {}, matching the primary: {}]",
+                                   found.title, found.value, found.timestamp, copies, expected, matching),
+            None => format!("Error: 🔑 {} has no primary copy [copies: {} of {}]", key, copies, expected)
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_inspect_key(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Lists the copy of a key held at each of its replica positions, to diagnose divergence.
+        The primary starts a walk like CheckReplicas and the last replica manager replies */
+        match data {
+            MsgData::InspectKey { key } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_inspect = Message::new(
+                        MsgType::InspectKey,
+                        client,
+                        &MsgData::InspectKey { key: key.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_inspect).await;
+                    return;
+                }
 
-                    _ => self.print_debug_msg(&format!("Unsupported consistency model - {:?}", cons))
+                let copies = vec![(self.get_info(), self.records.read().await.get(&key_hash).cloned())];
+                let k = self.get_current_k().await;
+                if k == 0 {
+                    self.reply_inspect_key(client, key, &copies).await;
+                    return;
+                }
+                let fw_inspect = Message::new(
+                    MsgType::FwInspectKey,
+                    client,
+                    &MsgData::FwInspectKey { key: key.clone(), copies, hops_left: k }
+                );
+                self.send_msg(self.get_succ().await, &fw_inspect).await;
+            }
+            MsgData::FwInspectKey { key, copies, hops_left } => {
+                let mut copies = copies.clone();
+                copies.push((self.get_info(), self.records.read().await.get(&HashFunc(key)).cloned()));
+                if *hops_left > 1 {
+                    let fw_inspect = Message::new(
+                        MsgType::FwInspectKey,
+                        client,
+                        &MsgData::FwInspectKey { key: key.clone(), copies, hops_left: hops_left - 1 }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_inspect).await;
+                    return;
                 }
+                self.reply_inspect_key(client, key, &copies).await;
             }
             _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
         }
-             
     }
 
-    async fn handle_fw_delete(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    // one line per replica position, copies that differ from the primary's version are flagged
+    async fn reply_inspect_key(&self, client:Option<&NodeInfo>, key:&str, copies:&[(NodeInfo, Option<Item>)]) {
+        let primary = copies.first().and_then(|(_, item)| item.as_ref());
+        let diverging = copies.iter()
+            .filter(|(_, item)| match (item, primary) {
+                (Some(copy), Some(first)) => copy.value != first.value || copy.version() != first.version(),
+                (None, None) => false,
+                _ => true
+            })
+            .count();
+        let mut reply = format!("🔑 {}: {} position(s), {} diverging from the primary", key, copies.len(), diverging);
+        for (pos, (node, item)) in copies.iter().enumerate() {
+            let line = match item {
+                Some(copy) => {
+                    let agrees = primary.is_some_and(|first| copy.value == first.value && copy.version() == first.version());
+                    format!("{}🔒{} 🕰️ {} [replica_idx: {}, pending: {}]{}", if copy.verify() { "" } else { "⚠️ corrupt " },
+                            copy.value, copy.timestamp, copy.replica_idx, copy.pending, if agrees { "" } else { " ⚠️ differs" })
+                }
+                None => "no copy".to_string() + if primary.is_some() { " ⚠️ differs" } else { "" }
+            };
+            reply.push_str(&format!("\n  {} 🖥️ {} ({}): {}", pos, node, node.id, line));
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_replica_set(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* The replica set of a key, for clients reading from replicas directly.
+        Walks the successor chain from the primary like InspectKey without reading any copies */
         match data {
-            MsgData::FwDelete { key, forward_back } => {
-                // forward back is used to avoid ping-pong messages between nodes...
-                let cons = self.get_consistency().await;
-                match cons {
-                    Consistency::Eventual => {
-                        if self.is_replica_manager(key).await >= 0 {
-    self.print_debug_msg("Acquiring write lock on records...");
-                            let res = self.records.write().await.remove(key);
-    self.print_debug_msg("Write lock released on records.");
-                            match res {
-                                Some(found) => {
-                                    let fw_del = Message::new(
-                                        MsgType::FwDelete,
-                                        None,
-                                        &MsgData::FwDelete { key: key.clone(), forward_back: *forward_back }
-                                    );
-                                    if found.replica_idx > 0 && *forward_back == true {
-                                        self.send_msg(self.get_prev().await, &fw_del).await;
-                                        return;
-                                    } 
-                                    if found.replica_idx < self.get_current_k().await && *forward_back == false {
-                                        self.send_msg(self.get_succ().await, &fw_del).await;
-                                        return;
-                                    }
-                                }
-                                _ => self.print_debug_msg("Error: Wrong delete forwarding"),
+            MsgData::ReplicaSet { key } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_set = Message::new(
+                        MsgType::ReplicaSet,
+                        client,
+                        &MsgData::ReplicaSet { key: key.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_set).await;
+                    return;
+                }
+
+                let nodes = vec![self.get_info()];
+                let k = self.get_current_k().await;
+                if k == 0 {
+                    self.reply_replica_set(client, key, &nodes).await;
+                    return;
+                }
+                let fw_set = Message::new(
+                    MsgType::FwReplicaSet,
+                    client,
+                    &MsgData::FwReplicaSet { key: key.clone(), nodes, hops_left: k }
+                );
+                self.send_msg(self.get_succ().await, &fw_set).await;
+            }
+            MsgData::FwReplicaSet { key, nodes, hops_left } => {
+                let mut nodes = nodes.clone();
+                nodes.push(self.get_info());
+                if *hops_left > 1 {
+                    let fw_set = Message::new(
+                        MsgType::FwReplicaSet,
+                        client,
+                        &MsgData::FwReplicaSet { key: key.clone(), nodes, hops_left: hops_left - 1 }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_set).await;
+                    return;
+                }
+                self.reply_replica_set(client, key, &nodes).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn reply_replica_set(&self, client:Option<&NodeInfo>, key:&str, nodes:&[NodeInfo]) {
+        let mut reply = format!("🔑 {}: replicas on {} node(s)", key, nodes.len());
+        for (replica_idx, node) in nodes.iter().enumerate() {
+            reply.push_str(&format!("\n  {} 🖥️ {} ({})", replica_idx, node, node.id));
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_verify_replicas(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* CheckReplicas for every primary key of the addressed node at once, and by position:
+        the n-th successor must hold each key at replica_idx n. With repair a wrong index
+        is set right and a missing copy is stored from the primary's item. A sample draws
+        its keys with the node's rng, so a --seed run checks the same keys every time */
+        match data {
+            MsgData::VerifyReplicas { repair, sample } => {
+                let mut items = self.primary_records().await;
+                let population = match sample {
+                    Some(fraction) if !(*fraction > 0.0 && *fraction <= 1.0) => {
+                        self.reject_request(client, &format!("sample must be a fraction in (0, 1], got {}", fraction)).await;
+                        return;
+                    }
+                    Some(fraction) => {
+                        let population = items.len();
+                        let size = ((population as f64 * fraction).ceil() as usize).min(population);
+                        items = items.choose_multiple(&mut *self.rng.lock().unwrap(), size).cloned().collect();
+                        Some(population)
+                    }
+                    None => None
+                };
+                let k = self.get_current_k().await;
+                if k == 0 || items.is_empty() {
+                    self.reply_verify_replicas(client, items.len(), &[], 0, population, 0).await;
+                    return;
+                }
+                let fw_verify = Message::new(
+                    MsgType::FwVerifyReplicas,
+                    client,
+                    &MsgData::FwVerifyReplicas { items, replica_idx: 1, hops_left: k, repair: *repair, problems: Vec::new(), repaired: 0,
+                                                 population, missing: Vec::new() }
+                );
+                self.send_msg(self.get_succ().await, &fw_verify).await;
+            }
+            MsgData::FwVerifyReplicas { items, replica_idx, hops_left, repair, problems, repaired, population, missing } => {
+                let (mut problems, mut repaired, mut missing) = (problems.clone(), *repaired, missing.clone());
+                for item in items.iter() {
+                    let key = item.key_hash();
+                    let found = self.records.read().await.get(&key).map(|copy| copy.replica_idx);
+                    match found {
+                        Some(idx) if idx == *replica_idx => continue,
+                        Some(idx) => problems.push(format!("🖥️ {} holds 🔑 {} at replica_idx {}, expected {}", self.get_info(), item.title, idx, replica_idx)),
+                        None => {
+                            problems.push(format!("🖥️ {} misses 🔑 {} (replica_idx {})", self.get_info(), item.title, replica_idx));
+                            // a key missing copies at several successors counts once
+                            if !missing.contains(&key) {
+                                missing.push(key);
+                            }
+                        }
+                    }
+                    if !*repair {
+                        continue;
+                    }
+                    let mut copy = Item { replica_idx: *replica_idx, pending: false, ..item.clone() };
+                    match found {
+                        Some(_) => {
+                            if let Some(exist) = self.records.write().await.get_mut(&key) {
+                                exist.replica_idx = *replica_idx;
                             }
                         }
+                        None => self.reconcile_copy(key, &mut copy).await
                     }
+                    repaired += 1;
+                }
+                if *hops_left > 1 {
+                    let fw_verify = Message::new(
+                        MsgType::FwVerifyReplicas,
+                        client,
+                        &MsgData::FwVerifyReplicas { items: items.clone(), replica_idx: replica_idx + 1, hops_left: hops_left - 1,
+                                                     repair: *repair, problems, repaired, population: *population, missing }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_verify).await;
+                    return;
+                }
+                self.reply_verify_replicas(client, items.len(), &problems, repaired, *population, missing.len()).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
 
-                    Consistency::Chain => {
+    async fn reply_verify_replicas(&self, client:Option<&NodeInfo>, keys:usize, problems:&[String], repaired:usize,
+                                   population:Option<usize>, missing:usize) {
+        let mut reply = match population {
+            Some(population) => {
+                let (estimate, low, high) = utils::sample_estimate(missing, keys, population);
+                format!("Verified the replica chain of {} sampled out of {} primary key(s): {} problem(s), {} repaired. \
+                         {} sampled key(s) miss a copy, estimated under-replicated: {:.0} (95% CI {:.0}..{:.0})",
+                        keys, population, problems.len(), repaired, missing, estimate, low, high)
+            }
+            None => format!("Verified the replica chain of {} primary key(s): {} problem(s), {} repaired", keys, problems.len(), repaired)
+        };
+        for problem in problems.iter() {
+            reply.push_str(&format!("\n  {}", problem));
+        }
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
+    async fn handle_compact(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Collapses a value grown by Eventual appends at the primary and overwrites
+        the replicas with the result, reusing the SyncKey push down the chain */
+        match data {
+            MsgData::Compact { key, strategy } => {
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_compact = Message::new(
+                        MsgType::Compact,
+                        client,
+                        &MsgData::Compact { key: key.clone(), strategy: *strategy }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_compact).await;
+                    return;
+                }
+
+                self.sleep_on_updates(key_hash).await;
+                let compacted = {
     self.print_debug_msg("Acquiring write lock on records...");
-                        let record_reader = self.records.read().await;
-    self.print_debug_msg("Write lock released on records.");
-                        let record = record_reader.get(&key).cloned();
-                        drop(record_reader);
-                        match record {
-                            Some(exist) => {
-                                if exist.replica_idx < self.get_current_k().await {
-                                    //drop(record_reader);
-                                    let mut new_lock = self.records.write().await;
-                                    let rec = new_lock.get_mut(&key);
-                                    rec.unwrap().pending = true;
-                                    drop(new_lock);
-                                    let fw_del = Message::new(
-                                        MsgType::FwDelete,
-                                        client,
-                                        &MsgData::FwDelete { key: *key, forward_back: false }
-                                    );
+                    let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock acquired on records.");
+                    record_writer.get_mut(&key_hash).map(|exist| {
+                        let before = exist.value.len();
+                        exist.compact(*strategy);
+                        self.track_bytes(before, exist.value.len());
+                        (before, exist.clone())
+                    })
+                };
+
+                let reply = match &compacted {
+                    Some((before, item)) => format!("Compacted 🔑 {} ({:?}): {} -> {} bytes", key, strategy, before, item.value.len()),
+                    None => format!("Error: 🔑 {} doesn't exist!", key)
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
 
-                                    self.send_msg(self.get_succ().await, &fw_del).await;
-                                    return;
-                                }
-                                else if exist.replica_idx == self.get_current_k().await {
-                                /* When reach tail: perform first 'physical' delete, reply to client
-                                   and initiate acks to previous nodes */
+                if let Some((_, item)) = compacted {
+                    if self.get_current_k().await > 0 {
+                        let fw_msg = Message::new(
+                            MsgType::FwSyncKey,
+                            None,
+                            &MsgData::FwSyncKey { item, updated: 0 }
+                        );
+                        self.send_msg(self.get_succ().await, &fw_msg).await;
+                    }
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_append(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Appends a timestamped entry to a log key. Entries are ordered by the primary,
+        which then overwrites the replicas with its copy through the SyncKey push */
+        match data {
+            MsgData::Append { key, entry } => {
+                if key.trim().is_empty() {
+                    self.reject_request(client, "key must not be empty").await;
+                    return;
+                }
+                let key_hash = HashFunc(key);
+                if !self.is_responsible(&key_hash).await {
+                    let fw_append = Message::new(
+                        MsgType::Append,
+                        client,
+                        &MsgData::Append { key: key.clone(), entry: entry.clone() }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_append).await;
+                    return;
+                }
+
+                self.sleep_on_updates(key_hash).await;
+                let appended = {
     self.print_debug_msg("Acquiring write lock on records...");
-                                   let mut record_writer = self.records.write().await;
-                                   record_writer.remove(key);
-    self.print_debug_msg("Write lock released on records.");
-                                    drop(record_writer);
+                    let mut record_writer = self.records.write().await;
+    self.print_debug_msg("Write lock acquired on records.");
+                    let record = record_writer.entry(key_hash).or_insert_with(|| Item::new(key, "", 0, false));
+                    record.append_log(entry);
+                    record.writer = Some(self.get_id());
+                    record.clone()
+                };
+                self.index_time(key_hash, appended.timestamp).await;
 
-                                   let user_msg = Message::new(
-                                    MsgType::Reply,
-                                    None,
-                                    &MsgData::Reply { reply: format!("Deleted (🔑 {} : 🔒{}) at 🕰️ {} successfully!", exist.title, exist.value, exist.timestamp) }
-                                   );
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply: format!("Appended to 🔑 {} (entry #{}) at 🕰️ {}", key, appended.log.len(), appended.timestamp) }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+
+                if self.get_current_k().await > 0 {
+                    let fw_msg = Message::new(
+                        MsgType::FwSyncKey,
+                        None,
+                        &MsgData::FwSyncKey { item: appended, updated: 0 }
+                    );
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                }
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_read_log(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::ReadLog { key, since, limit } => {
+                let key_hash = HashFunc(key);
+                // the primary orders the entries, so it also serves the reads
+                if !self.is_responsible(&key_hash).await {
+                    let fw_read = Message::new(
+                        MsgType::ReadLog,
+                        client,
+                        &MsgData::ReadLog { key: key.clone(), since: *since, limit: *limit }
+                    );
+                    self.forward_to_primary(client, &key_hash, &fw_read).await;
+                    return;
+                }
+
+                let resolved = self.sleep_on_updates(key_hash).await;
+                let reply = {
+    self.print_debug_msg("Acquiring read lock on records...");
+                    let record_reader = self.records.read().await;
+    self.print_debug_msg("Read lock acquired on records.");
+                    match record_reader.get(&key_hash) {
+                        _ if !resolved => format!("Error: pending update on 🔑 {} was cancelled, retry the read", key),
+                        Some(record) => utils::format_log_msg(key, &record.read_log(*since, *limit)),
+                        None => format!("Error: 🔑 {} doesn't exist!", key)
+                    }
+                };
+                let user_msg = Message::new(
+                    MsgType::Reply,
+                    None,
+                    &MsgData::Reply { reply }
+                );
+                client.unwrap().send_msg(&user_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_subscribe(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Subscriptions live on the primary of the key, which is the node that notifies.
+        They are not handed over when the primary changes on a join or depart */
+        let (key, subscribe) = match data {
+            MsgData::Subscribe { key } => (key, true),
+            MsgData::Unsubscribe { key } => (key, false),
+            _ => {
+                self.print_debug_msg(&format!("Unexpected data - {:?}", data));
+                return;
+            }
+        };
+        let key_hash = HashFunc(key);
+        if !self.is_responsible(&key_hash).await {
+            let fw_msg = Message::new(
+                if subscribe { MsgType::Subscribe } else { MsgType::Unsubscribe },
+                client,
+                data
+            );
+            self.forward_to_primary(client, &key_hash, &fw_msg).await;
+            return;
+        }
 
-                                   client.unwrap().send_msg(&user_msg).await;
+        let subscriber = match client {
+            Some(subscriber) => *subscriber,
+            None => return
+        };
+        let reply = {
+            let mut subscribers_writer = self.subscribers.write().await;
+            let list = subscribers_writer.entry(key_hash).or_insert_with(Vec::new);
+            list.retain(|known| known.id != subscriber.id);
+            if subscribe {
+                list.push(subscriber);
+                format!("Subscribed to 🔑 {} on node {}", key, self.get_info())
+            } else {
+                if list.is_empty() {
+                    subscribers_writer.remove(&key_hash);
+                }
+                format!("Unsubscribed from 🔑 {}", key)
+            }
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        subscriber.send_msg(&user_msg).await;
+    }
 
-                                   if exist.replica_idx > 0 {
-                                    let ack_del = Message::new(
-                                        MsgType::AckDelete,
-                                        None,
-                                        &MsgData::AckDelete { key: *key }
-                                    );
-                                    self.send_msg(self.get_prev().await, &ack_del).await;
-                                    }
+    async fn handle_pin(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Every node needs the pin to route the key, so it travels once around the ring
+        and the node that started it replies when it comes back. Copies stored before
+        the pin stay where they are and a pin to a node that later departs is not
+        cleared, requests for the key then wander until they time out */
+        let (key, node, header) = match data {
+            MsgData::Pin { key, node } => {
+                self.pins.write().await.insert(HashFunc(key), *node);
+                (key.clone(), *node, None)
+            }
+            MsgData::FwPin { key, node, header } => (key.clone(), *node, Some(*header)),
+            _ => {
+                self.print_debug_msg(&format!("Unexpected data - {:?}", data));
+                return;
+            }
+        };
+        let succ = self.get_succ().await;
+        let completed = match header {
+            Some(header) => header == self.get_id(),
+            None => succ.is_none() || succ.unwrap().id == self.get_id()
+        };
+        if completed {
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: format!("Pinned 🔑 {} to node {}", key, node) }
+            );
+            client.unwrap().send_msg(&user_msg).await;
+            return;
+        }
+        if header.is_some() {
+            self.pins.write().await.insert(HashFunc(&key), node);
+        }
+        let fw_msg = Message::new(
+            MsgType::FwPin,
+            client,
+            &MsgData::FwPin { key, node, header: header.unwrap_or(self.get_id()) }
+        );
+        self.send_msg(succ, &fw_msg).await;
+    }
 
-                                }
-                            }
+    // answers requests the node cannot serve so the client doesn't wait for its timeout
+    async fn reject_request(&self, sender:Option<&NodeInfo>, reason:&str) {
+        if let Some(sender) = sender {
+            let error_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: format!("Error: BadRequest: {}", reason) },
+            );
+            sender.send_msg(&error_msg).await;
+        }
+    }
 
-                            _ => self.print_debug_msg("Wrong delete forwarding")
-                        }
-                        
-                    }
+    async fn handle_shutdown(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Only the bootstrap starts a ring shutdown: it sends the request around the ring,
+        every peer passes it on before stopping and the bootstrap exits last */
+        match data {
+            MsgData::Shutdown {  } => {
+                if self.bootstrap.is_some() {
+                    let fw_msg = Message::new(
+                        MsgType::Shutdown,
+                        client,
+                        &MsgData::Shutdown {  }
+                    );
+                    self.send_msg(self.bootstrap, &fw_msg).await;
+                    return;
+                }
 
-                    _ => self.print_debug_msg(&format!("Unsupported Consistency model - {:?}", cons))
+                let succ_node = self.get_succ().await;
+                if succ_node.is_none() || succ_node.unwrap().id == self.get_id() {
+                    self.stop_node(client, 1).await;
+                    return;
                 }
+
+                let fw_msg = Message::new(
+                    MsgType::FwShutdown,
+                    client,
+                    &MsgData::FwShutdown { header: self.get_id(), stopped: 0 }
+                );
+                self.send_msg(succ_node, &fw_msg).await;
             }
             _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
         }
     }
 
-    async fn handle_ack_delete(&self, data:&MsgData) {
-        /* used for linearizability only
-            implement the physical delete here */
+    async fn handle_fw_shutdown(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::AckDelete { key } => {
-    self.print_debug_msg("Acquiring read lock on records...");
-            let record_reader = self.records.read().await;
-    self.print_debug_msg("Read lock acquired on records.");
-            let record = record_reader.get(&key).cloned();
-            drop(record_reader);
-                match record {
-                    Some(exist) => {
-                        let idx = exist.replica_idx.clone();
-                        if exist.pending {
-                            //drop(record_reader); 
-    self.print_debug_msg("Acquiring write lock on records...");
-                            self.records.write().await.remove(&key);
-    self.print_debug_msg("Write lock released on records.");
-                        } else {
-                            self.print_debug_msg("Error: 'logical' delete must occur first");
-                            return;
-                        }
-                        if  idx > 0 {
-                            let ack_del = Message::new(
-                                MsgType::AckDelete,
-                                None,
-                                &MsgData::AckDelete { key: *key }
-                            );
-                            
-                            self.send_msg(self.get_prev().await, &ack_del).await;
-                            return;
-                        }
-                        else if idx == 0  {
-                            // notify waiting readers on this key
-                            let waiting_list = self.pendings.read().await;
-
-                            if let Some(notify) = waiting_list.get(&key) {
-                                self.print_debug_msg("Sent a notification to ");
-                                notify.notify_waiters();  
-                                // remove this from queue
-                                //waiting_list.remove(&key);
-                            }
-                        }
-                    }
-                    _ => self.print_debug_msg("Wrong delete ack received"),
+            MsgData::FwShutdown { header, stopped } => {
+                if *header == self.get_id() {
+                    // circle completed, bootstrap goes last
+                    self.stop_node(client, stopped + 1).await;
+                    return;
                 }
-            }
 
-            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data)),
+                let fw_msg = Message::new(
+                    MsgType::FwShutdown,
+                    client,
+                    &MsgData::FwShutdown { header: *header, stopped: stopped + 1 }
+                );
+                self.send_msg(self.get_succ().await, &fw_msg).await;
+                self.stop_node(None, 0).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
         }
     }
 
+    async fn stop_node(&self, client:Option<&NodeInfo>, stopped:u32) {
+    /* Refuses any new request and drops local state before the process exits. Nothing is
+        persisted or handed off: every node of the ring stops, so its records are lost with it.
+        Reads still blocked on a pending key are woken with an error rather than dropped */
+        self.set_status(false);
+        for (_, wait) in self.pendings.write().await.drain() {
+            wait.expire();
+        }
+        self.records.write().await.clear();
+        self.stored_bytes.store(0, Ordering::SeqCst);
+
+        if let Some(user) = client {
+            let user_msg = Message::new(
+                MsgType::Reply,
+                None,
+                &MsgData::Reply { reply: format!("Ring shut down: {} node(s) stopped", stopped) }
+            );
+            user.send_msg(&user_msg).await;
+        }
+        self.print_debug_msg(&format!("Node {} is shutting down", self.get_info()));
+        self.stopped.notify_one();
+    }
 
     async fn handle_fw_overlay(&self, client:Option<&NodeInfo>, data:&MsgData) {
     /* send an Info message to successor in a circular loop 
         until it reaches myself again */
         match data {
-            MsgData::FwOverlay { peers } => {
-                if peers[0].id == self.get_id() {
+            MsgData::FwOverlay { peers, gather, counts, structured, bytes } => {
+                if peers[0].id == self.get_id() && *gather {
+                    self.gather_records(client, peers).await;
+                } else if peers[0].id == self.get_id() {
                     // circle completed here so return peers to user
-                    let user_msg = Message::new (
-                        MsgType::Reply,
-                        None,
-                        &MsgData::Reply { reply: utils::format_overlay_msg(&peers)}
-                    );
-                    client.unwrap().send_msg(&user_msg).await;
+                    self.reply_overlay(client, peers, counts, bytes, *structured).await;
+                } else if peers.iter().any(|peer| peer.id == self.get_id()) {
+                    // a successor pointer led back into the ring instead of to the start
+                    self.reply_stalled_sweep(client, peers, &format!("the sweep came back to it without passing {}:{}",
+                                                                    peers[0].ip_addr, peers[0].port)).await;
                 } else {
                     let mut peers_clone = peers.clone();
                     peers_clone.push(self.get_info());
+                    let mut counts_clone = counts.clone();
+                    let mut bytes_clone = bytes.clone();
+                    if !*gather {
+                        counts_clone.push(self.primary_count().await);
+                        bytes_clone.push(self.stored_bytes());
+                    }
                     let fw_msg = Message::new(
                         MsgType::FwOverlay,
                         client,
-                        &MsgData::FwOverlay { peers: peers_clone }
+                        &MsgData::FwOverlay { peers: peers_clone.clone(), gather: *gather, counts: counts_clone, structured: *structured, bytes: bytes_clone }
                     );
             
-                    self.send_msg(self.get_succ().await, &fw_msg).await; 
+                    self.forward_sweep(client, &peers_clone, &fw_msg).await;
                 }
 
             }
@@ -1758,32 +5708,236 @@ async fn sleep_on_updates(&self, key_hash: HashType) {
 
     }
 
+    /* Passes an overlay sweep to the successor. A sweep that cannot go on is answered right
+        away, naming this node as the one it stalled at, instead of leaving the client waiting */
+    async fn forward_sweep(&self, client:Option<&NodeInfo>, peers:&[NodeInfo], fw_msg:&Message) {
+        let reason = match self.get_succ().await {
+            Some(succ) => match self.send_msg(Some(succ), fw_msg).await {
+                Some(_) => return,
+                None => format!("its successor {}:{} is unreachable", succ.ip_addr, succ.port)
+            },
+            None => "it has no successor".to_string()
+        };
+        self.reply_stalled_sweep(client, peers, &reason).await;
+    }
+
+    async fn reply_stalled_sweep(&self, client:Option<&NodeInfo>, peers:&[NodeInfo], reason:&str) {
+        eprintln!("Overlay sweep stalled at {}: {}", self, reason);
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply: format!("Error: RingBroken: sweep stalled at {}:{} after reaching {} node(s), {}",
+                                             self.get_ip(), self.get_port(), peers.len(), reason) }
+        );
+        if let Some(client) = client {
+            client.send_msg(&user_msg).await;
+        }
+    }
+
+    // structured replies leave the rendering (e.g. DOT) to the cli
+    async fn reply_overlay(&self, client:Option<&NodeInfo>, peers:&[NodeInfo], counts:&[usize], bytes:&[u64], structured:bool) {
+        let data = if structured {
+            MsgData::Ring { peers: peers.to_vec(), counts: counts.to_vec(), bytes: bytes.to_vec() }
+        } else {
+            MsgData::Reply { reply: utils::format_overlay_msg(peers) }
+        };
+        let user_msg = Message::new(MsgType::Reply, None, &data);
+        client.unwrap().send_msg(&user_msg).await;
+    }
+
     async fn handle_overlay(&self, client:Option<&NodeInfo>, data:&MsgData) {
         match data {
-            MsgData::Overlay {  } => {
+            MsgData::Overlay { structured } => {
                 let mut netvec : Vec<NodeInfo> = Vec::new();
                 netvec.push(self.get_info());
+                let counts = vec![self.primary_count().await];
+                let bytes = vec![self.stored_bytes()];
 
                 let succ_node = self.get_succ().await;
                 if succ_node.unwrap().id == self.get_id() {
                     // node is alone 
+                    self.reply_overlay(client, &netvec, &counts, &bytes, *structured).await;
+                    return;
+                }
+                // begin the traversal
+                let fw_msg = Message::new(
+                    MsgType::FwOverlay,
+                    client,
+                    &MsgData::FwOverlay { peers: netvec.clone(), gather: false, counts, structured: *structured, bytes }
+                );
+                self.forward_sweep(client, &netvec, &fw_msg).await;
+
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_who_is_my_neighbor(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* Repairs the neighbours of a single node without a full rejoin.
+        The asker hands the request to the bootstrap which walks the still healthy part of
+        the ring twice: forward through successors to find the asker's previous, then
+        backward through previous pointers to find its successor. Each walk stops at the
+        first node adjacent to the asker's id and sends it an 'Update', the backward walk
+        then replies with the neighbours both walks set */
+        match data {
+            MsgData::WhoIsMyNeighbor { asker, forward, header, previous } => {
+                if header.is_none() {
+                    match self.bootstrap {
+                        // the cli names the asker by address, the walks need the id it was placed at
+                        Some(_) if (asker.ip_addr, asker.port) == (self.get_ip(), self.get_port()) => {
+                            let ask_msg = Message::new(
+                                MsgType::WhoIsMyNeighbor,
+                                client,
+                                &MsgData::WhoIsMyNeighbor { asker: self.get_info(), forward: true, header: None, previous: None }
+                            );
+                            self.send_msg(self.bootstrap, &ask_msg).await;
+                        }
+                        Some(_) => self.print_debug_msg("Neighbour request must start from the asker"),
+                        None if asker.id == self.get_id() => {
+                            let user_msg = Message::new(
+                                MsgType::Reply,
+                                None,
+                                &MsgData::Reply { reply: "Bootstrap node is the reference point and cannot repair itself".to_string() }
+                            );
+                            client.unwrap().send_msg(&user_msg).await;
+                        }
+                        None => self.locate_neighbor(client, asker, true, self.get_id(), None).await
+                    }
+                    return;
+                }
+                self.locate_neighbor(client, asker, *forward, header.unwrap(), *previous).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn locate_neighbor(&self, client:Option<&NodeInfo>, asker:&NodeInfo, forward:bool, header:HashType, previous:Option<NodeInfo>) {
+        let next = if forward { self.get_succ().await } else { self.get_prev().await };
+        let adjacent = match next {
+            Some(next_node) => next_node.id == asker.id ||
+                               (forward && utils::in_arc(asker.id, self.get_id(), next_node.id)) ||
+                               (!forward && utils::in_arc(asker.id, next_node.id, self.get_id())),
+            None => false
+        };
+
+        if adjacent {
+            let (prev_info, succ_info) = if forward { (Some(self.get_info()), None) } else { (None, Some(self.get_info())) };
+            let update_msg = Message::new(
+                MsgType::Update,
+                None,
+                &MsgData::Update { prev_info, succ_info }
+            );
+            asker.send_msg(&update_msg).await;
+            if forward {
+                self.start_backward_walk(client, asker, header, Some(self.get_info())).await;
+            } else {
+                self.reply_neighbors(client, asker, previous, Some(self.get_info())).await;
+            }
+            return;
+        }
+
+        // a walk that cannot go on leaves that side unrepaired, the other one still runs
+        let stalled = match next {
+            None => Some("missing pointer".to_string()),
+            Some(next_node) if next_node.id == header => Some(format!("no neighbour found for {} after a full walk", asker)),
+            Some(_) => None
+        };
+        if let Some(reason) = stalled {
+            self.print_debug_msg(&format!("Neighbour walk stopped: {}", reason));
+            if forward {
+                self.start_backward_walk(client, asker, header, None).await;
+            } else {
+                self.reply_neighbors(client, asker, previous, None).await;
+            }
+            return;
+        }
+
+        let fw_msg = Message::new(
+            MsgType::WhoIsMyNeighbor,
+            client,
+            &MsgData::WhoIsMyNeighbor { asker: *asker, forward, header: Some(header), previous }
+        );
+        self.send_msg(next, &fw_msg).await;
+    }
+
+    // the backward walk starts over at the bootstrap, which is where the forward walk started
+    async fn start_backward_walk(&self, client:Option<&NodeInfo>, asker:&NodeInfo, header:HashType, previous:Option<NodeInfo>) {
+        let start = if self.bootstrap.is_none() { Some(self.get_info()) } else { self.bootstrap };
+        let fw_msg = Message::new(
+            MsgType::WhoIsMyNeighbor,
+            client,
+            &MsgData::WhoIsMyNeighbor { asker: *asker, forward: false, header: Some(header), previous }
+        );
+        self.send_msg(start, &fw_msg).await;
+    }
+
+    async fn reply_neighbors(&self, client:Option<&NodeInfo>, asker:&NodeInfo, previous:Option<NodeInfo>, successor:Option<NodeInfo>) {
+        let found = |neighbor:Option<NodeInfo>| neighbor.map_or("not found".to_string(), |node| node.to_string());
+        let reply = match (previous, successor) {
+            (None, None) => format!("Error: no neighbours found for node {}", asker),
+            _ => format!("Node {} repaired its neighbours: previous is {}, successor is {}", asker, found(previous), found(successor))
+        };
+        let user_msg = Message::new(
+            MsgType::Reply,
+            None,
+            &MsgData::Reply { reply }
+        );
+        if let Some(user) = client {
+            user.send_msg(&user_msg).await;
+        }
+    }
+
+    async fn handle_clock_check(&self, client:Option<&NodeInfo>, data:&MsgData) {
+        match data {
+            MsgData::ClockCheck {  } => {
+                let samples = vec![utils::ClockSample { node: self.get_info(), now: Utc::now() }];
+
+                let succ_node = self.get_succ().await;
+                if succ_node.unwrap().id == self.get_id() {
+                    // node is alone
                     let user_msg = Message::new(
                         MsgType::Reply,
                         None,
-                        &MsgData::Reply{ reply: utils::format_overlay_msg(&netvec)}
+                        &MsgData::Reply { reply: utils::format_clockcheck_msg(&samples, Utc::now()) }
                     );
-
                     client.unwrap().send_msg(&user_msg).await;
                     return;
                 }
-                // begin the traversal
+
                 let fw_msg = Message::new(
-                    MsgType::FwOverlay,
+                    MsgType::FwClockCheck,
                     client,
-                    &MsgData::FwOverlay { peers: netvec }
+                    &MsgData::FwClockCheck { samples }
                 );
-                self.send_msg(succ_node, &fw_msg).await;  
+                self.send_msg(succ_node, &fw_msg).await;
+            }
+            _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
+        }
+    }
+
+    async fn handle_fw_clock_check(&self, client:Option<&NodeInfo>, data:&MsgData) {
+    /* each node appends its own wall clock and passes the list on,
+        the initiator closes the circle and computes the skew table */
+        match data {
+            MsgData::FwClockCheck { samples } => {
+                if samples[0].node.id == self.get_id() {
+                    let user_msg = Message::new(
+                        MsgType::Reply,
+                        None,
+                        &MsgData::Reply { reply: utils::format_clockcheck_msg(samples, Utc::now()) }
+                    );
+                    client.unwrap().send_msg(&user_msg).await;
+                } else {
+                    let mut samples_clone = samples.clone();
+                    samples_clone.push(utils::ClockSample { node: self.get_info(), now: Utc::now() });
+                    let fw_msg = Message::new(
+                        MsgType::FwClockCheck,
+                        client,
+                        &MsgData::FwClockCheck { samples: samples_clone }
+                    );
 
+                    self.send_msg(self.get_succ().await, &fw_msg).await;
+                }
             }
             _ => self.print_debug_msg(&format!("Unexpected data - {:?}", data))
         }
@@ -1807,17 +5961,66 @@ impl ConnectionHandler for Node {
         let mut reader = BufReader::new(stream);
         let mut total_data = Vec::new();
         let mut buffer = [0; 1024];
+        // kept until the request has been handled, which is as long as total_data lives
+        let mut hold = self.reassembly.as_ref().map(|budget| budget.hold());
+        /* A peer that stops half way through a message, or never stops sending,
+           must not keep this task around: reads share one deadline and the buffer is capped */
+        let deadline = Instant::now() + Duration::from_millis(crate::REQUEST_READ_TIMEOUT_MS);
+        // the client named ahead of the data, who hears about a request refused for its size
+        let mut header: Option<Option<NodeInfo>> = None;
 
         loop {
-            match reader.read(&mut buffer).await {
+            let read = match tokio::time::timeout_at(deadline, reader.read(&mut buffer)).await {
+                Ok(read) => read,
+                Err(_) => {
+                    eprintln!("Gave up on an incomplete message from {} after {} bytes", peer_addr, total_data.len());
+                    return;
+                }
+            };
+            match read {
                 Ok(0) => {
                     eprintln!("Connection closed by peer.");
                     return;
                 }
                 Ok(n) => {
                     total_data.extend_from_slice(&buffer[..n]);
+                    /* The size is announced first, so a request that cannot fit is refused as soon
+                       as the fields ahead of its data have arrived, before any of the body is read */
+                    if header.is_none() {
+                        let (fields, complete) = messages::header_fields(&total_data);
+                        if complete || total_data.len() > self.max_message_bytes.min(crate::MAX_HEADER_BYTES) {
+                            let client = fields.get("client")
+                                .and_then(|c| serde_json::from_value::<NodeInfo>(c.clone()).ok());
+                            let announced = fields.get("size").and_then(|v| v.as_u64()).map(|size| size as usize);
+                            if let Some(size) = announced.filter(|size| *size > self.max_message_bytes) {
+                                eprintln!("Message from {} announces {} bytes, over the {} limit", peer_addr, size, self.max_message_bytes);
+                                drop(total_data);
+                                self.reject_request(client.as_ref(),
+                                    &format!("message of {} bytes exceeds the {} byte limit", size, self.max_message_bytes)).await;
+                                return;
+                            }
+                            header = Some(client);
+                        }
+                    }
+                    if total_data.len() > self.max_message_bytes {
+                        eprintln!("Message from {} exceeds {} bytes, dropped", peer_addr, self.max_message_bytes);
+                        drop(total_data);
+                        self.reject_request(header.flatten().as_ref(),
+                            &format!("message exceeds the {} byte limit", self.max_message_bytes)).await;
+                        return;
+                    }
+                    if let Some(hold) = hold.as_mut() {
+                        if !hold.cover(total_data.len(), deadline).await {
+                            eprintln!("Reassembly budget exhausted, message from {} dropped after {} bytes", peer_addr, total_data.len());
+                            return;
+                        }
+                    }
                     
-                    // Try to parse as JSON
+                    /* Try to parse as JSON. A message can only be complete once the data ends in a
+                       closing brace, parsing on every read would make large transfers quadratic */
+                    if total_data.iter().rev().find(|byte| !byte.is_ascii_whitespace()) != Some(&b'}') {
+                        continue;
+                    }
                     match serde_json::from_slice::<Value>(&total_data) {
                         Ok(json_value) => {
                             // Ensure the "size" field exists
@@ -1829,18 +6032,39 @@ impl ConnectionHandler for Node {
                                 }
                             };
 
+                            // only reached by messages whose start could not be read as fields
+                            if total_size > self.max_message_bytes {
+                                eprintln!("Message from {} announces {} bytes, over the {} limit", peer_addr, total_size, self.max_message_bytes);
+                                let sender = json_value.get("client")
+                                    .and_then(|c| serde_json::from_value::<NodeInfo>(c.clone()).ok());
+                                drop(total_data);
+                                self.reject_request(sender.as_ref(),
+                                    &format!("message of {} bytes exceeds the {} byte limit", total_size, self.max_message_bytes)).await;
+                                return;
+                            }
+
                             // Keep reading until we receive the expected number of bytes
                             while total_data.len() < total_size {
                                 let mut chunk = vec![0; 1024];
-                                let bytes_read = match reader.read(&mut chunk).await {
-                                    Ok(0) => break, // Connection closed
-                                    Ok(n) => n,
-                                    Err(e) => {
+                                let bytes_read = match tokio::time::timeout_at(deadline, reader.read(&mut chunk)).await {
+                                    Ok(Ok(0)) => break, // Connection closed
+                                    Ok(Ok(n)) => n,
+                                    Ok(Err(e)) => {
                                         eprintln!("Error while reading from stream: {}", e);
                                         return;
                                     }
+                                    Err(_) => {
+                                        eprintln!("Gave up on an incomplete message from {} after {} of {} bytes", peer_addr, total_data.len(), total_size);
+                                        return;
+                                    }
                                 };
                                 total_data.extend_from_slice(&chunk[..bytes_read]);
+                                if let Some(hold) = hold.as_mut() {
+                                    if !hold.cover(total_data.len(), deadline).await {
+                                        eprintln!("Reassembly budget exhausted, message from {} dropped after {} bytes", peer_addr, total_data.len());
+                                        return;
+                                    }
+                                }
                             }
 
                             // Deserialize the complete JSON
@@ -1852,11 +6076,42 @@ impl ConnectionHandler for Node {
                                 }
                             };
 
+                            /* Check the protocol version before decoding the body so that peers
+                               running an incompatible release get a clear reply instead of a serde error.
+                               Peers that predate versioning send none and count as version 0 */
+                            let version = full_json.get("proto_version").and_then(|v| v.as_u64()).unwrap_or(0) as u16;
+                            if messages::proto_major(version) != messages::proto_major(messages::PROTO_VERSION) {
+                                eprintln!("Protocol version mismatch: got {:#06x}, expected {:#06x}", version, messages::PROTO_VERSION);
+                                let sender = full_json.get("client")
+                                    .and_then(|c| serde_json::from_value::<NodeInfo>(c.clone()).ok());
+                                if let Some(sender) = sender {
+                                    let error_msg = Message::new(
+                                        MsgType::Reply,
+                                        None,
+                                        &MsgData::Reply {
+                                            reply: format!("Error: protocol version mismatch (node speaks {:#06x}, request used {:#06x})",
+                                                            messages::PROTO_VERSION, version),
+                                        },
+                                    );
+                                    sender.send_msg(&error_msg).await;
+                                }
+                                return;
+                            }
+
                             // Convert JSON Value into Message struct
-                            let msg: Message = match serde_json::from_value(full_json) {
+                            let sender = full_json.get("client")
+                                .and_then(|c| serde_json::from_value::<NodeInfo>(c.clone()).ok());
+                            let msg: Message = match serde_json::from_value(full_json.clone()) {
                                 Ok(msg) => msg,
                                 Err(e) => {
                                     eprintln!("Failed to convert JSON value to Message: {}", e);
+                                    // a newer peer may send a type this node doesn't know yet
+                                    let reason = match full_json.get("type") {
+                                        Some(kind) if serde_json::from_value::<MsgType>(kind.clone()).is_err() =>
+                                            format!("unsupported message type {}", kind),
+                                        _ => format!("malformed message ({})", e)
+                                    };
+                                    self.reject_request(sender.as_ref(), &reason).await;
                                     return;
                                 }
                             };
@@ -1865,18 +6120,27 @@ impl ConnectionHandler for Node {
 
                             let sender_info = msg.extract_client();
                             let msg_type = msg.extract_type();
-                            let msg_data = msg.extract_data();
+                            let mut msg_data = msg.extract_data();
+                            let key_norm = self.key_norm().await;
+                            if !key_norm.is_identity() {
+                                msg_data.normalize_keys(&key_norm);
+                            }
 
                             match msg_type {
-                                MsgType::Join | MsgType::AckJoin => (),
+                                MsgType::Join | MsgType::AckJoin | MsgType::Transfer => (),
                                 _ => {
-                                    if !self.get_status() {
+                                    let unavailable = if !self.get_status() {
+                                        Some(self.offline_reply().await)
+                                    } else if !self.is_linked().await && !matches!(msg_type, MsgType::Update | MsgType::WhoIsMyNeighbor) {
+                                        Some(format!("Node {} has not joined the ring yet", self.get_info()))
+                                    } else {
+                                        None
+                                    };
+                                    if let Some(reply) = unavailable {
                                         let error_msg = Message::new(
                                             MsgType::Reply,
                                             None,
-                                            &MsgData::Reply {
-                                                reply: format!("Node {} is offline", self.get_info()),
-                                            },
+                                            &MsgData::Reply { reply },
                                         );
                                         if let Some(sender) = sender_info {
                                             sender.send_msg(&error_msg).await;
@@ -1886,28 +6150,93 @@ impl ConnectionHandler for Node {
                                 }
                             }
 
-                            match msg_type {
-                                MsgType::Join => self.join_ring(sender_info).await,
-                                MsgType::FwJoin => self.handle_join(sender_info, &msg_data).await,
-                                MsgType::AckJoin => self.handle_ack_join(sender_info, &msg_data).await,
-                                MsgType::Update => self.handle_update(&msg_data).await,
-                                MsgType::Quit => self.handle_quit(sender_info, &msg_data).await,
-                                MsgType::Query => self.handle_query(sender_info, &msg_data).await,
-                                MsgType::FwQuery => self.handle_fw_query(sender_info, &msg_data).await,
-                                MsgType::QueryAll => self.handle_query_all(sender_info, &msg_data).await,
-                                MsgType::FwQueryAll => self.handle_fw_query_all(sender_info, &msg_data).await,
-                                MsgType::Insert => self.handle_insert(sender_info, &msg_data).await,
-                                MsgType::FwInsert => self.handle_fw_insert(sender_info, &msg_data).await,
-                                MsgType::AckInsert => self.handle_ack_insert(&msg_data).await,
-                                MsgType::Delete => self.handle_delete(sender_info, &msg_data).await,
-                                MsgType::FwDelete => self.handle_fw_delete(sender_info, &msg_data).await,
-                                MsgType::AckDelete => self.handle_ack_delete(&msg_data).await,
-                                MsgType::Overlay => self.handle_overlay(sender_info, &msg_data).await,
-                                MsgType::FwOverlay => self.handle_fw_overlay(sender_info, &msg_data).await,
-                                MsgType::Relocate => self.handle_relocate(&msg_data).await,
-                                _ => eprintln!("Invalid message type: {:?}", msg_type),
+                            if !self.admit_request(peer_addr.ip(), msg_type, msg.extract_sender()).await {
+                                eprintln!("Rate limit exceeded by {}, {:?} rejected", peer_addr.ip(), msg_type);
+                                let error_msg = Message::new(
+                                    MsgType::Reply,
+                                    None,
+                                    &MsgData::Reply { reply: format!("Error: TooManyRequests: rate limit of node {} exceeded, retry later", self.get_info()) },
+                                );
+                                if let Some(sender) = sender_info {
+                                    sender.send_msg(&error_msg).await;
+                                }
+                                return;
                             }
 
+                            // everything sent while handling this request carries its trace id and this node as sender
+                            let trace_id = msg.extract_trace_id().unwrap_or_else(Uuid::new_v4);
+                            messages::SENDER.scope(self.get_info(), messages::TRACE_ID.scope(trace_id, async {
+                                match msg_type {
+                                    MsgType::Join => self.join_ring(sender_info).await,
+                                    MsgType::FwJoin => self.handle_join(sender_info, &msg_data).await,
+                                    MsgType::AckJoin => self.handle_ack_join(sender_info, &msg_data).await,
+                                    MsgType::Update => self.handle_update(&msg_data).await,
+                                    MsgType::Quit => self.handle_quit(sender_info, &msg_data).await,
+                                    MsgType::Query => self.handle_query(sender_info, &msg_data).await,
+                                    MsgType::FwQuery => self.handle_fw_query(sender_info, &msg_data).await,
+                                    MsgType::QueryAll => self.handle_query_all(sender_info, &msg_data).await,
+                                    MsgType::FwQueryAll => self.handle_fw_query_all(sender_info, &msg_data).await,
+                                    MsgType::Insert => self.handle_insert(sender_info, &msg_data).await,
+                                    MsgType::FwInsert => self.handle_fw_insert(sender_info, &msg_data).await,
+                                    MsgType::AckInsert => self.handle_ack_insert(sender_info, &msg_data).await,
+                                    MsgType::Delete => self.handle_delete(sender_info, &msg_data).await,
+                                    MsgType::FwDelete => self.handle_fw_delete(sender_info, &msg_data).await,
+                                    MsgType::AckDelete => self.handle_ack_delete(sender_info, &msg_data).await,
+                                    MsgType::Overlay => self.handle_overlay(sender_info, &msg_data).await,
+                                    MsgType::FwOverlay => self.handle_fw_overlay(sender_info, &msg_data).await,
+                                    MsgType::Relocate => self.handle_relocate(&msg_data).await,
+                                    MsgType::ClockCheck => self.handle_clock_check(sender_info, &msg_data).await,
+                                    MsgType::FwClockCheck => self.handle_fw_clock_check(sender_info, &msg_data).await,
+                                    MsgType::WhoIsMyNeighbor => self.handle_who_is_my_neighbor(sender_info, &msg_data).await,
+                                    MsgType::Touch => self.handle_touch(sender_info, &msg_data).await,
+                                    MsgType::FwTouch => self.handle_fw_touch(&msg_data).await,
+                                    MsgType::Shutdown => self.handle_shutdown(sender_info, &msg_data).await,
+                                    MsgType::FwShutdown => self.handle_fw_shutdown(sender_info, &msg_data).await,
+                                    MsgType::Repair => self.handle_repair(sender_info, &msg_data).await,
+                                    MsgType::AckRepair => self.handle_ack_repair(&msg_data).await,
+                                    MsgType::LocalRecords => self.handle_local_records(reader.get_mut()).await,
+                                    MsgType::Proxy => self.handle_proxy(reader.get_mut(), &msg_data).await,
+                                    MsgType::SyncKey => self.handle_sync_key(sender_info, &msg_data).await,
+                                    MsgType::FwSyncKey => self.handle_fw_sync_key(sender_info, &msg_data).await,
+                                    MsgType::Transfer => self.handle_transfer(&msg_data).await,
+                                    MsgType::MultiGet | MsgType::MultiQuery => self.handle_multi_get(sender_info, &msg_data).await,
+                                    MsgType::Reply => self.handle_reply(&msg_data).await,
+                                    MsgType::FwMultiGet => self.handle_fw_multi_get(sender_info, &msg_data).await,
+                                    MsgType::Gossip => self.handle_gossip(&msg_data).await,
+                                    MsgType::Members => self.handle_members(sender_info, &msg_data).await,
+                                    MsgType::Compact => self.handle_compact(sender_info, &msg_data).await,
+                                    MsgType::SimulateJoin => self.handle_simulate_join(sender_info, &msg_data).await,
+                                    MsgType::NodeRecords => self.handle_node_records(sender_info, &msg_data).await,
+                                    MsgType::Append => self.handle_append(sender_info, &msg_data).await,
+                                    MsgType::ReadLog => self.handle_read_log(sender_info, &msg_data).await,
+                                    MsgType::Pin | MsgType::FwPin => self.handle_pin(sender_info, &msg_data).await,
+                                    MsgType::Subscribe | MsgType::Unsubscribe => self.handle_subscribe(sender_info, &msg_data).await,
+                                    MsgType::Pendings => self.handle_pendings(sender_info, &msg_data).await,
+                                    MsgType::CheckReplicas | MsgType::FwCheckReplicas => self.handle_check_replicas(sender_info, &msg_data).await,
+                                    MsgType::Span => self.handle_span(&msg_data).await,
+                                    MsgType::Trace => self.handle_trace(sender_info, &msg_data).await,
+                                    MsgType::CancelPending => self.handle_cancel_pending(sender_info, &msg_data).await,
+                                    MsgType::SetReadOnly => self.handle_set_read_only(sender_info, &msg_data).await,
+                                    MsgType::ReadStats => self.handle_read_stats(sender_info, &msg_data).await,
+                                    MsgType::TimeRangeQuery | MsgType::FwTimeRangeQuery => self.handle_time_range_query(sender_info, &msg_data).await,
+                                    MsgType::Rename => self.handle_rename(sender_info, &msg_data).await,
+                                    MsgType::DeadLetters => self.handle_dead_letters(sender_info, &msg_data).await,
+                                    MsgType::Reassembly => self.handle_reassembly(sender_info, &msg_data).await,
+                                    MsgType::VerifyReplicas | MsgType::FwVerifyReplicas => self.handle_verify_replicas(sender_info, &msg_data).await,
+                                    MsgType::KeyCount | MsgType::FwKeyCount => self.handle_key_count(sender_info, &msg_data).await,
+                                    MsgType::InspectKey | MsgType::FwInspectKey => self.handle_inspect_key(sender_info, &msg_data).await,
+                                    MsgType::ReplicaSet | MsgType::FwReplicaSet => self.handle_replica_set(sender_info, &msg_data).await,
+                                    MsgType::FwQuorumRead => self.handle_fw_quorum_read(sender_info, &msg_data).await,
+                                    MsgType::Reconfigure => self.handle_reconfigure(sender_info, &msg_data).await,
+                                    #[cfg(feature = "testing")]
+                                    MsgType::ForceRelocate => self.handle_force_relocate(sender_info, &msg_data).await,
+                                    _ => {
+                                        eprintln!("Invalid message type: {:?}", msg_type);
+                                        self.reject_request(sender_info, &format!("unsupported message type {:?}", msg_type)).await;
+                                    }
+                                }
+                            })).await;
+
                             return; // Successfully processed the message
                         }
                         Err(_) => {
@@ -1940,8 +6269,8 @@ impl fmt::Display for ReplicationConfig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Replication [ k: {}, consistency: {:?}, replica_ranges: {:?}]",
-            self.replication_factor, self.replication_mode, self.replica_ranges
+            "Replication [ k: {}, consistency: {:?}, R: {}, W: {}, reply_from_head: {}, key_norm: {}, replica_ranges: {:?}]",
+            self.replication_factor, self.replication_mode, self.read_quorum, self.write_quorum, self.reply_from_head, self.key_norm, self.replica_ranges
         )
     }
 }
@@ -1963,7 +6292,10 @@ impl fmt::Display for Node {
             Replica Managers: {:?},
  Status: {:?}
 ]",
-            self.info, *prev, *succ, replica_config, self.status
+            self.get_info(), *prev, *succ, replica_config, self.status
         )
     }
 }
+
+#[cfg(test)]
+mod tests;
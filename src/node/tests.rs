@@ -0,0 +1,247 @@
+/* In-process rings for the node tests. Every node serves on its own port of the local address
+    from the test's runtime, so a ring is torn down with the runtime when its test returns.
+    Replies come back to a listener the test binds, the way they reach the cli */
+
+use std::net::TcpListener as StdListener;
+use std::sync::atomic::AtomicU16;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use super::*;
+use crate::utils::get_local_ip;
+
+mod bench;
+mod join;
+mod lifecycle;
+mod partition;
+mod protocol;
+mod quorum;
+mod reads;
+mod replication;
+mod writes;
+
+const REPLY_WAIT: Duration = Duration::from_secs(15);
+const SETTLE_WAIT: Duration = Duration::from_secs(10);
+
+// handed out once per test process, ports some other process holds are skipped
+static NEXT_PORT: AtomicU16 = AtomicU16::new(21000);
+
+pub(super) fn free_port() -> u16 {
+    loop {
+        let port = NEXT_PORT.fetch_add(1, Ordering::SeqCst);
+        assert!(port < 32000, "out of test ports");
+        if StdListener::bind((get_local_ip(), port)).is_ok() {
+            return port;
+        }
+    }
+}
+
+// starts the node's server and waits until it is listening
+pub(super) async fn serve(node: &Node) {
+    let server = node.clone();
+    tokio::spawn(async move { server.init().await });
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while !node.get_status() {
+        assert!(Instant::now() < deadline, "node {} did not start", node.get_info());
+        sleep(Duration::from_millis(5)).await;
+    }
+}
+
+// whether the node accepts connections, false once its server stopped
+pub(super) async fn listening(node: &Node) -> bool {
+    tokio::net::TcpStream::connect((node.get_ip(), node.get_port())).await.is_ok()
+}
+
+// a bootstrap keeping 'copies' copies of every key, alone in its ring
+pub(super) async fn bootstrap(copies: u8, mode: Consistency, opts: NodeOptions) -> Node {
+    let node = Node::new(&get_local_ip(), Some(free_port()), Some(copies - 1), Some(mode), None, opts);
+    serve(&node).await;
+    node
+}
+
+// a peer that is served but has not joined yet
+pub(super) async fn peer(boot: &Node, opts: NodeOptions) -> Node {
+    let node = Node::new(&get_local_ip(), Some(free_port()), None, None, Some(boot.get_info()), opts);
+    serve(&node).await;
+    node
+}
+
+pub(super) async fn join(node: &Node) -> String {
+    reply(node, MsgType::Join, MsgData::Join { }).await
+}
+
+pub(super) async fn depart(node: &Node) -> String {
+    reply(node, MsgType::Quit, MsgData::Quit { warm: false, drain: false }).await
+}
+
+// a bootstrap and size - 1 peers joined one after the other, with every pointer in place
+pub(super) async fn ring(copies: u8, mode: Consistency, size: usize) -> Vec<Node> {
+    ring_with(copies, mode, size, NodeOptions::default).await
+}
+
+pub(super) async fn ring_with(copies: u8, mode: Consistency, size: usize, opts: impl Fn() -> NodeOptions) -> Vec<Node> {
+    let boot = bootstrap(copies, mode, opts()).await;
+    let mut nodes = vec![boot.clone()];
+    for _ in 1..size {
+        let node = peer(&boot, opts()).await;
+        let joined = join(&node).await;
+        assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+        nodes.push(node);
+        settle(&nodes).await;
+    }
+    nodes
+}
+
+/* Waits until successor pointers run once through all nodes and every predecessor agrees,
+    then leaves a moment for the relocations the last topology change sent along the ring */
+pub(super) async fn settle(nodes: &[Node]) {
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while !is_cycle(nodes).await {
+        assert!(Instant::now() < deadline, "ring did not settle");
+        sleep(Duration::from_millis(20)).await;
+    }
+    sleep(Duration::from_millis(300)).await;
+}
+
+async fn is_cycle(nodes: &[Node]) -> bool {
+    let mut neighbours = HashMap::new();
+    for node in nodes {
+        neighbours.insert(node.get_id(), node.neighbors().await);
+    }
+    let mut at = nodes[0].get_id();
+    for _ in 0..nodes.len() {
+        let succ = match neighbours.get(&at).and_then(|(_, succ)| *succ) {
+            Some(succ) => succ.id,
+            None => return false,
+        };
+        match neighbours.get(&succ) {
+            Some((Some(prev), _)) if prev.id == at => at = succ,
+            _ => return false,
+        }
+    }
+    at == nodes[0].get_id()
+}
+
+// replies are read from a listener of the test, as the cli reads them from its response port
+pub(super) struct Client {
+    listener: TcpListener,
+    info: NodeInfo,
+}
+
+impl Client {
+    pub(super) async fn bind() -> Client {
+        let listener = TcpListener::bind((get_local_ip(), 0)).await.expect("bind client");
+        let port = listener.local_addr().expect("client address").port();
+        Client { listener, info: NodeInfo::new(get_local_ip(), port) }
+    }
+
+    pub(super) fn info(&self) -> NodeInfo {
+        self.info
+    }
+
+    pub(super) async fn send(&self, to: &Node, msg_type: MsgType, data: MsgData) {
+        send_raw(to, &serde_json::json!(Message::new(msg_type, Some(&self.info), &data)).to_string()).await;
+    }
+
+    pub(super) async fn recv(&self) -> MsgData {
+        self.try_recv(REPLY_WAIT).await.expect("no reply in time")
+    }
+
+    pub(super) async fn try_recv(&self, within: Duration) -> Option<MsgData> {
+        let (mut stream, _) = timeout(within, self.listener.accept()).await.ok()?.ok()?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.expect("read reply");
+        let msg: Message = serde_json::from_slice(&response).expect("reply is a message");
+        Some(msg.extract_data())
+    }
+}
+
+pub(super) async fn send_raw(to: &Node, payload: &str) {
+    let mut stream = tokio::net::TcpStream::connect((to.get_ip(), to.get_port())).await.expect("connect to node");
+    stream.write_all(payload.as_bytes()).await.expect("send request");
+    stream.write_all(b"\n").await.expect("send request");
+}
+
+pub(super) async fn request(to: &Node, msg_type: MsgType, data: MsgData) -> MsgData {
+    let client = Client::bind().await;
+    client.send(to, msg_type, data).await;
+    client.recv().await
+}
+
+pub(super) async fn reply(to: &Node, msg_type: MsgType, data: MsgData) -> String {
+    match request(to, msg_type, data).await {
+        MsgData::Reply { reply } => reply,
+        other => panic!("expected a reply, got {:?}", other),
+    }
+}
+
+pub(super) fn insert_data(key: &str, value: &str) -> MsgData {
+    MsgData::Insert { key: key.to_string(), value: value.to_string(), wait_for_replication: false, consistency: None,
+                      colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None }
+}
+
+pub(super) async fn insert(to: &Node, key: &str, value: &str) -> String {
+    reply(to, MsgType::Insert, insert_data(key, value)).await
+}
+
+pub(super) fn query_data(key: &str, kind: QueryKind, prefer: ReadPreference) -> MsgData {
+    MsgData::Query { key: key.to_string(), kind, prefer, consistency: None, colocate_with: None }
+}
+
+// the record a query entering at 'to' is answered with
+pub(super) async fn read(to: &Node, key: &str) -> Option<Item> {
+    match request(to, MsgType::Query, query_data(key, QueryKind::Record, ReadPreference::Nearest)).await {
+        MsgData::Record { record, .. } => record,
+        other => panic!("expected a record, got {:?}", other),
+    }
+}
+
+// the local copy of key on every node holding one, looked up without messages
+pub(super) async fn copies(nodes: &[Node], key: &str) -> Vec<(NodeInfo, Item)> {
+    let mut found = Vec::new();
+    for node in nodes {
+        if let Some(item) = node.records.read().await.get(&HashFunc(key)) {
+            found.push((node.get_info(), item.clone()));
+        }
+    }
+    found
+}
+
+// polls until every copy of key is in place or the wait is over, then returns the copies
+pub(super) async fn copies_when(nodes: &[Node], key: &str, done: impl Fn(&[(NodeInfo, Item)]) -> bool) -> Vec<(NodeInfo, Item)> {
+    let deadline = Instant::now() + SETTLE_WAIT;
+    loop {
+        let found = copies(nodes, key).await;
+        if done(&found) || Instant::now() > deadline {
+            return found;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+// the node a Chord ring of these ids places 'position' on
+pub(super) fn chord_owner(ids: &[HashType], position: HashType) -> HashType {
+    let mut ids = ids.to_vec();
+    ids.sort();
+    ids.iter().find(|id| **id >= position).copied().unwrap_or(ids[0])
+}
+
+// the node holding the copy of key at replica_idx, found without messages
+pub(super) async fn holder<'a>(nodes: &'a [Node], key: &str, replica_idx: u8) -> &'a Node {
+    for node in nodes {
+        if node.records.read().await.get(&HashFunc(key)).is_some_and(|item| item.replica_idx == replica_idx) {
+            return node;
+        }
+    }
+    panic!("no copy {} of {}", replica_idx, key);
+}
+
+pub(super) async fn overwrite(node: &Node, key: &str, value: &str, timestamp: DateTime<Utc>) {
+    let mut records = node.records.write().await;
+    let item = records.get_mut(&HashFunc(key)).expect("copy to overwrite");
+    item.value = value.to_string();
+    item.timestamp = timestamp;
+    item.checksum = Some(utils::value_checksum(value));
+}
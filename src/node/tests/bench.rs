@@ -0,0 +1,38 @@
+/* Benchmarks over an in-process ring. They take a while to build their rings and only print
+    their measurements, so they are ignored by default:
+    cargo test --release -- --ignored --nocapture bench */
+
+use super::*;
+
+const RING_SIZE: usize = 32;
+const KEYS: usize = 256;
+const ROUNDS: usize = 20;
+
+async fn query_all_latency(entry: &Node, parallel: bool) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(ROUNDS);
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let all = reply(entry, MsgType::QueryAll, MsgData::QueryAll { parallel, restart_on_churn: false }).await;
+        latencies.push(start.elapsed());
+        assert!((0..KEYS).all(|i| all.contains(&format!("key{}", i))), "parallel={} missed records", parallel);
+    }
+    latencies.sort();
+    latencies
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn bench_query_all_sequential_vs_parallel() {
+    let nodes = ring(1, Consistency::Eventual, RING_SIZE).await;
+    for i in 0..KEYS {
+        insert(&nodes[i % RING_SIZE], &format!("key{}", i), "value").await;
+    }
+
+    for parallel in [false, true] {
+        let latencies = query_all_latency(&nodes[0], parallel).await;
+        let mean = latencies.iter().sum::<Duration>() / ROUNDS as u32;
+        println!("mode={} nodes={} rounds={} mean_us={} p50_us={} max_us={}",
+                 if parallel { "parallel" } else { "sequential" }, RING_SIZE, ROUNDS,
+                 mean.as_micros(), latencies[ROUNDS / 2].as_micros(), latencies[ROUNDS - 1].as_micros());
+    }
+}
@@ -0,0 +1,604 @@
+use super::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_successor_is_repaired_by_neighbour_request() {
+    let nodes = ring(1, Consistency::Eventual, 3).await;
+    let (_, succ) = nodes[1].neighbors().await;
+    let expected = succ.map(|succ| succ.id);
+    nodes[1].set_succ(None).await;
+
+    let ask = MsgData::WhoIsMyNeighbor { asker: nodes[1].get_info(), forward: true, header: None, previous: None };
+    let repaired = reply(&nodes[1], MsgType::WhoIsMyNeighbor, ask).await;
+    assert!(repaired.contains("repaired its neighbours"), "{}", repaired);
+    assert!(repaired.contains(&format!("successor is {}", succ.unwrap())), "{}", repaired);
+
+    settle(&nodes).await;
+    let (_, succ) = nodes[1].neighbors().await;
+    assert_eq!(succ.map(|succ| succ.id), expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_previous_is_named_in_the_neighbour_reply() {
+    let nodes = ring(1, Consistency::Eventual, 3).await;
+    let (prev, _) = nodes[2].neighbors().await;
+    nodes[2].set_prev(None).await;
+
+    let ask = MsgData::WhoIsMyNeighbor { asker: nodes[2].get_info(), forward: true, header: None, previous: None };
+    let repaired = reply(&nodes[2], MsgType::WhoIsMyNeighbor, ask).await;
+    assert!(repaired.contains(&format!("previous is {}", prev.unwrap())), "{}", repaired);
+
+    settle(&nodes).await;
+    let (now, _) = nodes[2].neighbors().await;
+    assert_eq!(now.map(|prev| prev.id), prev.map(|prev| prev.id));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replica_ranges_follow_the_predecessors_after_joins() {
+    let copies = 3;
+    let nodes = ring(copies, Consistency::Eventual, 5).await;
+    for i in 0..20 {
+        insert(&nodes[i % nodes.len()], &format!("key{}", i), "value").await;
+    }
+
+    let mut ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    ids.sort();
+    for node in nodes.iter() {
+        let at = ids.iter().position(|id| *id == node.get_id()).unwrap();
+        let pred = |back: usize| ids[(at + ids.len() - back) % ids.len()];
+        let ranges = node.snapshot_ranges().await;
+        // replica i covers the range the i-th predecessor is primary for, up to and including its id
+        for back in 1..copies as usize {
+            assert_eq!(ranges.is_subset(pred(back)), back as i16, "{} for predecessor {}: {:?}", node.get_info(), back, ranges);
+        }
+        assert_eq!(ranges.is_subset(pred(copies as usize)), -1, "{}: {:?}", node.get_info(), ranges);
+        assert_eq!(ranges.is_subset(node.get_id()), -1, "{}: {:?}", node.get_info(), ranges);
+    }
+
+    // every key ends up with one copy per replica index, each on the node whose ranges say so
+    for i in 0..20 {
+        let name = format!("key{}", i);
+        copies_when(&nodes, &name, |found| found.len() == copies as usize).await;
+        let key = HashFunc(&name);
+        let mut held = Vec::new();
+        for node in nodes.iter() {
+            for (hash, idx, pending) in node.record_keys().await {
+                if hash == key {
+                    assert!(!pending);
+                    assert_eq!(node.is_replica_manager(&key).await, idx as i16, "{} holds key{}", node.get_info(), i);
+                    held.push(idx);
+                }
+            }
+        }
+        held.sort();
+        assert_eq!(held, vec![0, 1, 2], "key{}", i);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn large_join_transfer_arrives_in_bounded_chunks() {
+    const ITEMS: usize = 100_000;
+    // the whole transfer is tens of megabytes, a node drops any message above this unread
+    let bounded = || NodeOptions { max_message_bytes: 1024 * 1024, ..NodeOptions::default() };
+    let boot = bootstrap(2, Consistency::Eventual, bounded()).await;
+    {
+        let mut records = boot.records.write().await;
+        for i in 0..ITEMS {
+            let item = Item::new(&format!("key{}", i), "value", 0, false);
+            records.insert(item.key_hash(), item);
+        }
+    }
+
+    // with two copies the new node takes over every key, as primary or as replica
+    let node = peer(&boot, bounded()).await;
+    let joined = join(&node).await;
+    assert!(joined.contains("joined the ring"), "{}", joined);
+    let nodes = [boot.clone(), node.clone()];
+    settle(&nodes).await;
+
+    let received = node.records.read().await.clone();
+    let kept = boot.records.read().await.clone();
+    assert_eq!(received.len(), ITEMS);
+    assert_eq!(kept.len(), ITEMS);
+    for (key, item) in received.iter() {
+        let other = kept.get(key).expect("both nodes hold every key");
+        assert_eq!(item.replica_idx + other.replica_idx, 1, "{}", item.title);
+        assert_eq!(item.replica_idx == 0, node.is_responsible(key).await, "{}", item.title);
+    }
+    assert!(node.incoming.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn staged_chunks_stay_with_their_transfer() {
+    let node = bootstrap(2, Consistency::Eventual, NodeOptions::default()).await;
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    for (transfer, title) in [(first, "a"), (second, "b"), (first, "c")] {
+        let items = vec![Item::new(title, "value", 1, false)];
+        node.handle_transfer(&MsgData::Transfer { items, prefetch: false, transfer }).await;
+    }
+
+    let titles = |items: Vec<Item>| items.into_iter().map(|item| item.title).collect::<Vec<_>>();
+    assert_eq!(titles(node.take_staged(first).await), vec!["a", "c"]);
+    assert_eq!(titles(node.take_staged(second).await), vec!["b"]);
+    assert!(node.take_staged(first).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejoin_goes_through_gossiped_members_when_the_bootstrap_is_down() {
+    let nodes = ring(1, Consistency::Eventual, 4).await;
+    let deadline = Instant::now() + SETTLE_WAIT;
+    for node in nodes.iter() {
+        while node.members.read().await.len() < nodes.len() - 1 {
+            assert!(Instant::now() < deadline, "gossip did not reach {}", node.get_info());
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+    let keys: Vec<String> = (0..40).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[1], key, key).await;
+    }
+
+    // ring order from the bootstrap on, the node two steps after it has no link to the bootstrap
+    let mut order: Vec<&Node> = nodes.iter().collect();
+    order.sort_by_key(|node| node.get_id());
+    let boot_at = order.iter().position(|node| node.get_id() == nodes[0].get_id()).unwrap();
+    order.rotate_left(boot_at);
+    let leaving = order[2];
+    let left = depart(leaving).await;
+    assert!(!left.starts_with("Error"), "{}", left);
+    settle(&[order[0].clone(), order[1].clone(), order[3].clone()]).await;
+
+    nodes[0].stopped.notify_one();
+    while tokio::net::TcpStream::connect((nodes[0].get_ip(), nodes[0].get_port())).await.is_ok() {
+        assert!(Instant::now() < deadline + SETTLE_WAIT, "bootstrap is still serving");
+        sleep(Duration::from_millis(20)).await;
+    }
+    let joined = join(leaving).await;
+    assert!(joined.contains("joined the ring"), "rejoin failed: {}", joined);
+    let (prev, succ) = leaving.neighbors().await;
+    assert_eq!((prev.map(|node| node.id), succ.map(|node| node.id)), (Some(order[1].get_id()), Some(order[3].get_id())));
+
+    /* lookups entering at the previous of the owner are forwarded once, never through the bootstrap:
+        the rejoined node is reached from the node before it and took its keys back */
+    let ids: Vec<HashType> = order.iter().map(|node| node.get_id()).collect();
+    let mut found = 0;
+    for (entry, owner) in [(order[1], leaving), (leaving, order[3])] {
+        for key in keys.iter().filter(|key| chord_owner(&ids, HashFunc(key)) == owner.get_id()) {
+            assert!(owner.records.read().await.contains_key(&HashFunc(key)), "{} is not on its owner", key);
+            assert_eq!(read(entry, key).await.expect("key routed to its owner").value, *key);
+            found += 1;
+        }
+    }
+    assert!(found > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn seed_file_builds_the_ring_once() {
+    let boot = bootstrap(2, Consistency::Eventual, NodeOptions::default()).await;
+    let mut nodes = vec![boot.clone()];
+    for _ in 0..4 {
+        nodes.push(peer(&boot, NodeOptions::default()).await);
+    }
+    // the bootstrap is listed too, it is already in the ring
+    let content: String = nodes.iter()
+        .map(|node| format!("[[nodes]]\nip = \"{}\"\nport = {}\n", node.get_ip(), node.get_port()))
+        .collect();
+    let seeds: crate::cli::SeedFile = toml::from_str(&content).expect("valid seed file");
+    let seeds = Arc::new(seeds);
+
+    let first = Arc::clone(&seeds);
+    let summary = tokio::task::spawn_blocking(move || crate::cli::join_seeds(&first)).await.expect("seed run");
+    assert_eq!(summary, Ok("5 seed(s): 4 joined, 1 already present, 0 failed".to_string()));
+    settle(&nodes).await;
+
+    // every node sits between the nodes before and after it by id
+    let mut order: Vec<&Node> = nodes.iter().collect();
+    order.sort_by_key(|node| node.get_id());
+    for (i, node) in order.iter().enumerate() {
+        let (prev, succ) = node.neighbors().await;
+        assert_eq!(prev.map(|prev| prev.id), Some(order[(i + order.len() - 1) % order.len()].get_id()));
+        assert_eq!(succ.map(|succ| succ.id), Some(order[(i + 1) % order.len()].get_id()));
+    }
+
+    // a second run finds every seed in place and changes nothing
+    let summary = tokio::task::spawn_blocking(move || crate::cli::join_seeds(&seeds)).await.expect("seed run");
+    assert_eq!(summary, Ok("5 seed(s): 0 joined, 5 already present, 0 failed".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn simulated_join_predicts_the_transfer_of_the_real_one() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    for i in 0..40 {
+        insert(&nodes[i % nodes.len()], &format!("key{}", i), "value").await;
+    }
+    for i in 0..40 {
+        copies_when(&nodes, &format!("key{}", i), |found| found.len() == 2).await;
+    }
+
+    let node = peer(&nodes[0], NodeOptions::default()).await;
+    let preview = reply(&nodes[1], MsgType::SimulateJoin, MsgData::SimulateJoin { at_id: node.get_id() }).await;
+    let count = |before: &str| -> usize {
+        let head = preview.split(before).next().unwrap_or_else(|| panic!("no '{}' in {}", before, preview));
+        head.split_whitespace().last().and_then(|n| n.parse().ok()).unwrap_or_else(|| panic!("no count before '{}' in {}", before, preview))
+    };
+    let (moving, transferred) = (count(" key(s) would change primary"), count(" record(s) would be transferred"));
+    assert!(transferred > 0, "{}", preview);
+
+    let joined = join(&node).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    let received = node.records.read().await.clone();
+    assert_eq!(received.len(), transferred, "{}", preview);
+    assert_eq!(received.values().filter(|item| item.replica_idx == 0).count(), moving, "{}", preview);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_over_a_broken_successor_pointer_fails_cleanly() {
+    let nodes = ring(1, Consistency::Eventual, 3).await;
+    let boot = &nodes[0];
+    let (prev, _) = boot.neighbors().await;
+    let prev = prev.expect("bootstrap has a previous");
+    // the successor pointer skips the rest of the ring, so joins the bootstrap does not own circle on it
+    boot.set_succ(Some(boot.get_info())).await;
+
+    let node = loop {
+        let candidate = peer(boot, NodeOptions::default()).await;
+        if !ChordPartitioner.owns(&candidate.get_id(), &prev.id, &boot.get_id()) {
+            break candidate;
+        }
+    };
+    let joined = join(&node).await;
+    assert!(joined.starts_with("Error: ring appears inconsistent"), "{}", joined);
+    let (prev, succ) = node.neighbors().await;
+    assert!(prev.is_none() && succ.is_none(), "{:?} {:?}", prev, succ);
+    assert!(node.records.read().await.is_empty());
+    assert!(boot.members.read().await.get(&node.get_id()).is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_right_after_a_join_are_not_stale() {
+    for mode in [Consistency::Chain, Consistency::Eventual] {
+        let nodes = ring(2, mode, 3).await;
+        let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+        for key in keys.iter() {
+            insert(&nodes[0], key, "current").await;
+        }
+        let node = peer(&nodes[0], NodeOptions::default()).await;
+        let joined = join(&node).await;
+        assert!(joined.contains("joined the ring"), "{}", joined);
+
+        // replica copies the new node was handed have not caught up, as if a write had overtaken the transfer
+        let handed: Vec<String> = node.records.read().await.values()
+            .filter(|item| item.replica_idx > 0)
+            .map(|item| item.title.clone())
+            .collect();
+        if matches!(mode, Consistency::Eventual) {
+            assert!(!handed.is_empty(), "the new node was handed no replica copies");
+            for key in handed.iter() {
+                overwrite(&node, key, "stale", Utc::now() - chrono::Duration::seconds(60)).await;
+            }
+        }
+        assert!(node.grace_left().await.is_some(), "{:?}: read after the grace period", mode);
+        for key in keys.iter() {
+            let found = read(&node, key).await.expect("key was stored");
+            assert_eq!(found.value, "current", "{:?}: {}", mode, key);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cli_join_and_depart_act_on_the_addressed_node() {
+    let mut nodes = ring(2, Consistency::Eventual, 3).await;
+    for i in 0..20 {
+        insert(&nodes[0], &format!("key{}", i), "value").await;
+    }
+    let node = peer(&nodes[0], NodeOptions::default()).await;
+    let (ip, port) = (node.get_ip(), node.get_port());
+    let cli = move |command: &str| {
+        let args: Vec<String> = vec!["dht".to_string(), "cli".to_string(), ip.to_string(), port.to_string(), command.to_string()];
+        tokio::task::spawn_blocking(move || crate::cli::run_command(ip, port, &args))
+    };
+
+    assert!(cli("join").await.expect("cli join"));
+    nodes.push(node.clone());
+    settle(&nodes).await;
+    assert!(node.is_linked().await);
+    assert!(nodes[..3].iter().all(|other| other.get_status()), "the join moved another node");
+
+    assert!(cli("depart").await.expect("cli depart"));
+    nodes.pop();
+    settle(&nodes).await;
+    assert!(!node.get_status());
+    assert!(node.records.read().await.is_empty());
+    for i in 0..20 {
+        let key = format!("key{}", i);
+        assert_eq!(copies_when(&nodes, &key, |found| found.len() == 2).await.len(), 2, "{}", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn capped_ring_refuses_the_extra_join_through_any_member() {
+    let nodes = ring_with(1, Consistency::Eventual, 3, || NodeOptions { max_nodes: Some(3), ..NodeOptions::default() }).await;
+    for entry in nodes.iter() {
+        let node = peer(&nodes[0], NodeOptions { join_via: Some(entry.get_info()), ..NodeOptions::default() }).await;
+        let refused = join(&node).await;
+        assert!(refused.starts_with("Error: ring full, 3 of 3 nodes joined"), "entering at {}: {}", entry.get_info(), refused);
+        assert!(!node.is_linked().await);
+    }
+    match request(&nodes[1], MsgType::Overlay, MsgData::Overlay { structured: true }).await {
+        MsgData::Ring { peers, .. } => assert_eq!(peers.len(), 3),
+        other => panic!("expected the ring, got {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_and_depart_are_audited_in_order() {
+    let log_of = |name: &str| std::env::temp_dir().join(format!("dht-audit-{}-{}.log", name, std::process::id()));
+    let (boot_log, peer_log) = (log_of("boot"), log_of("peer"));
+    for log in [&boot_log, &peer_log] {
+        let _ = std::fs::remove_file(log);
+    }
+    let audited = |log: &std::path::PathBuf| NodeOptions { audit_log: Some(log.to_string_lossy().into_owned()), ..NodeOptions::default() };
+    let boot = bootstrap(2, Consistency::Eventual, audited(&boot_log)).await;
+    let other = peer(&boot, audited(&peer_log)).await;
+    let joined = join(&other).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    settle(&[boot.clone(), other.clone()]).await;
+    let departed = depart(&other).await;
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    settle(std::slice::from_ref(&boot)).await;
+
+    let (boot_id, peer_id) = (boot.get_id().to_string(), other.get_id().to_string());
+    // the events of a node's log, checked to be its own and in time order
+    let events = |log: &std::path::PathBuf, id: &str| {
+        let mut last = None;
+        let mut events = Vec::new();
+        for line in std::fs::read_to_string(log).expect("audit log written").lines() {
+            let mut fields = line.splitn(3, ' ');
+            let at = DateTime::parse_from_rfc3339(fields.next().expect("timestamp")).expect("rfc3339 timestamp");
+            assert!(last.is_none_or(|last| last <= at), "out of order: {}", line);
+            last = Some(at);
+            assert_eq!(fields.next(), Some(id), "{}", line);
+            events.push(fields.next().expect("event").to_string());
+        }
+        events
+    };
+    // each expected event is found after the one before it
+    let assert_in_order = |events: &[String], expected: &[String]| {
+        let mut rest = events.iter();
+        for want in expected {
+            assert!(rest.any(|event| event.starts_with(want.as_str())), "{:?} missing or out of order in {:?}", want, events);
+        }
+    };
+    assert_in_order(&events(&boot_log, &boot_id), &[
+        "started a ring alone".to_string(),
+        "ranges UnionRange".to_string(),
+        format!("prev {} -> {}", boot_id, peer_id),
+        format!("succ {} -> {}", boot_id, peer_id),
+        format!("succ {} -> {}", peer_id, boot_id),
+        format!("prev {} -> {}", peer_id, boot_id),
+        "relocate depart".to_string(),
+    ]);
+    assert_in_order(&events(&peer_log, &peer_id), &[
+        format!("prev none -> {}", boot_id),
+        format!("succ none -> {}", boot_id),
+        "joined the ring".to_string(),
+        "departed the ring".to_string(),
+    ]);
+    for log in [&boot_log, &peer_log] {
+        let _ = std::fs::remove_file(log);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn second_join_inside_the_stability_window_is_told_to_retry() {
+    let stabilizing = || NodeOptions { stabilize_ms: Some(1500), ..NodeOptions::default() };
+    let boot = bootstrap(2, Consistency::Eventual, stabilizing()).await;
+    let (first, second) = (peer(&boot, stabilizing()).await, peer(&boot, stabilizing()).await);
+    let joined = join(&first).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+
+    // both nodes the second join could land at took part in the first one, whichever range it falls in
+    let refused = join(&second).await;
+    assert!(refused.starts_with("Error: Stabilizing") && refused.contains("retry in"), "{}", refused);
+    assert!(second.get_prev().await.is_none() && second.get_succ().await.is_none());
+    settle(&[boot.clone(), first.clone()]).await;
+
+    sleep(Duration::from_millis(1500)).await;
+    let joined = join(&second).await;
+    assert!(joined.contains("joined the ring"), "retried join failed: {}", joined);
+    let nodes = vec![boot, first, second];
+    settle(&nodes).await;
+    assert!(is_cycle(&nodes).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_via_a_member_places_the_node_without_the_bootstrap() {
+    let mut nodes = ring(2, Consistency::Eventual, 3).await;
+    for i in 0..30 {
+        insert(&nodes[i % nodes.len()], &format!("key{}", i), "value").await;
+    }
+    // the bootstrap it was given does not answer, only the member does
+    let unreachable = NodeInfo::new(get_local_ip(), free_port());
+    let opts = NodeOptions { join_via: Some(nodes[2].get_info()), ..NodeOptions::default() };
+    let node = Node::new(&get_local_ip(), Some(free_port()), None, None, Some(unreachable), opts);
+    serve(&node).await;
+    let joined = join(&node).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    nodes.push(node.clone());
+    settle(&nodes).await;
+
+    let mut ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    ids.sort();
+    let at = ids.iter().position(|id| *id == node.get_id()).expect("joined id");
+    let (prev, succ) = (ids[(at + ids.len() - 1) % ids.len()], ids[(at + 1) % ids.len()]);
+    assert_eq!(node.get_prev().await.map(|prev| prev.id), Some(prev));
+    assert_eq!(node.get_succ().await.map(|succ| succ.id), Some(succ));
+    for i in 0..30 {
+        let key = format!("key{}", i);
+        let owner = chord_owner(&ids, HashFunc(&key));
+        let found = copies_when(&nodes, &key, |found| found.len() == 2 && found.iter().any(|(node, item)| node.id == owner && item.replica_idx == 0)).await;
+        assert!(found.len() == 2 && found.iter().any(|(node, item)| node.id == owner && item.replica_idx == 0), "{}: {:?}", key, found);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_forced_onto_a_member_id_is_refused_as_a_collision() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    insert(&nodes[0], "key", "value").await;
+    // the bootstrap answers the join itself, a peer's id is found among the members
+    for taken in [&nodes[0], &nodes[2]] {
+        let intruder = peer(&nodes[0], NodeOptions::default()).await;
+        intruder.info.write().unwrap().id = taken.get_id();
+        let joined = join(&intruder).await;
+        assert!(joined.starts_with("Error: IdCollision"), "{}", joined);
+        assert!(joined.contains(&taken.get_info().to_string()), "{}", joined);
+        assert!(intruder.get_succ().await.is_none() && intruder.get_prev().await.is_none());
+    }
+    settle(&nodes).await;
+    assert_eq!(copies(&nodes, "key").await.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reply_deadline_grows_with_the_members_learnt() {
+    let boot = bootstrap(1, Consistency::Eventual, NodeOptions::default()).await;
+    let hop = Duration::from_millis(crate::HOP_TIMEOUT_MS);
+    let base = Duration::from_millis(crate::GATHER_TIMEOUT_MS);
+    assert_eq!(boot.ring_timeout(crate::GATHER_TIMEOUT_MS).await, base + hop);
+
+    let mut nodes = vec![boot.clone()];
+    for _ in 0..4 {
+        let node = peer(&boot, NodeOptions::default()).await;
+        let joined = join(&node).await;
+        assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+        nodes.push(node);
+        settle(&nodes).await;
+    }
+    // members are learnt by gossip, one hop is added for every node the deadline may cross
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while boot.members.read().await.len() < 4 && Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(boot.ring_timeout(crate::GATHER_TIMEOUT_MS).await, base + hop * 5);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn health_after_churn_reports_a_healthy_ring() {
+    let mut nodes = ring(2, Consistency::Eventual, 3).await;
+    let keys: Vec<String> = (0..30).map(|i| format!("key{}", i)).collect();
+    for (i, key) in keys.iter().enumerate() {
+        insert(&nodes[i % nodes.len()], key, "value").await;
+    }
+
+    // one node joins and another departs, each settled before the next
+    let newcomer = peer(&nodes[0], NodeOptions::default()).await;
+    let joined = join(&newcomer).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    nodes.push(newcomer);
+    settle(&nodes).await;
+    let leaving = nodes.remove(1);
+    let departed = depart(&leaving).await;
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    settle(&nodes).await;
+    for key in keys.iter() {
+        assert_eq!(copies_when(&nodes, key, |found| found.len() == 2).await.len(), 2, "{} under-replicated", key);
+    }
+
+    let (ip, port) = (nodes[0].get_ip(), nodes[0].get_port());
+    let args: Vec<String> = ["--sample", "50"].iter().map(|arg| arg.to_string()).collect();
+    let report = tokio::task::spawn_blocking(move || crate::cli::run_health(ip, port, &args)).await.expect("health run").expect("health report");
+    assert_eq!(report["healthy"], true, "{}", report);
+    assert_eq!((report["nodes"].as_u64(), report["keys"].as_u64(), report["keys_sampled"].as_u64()), (Some(3), Some(30), Some(30)), "{}", report);
+    for metric in ["wrong_replica_count", "max_replica_divergence", "replica_problems", "routing_errors"] {
+        assert_eq!(report[metric], 0, "{}: {}", metric, report);
+    }
+    assert_eq!(report["ring_violations"], serde_json::json!([]), "{}", report);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn same_seed_draws_the_same_gossip_samples() {
+    let port = free_port();
+    let seeded = |seed| Node::new(&get_local_ip(), Some(port), Some(1), Some(Consistency::Eventual), None, NodeOptions { seed, ..NodeOptions::default() });
+    let members: Vec<NodeInfo> = (0..40).map(|i| NodeInfo::new(get_local_ip(), 30000 + i)).collect();
+    let mut samples = Vec::new();
+    for node in [seeded(Some(7)), seeded(Some(7)), seeded(Some(8))] {
+        node.learn_members(&members).await;
+        let mut drawn = Vec::new();
+        for _ in 0..5 {
+            drawn.push(node.sample_members().await.iter().map(|peer| peer.get_port()).collect::<Vec<u16>>());
+        }
+        samples.push(drawn);
+    }
+    assert_eq!(samples[0], samples[1]);
+    assert_ne!(samples[0], samples[2]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sweep_past_a_broken_successor_names_the_node_it_stalled_at() {
+    let nodes = ring(1, Consistency::Eventual, 3).await;
+    let (_, entry) = nodes[1].neighbors().await;
+    let entry = nodes.iter().find(|node| Some(node.get_id()) == entry.map(|succ| succ.id)).expect("successor is a member");
+    let dead = NodeInfo::new(get_local_ip(), free_port());
+    nodes[1].set_succ(Some(dead)).await;
+
+    // entering just after the broken node, the sweep reaches every member before it stalls
+    let stalled = reply(entry, MsgType::Overlay, MsgData::Overlay { structured: true }).await;
+    assert!(stalled.starts_with(&format!("Error: RingBroken: sweep stalled at {}:{} after reaching 3 node(s)",
+                                         nodes[1].get_ip(), nodes[1].get_port())), "{}", stalled);
+    assert!(stalled.contains(&format!("its successor {}:{} is unreachable", dead.ip_addr, dead.port)), "{}", stalled);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn second_node_and_the_bootstrap_become_each_others_both_neighbours() {
+    let neighbour_ids = |node: &Node| {
+        let node = node.clone();
+        async move {
+            let (prev, succ) = node.neighbors().await;
+            (prev.map(|prev| prev.id), succ.map(|succ| succ.id))
+        }
+    };
+    let boot = bootstrap(2, Consistency::Eventual, NodeOptions::default()).await;
+    assert_eq!(neighbour_ids(&boot).await, (Some(boot.get_id()), Some(boot.get_id())));
+
+    let other = peer(&boot, NodeOptions::default()).await;
+    let joined = join(&other).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    let nodes = vec![boot.clone(), other.clone()];
+    settle(&nodes).await;
+    assert_eq!(neighbour_ids(&boot).await, (Some(other.get_id()), Some(other.get_id())));
+    assert_eq!(neighbour_ids(&other).await, (Some(boot.get_id()), Some(boot.get_id())));
+
+    // the 2-cycle carries both copies of every key, whichever node it enters at
+    for i in 0..10 {
+        insert(&nodes[i % 2], &format!("key{}", i), "value").await;
+    }
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        assert_eq!(copies_when(&nodes, &key, |found| found.len() == 2).await.len(), 2, "{} under-replicated", key);
+        assert_eq!(read(&nodes[(i + 1) % 2], &key).await.map(|item| item.value), Some("value".to_string()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_that_no_ring_answers_fails_or_starts_a_ring_by_policy() {
+    let unreachable = NodeInfo::new(get_local_ip(), free_port());
+    let alone = |empty_ring: EmptyRing| async move {
+        let opts = NodeOptions { empty_ring, ..NodeOptions::default() };
+        let node = Node::new(&get_local_ip(), Some(free_port()), None, None, Some(unreachable), opts);
+        serve(&node).await;
+        node
+    };
+
+    let refused = alone(EmptyRing::Fail).await;
+    let joined = join(&refused).await;
+    assert!(joined.starts_with(&format!("Error: EmptyRing: no ring answered at {}", unreachable)), "{}", joined);
+    assert!(!refused.is_linked().await);
+
+    let started = alone(EmptyRing::Start).await;
+    let joined = join(&started).await;
+    assert!(joined.contains("found no ring") && joined.contains("started one on its own"), "{}", joined);
+    assert!(started.is_linked().await);
+    let (prev, succ) = started.neighbors().await;
+    assert_eq!((prev.map(|prev| prev.id), succ.map(|succ| succ.id)), (Some(started.get_id()), Some(started.get_id())));
+    let inserted = insert(&started, "solo", "value").await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+    assert_eq!(read(&started, "solo").await.map(|item| item.value), Some("value".to_string()));
+}
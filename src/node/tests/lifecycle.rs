@@ -0,0 +1,191 @@
+use super::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_ring_stops_every_node() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    insert(&nodes[1], "key", "value").await;
+
+    // asked at a peer, the request is handed to the bootstrap which stops last
+    let stopped = reply(&nodes[2], MsgType::Shutdown, MsgData::Shutdown { }).await;
+    assert!(stopped.contains("4 node(s) stopped"), "{}", stopped);
+
+    let deadline = Instant::now() + SETTLE_WAIT;
+    for node in nodes.iter() {
+        while listening(node).await {
+            assert!(Instant::now() < deadline, "node {} is still serving", node.get_info());
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!node.get_status());
+        assert!(node.records.read().await.is_empty());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_without_neighbours_claims_no_key() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    insert(&nodes[0], "key", "value").await;
+    // online, but its join has not set the pointers yet
+    let joining = peer(&nodes[0], NodeOptions::default()).await;
+    joining.set_status(true);
+
+    assert!(!joining.is_responsible(&HashFunc("key")).await);
+    assert_eq!(joining.is_replica_manager(&HashFunc("key")).await, -1);
+    for (msg_type, data) in [(MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Nearest)), (MsgType::Insert, insert_data("other", "value"))] {
+        let answer = reply(&joining, msg_type, data).await;
+        assert!(answer.contains("has not joined the ring yet"), "{}", answer);
+    }
+    assert!(joining.records.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_answers_reads_blocked_on_a_pending_key() {
+    let nodes = ring(1, Consistency::Chain, 1).await;
+    insert(&nodes[0], "key", "value").await;
+    nodes[0].records.write().await.get_mut(&HashFunc("key")).expect("inserted").pending = true;
+
+    let reader = Client::bind().await;
+    reader.send(&nodes[0], MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Nearest)).await;
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while nodes[0].pendings.read().await.get(&HashFunc("key")).map_or(0, |wait| wait.waiters.load(Ordering::SeqCst)) == 0 {
+        assert!(Instant::now() < deadline, "the read did not block");
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let stopped = reply(&nodes[0], MsgType::Shutdown, MsgData::Shutdown { }).await;
+    assert!(stopped.contains("1 node(s) stopped"), "{}", stopped);
+    match reader.recv().await {
+        MsgData::Reply { reply } => assert!(reply.starts_with("Error: pending update"), "{}", reply),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn depart_waits_out_a_briefly_unreachable_successor() {
+    let mut nodes = ring(2, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..30).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+    }
+    for key in keys.iter() {
+        copies_when(&nodes, key, |found| found.len() == 2).await;
+    }
+
+    // a peer whose successor is a peer too, so the successor can be restarted as it was
+    let mut at = 1;
+    while nodes[at].get_succ().await.expect("linked").id == nodes[0].get_id() {
+        at += 1;
+    }
+    let leaving = nodes.remove(at);
+    let succ_id = leaving.get_succ().await.expect("linked").id;
+    let succ = nodes.iter().find(|node| node.get_id() == succ_id).expect("successor in the ring").clone();
+    succ.stopped.notify_one();
+    while listening(&succ).await {
+        sleep(Duration::from_millis(5)).await;
+    }
+    let restarted = succ.clone();
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(300)).await;
+        restarted.init().await;
+    });
+
+    let left = depart(&leaving).await;
+    assert!(left.contains("has left the network"), "{}", left);
+    assert!(listening(&succ).await);
+    settle(&nodes).await;
+    for key in keys.iter() {
+        assert_eq!(copies_when(&nodes, key, |found| found.len() == 2).await.len(), 2, "{}", key);
+        assert_eq!(read(&nodes[0], key).await.expect("key survived the depart").value, "value");
+    }
+}
+
+/* How long the departing node hands its keys over after it stops doing anything else:
+    from the Quit for a reactive depart, from the end of the prefetch for a warm one,
+    until the node goes offline. Every key is then checked on the successor */
+async fn handover_window(warm: bool, count: usize) -> Duration {
+    let nodes = ring(1, Consistency::Eventual, 2).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let (leaving, succ) = (&nodes[1], &nodes[0]);
+    let keys: Vec<String> = (0..).map(|i| format!("key{}", i))
+        .filter(|key| chord_owner(&ids, HashFunc(key)) == leaving.get_id())
+        .take(count)
+        .collect();
+    let value = "v".repeat(4096);
+    for key in keys.iter() {
+        insert(leaving, key, &value).await;
+    }
+
+    let quitting = leaving.clone();
+    let started = Instant::now();
+    let departed = tokio::spawn(async move { reply(&quitting, MsgType::Quit, MsgData::Quit { warm, drain: false }).await });
+    let mut cutover = if warm { None } else { Some(started) };
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while leaving.get_status() {
+        if cutover.is_none() && succ.prefetched.read().await.len() == count {
+            cutover = Some(Instant::now());
+        }
+        assert!(Instant::now() < deadline, "depart did not finish");
+        sleep(Duration::from_millis(1)).await;
+    }
+    let window = cutover.map_or(Duration::ZERO, |cutover| cutover.elapsed());
+    let departed = departed.await.expect("depart task");
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    for key in keys.iter() {
+        assert_eq!(read(succ, key).await.map(|item| item.value.len()), Some(value.len()), "{} lost", key);
+    }
+    window
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warm_depart_hands_over_in_a_shorter_window_than_a_reactive_one() {
+    let reactive = handover_window(false, 1500).await;
+    let warm = handover_window(true, 1500).await;
+    assert!(warm < reactive, "warm {:?} not shorter than reactive {:?}", warm, reactive);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_at_a_departed_primary_is_redirected_to_its_replica() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    // a key owned by a peer, the bootstrap never departs
+    let key = (0..).map(|i| format!("key{}", i)).find(|key| chord_owner(&ids, HashFunc(key)) != nodes[0].get_id()).expect("a peer's key");
+    insert(&nodes[0], &key, "value").await;
+    copies_when(&nodes, &key, |found| found.len() == 2).await;
+    let primary = holder(&nodes, &key, 0).await.clone();
+    let replica = holder(&nodes, &key, 1).await.clone();
+
+    let departed = depart(&primary).await;
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    let rest: Vec<Node> = nodes.iter().filter(|node| node.get_id() != primary.get_id()).cloned().collect();
+    settle(&rest).await;
+
+    let redirected = reply(&primary, MsgType::Query, query_data(&key, QueryKind::Value, ReadPreference::Nearest)).await;
+    let retry_at = format!("retry at {}:{}", replica.get_ip(), replica.get_port());
+    assert!(redirected.starts_with("Error: Offline:") && redirected.ends_with(&retry_at), "{}", redirected);
+    assert_eq!(read(&replica, &key).await.expect("key survived the depart").value, "value");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lone_bootstrap_leaves_only_by_draining_every_key() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let boot = &nodes[0];
+    for i in 0..20 {
+        insert(boot, &format!("key{}", i), &format!("value{}", i)).await;
+    }
+
+    let refused = depart(boot).await;
+    assert!(refused.starts_with("Error: BadRequest: bootstrap node still holds 20 keys"), "{}", refused);
+    assert!(boot.get_status());
+    assert_eq!(boot.records.read().await.len(), 20);
+
+    let drained = match request(boot, MsgType::Quit, MsgData::Quit { warm: false, drain: true }).await {
+        MsgData::LocalRecords { records } => records,
+        other => panic!("expected the drained records, got {:?}", other),
+    };
+    let mut drained: Vec<(String, String)> = drained.into_iter().map(|item| (item.title, item.value)).collect();
+    drained.sort();
+    let mut expected: Vec<(String, String)> = (0..20).map(|i| (format!("key{}", i), format!("value{}", i))).collect();
+    expected.sort();
+    assert_eq!(drained, expected);
+    assert!(!boot.get_status());
+    assert!(boot.records.read().await.is_empty());
+}
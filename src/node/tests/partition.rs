@@ -0,0 +1,152 @@
+use super::*;
+use crate::partition::MirrorPartitioner;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mirror_partitioner_places_and_routes_by_the_mirrored_key() {
+    let mirror = || NodeOptions { partitioner: Some(Arc::new(MirrorPartitioner)), ..NodeOptions::default() };
+    let nodes = ring_with(1, Consistency::Eventual, 4, mirror).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+
+    let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+    for (i, key) in keys.iter().enumerate() {
+        let inserted = insert(&nodes[i % nodes.len()], key, key).await;
+        assert!(!inserted.starts_with("Error"), "{}", inserted);
+    }
+
+    let mut moved = 0;
+    for key in keys.iter() {
+        let hash = HashFunc(key);
+        let mirrored = HashType(hash.0.map(|byte| !byte));
+        let found = copies(&nodes, key).await;
+        assert_eq!(found.len(), 1, "{}", key);
+        assert_eq!(found[0].0.id, chord_owner(&ids, mirrored), "{} is not on the owner of its mirror", key);
+        if found[0].0.id != chord_owner(&ids, hash) {
+            moved += 1;
+        }
+        // a read entering anywhere is routed to the same node
+        for node in nodes.iter() {
+            assert_eq!(read(node, key).await.expect("key was stored").value, *key);
+        }
+    }
+    // otherwise the test would not tell the two placements apart
+    assert!(moved > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_of_weight_two_owns_twice_the_keys() {
+    let boot = bootstrap(1, Consistency::Eventual, NodeOptions::default()).await;
+    let heavy = peer(&boot, NodeOptions { weight: 2, ..NodeOptions::default() }).await;
+    let joined = join(&heavy).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    let nodes = vec![boot.clone(), heavy.clone()];
+    settle(&nodes).await;
+    // the successor placed the heavy node, so it no longer sits at its hashed id
+    assert_ne!(heavy.get_id(), HashIP(heavy.get_ip(), heavy.get_port()));
+
+    for i in 0..900 {
+        let inserted = insert(&nodes[i % 2], &format!("key{}", i), "value").await;
+        assert!(!inserted.starts_with("Error"), "{}", inserted);
+    }
+    let owned = |node: &Node| {
+        let node = node.clone();
+        async move { node.records.read().await.values().filter(|item| item.replica_idx == 0).count() }
+    };
+    let (light, heavy) = (owned(&boot).await, owned(&heavy).await);
+    assert_eq!(light + heavy, 900);
+    // 600 and 300 expected, both counts stay within 4 standard deviations
+    let ratio = heavy as f64 / light as f64;
+    assert!((1.5..2.7).contains(&ratio), "weight 2 owns {} keys, weight 1 owns {}", heavy, light);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn weighted_node_is_found_by_address_and_departs_cleanly() {
+    let boot = bootstrap(2, Consistency::Eventual, NodeOptions::default()).await;
+    let heavy = peer(&boot, NodeOptions { weight: 2, ..NodeOptions::default() }).await;
+    let light = peer(&boot, NodeOptions::default()).await;
+    let mut nodes = vec![boot.clone()];
+    for node in [&heavy, &light] {
+        let joined = join(node).await;
+        assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+        nodes.push(node.clone());
+        settle(&nodes).await;
+    }
+    for i in 0..30 {
+        insert(&nodes[i % 3], &format!("key{}", i), "value").await;
+    }
+
+    // looked up by the address it listens on, not by the id that address hashes to
+    let (ip, port) = (boot.get_ip(), boot.get_port());
+    let addr = format!("{}:{}", heavy.get_ip(), heavy.get_port());
+    let found = tokio::task::spawn_blocking(move || crate::cli::parse_node_id(ip, port, &addr)).await.expect("lookup");
+    assert_eq!(found, Some(heavy.get_id()));
+
+    match request(&boot, MsgType::NodeRecords, MsgData::NodeRecords { target: heavy.get_id() }).await {
+        MsgData::Records { node, .. } => assert_eq!(node.id, heavy.get_id()),
+        other => panic!("expected the records of the weighted node, got {:?}", other),
+    }
+
+    let departed = depart(&heavy).await;
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    let rest = vec![boot.clone(), light.clone()];
+    settle(&rest).await;
+    for i in 0..30 {
+        let key = format!("key{}", i);
+        assert_eq!(read(&light, &key).await.map(|item| item.value), Some("value".to_string()), "{} lost", key);
+        assert_eq!(copies_when(&rest, &key, |found| found.len() == 2).await.len(), 2, "{} under-replicated", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pinned_key_is_stored_and_read_on_its_designated_node() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let owner = chord_owner(&ids, HashFunc("placed"));
+    let target = nodes.iter().find(|node| node.get_id() != owner).expect("a node the hash does not pick");
+
+    let pinned = reply(&nodes[1], MsgType::Pin, MsgData::Pin { key: "placed".to_string(), node: target.get_info() }).await;
+    assert!(pinned.starts_with("Pinned"), "{}", pinned);
+    let entry = nodes.iter().find(|node| node.get_id() != target.get_id()).expect("an entry away from the target");
+    let inserted = insert(entry, "placed", "here").await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+
+    // replication runs from the designated node, not from the hash owner
+    assert_eq!(holder(&nodes, "placed", 0).await.get_id(), target.get_id());
+    let found = copies_when(&nodes, "placed", |found| found.len() == 2 && found.iter().all(|(_, item)| item.value == "here")).await;
+    let succ = target.get_succ().await.expect("target has a successor");
+    assert!(found.iter().any(|(node, item)| node.id == succ.id && item.replica_idx == 1), "{:?}", found);
+    for node in nodes.iter() {
+        assert_eq!(read(node, "placed").await.expect("key was stored").value, "here");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn colocated_child_lands_on_the_node_of_its_parent() {
+    let nodes = ring(1, Consistency::Eventual, 4).await;
+    for i in 0..8 {
+        let (parent, child) = (format!("user{}", i), format!("user{}/orders", i));
+        let inserted = insert(&nodes[i % nodes.len()], &parent, "profile").await;
+        assert!(!inserted.starts_with("Error"), "{}", inserted);
+        let colocated = MsgData::Insert { key: child.clone(), value: "orders".to_string(), wait_for_replication: false, consistency: None,
+                                          colocate_with: Some(parent.clone()), max_value_len: None, create_only: false, json: false, copy_of: None };
+        let inserted = reply(&nodes[(i + 1) % nodes.len()], MsgType::Insert, colocated).await;
+        assert!(!inserted.starts_with("Error"), "{}", inserted);
+
+        let parent_node = holder(&nodes, &parent, 0).await;
+        let child_hash = utils::HashColocated(&child, &parent);
+        let mut child_nodes = Vec::new();
+        for node in nodes.iter() {
+            if node.records.read().await.get(&child_hash).is_some_and(|item| item.replica_idx == 0) {
+                child_nodes.push(node.get_id());
+            }
+        }
+        assert_eq!(child_nodes, vec![parent_node.get_id()], "{} is not next to {}", child, parent);
+
+        // the child is read back through the parent it was placed with
+        let query = MsgData::Query { key: child.clone(), kind: QueryKind::Record, prefer: ReadPreference::Nearest,
+                                     consistency: None, colocate_with: Some(parent.clone()) };
+        match request(&nodes[(i + 2) % nodes.len()], MsgType::Query, query).await {
+            MsgData::Record { record: Some(found), .. } => assert_eq!(found.value, "orders"),
+            other => panic!("expected the colocated record, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use super::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn incompatible_version_gets_structured_error() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let client = Client::bind().await;
+    let mut msg = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value")));
+    // next major release, same number of digits so the announced size still holds
+    msg["proto_version"] = serde_json::json!(0x0200);
+    send_raw(&nodes[0], &msg.to_string()).await;
+
+    match client.recv().await {
+        MsgData::Reply { reply } => assert!(reply.starts_with("Error: protocol version mismatch"), "{}", reply),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+    assert!(copies(&nodes, "key").await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unversioned_request_is_refused_as_incompatible() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let client = Client::bind().await;
+    let mut msg = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value")));
+    msg.as_object_mut().unwrap().remove("proto_version");
+    send_raw(&nodes[0], &msg.to_string()).await;
+
+    match client.recv().await {
+        MsgData::Reply { reply } => assert!(reply.contains("request used 0x0000"), "{}", reply),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+    assert!(copies(&nodes, "key").await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_message_type_gets_a_prompt_structured_error() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let client = Client::bind().await;
+    let mut msg = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value")));
+    // a type of some newer release, as long as the one it replaces so the announced size still holds
+    msg["type"] = serde_json::json!("Inzert");
+    send_raw(&nodes[0], &msg.to_string()).await;
+    match client.try_recv(Duration::from_secs(2)).await {
+        Some(MsgData::Reply { reply }) => assert!(reply.starts_with("Error: BadRequest: unsupported message type"), "{}", reply),
+        other => panic!("expected a prompt reply, got {:?}", other),
+    }
+
+    // a known type only ever sent to clients is refused the same way
+    let client = Client::bind().await;
+    client.send(&nodes[0], MsgType::Notify, MsgData::Notify { key: "key".to_string(), item: None }).await;
+    match client.try_recv(Duration::from_secs(2)).await {
+        Some(MsgData::Reply { reply }) => assert!(reply.starts_with("Error: BadRequest: unsupported message type Notify"), "{}", reply),
+        other => panic!("expected a prompt reply, got {:?}", other),
+    }
+    assert!(copies(&nodes, "key").await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bench_reports_every_operation_and_ordered_percentiles() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let (ip, port) = (nodes[0].get_ip(), nodes[0].get_port());
+    let args: Vec<String> = ["--ops", "200", "--mode", "mixed", "--read-ratio", "0.8", "--concurrency", "4", "--keys", "20", "--seed", "7"]
+        .iter().map(|arg| arg.to_string()).collect();
+    let summary = tokio::task::spawn_blocking(move || crate::cli::run_bench(ip, port, &args)).await.expect("bench run").expect("bench summary");
+
+    let fields: HashMap<&str, &str> = summary.split_whitespace().filter_map(|field| field.split_once('=')).collect();
+    let number = |name: &str| fields.get(name).and_then(|value| value.parse::<f64>().ok()).unwrap_or_else(|| panic!("no {} in {}", name, summary));
+    assert_eq!((number("ops"), number("ok"), number("failed"), number("concurrency")), (200.0, 200.0, 0.0, 4.0), "{}", summary);
+    assert!(number("throughput_ops") > 0.0, "{}", summary);
+    assert!(number("p50_us") <= number("p90_us") && number("p90_us") <= number("p99_us") && number("p99_us") <= number("max_us"), "{}", summary);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn truncated_then_idle_streams_are_given_up_at_the_deadline() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let client = Client::bind().await;
+    let full = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value"))).to_string();
+    // cut inside the json, and a complete json announcing more than it carries
+    let mut short = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value")));
+    short["size"] = serde_json::json!(full.len() * 2);
+    let payloads = [full[..full.len() / 2].to_string(), short.to_string()];
+
+    let started = Instant::now();
+    let mut streams = Vec::new();
+    for payload in payloads.iter() {
+        let mut stream = tokio::net::TcpStream::connect((nodes[0].get_ip(), nodes[0].get_port())).await.expect("connect to node");
+        stream.write_all(payload.as_bytes()).await.expect("send the partial request");
+        streams.push(stream);
+    }
+    // the node keeps serving while the handlers wait
+    assert!(insert(&nodes[0], "other", "value").await.starts_with("Inserted"));
+
+    let limit = Duration::from_millis(crate::REQUEST_READ_TIMEOUT_MS);
+    for mut stream in streams {
+        let mut rest = Vec::new();
+        let closed = timeout(limit + Duration::from_secs(5), stream.read_to_end(&mut rest)).await;
+        assert!(matches!(closed, Ok(Ok(0))), "stream still open: {:?}", closed);
+    }
+    assert!(started.elapsed() >= limit - Duration::from_millis(500), "gave up after {:?}", started.elapsed());
+    assert!(copies(&nodes, "key").await.is_empty());
+    assert!(client.try_recv(Duration::from_millis(100)).await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_above_the_rate_limit_is_refused_while_peers_flow() {
+    let limited = || NodeOptions { rate_limit: Some(5), ..NodeOptions::default() };
+    let nodes = ring_with(2, Consistency::Eventual, 2, limited).await;
+    let client = Client::bind().await;
+
+    // sends one insert from a local address, the message naming `sender` as the member it comes from
+    async fn send_from(to: NodeInfo, client: NodeInfo, from: std::net::Ipv4Addr, key: String, sender: Option<NodeInfo>) {
+        let socket = tokio::net::TcpSocket::new_v4().expect("socket");
+        socket.bind((from, 0).into()).expect("bind a local address");
+        let mut stream = socket.connect((to.get_ip(), to.get_port()).into()).await.expect("connect to node");
+        let build = || Message::new(MsgType::Insert, Some(&client), &insert_data(&key, "value"));
+        let msg = match sender {
+            Some(sender) => messages::SENDER.scope(sender, async { build() }).await,
+            None => build(),
+        };
+        stream.write_all(serde_json::json!(msg).to_string().as_bytes()).await.expect("send request");
+        stream.write_all(b"\n").await.expect("send request");
+    }
+    async fn tally(client: &Client, blast: usize) -> (usize, usize) {
+        let (mut accepted, mut refused) = (0, 0);
+        for _ in 0..blast {
+            match client.recv().await {
+                MsgData::Reply { reply } if reply.starts_with("Error: TooManyRequests") => refused += 1,
+                MsgData::Reply { reply } if reply.starts_with("Inserted") => accepted += 1,
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+        (accepted, refused)
+    }
+    let (to, me) = (nodes[0].get_info(), client.info());
+    let blast = 30;
+
+    // a client on the host the members share is limited like any other
+    for i in 0..blast {
+        send_from(to, me, nodes[0].get_ip(), format!("local{}", i), None).await;
+    }
+    let (accepted, refused) = tally(&client, blast).await;
+    assert!(refused > 0 && accepted >= 5, "{} accepted, {} refused", accepted, refused);
+
+    // so is one naming a member as its sender from an address that member doesn't have
+    for i in 0..blast {
+        send_from(to, me, std::net::Ipv4Addr::new(127, 0, 0, 2), format!("forged{}", i), Some(nodes[1].get_info())).await;
+    }
+    let (accepted, refused) = tally(&client, blast).await;
+    assert!(refused > 0 && accepted >= 5, "{} accepted, {} refused", accepted, refused);
+
+    /* Clients on separate addresses stay within their own limits, while the forwards of all
+       their inserts reach the other node from one member faster than any client may send */
+    for i in 0..blast {
+        send_from(to, me, std::net::Ipv4Addr::new(127, 0, 1, 1 + i as u8), format!("spread{}", i), None).await;
+    }
+    assert_eq!(tally(&client, blast).await, (blast, 0));
+    for i in 0..blast {
+        let key = format!("spread{}", i);
+        assert_eq!(copies_when(&nodes, &key, |found| found.len() == 2).await.len(), 2, "{} was not replicated", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn foreign_key_on_a_self_looping_node_gets_a_routing_error() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let node = &nodes[0];
+    // the successor still loops back here, the previous claims the key's position
+    let mut prev = NodeInfo::new(get_local_ip(), free_port());
+    prev.id = HashFunc("key");
+    node.set_prev(Some(prev)).await;
+    assert!(!node.is_responsible(&HashFunc("key")).await);
+
+    let requests = [
+        (MsgType::Insert, insert_data("key", "value")),
+        (MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Nearest)),
+        (MsgType::Delete, MsgData::Delete { key: "key".to_string(), consistency: None, colocate_with: None, if_version: None }),
+    ];
+    for (msg_type, data) in requests {
+        let answer = timeout(REPLY_WAIT, reply(node, msg_type, data)).await.expect("request circled instead of failing");
+        assert!(answer.starts_with("Error: RoutingError"), "{}", answer);
+    }
+    assert!(node.records.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn proxied_requests_are_answered_on_their_own_connection() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    // listening, but a proxied request must never dial it
+    let client = Client::bind().await;
+    let (info, entry) = (client.info(), (nodes[1].get_ip(), nodes[1].get_port()));
+    let proxied = |msg_type, data| async move {
+        let msg = Message::new(MsgType::Proxy, Some(&info), &MsgData::Proxy { msg_type, data: Box::new(data) });
+        let mut stream = tokio::net::TcpStream::connect(entry).await.expect("connect to node");
+        stream.write_all(serde_json::json!(msg).to_string().as_bytes()).await.expect("send request");
+        stream.write_all(b"\n").await.expect("send request");
+        let mut answer = Vec::new();
+        timeout(REPLY_WAIT, stream.read_to_end(&mut answer)).await.expect("no reply in time").expect("read reply");
+        serde_json::from_slice::<Message>(&answer).expect("a reply message").extract_data()
+    };
+
+    match proxied(MsgType::Insert, insert_data("key", "value")).await {
+        MsgData::Reply { reply } => assert!(reply.starts_with("Inserted"), "{}", reply),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+    match proxied(MsgType::Query, query_data("key", QueryKind::Record, ReadPreference::Nearest)).await {
+        MsgData::Record { record: Some(found), .. } => assert_eq!(found.value, "value"),
+        other => panic!("expected the record, got {:?}", other),
+    }
+    assert!(client.try_recv(Duration::from_millis(500)).await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gigabyte_size_header_is_refused_before_any_body_is_read() {
+    let nodes = ring(1, Consistency::Eventual, 1).await;
+    let client = Client::bind().await;
+    let mut msg = serde_json::json!(Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", "value")));
+    msg["size"] = serde_json::json!(1usize << 30);
+
+    let started = Instant::now();
+    let mut stream = tokio::net::TcpStream::connect((nodes[0].get_ip(), nodes[0].get_port())).await.expect("connect to node");
+    stream.write_all(msg.to_string().as_bytes()).await.expect("send the header");
+    match client.try_recv(Duration::from_secs(2)).await {
+        Some(MsgData::Reply { reply }) => {
+            let expected = format!("Error: BadRequest: message of {} bytes exceeds the {} byte limit", 1usize << 30, crate::MAX_MESSAGE_BYTES);
+            assert_eq!(reply, expected);
+        }
+        other => panic!("expected a prompt reply, got {:?}", other),
+    }
+    // the connection is dropped at once instead of waiting for, and buffering, the announced body
+    let mut rest = Vec::new();
+    let closed = timeout(Duration::from_secs(2), stream.read_to_end(&mut rest)).await;
+    assert!(matches!(closed, Ok(Ok(0))), "stream still open: {:?}", closed);
+    assert!(started.elapsed() < Duration::from_millis(crate::REQUEST_READ_TIMEOUT_MS));
+    assert!(copies(&nodes, "key").await.is_empty());
+    assert!(insert(&nodes[0], "other", "value").await.starts_with("Inserted"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn oversized_message_is_refused_while_its_body_is_still_arriving() {
+    let capped = || NodeOptions { max_message_bytes: 64 * 1024, ..NodeOptions::default() };
+    let nodes = ring_with(1, Consistency::Eventual, 1, capped).await;
+    let client = Client::bind().await;
+    let msg = Message::new(MsgType::Insert, Some(&client.info()), &insert_data("key", &"x".repeat(256 * 1024)));
+    let text = msg.to_wire();
+
+    // the body keeps coming after the header, the reply must not wait for it to end
+    let mut stream = tokio::net::TcpStream::connect((nodes[0].get_ip(), nodes[0].get_port())).await.expect("connect to node");
+    stream.write_all(&text.as_bytes()[..1024]).await.expect("send the header");
+    let writer = tokio::spawn(async move {
+        let _ = stream.write_all(&text.as_bytes()[1024..text.len() - 1]).await;
+        sleep(Duration::from_secs(3)).await;
+    });
+    match client.try_recv(Duration::from_secs(2)).await {
+        Some(MsgData::Reply { reply }) => {
+            assert_eq!(reply, format!("Error: BadRequest: message of {} bytes exceeds the {} byte limit", msg.extract_size(), 64 * 1024));
+        }
+        other => panic!("expected a prompt reply, got {:?}", other),
+    }
+    writer.abort();
+    assert!(copies(&nodes, "key").await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_large_messages_stay_within_the_reassembly_budget() {
+    let budgeted = || NodeOptions { reassembly_budget_kb: Some(1024), ..NodeOptions::default() };
+    let nodes = ring_with(1, Consistency::Eventual, 1, budgeted).await;
+    let value = "x".repeat(300 * 1024);
+    let client = Client::bind().await;
+
+    // together four times the budget, so some have to wait for others to be handled
+    let mut senders = Vec::new();
+    for i in 0..12 {
+        let msg = Message::new(MsgType::Insert, Some(&client.info()), &insert_data(&format!("large{}", i), &value));
+        let node = nodes[0].clone();
+        senders.push(tokio::spawn(async move { send_raw(&node, &serde_json::json!(msg).to_string()).await }));
+    }
+    for sender in senders {
+        sender.await.expect("sender");
+    }
+    let mut inserted = Vec::new();
+    while let Some(answer) = client.try_recv(Duration::from_secs(5)).await {
+        match answer {
+            MsgData::Reply { reply } if reply.starts_with("Inserted") => inserted.push(reply),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    // requests that could only have waited on each other are dropped, never read past the budget
+    let budget = nodes[0].reassembly.as_ref().expect("a budget");
+    let (peak, dropped) = (budget.peak_kb.load(Ordering::SeqCst), budget.rejected.load(Ordering::SeqCst));
+    assert!(peak > 300 && peak <= 1024, "peak {} KB", peak);
+    assert_eq!(budget.in_flight_kb(), 0);
+    assert_eq!(inserted.len() + dropped, 12, "{} inserted, {} dropped", inserted.len(), dropped);
+    assert!(inserted.len() >= 3, "{} inserted, {} dropped", inserted.len(), dropped);
+    let stored = nodes[0].records.read().await.values().filter(|item| item.value.len() == value.len()).count();
+    assert_eq!(stored, inserted.len());
+}
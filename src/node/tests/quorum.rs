@@ -0,0 +1,84 @@
+use super::*;
+
+const COPIES: u8 = 3;
+
+fn quorum(read_quorum: u8, write_quorum: u8) -> impl Fn() -> NodeOptions {
+    move || NodeOptions { quorum: Some((read_quorum, write_quorum)), ..NodeOptions::default() }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_all_read_one() {
+    let nodes = ring(COPIES, Consistency::Quorum, 4).await;
+    let set = reply(&nodes[3], MsgType::Reconfigure, MsgData::Reconfigure { read_quorum: 1, write_quorum: COPIES, header: None, checked: false }).await;
+    assert!(set.starts_with("Quorum set to R=1 W=3"), "{}", set);
+    for node in nodes.iter() {
+        assert_eq!(node.get_quorum().await, (1, COPIES));
+    }
+
+    // W = k: the reply comes from the last replica, so every copy is in place once it arrives
+    let inserted = insert(&nodes[0], "key", "v1").await;
+    assert!(inserted.contains("replicas_acked: 3, durable: true"), "{}", inserted);
+    let found = copies(&nodes, "key").await;
+    assert_eq!(found.len(), COPIES as usize);
+    assert!(found.iter().all(|(_, item)| item.value == "v1"));
+
+    // R = 1: the primary answers alone, a newer copy further down is not consulted
+    let tail = holder(&nodes, "key", COPIES - 1).await;
+    overwrite(tail, "key", "newer", Utc::now() + chrono::Duration::seconds(1)).await;
+    assert_eq!(read(&nodes[2], "key").await.expect("key was stored").value, "v1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_one_read_all() {
+    let nodes = ring_with(COPIES, Consistency::Quorum, 4, quorum(COPIES, 1)).await;
+
+    // W = 1: the primary replies before any replica holds the write
+    let inserted = insert(&nodes[1], "key", "v1").await;
+    assert!(inserted.contains("replicas_acked: 1, durable: false"), "{}", inserted);
+    assert_eq!(copies_when(&nodes, "key", |found| found.len() == COPIES as usize).await.len(), COPIES as usize);
+
+    // R = k: a replica holding a newer version than the primary wins the read
+    let tail = holder(&nodes, "key", COPIES - 1).await;
+    overwrite(tail, "key", "newer", Utc::now() + chrono::Duration::seconds(1)).await;
+    assert_eq!(read(&nodes[3], "key").await.expect("key was stored").value, "newer");
+
+    // deletes count copies the same way, the primary's removal is enough to reply
+    let deleted = reply(&nodes[2], MsgType::Delete, MsgData::Delete { key: "key".to_string(), consistency: None, colocate_with: None, if_version: None }).await;
+    assert!(deleted.contains("replicas_removed: 1"), "{}", deleted);
+    assert!(copies_when(&nodes, "key", |found| found.is_empty()).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconfigure_is_refused_by_any_node_it_does_not_fit() {
+    let nodes = ring_with(COPIES, Consistency::Quorum, 4, quorum(2, 2)).await;
+    // a node further along the walk keeps fewer copies than the one asked
+    nodes[2].replication.write().await.replication_factor = 1;
+
+    let refused = reply(&nodes[0], MsgType::Reconfigure, MsgData::Reconfigure { read_quorum: 3, write_quorum: 1, header: None, checked: false }).await;
+    assert!(refused.starts_with("Error: BadRequest: quorum sizes must be between 1 and 2 copies"), "{}", refused);
+    assert!(refused.contains(&nodes[2].get_info().to_string()), "{}", refused);
+    for node in nodes.iter() {
+        assert_eq!(node.get_quorum().await, (2, 2), "node {} applied a refused quorum", node.get_info());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quorum_read_breaks_timestamp_ties_by_writer() {
+    let nodes = ring_with(COPIES, Consistency::Quorum, 4, quorum(COPIES, 1)).await;
+    insert(&nodes[0], "key", "v1").await;
+    copies_when(&nodes, "key", |found| found.len() == COPIES as usize).await;
+
+    // two writers stamped the same instant, the higher writer id wins wherever its copy sits
+    let tied = Utc::now() + chrono::Duration::seconds(1);
+    let (low, high) = (HashType([0x11; 20]), HashType([0xee; 20]));
+    for (primary_writer, tail_writer) in [(low, high), (high, low)] {
+        for (idx, writer) in [(0, primary_writer), (COPIES - 1, tail_writer)] {
+            let node = holder(&nodes, "key", idx).await;
+            overwrite(node, "key", &format!("by {}", writer), tied).await;
+            node.records.write().await.get_mut(&HashFunc("key")).expect("copy").writer = Some(writer);
+        }
+        for entry in nodes.iter() {
+            assert_eq!(read(entry, "key").await.expect("key was stored").value, format!("by {}", high), "entering at {}", entry.get_info());
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use super::*;
+
+async fn exists(to: &Node, key: &str) -> String {
+    reply(to, MsgType::Query, query_data(key, QueryKind::Exists, ReadPreference::Nearest)).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exists_answers_without_the_value() {
+    for mode in [Consistency::Eventual, Consistency::Chain] {
+        let nodes = ring(2, mode, 3).await;
+        let value = "a value that must not travel back";
+        insert(&nodes[0], "present", value).await;
+        copies_when(&nodes, "present", |found| found.len() == 2).await;
+
+        let present = exists(&nodes[2], "present").await;
+        assert!(present.contains("present = true"), "{:?}: {}", mode, present);
+        assert!(!present.contains(value), "{:?}: {}", mode, present);
+        let absent = exists(&nodes[1], "absent").await;
+        assert!(absent.ends_with("= false"), "{:?}: {}", mode, absent);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn primary_preference_reads_the_freshest_copy() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    insert(&nodes[0], "key", "v1").await;
+    copies_when(&nodes, "key", |found| found.len() == 3).await;
+
+    // the replicas have not caught up with the primary yet
+    let primary = holder(&nodes, "key", 0).await;
+    overwrite(primary, "key", "v2", Utc::now() + chrono::Duration::seconds(1)).await;
+    let stale = holder(&nodes, "key", 2).await;
+    assert_eq!(read(stale, "key").await.expect("key was stored").value, "v1");
+
+    for node in nodes.iter() {
+        let found = match request(node, MsgType::Query, query_data("key", QueryKind::Record, ReadPreference::Primary)).await {
+            MsgData::Record { record, .. } => record.expect("key was stored"),
+            other => panic!("expected a record, got {:?}", other),
+        };
+        assert_eq!(found.value, "v2", "entering at {}", node.get_info());
+        assert_eq!(found.replica_idx, 0);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupted_copy_is_repaired_from_a_good_replica() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    insert(&nodes[0], "key", "intact").await;
+    copies_when(&nodes, "key", |found| found.len() == 2).await;
+
+    // the value changes under the stored checksum, as a flipped byte would
+    let primary = holder(&nodes, "key", 0).await;
+    primary.records.write().await.get_mut(&HashFunc("key")).expect("primary copy").value = "intbct".to_string();
+
+    let found = reply(primary, MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Primary)).await;
+    assert!(found.contains("intact") && found.ends_with("[repaired]"), "{}", found);
+    let repaired = copies_when(&nodes, "key", |found| found.iter().all(|(_, item)| item.value == "intact")).await;
+    assert!(repaired.iter().all(|(_, item)| item.value == "intact" && item.verify()), "{:?}", repaired);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupted_copy_without_replicas_is_an_error() {
+    let nodes = ring(1, Consistency::Eventual, 2).await;
+    insert(&nodes[0], "key", "intact").await;
+    let primary = holder(&nodes, "key", 0).await;
+    primary.records.write().await.get_mut(&HashFunc("key")).expect("primary copy").value = "intbct".to_string();
+
+    let found = reply(primary, MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Primary)).await;
+    assert!(found.starts_with("Error:") && !found.contains("intbct"), "{}", found);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_get_hides_versions_after_the_snapshot() {
+    for mode in [Consistency::Eventual, Consistency::Chain] {
+        let nodes = ring(2, mode, 3).await;
+        insert(&nodes[0], "early", "before").await;
+        sleep(Duration::from_millis(20)).await;
+        let snapshot_ts = Utc::now();
+        sleep(Duration::from_millis(20)).await;
+        insert(&nodes[1], "late", "after").await;
+        copies_when(&nodes, "late", |found| found.len() == 2).await;
+
+        let keys = ["early", "late", "absent"].iter().map(|key| key.to_string()).collect();
+        let snapshot = reply(&nodes[2], MsgType::MultiGet, MsgData::MultiGet { keys, snapshot_ts: Some(snapshot_ts) }).await;
+        assert!(snapshot.contains(&format!("SNAPSHOT @ 🕰️ {}", snapshot_ts)), "{:?}: {}", mode, snapshot);
+        assert!(snapshot.contains("(🔑early : 🔒before"), "{:?}: {}", mode, snapshot);
+        assert!(snapshot.contains("(🔑late : changed after snapshot"), "{:?}: {}", mode, snapshot);
+        assert!(!snapshot.contains("after,"), "{:?}: {}", mode, snapshot);
+        assert!(snapshot.contains("(🔑absent : doesn't exist)"), "{:?}: {}", mode, snapshot);
+
+        // without a snapshot the read is taken as of now and sees both
+        let keys = ["early", "late"].iter().map(|key| key.to_string()).collect();
+        let now = reply(&nodes[2], MsgType::MultiGet, MsgData::MultiGet { keys, snapshot_ts: None }).await;
+        assert!(now.contains("(🔑late : 🔒after"), "{:?}: {}", mode, now);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_tell_which_replica_answered() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..10).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+        assert_eq!(copies_when(&nodes, key, |found| found.len() == 3).await.len(), 3, "{}", key);
+    }
+
+    let answered = |data: MsgData| match data {
+        MsgData::Record { record: Some(item), .. } => item.replica_idx,
+        other => panic!("expected a record, got {:?}", other),
+    };
+    let mut spread = [0usize; 3];
+    for (i, key) in keys.iter().enumerate() {
+        // a targeted read is served by exactly that replica
+        for idx in 0..3u8 {
+            let data = request(&nodes[i % nodes.len()], MsgType::Query, query_data(key, QueryKind::Record, ReadPreference::Replica(idx))).await;
+            assert_eq!(answered(data), idx, "{}", key);
+        }
+        for entry in nodes.iter() {
+            let data = request(entry, MsgType::Query, query_data(key, QueryKind::Record, ReadPreference::Random)).await;
+            spread[answered(data) as usize] += 1;
+        }
+    }
+    // random reads land on every replica index, the primary included
+    assert_eq!(spread.iter().sum::<usize>(), keys.len() * nodes.len());
+    assert!(spread.iter().all(|reads| *reads > 0), "reads per replica index: {:?}", spread);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_readers_beyond_the_worker_permits_get_the_ack() {
+    let nodes = ring_with(1, Consistency::Chain, 1, || NodeOptions { workers: 1, ..NodeOptions::default() }).await;
+    insert(&nodes[0], "key", "v1").await;
+    nodes[0].records.write().await.get_mut(&HashFunc("key")).expect("inserted").pending = true;
+
+    // more blocked readers than the single worker has permits
+    let readers = crate::network::IN_FLIGHT_PER_WORKER + 8;
+    let mut reads = Vec::new();
+    for _ in 0..readers {
+        let node = nodes[0].clone();
+        reads.push(tokio::spawn(async move { read(&node, "key").await }));
+    }
+    let deadline = Instant::now() + SETTLE_WAIT;
+    loop {
+        let waiting = nodes[0].pendings.read().await.get(&HashFunc("key")).map_or(0, |wait| wait.waiters.load(Ordering::SeqCst));
+        if waiting == readers {
+            break;
+        }
+        assert!(Instant::now() < deadline, "only {} of {} readers blocked", waiting, readers);
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let client = Client::bind().await;
+    client.send(&nodes[0], MsgType::AckInsert, MsgData::AckInsert { key: HashFunc("key") }).await;
+    for read in reads {
+        let record = timeout(REPLY_WAIT, read).await.expect("reader still blocked").expect("read task");
+        assert_eq!(record.map(|item| item.value), Some("v1".to_string()));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_records_lists_exactly_the_keys_the_node_is_responsible_for() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..40).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+    }
+
+    for node in nodes.iter() {
+        let records = match request(&nodes[0], MsgType::NodeRecords, MsgData::NodeRecords { target: node.get_id() }).await {
+            MsgData::Records { node: listed, records } => {
+                assert_eq!(listed.id, node.get_id());
+                records
+            }
+            other => panic!("expected records, got {:?}", other),
+        };
+        let mut listed: Vec<String> = records.into_iter().map(|item| item.title).collect();
+        let mut responsible = Vec::new();
+        for key in keys.iter() {
+            if node.is_responsible(&HashFunc(key)).await {
+                responsible.push(key.clone());
+            }
+        }
+        listed.sort();
+        responsible.sort();
+        assert_eq!(listed, responsible, "node {}", node.get_info());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_query_agrees_with_individual_queries() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..1000).map(|i| format!("key{}", i)).collect();
+    // every third key is never written, so absent keys are compared too
+    for (i, key) in keys.iter().enumerate().filter(|(i, _)| i % 3 != 0) {
+        insert(&nodes[i % nodes.len()], key, &format!("value{}", i)).await;
+    }
+
+    let results = match request(&nodes[1], MsgType::MultiQuery, MsgData::MultiQuery { keys: keys.clone() }).await {
+        MsgData::MultiQueryResult { results } => results,
+        other => panic!("expected query results, got {:?}", other),
+    };
+    assert_eq!(results.len(), keys.len());
+    let answered: HashMap<String, Option<String>> = results.into_iter().collect();
+    for (i, key) in keys.iter().enumerate() {
+        let single = read(&nodes[i % nodes.len()], key).await.map(|item| item.value);
+        assert_eq!(answered.get(key), Some(&single), "{}", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cache_hit_answers_without_forwarding() {
+    let opts = || NodeOptions { read_cache: Some(16), tracing: true, ..NodeOptions::default() };
+    let nodes = ring_with(1, Consistency::Eventual, 3, opts).await;
+    insert(&nodes[0], "remote", "value").await;
+    let holder = holder(&nodes, "remote", 0).await;
+    let entry = nodes.iter().find(|node| node.get_id() != holder.get_id()).expect("a node without the key");
+    let value = || query_data("remote", QueryKind::Value, ReadPreference::Nearest);
+
+    async fn spans(boot: &Node, trace_id: Uuid) -> Vec<Span> {
+        // reports reach the bootstrap in the background
+        sleep(Duration::from_millis(200)).await;
+        boot.traces.read().await.get(&trace_id)
+    }
+    let (miss, hit) = (Uuid::new_v4(), Uuid::new_v4());
+    let first = messages::TRACE_ID.scope(miss, reply(entry, MsgType::Query, value())).await;
+    assert!(first.contains("🔒value"), "{}", first);
+    assert!(!spans(&nodes[0], miss).await.is_empty(), "the first read was not forwarded");
+
+    let second = messages::TRACE_ID.scope(hit, reply(entry, MsgType::Query, value())).await;
+    assert_eq!(second, first);
+    let forwards = spans(&nodes[0], hit).await;
+    assert!(forwards.is_empty(), "the cached read was forwarded: {:?}", forwards);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelling_a_stuck_pending_releases_its_readers() {
+    let nodes = ring(1, Consistency::Chain, 1).await;
+    insert(&nodes[0], "key", "v1").await;
+    // the ack of the last write never arrives
+    nodes[0].records.write().await.get_mut(&HashFunc("key")).expect("inserted").pending = true;
+
+    let mut reads = Vec::new();
+    for _ in 0..3 {
+        let node = nodes[0].clone();
+        reads.push(tokio::spawn(async move { reply(&node, MsgType::Query, query_data("key", QueryKind::Value, ReadPreference::Nearest)).await }));
+    }
+    let deadline = Instant::now() + SETTLE_WAIT;
+    loop {
+        let listed = reply(&nodes[0], MsgType::Pendings, MsgData::Pendings { }).await;
+        if listed.contains("⏳ 🔑 key (3 waiting reader(s))") {
+            assert!(listed.contains("has 1 pending key(s)"), "{}", listed);
+            break;
+        }
+        assert!(Instant::now() < deadline, "readers did not block: {}", listed);
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    let cancelled = reply(&nodes[0], MsgType::CancelPending, MsgData::CancelPending { key: "key".to_string() }).await;
+    assert!(cancelled.starts_with("Cancelled pending 🔑 key") && cancelled.ends_with("3 waiting reader(s) released"), "{}", cancelled);
+    for read in reads {
+        let answer = timeout(REPLY_WAIT, read).await.expect("reader still blocked").expect("read task");
+        assert!(answer.starts_with("Error: pending update on 🔑 key was cancelled"), "{}", answer);
+    }
+
+    let listed = reply(&nodes[0], MsgType::Pendings, MsgData::Pendings { }).await;
+    assert!(listed.contains("has 0 pending key(s)"), "{}", listed);
+    assert_eq!(read(&nodes[0], "key").await.expect("key was stored").value, "v1");
+    let again = reply(&nodes[0], MsgType::CancelPending, MsgData::CancelPending { key: "key".to_string() }).await;
+    assert!(again.starts_with("Error: 🔑 key is not pending"), "{}", again);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sweep_lists_each_key_once_when_a_visited_node_departs() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..40).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+    }
+    // the nodes in ring order, starting at the header of the sweep
+    let mut order = vec![nodes[0].clone()];
+    while order.len() < nodes.len() {
+        let succ = order.last().unwrap().get_succ().await.expect("linked").id;
+        order.push(nodes.iter().find(|node| node.get_id() == succ).expect("member").clone());
+    }
+
+    // the sweep has passed the first three nodes when the third departs, its keys reappear at the fourth
+    let mut collected = Vec::new();
+    for node in order[..3].iter() {
+        let items = node.query_all_items(&collected).await;
+        collected.extend(items);
+    }
+    let left = depart(&order[2]).await;
+    assert!(left.contains("has left the network"), "{}", left);
+    let remaining = [order[0].clone(), order[1].clone(), order[3].clone()];
+    settle(&remaining).await;
+
+    let client = Client::bind().await;
+    let visited = order[..3].iter().map(|node| node.get_id()).collect();
+    client.send(&order[3], MsgType::FwQueryAll, MsgData::FwQueryAll { record_list: collected, header: order[0].get_id(), visited,
+                                                                      churned: false, restart_on_churn: false, restarts: 0 }).await;
+    let all = match client.recv().await {
+        MsgData::Reply { reply } => reply,
+        other => panic!("expected a reply, got {:?}", other),
+    };
+    assert!(all.contains("The ring changed during the sweep"), "{}", all);
+    let mut listed: Vec<&str> = all.lines()
+        .filter_map(|line| line.strip_prefix("(🔑"))
+        .map(|line| line.split(" : ").next().unwrap())
+        .collect();
+    listed.sort();
+    let mut expected: Vec<&str> = keys.iter().map(|key| key.as_str()).collect();
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stuck_pendings_stay_bounded_and_idle_ones_are_reaped() {
+    let bounded = || NodeOptions { pendings_limit: 4, pending_expiry_ms: 600, ..NodeOptions::default() };
+    let nodes = ring_with(1, Consistency::Chain, 1, bounded).await;
+    let keys: Vec<String> = (0..10).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "v1").await;
+        // the ack of the last write never arrives
+        nodes[0].records.write().await.get_mut(&HashFunc(key)).expect("inserted").pending = true;
+    }
+
+    let mut reads = Vec::new();
+    for key in keys.iter() {
+        let (node, query) = (nodes[0].clone(), query_data(key, QueryKind::Value, ReadPreference::Nearest));
+        reads.push(tokio::spawn(async move { reply(&node, MsgType::Query, query).await }));
+        // one key at a time, so the longest idle one is always the earliest
+        let deadline = Instant::now() + SETTLE_WAIT;
+        while nodes[0].pendings.read().await.get(&HashFunc(key)).is_none_or(|wait| wait.waiters.load(Ordering::SeqCst) != 1) {
+            assert!(Instant::now() < deadline, "reader of {} did not block", key);
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(nodes[0].pendings.read().await.len() <= 4);
+    }
+
+    // the first six were expired to make room, the last four once idle for the expiry
+    let started = Instant::now();
+    for (i, read) in reads.into_iter().enumerate() {
+        let answer = timeout(REPLY_WAIT, read).await.expect("reader still blocked").expect("read task");
+        assert!(answer.starts_with(&format!("Error: pending update on 🔑 {} was cancelled", keys[i])), "{}", answer);
+    }
+    assert!(started.elapsed() >= Duration::from_millis(300), "idle readers were released before their expiry");
+    assert!(nodes[0].pendings.read().await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn query_entering_at_a_replica_is_answered_without_forwarding() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..12).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+        copies_when(&nodes, key, |found| found.len() == 3).await;
+    }
+    // freshly joined nodes hand reads on until their grace is over
+    for node in nodes.iter() {
+        if let Some(left) = node.grace_left().await {
+            sleep(left).await;
+        }
+    }
+    let forwarded = || async {
+        let mut total = 0;
+        for node in nodes.iter() {
+            total += node.reads.forwarded.load(Ordering::Relaxed);
+        }
+        total
+    };
+
+    let before = forwarded().await;
+    for key in keys.iter() {
+        for replica_idx in 1..3 {
+            let entry = holder(&nodes, key, replica_idx).await;
+            let local = entry.reads.local.load(Ordering::Relaxed);
+            assert_eq!(read(entry, key).await.expect("key was stored").value, "value");
+            assert_eq!(entry.reads.local.load(Ordering::Relaxed), local + 1, "replica {} of {} did not answer", replica_idx, key);
+        }
+    }
+    assert_eq!(forwarded().await, before, "a read entering at a replica was forwarded");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn time_range_query_returns_only_primaries_written_in_the_window() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let keys: Vec<String> = (0..10).map(|i| format!("key{}", i)).collect();
+    let mut written = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        insert(&nodes[i % nodes.len()], key, "value").await;
+        written.push(holder(&nodes, key, 0).await.records.read().await[&HashFunc(key)].timestamp);
+        sleep(Duration::from_millis(20)).await;
+    }
+    // halfway between writes, so no bound falls on a timestamp
+    let after = written[2] + (written[3] - written[2]) / 2;
+    let before = written[8] + (written[9] - written[8]) / 2;
+    // rewritten since, so it left the window
+    insert(&nodes[0], &keys[4], "again").await;
+
+    let listed = reply(&nodes[1], MsgType::TimeRangeQuery, MsgData::TimeRangeQuery { after, before }).await;
+    assert!(listed.ends_with("5 record(s)\n"), "{}", listed);
+    for (i, key) in keys.iter().enumerate() {
+        let inside = (3..=8).contains(&i) && i != 4;
+        assert_eq!(listed.contains(&format!("(🔑{} :", key)), inside, "{}", listed);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn key_count_sweep_estimates_within_the_error_bound() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let count = 2000;
+    for i in 0..count {
+        insert(&nodes[i % nodes.len()], &format!("key{}", i), "value").await;
+    }
+    let counted = reply(&nodes[1], MsgType::KeyCount, MsgData::KeyCount { }).await;
+    assert!(counted.contains("across 3 node(s)"), "{}", counted);
+    let estimate: f64 = counted.strip_prefix("About ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|estimate| estimate.parse().ok())
+        .unwrap_or_else(|| panic!("no estimate in {}", counted));
+    // replicas are counted once, the bound is 4 standard errors
+    let error = (estimate - count as f64).abs() / count as f64;
+    assert!(error < 4.0 * HyperLogLog::std_error(), "{}", counted);
+}
@@ -0,0 +1,532 @@
+use super::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shrinking_below_k_keeps_a_copy_on_every_node() {
+    let mut nodes = ring(5, Consistency::Eventual, 5).await;
+    let keys: Vec<String> = (0..20).map(|i| format!("key_{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "value").await;
+    }
+    for key in keys.iter() {
+        assert_eq!(copies_when(&nodes, key, |found| found.len() == 5).await.len(), 5, "{}", key);
+    }
+
+    for _ in 0..2 {
+        let leaving = nodes.pop().expect("a peer to depart");
+        let left = depart(&leaving).await;
+        assert!(!left.starts_with("Error"), "{}", left);
+        settle(&nodes).await;
+    }
+
+    for key in keys.iter() {
+        let found = copies_when(&nodes, key, |found| found.len() == 3).await;
+        assert_eq!(found.len(), 3, "{} has copies on {:?}", key, found.iter().map(|(node, _)| node.port).collect::<Vec<_>>());
+        let mut indices: Vec<u8> = found.iter().map(|(_, item)| item.replica_idx).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2], "{}", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_key_overwrites_a_stale_replica() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    insert(&nodes[0], "key", "v1").await;
+    copies_when(&nodes, "key", |found| found.len() == 3).await;
+    let stale = holder(&nodes, "key", 2).await;
+    overwrite(stale, "key", "v0", Utc::now() - chrono::Duration::seconds(60)).await;
+
+    // entered away from the primary, the request is routed there first
+    let primary = holder(&nodes, "key", 0).await;
+    let entry = nodes.iter().find(|node| node.get_id() != primary.get_id()).expect("a node besides the primary");
+    let synced = reply(entry, MsgType::SyncKey, MsgData::SyncKey { key: "key".to_string() }).await;
+    assert!(synced.contains("1 of 2 replica(s) updated"), "{}", synced);
+
+    let primary = primary.records.read().await.get(&HashFunc("key")).cloned().expect("primary copy");
+    let found = copies(&nodes, "key").await;
+    assert_eq!(found.len(), 3);
+    for (node, item) in found {
+        assert_eq!((item.value.as_str(), item.version()), (primary.value.as_str(), primary.version()), "copy on {}", node);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn equal_timestamps_converge_on_the_higher_writer() {
+    let nodes = ring(2, Consistency::Eventual, 2).await;
+    insert(&nodes[1], "key", "v1").await;
+    // replicas keep the version the first copy was stamped with
+    let found = copies_when(&nodes, "key", |found| found.len() == 2).await;
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].1.version(), found[1].1.version());
+
+    let timestamp = Utc::now();
+    let write = |value: &str, writer: &Node| Item { value: value.to_string(), timestamp, writer: Some(writer.get_id()), ..found[0].1.clone() };
+    let (first, second) = (write("from_0", &nodes[0]), write("from_1", &nodes[1]));
+    // the two nodes see the same pair of writes in opposite orders
+    for (node, order) in nodes.iter().zip([[&first, &second], [&second, &first]]) {
+        for copy in order {
+            node.reconcile_copy(HashFunc("key"), &mut copy.clone()).await;
+        }
+    }
+
+    let winner = if first.newer_than(&second) { &first } else { &second };
+    for (node, item) in copies(&nodes, "key").await {
+        assert_eq!((item.value.as_str(), item.version()), (winner.value.as_str(), winner.version()), "copy on {}", node);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_replicas_nothing_is_forwarded_to_a_replica() {
+    let nodes = ring_with(1, Consistency::Eventual, 3, || NodeOptions { tracing: true, ..NodeOptions::default() }).await;
+    let mut traced = Vec::new();
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        let entry = &nodes[i % nodes.len()];
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let inserted = messages::TRACE_ID.scope(ids[0], reply(entry, MsgType::Insert, insert_data(&key, "value"))).await;
+        assert!(inserted.contains(&key), "{}", inserted);
+        assert_eq!(copies(&nodes, &key).await.len(), 1, "{}", key);
+        let found = messages::TRACE_ID.scope(ids[1], read(entry, &key)).await;
+        assert_eq!(found.map(|item| item.value), Some("value".to_string()), "{}", key);
+        let deleted = messages::TRACE_ID.scope(ids[2], reply(entry, MsgType::Delete, MsgData::Delete { key: key.clone(), consistency: None, colocate_with: None, if_version: None })).await;
+        assert!(!deleted.starts_with("Error"), "{}", deleted);
+        assert!(copies(&nodes, &key).await.is_empty(), "{}", key);
+        traced.extend(ids);
+    }
+
+    // requests entering away from the primary are routed, so spans do reach the bootstrap
+    async fn collected(boot: &Node, traced: &[Uuid]) -> Vec<Span> {
+        let sink = boot.traces.read().await;
+        traced.iter().flat_map(|id| sink.get(id)).collect()
+    }
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while collected(&nodes[0], &traced).await.is_empty() {
+        assert!(Instant::now() < deadline, "no spans were reported");
+        sleep(Duration::from_millis(20)).await;
+    }
+    sleep(Duration::from_millis(200)).await;
+    let spans = collected(&nodes[0], &traced).await;
+    let replicated: Vec<&Span> = spans.iter()
+        .filter(|span| matches!(span.msg_type, MsgType::FwInsert | MsgType::FwDelete | MsgType::FwSyncKey | MsgType::AckInsert | MsgType::AckDelete))
+        .collect();
+    assert!(replicated.is_empty(), "{:?}", replicated);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn insert_reply_counts_the_copies_written_by_then() {
+    for (mode, acked, durable) in [(Consistency::Chain, 3, true), (Consistency::Eventual, 1, false)] {
+        let nodes = ring(3, mode, 4).await;
+        for i in 0..5 {
+            let key = format!("key{}", i);
+            let inserted = insert(&nodes[i % nodes.len()], &key, "value").await;
+            assert!(inserted.contains(&format!("[replicas_acked: {}, durable: {}]", acked, durable)), "{:?}: {}", mode, inserted);
+            if durable {
+                assert_eq!(copies(&nodes, &key).await.len(), 3, "{:?}: {}", mode, key);
+            }
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drifted_ranges_are_detected_and_corrected() {
+    let checked = || NodeOptions { range_check_ms: 100, range_auto_correct: true, ..NodeOptions::default() };
+    let nodes = ring_with(3, Consistency::Eventual, 4, checked).await;
+    let node = &nodes[2];
+    let bounds = |ranges: UnionRange<HashType>| ranges.iter().map(|range| range.get_bounds()).collect::<Vec<_>>();
+    let intact = bounds(node.get_replica_ranges().await);
+    assert_eq!(intact.len(), 2);
+    assert_eq!(node.range_drift(), 0);
+
+    // the newest range stops short of the predecessor and a stray range sits beyond the furthest
+    {
+        let mut replication = node.replication.write().await;
+        let ranges = &mut replication.replica_ranges;
+        let (lower, upper) = ranges.get_tail().get_bounds();
+        ranges.pop_tail();
+        ranges.insert(Range::new(lower, HashType(upper.0.map(|byte| byte / 2)), false, true));
+        let (head_lower, _) = ranges.get_head().get_bounds();
+        ranges.insert_head(Range::new(HashType(head_lower.0.map(|byte| byte / 2)), head_lower, false, true));
+    }
+
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while bounds(node.get_replica_ranges().await) != intact {
+        assert!(Instant::now() < deadline, "ranges were not corrected: {:?}", node.get_replica_ranges().await);
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert!(node.range_drift() >= 2, "drift seen {} times", node.range_drift());
+    // nodes whose ranges were left alone found nothing to correct
+    assert!(nodes.iter().filter(|other| other.get_id() != node.get_id()).all(|other| other.range_drift() == 0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn waiting_for_replication_replies_once_every_copy_is_held() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        let data = MsgData::Insert { key: key.clone(), value: "value".to_string(), wait_for_replication: true, consistency: None,
+                                     colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None };
+        let inserted = reply(&nodes[i % nodes.len()], MsgType::Insert, data).await;
+        assert!(inserted.contains("[replicas_acked: 3, durable: true]"), "{}", inserted);
+        // looked up right away, without waiting for the copies to arrive
+        let found = copies(&nodes, &key).await;
+        assert_eq!(found.len(), 3, "{}: {:?}", key, found);
+        assert!(found.iter().all(|(_, item)| item.value == "value" && !item.pending), "{:?}", found);
+    }
+}
+
+#[cfg(feature = "testing")]
+#[tokio::test(flavor = "multi_thread")]
+async fn forced_join_relocate_shifts_the_replica_indices() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    for i in 0..30 {
+        let key = format!("key{}", i);
+        insert(&nodes[i % nodes.len()], &key, "value").await;
+        copies_when(&nodes, &key, |found| found.len() == 3).await;
+    }
+    let before: HashMap<HashType, u8> = nodes[1].records.read().await.iter().map(|(key, item)| (*key, item.replica_idx)).collect();
+    assert!((0..3).all(|idx| before.values().any(|held| *held == idx)), "{:?}", before);
+
+    // as the first hop after a join at the predecessor: the primaries stay, the replicas move one down
+    let after = match request(&nodes[1], MsgType::ForceRelocate, MsgData::ForceRelocate { inc: true, k_remaining: 1 }).await {
+        MsgData::Records { records, .. } => records,
+        other => panic!("expected records, got {:?}", other),
+    };
+    let after: HashMap<HashType, u8> = after.iter().map(|item| (item.key_hash(), item.replica_idx)).collect();
+    for (key, idx) in before.iter() {
+        match idx {
+            0 => assert_eq!(after.get(key), Some(&0)),
+            1 => assert_eq!(after.get(key), Some(&2)),
+            _ => assert_eq!(after.get(key), None, "the copy past k stayed")
+        }
+    }
+    assert_eq!(after.len(), before.values().filter(|idx| **idx < 2).count());
+
+    let refused = reply(&nodes[1], MsgType::ForceRelocate, MsgData::ForceRelocate { inc: true, k_remaining: 2 }).await;
+    assert!(refused.starts_with("Error: BadRequest: k_remaining must be below"), "{}", refused);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_insert_then_eventual_read_on_one_key() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    let data = MsgData::Insert { key: "critical".to_string(), value: "v1".to_string(), wait_for_replication: false, consistency: Some(Consistency::Chain),
+                                 colocate_with: None, max_value_len: None, create_only: false, json: false, copy_of: None };
+    let inserted = reply(&nodes[1], MsgType::Insert, data).await;
+    assert!(inserted.contains("[replicas_acked: 3, durable: true]"), "{}", inserted);
+    // the chain reply comes from the tail, so any copy an Eventual read lands on has the write, acked or not
+    let found = copies(&nodes, "critical").await;
+    assert_eq!(found.len(), 3, "{:?}", found);
+    assert!(found.iter().all(|(_, item)| item.value == "v1"), "{:?}", found);
+    for entry in nodes.iter() {
+        for prefer in [ReadPreference::Nearest, ReadPreference::Random, ReadPreference::Primary] {
+            let query = MsgData::Query { key: "critical".to_string(), kind: QueryKind::Record, prefer, consistency: Some(Consistency::Eventual), colocate_with: None };
+            match request(entry, MsgType::Query, query).await {
+                MsgData::Record { record: Some(item), .. } => assert_eq!(item.value, "v1", "{:?} at {}", prefer, entry.get_info()),
+                other => panic!("expected the record, got {:?}", other),
+            }
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replica_check_counts_a_copy_removed_out_of_band() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    insert(&nodes[0], "key", "value").await;
+    copies_when(&nodes, "key", |found| found.len() == 3).await;
+    let check = || reply(&nodes[2], MsgType::CheckReplicas, MsgData::CheckReplicas { key: "key".to_string() });
+    let full = check().await;
+    assert!(full.ends_with("[copies: 3 of 3, matching the primary: 3]"), "{}", full);
+
+    let lost = holder(&nodes, "key", 1).await;
+    lost.records.write().await.remove(&HashFunc("key"));
+    let under = check().await;
+    assert!(under.ends_with("[copies: 2 of 3, matching the primary: 2]"), "{}", under);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn head_replies_once_the_acks_are_back() {
+    let nodes = ring_with(3, Consistency::Chain, 4, || NodeOptions { reply_from_head: true, ..NodeOptions::default() }).await;
+    for i in 0..20 {
+        let key = format!("key{}", i);
+        let inserted = insert(&nodes[i % nodes.len()], &key, "value").await;
+        assert!(inserted.contains("[replicas_acked: 3, durable: true]"), "{}", inserted);
+        // the ack clears each copy on its way up, so the head can only reply after the last of them
+        let found = copies(&nodes, &key).await;
+        assert_eq!(found.len(), 3, "{}", key);
+        assert!(found.iter().all(|(_, item)| !item.pending), "{}: {:?}", key, found);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_insert_trace_follows_the_chain_and_back() {
+    let nodes = ring_with(3, Consistency::Chain, 4, || NodeOptions { tracing: true, ..NodeOptions::default() }).await;
+    let trace_id = Uuid::new_v4();
+    let inserted = messages::TRACE_ID.scope(trace_id, insert(&nodes[2], "traced", "value")).await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+    copies_when(&nodes, "traced", |found| found.iter().all(|(_, item)| !item.pending)).await;
+    let mut holders = Vec::new();
+    for idx in 0..3 {
+        holders.push(holder(&nodes, "traced", idx).await.get_id());
+    }
+
+    // asked at a peer, the trace is fetched from the bootstrap
+    let deadline = Instant::now() + SETTLE_WAIT;
+    let spans = loop {
+        let spans = match request(&nodes[1], MsgType::Trace, MsgData::Trace { trace_id }).await {
+            MsgData::Spans { spans, .. } => spans,
+            other => panic!("expected spans, got {:?}", other),
+        };
+        if spans.iter().filter(|span| matches!(span.msg_type, MsgType::AckInsert)).count() == 2 {
+            break spans;
+        }
+        assert!(Instant::now() < deadline, "trace incomplete: {:?}", spans);
+        sleep(Duration::from_millis(20)).await;
+    };
+    let hops: Vec<(&str, HashType, HashType)> = spans.iter()
+        .filter_map(|span| match span.msg_type {
+            MsgType::FwInsert => Some(("FwInsert", span.node.id, span.next_hop.id)),
+            MsgType::AckInsert => Some(("AckInsert", span.node.id, span.next_hop.id)),
+            _ => None
+        })
+        .collect();
+    assert_eq!(hops, vec![
+        ("FwInsert", holders[0], holders[1]),
+        ("FwInsert", holders[1], holders[2]),
+        ("AckInsert", holders[2], holders[1]),
+        ("AckInsert", holders[1], holders[0]),
+    ]);
+    // routing to the primary happens before the chain starts
+    let first_fw = spans.iter().position(|span| matches!(span.msg_type, MsgType::FwInsert)).expect("chain hops");
+    assert!(spans[..first_fw].iter().all(|span| matches!(span.msg_type, MsgType::Insert)), "{:?}", spans);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inspect_flags_the_replica_that_diverged_from_the_primary() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    insert(&nodes[0], "key", "value").await;
+    copies_when(&nodes, "key", |found| found.len() == 3).await;
+    let stale = holder(&nodes, "key", 2).await;
+    overwrite(stale, "key", "stale", Utc::now() - chrono::Duration::seconds(60)).await;
+
+    let inspected = reply(&nodes[1], MsgType::InspectKey, MsgData::InspectKey { key: "key".to_string() }).await;
+    assert!(inspected.starts_with("🔑 key: 3 position(s), 1 diverging from the primary"), "{}", inspected);
+    let lines: Vec<&str> = inspected.lines().skip(1).collect();
+    assert_eq!(lines.len(), 3, "{}", inspected);
+    for (pos, line) in lines.iter().enumerate() {
+        assert!(line.trim_start().starts_with(&format!("{} 🖥️", pos)), "{}", inspected);
+        assert_eq!(line.ends_with("⚠️ differs"), pos == 2, "{}", inspected);
+    }
+    assert!(lines[2].contains(&format!("({})", stale.get_id())) && lines[2].contains("🔒stale"), "{}", inspected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn copy_for_a_briefly_unreachable_replica_is_redelivered() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let node_of = |id: HashType| nodes.iter().find(|node| node.get_id() == id).expect("node of the ring");
+    // a key whose replica is a peer, so it can be restarted as it was
+    let mut key = String::new();
+    let mut replica_id = None;
+    for i in 0.. {
+        key = format!("key{}", i);
+        let succ = node_of(chord_owner(&ids, HashFunc(&key))).get_succ().await.expect("linked").id;
+        if succ != nodes[0].get_id() {
+            replica_id = Some(succ);
+            break;
+        }
+    }
+    let primary = node_of(chord_owner(&ids, HashFunc(&key)));
+    let replica = node_of(replica_id.expect("a peer replica")).clone();
+
+    replica.stopped.notify_one();
+    while listening(&replica).await {
+        sleep(Duration::from_millis(5)).await;
+    }
+    let inserted = insert(primary, &key, "value").await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+    let queued = reply(primary, MsgType::DeadLetters, MsgData::DeadLetters { }).await;
+    assert!(queued.contains("dead letters: 1 queued"), "{}", queued);
+
+    let restarted = replica.clone();
+    tokio::spawn(async move { restarted.init().await });
+    let found = copies_when(&nodes, &key, |found| found.len() == 2).await;
+    assert!(found.iter().any(|(node, item)| node.id == replica.get_id() && item.replica_idx == 1 && item.value == "value"), "{:?}", found);
+    let drained = reply(primary, MsgType::DeadLetters, MsgData::DeadLetters { }).await;
+    assert!(drained.contains("0 queued") && drained.contains("1 redelivered"), "{}", drained);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_during_relocations_never_miss_an_existing_key() {
+    let mut nodes = ring(3, Consistency::Eventual, 4).await;
+    let keys: Vec<String> = (0..40).map(|i| format!("key{}", i)).collect();
+    for (i, key) in keys.iter().enumerate() {
+        insert(&nodes[i % nodes.len()], key, "value").await;
+        copies_when(&nodes, key, |found| found.len() == 3).await;
+    }
+
+    // readers enter at the nodes that stay while a join and a depart shift the replica indices
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut readers = Vec::new();
+    for entry in nodes[..2].iter() {
+        let (entry, keys, stop) = (entry.clone(), keys.clone(), stop.clone());
+        readers.push(tokio::spawn(async move {
+            let mut reads = 0;
+            while !stop.load(Ordering::SeqCst) {
+                for key in keys.iter() {
+                    let found = read(&entry, key).await;
+                    assert_eq!(found.map(|item| item.value), Some("value".to_string()), "{} missing during a relocation", key);
+                    reads += 1;
+                }
+            }
+            reads
+        }));
+    }
+    let newcomer = peer(&nodes[0], NodeOptions::default()).await;
+    let joined = join(&newcomer).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    nodes.push(newcomer);
+    // one topology change at a time, concurrent ones are refused with --stabilize-ms
+    settle(&nodes).await;
+    for key in keys.iter() {
+        copies_when(&nodes, key, |found| found.len() == 3).await;
+    }
+    let leaving = nodes.remove(3);
+    let departed = depart(&leaving).await;
+    assert!(!departed.starts_with("Error"), "{}", departed);
+    settle(&nodes).await;
+    stop.store(true, Ordering::SeqCst);
+
+    for reader in readers {
+        let reads = reader.await.expect("no read missed a key");
+        assert!(reads >= keys.len(), "reader stopped after {} reads", reads);
+    }
+    for key in keys.iter() {
+        assert_eq!(copies_when(&nodes, key, |found| found.len() == 3).await.len(), 3, "{}", key);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn verify_replicas_detects_and_repairs_a_corrupt_replica_idx() {
+    let nodes = ring(3, Consistency::Eventual, 4).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let primary = &nodes[1];
+    let keys: Vec<String> = (0..).map(|i| format!("key{}", i))
+        .filter(|key| chord_owner(&ids, HashFunc(key)) == primary.get_id())
+        .take(2)
+        .collect();
+    for key in keys.iter() {
+        insert(primary, key, "value").await;
+        copies_when(&nodes, key, |found| found.len() == 3).await;
+    }
+    // one copy at the wrong depth, one lost
+    let shifted = holder(&nodes, &keys[0], 1).await;
+    shifted.records.write().await.get_mut(&HashFunc(&keys[0])).expect("copy").replica_idx = 2;
+    let emptied = holder(&nodes, &keys[1], 2).await;
+    emptied.records.write().await.remove(&HashFunc(&keys[1]));
+
+    let verify = |repair| MsgData::VerifyReplicas { repair, sample: None };
+    let found = reply(primary, MsgType::VerifyReplicas, verify(false)).await;
+    assert!(found.starts_with("Verified the replica chain of 2 primary key(s): 2 problem(s), 0 repaired"), "{}", found);
+    assert!(found.contains(&format!("holds 🔑 {} at replica_idx 2, expected 1", keys[0])), "{}", found);
+    assert!(found.contains(&format!("misses 🔑 {} (replica_idx 2)", keys[1])), "{}", found);
+    // reporting alone changes nothing
+    assert_eq!(shifted.records.read().await[&HashFunc(&keys[0])].replica_idx, 2);
+
+    let repaired = reply(primary, MsgType::VerifyReplicas, verify(true)).await;
+    assert!(repaired.starts_with("Verified the replica chain of 2 primary key(s): 2 problem(s), 2 repaired"), "{}", repaired);
+    for key in keys.iter() {
+        let found = copies(&nodes, key).await;
+        let mut depths: Vec<u8> = found.iter().map(|(_, item)| item.replica_idx).collect();
+        depths.sort();
+        assert_eq!(depths, vec![0, 1, 2], "{}: {:?}", key, found);
+    }
+    let clean = reply(primary, MsgType::VerifyReplicas, verify(false)).await;
+    assert!(clean.starts_with("Verified the replica chain of 2 primary key(s): 0 problem(s)"), "{}", clean);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replica_set_names_the_nodes_actually_holding_each_copy() {
+    let nodes = ring(3, Consistency::Eventual, 5).await;
+    for i in 0..6 {
+        let key = format!("key{}", i);
+        insert(&nodes[i % nodes.len()], &key, "value").await;
+        let found = copies_when(&nodes, &key, |found| found.len() == 3).await;
+
+        let listed = reply(&nodes[(i + 2) % nodes.len()], MsgType::ReplicaSet, MsgData::ReplicaSet { key: key.clone() }).await;
+        assert!(listed.starts_with(&format!("🔑 {}: replicas on 3 node(s)", key)), "{}", listed);
+        for (node, item) in found.iter() {
+            let line = format!("\n  {} 🖥️ {} ({})", item.replica_idx, node, node.id);
+            assert!(listed.contains(&line), "{} misses{}", listed, line);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stalled_replica_shows_as_lag_under_eventual_but_not_under_chain() {
+    let stall = Duration::from_millis(400);
+    for mode in [Consistency::Eventual, Consistency::Chain] {
+        let nodes = ring(2, mode, 3).await;
+        let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+        let primary = nodes.iter().find(|node| node.get_id() == chord_owner(&ids, HashFunc("lagging"))).expect("primary");
+        let succ = primary.get_succ().await.expect("linked").id;
+        let replica = nodes.iter().find(|node| node.get_id() == succ).expect("replica");
+
+        // the replica can neither store the copy nor answer for it while its records are held
+        let held = replica.records.write().await;
+        let (ip, port) = (nodes[0].get_ip(), nodes[0].get_port());
+        let args: Vec<String> = ["lagging", "value", "--timeout", "5000"].iter().map(|arg| arg.to_string()).collect();
+        let lag = tokio::task::spawn_blocking(move || crate::cli::run_lag(ip, port, &args));
+        sleep(stall).await;
+        drop(held);
+        let lags = lag.await.expect("lag run").expect("lag report");
+
+        assert_eq!(lags.len(), 2, "{:?}", lags);
+        let replica_lag = lags[1].0.expect("replica converged");
+        match mode {
+            // acked by the primary alone, the copy lands once the replica is free again
+            Consistency::Eventual => assert!(replica_lag >= stall / 2, "{:?}", lags),
+            // the ack waited for the stalled copy, which is then found by the first read
+            _ => assert!(lags.iter().all(|(lag, reads)| lag.is_some() && *reads == 1), "{:?}", lags),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sampled_verify_estimate_stays_within_its_statistical_bound() {
+    let nodes = ring(2, Consistency::Eventual, 2).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let (primary, replica) = (&nodes[0], &nodes[1]);
+    let keys: Vec<String> = (0..).map(|i| format!("key{}", i))
+        .filter(|key| chord_owner(&ids, HashFunc(key)) == primary.get_id())
+        .take(200)
+        .collect();
+    for key in keys.iter() {
+        insert(primary, key, "value").await;
+        copies_when(&nodes, key, |found| found.len() == 2).await;
+    }
+    // every fourth key loses its only replica
+    for key in keys.iter().step_by(4) {
+        replica.records.write().await.remove(&HashFunc(key));
+    }
+
+    // "... N sampled key(s) miss a copy, estimated under-replicated: E (95% CI L..H)"
+    let estimate = |reply: &str| -> (f64, f64, f64) {
+        let tail = reply.lines().next().and_then(|line| line.split("estimated under-replicated: ").nth(1)).expect("an estimate");
+        let (estimate, interval) = tail.split_once(" (95% CI ").expect("an interval");
+        let (low, high) = interval.trim_end_matches(')').split_once("..").expect("interval bounds");
+        let number = |text: &str| text.parse::<f64>().unwrap_or_else(|e| panic!("{}: {}", text, e));
+        (number(estimate), number(low), number(high))
+    };
+    let verify = |sample| MsgData::VerifyReplicas { repair: false, sample: Some(sample) };
+
+    let full = reply(primary, MsgType::VerifyReplicas, verify(1.0)).await;
+    assert!(full.starts_with("Verified the replica chain of 200 sampled out of 200 primary key(s)"), "{}", full);
+    assert_eq!(estimate(&full), (50.0, 50.0, 50.0), "{}", full);
+
+    let sampled = reply(primary, MsgType::VerifyReplicas, verify(0.25)).await;
+    assert!(sampled.starts_with("Verified the replica chain of 50 sampled out of 200 primary key(s)"), "{}", sampled);
+    let (found, low, high) = estimate(&sampled);
+    assert!(low <= found && found <= high, "{}", sampled);
+    // hypergeometric spread of the estimate, the check fails far less often than once in 10^4 runs
+    let (n, m, p): (f64, f64, f64) = (200.0, 50.0, 0.25);
+    let sd = n * (p * (1.0 - p) / m * (n - m) / (n - 1.0)).sqrt();
+    assert!((found - 50.0).abs() <= 4.0 * sd, "estimate {} is more than 4 sd from 50: {}", found, sampled);
+}
@@ -0,0 +1,430 @@
+use super::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn large_value_round_trips_intact() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    // what the cli reads from stdin for 'insert <key> -', line breaks included
+    let value: String = (0..200).map(|line| format!("line {:03} {}\n", line, "x".repeat(300))).collect();
+    let reply = insert(&nodes[1], "piped", &value).await;
+    assert!(reply.contains("piped"), "{}", reply);
+    let stored = read(&nodes[2], "piped").await.expect("key was stored");
+    assert_eq!(stored.value, value);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn touch_refreshes_every_copy_and_keeps_the_value() {
+    let nodes = ring(3, Consistency::Eventual, 3).await;
+    insert(&nodes[0], "lease", "held").await;
+    let before = copies_when(&nodes, "lease", |found| found.len() == 3).await;
+    assert_eq!(before.len(), 3);
+    let inserted = before[0].1.timestamp;
+
+    sleep(Duration::from_millis(10)).await;
+    let touched = reply(&nodes[1], MsgType::Touch, MsgData::Touch { key: "lease".to_string() }).await;
+    assert!(touched.starts_with("Touched"), "{}", touched);
+
+    // replicas take the primary's new timestamp, not one of their own
+    let after = copies_when(&nodes, "lease", |found| found.iter().all(|(_, item)| item.timestamp > inserted)).await;
+    let refreshed = after[0].1.timestamp;
+    for (holder, item) in after.iter() {
+        assert_eq!(item.timestamp, refreshed, "stale copy on {}", holder);
+        assert_eq!(item.value, "held");
+    }
+
+    let missing = reply(&nodes[2], MsgType::Touch, MsgData::Touch { key: "never inserted".to_string() }).await;
+    assert!(missing.starts_with("Error"), "{}", missing);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compact_keeps_the_newest_append_on_every_copy() {
+    let nodes = ring(3, Consistency::Eventual, 3).await;
+    for value in ["first", "second", "third"] {
+        insert(&nodes[0], "log", value).await;
+    }
+    let appended = copies_when(&nodes, "log", |found| found.len() == 3 && found.iter().all(|(_, item)| item.value == "firstsecondthird")).await;
+    assert!(appended.iter().all(|(_, item)| item.value == "firstsecondthird"), "{:?}", appended);
+
+    let compacted = reply(&nodes[1], MsgType::Compact, MsgData::Compact { key: "log".to_string(), strategy: CompactStrategy::Last }).await;
+    assert!(compacted.starts_with("Compacted"), "{}", compacted);
+    let found = copies_when(&nodes, "log", |found| found.iter().all(|(_, item)| item.value == "third")).await;
+    assert_eq!(found.len(), 3);
+    for (node, item) in found {
+        assert_eq!(item.value, "third", "copy on {}", node);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chain_writes_wait_for_a_slot_and_are_refused_without_one() {
+    let nodes = ring(2, Consistency::Chain, 2).await;
+    let mut primary = &nodes[0];
+    for node in nodes.iter() {
+        if node.is_responsible(&HashFunc("key")).await {
+            primary = node;
+        }
+    }
+    // a slow tail that never acks, as far as the head can tell
+    primary.chain_window.try_acquire_many(crate::CHAIN_WINDOW as u32).expect("a free window").forget();
+
+    let head = primary.clone();
+    let waiting = tokio::spawn(async move { insert(&head, "key", "v1").await });
+    sleep(Duration::from_millis(300)).await;
+    assert!(!waiting.is_finished(), "the write went on without a slot");
+    assert!(copies(&nodes, "key").await.is_empty());
+    primary.release_chain_slot();
+    let inserted = waiting.await.expect("insert task");
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+
+    // the ack gave the slot back, once it is taken again the next write waits it out and is refused
+    copies_when(&nodes, "key", |found| found.len() == 2 && found.iter().all(|(_, item)| !item.pending)).await;
+    primary.chain_window.try_acquire().expect("the slot given back by the ack").forget();
+    let refused = insert(primary, "key", "v2").await;
+    assert!(refused.starts_with("Error: ChainFull"), "{}", refused);
+    for (node, item) in copies(&nodes, "key").await {
+        assert_eq!(item.value, "v1", "copy on {}", node);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn appended_entries_read_back_in_append_order() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    for i in 0..5 {
+        let appended = reply(&nodes[i % nodes.len()], MsgType::Append, MsgData::Append { key: "journal".to_string(), entry: format!("entry {}", i) }).await;
+        assert!(appended.contains(&format!("(entry #{})", i + 1)), "{}", appended);
+    }
+
+    let log = reply(&nodes[2], MsgType::ReadLog, MsgData::ReadLog { key: "journal".to_string(), since: None, limit: None }).await;
+    assert!(log.contains("LOG📜 🔑journal (5)"), "{}", log);
+    let entries: Vec<&str> = log.lines().filter_map(|line| line.split(" : ").nth(1)).collect();
+    assert_eq!(entries, (0..5).map(|i| format!("entry {}", i)).collect::<Vec<_>>());
+
+    let limited = reply(&nodes[1], MsgType::ReadLog, MsgData::ReadLog { key: "journal".to_string(), since: None, limit: Some(2) }).await;
+    assert!(limited.contains("(2)") && limited.contains("entry 1") && !limited.contains("entry 2"), "{}", limited);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subscriber_is_pushed_every_change_until_it_unsubscribes() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let subscriber = Client::bind().await;
+    subscriber.send(&nodes[1], MsgType::Subscribe, MsgData::Subscribe { key: "watched".to_string() }).await;
+    match subscriber.recv().await {
+        MsgData::Reply { reply } => assert!(reply.starts_with("Subscribed to 🔑 watched"), "{}", reply),
+        other => panic!("expected a reply, got {:?}", other),
+    }
+
+    insert(&nodes[2], "watched", "v1").await;
+    match subscriber.recv().await {
+        MsgData::Notify { key, item: Some(item) } => assert_eq!((key.as_str(), item.value.as_str()), ("watched", "v1")),
+        other => panic!("expected a notification, got {:?}", other),
+    }
+    insert(&nodes[0], "other", "v1").await;
+    reply(&nodes[0], MsgType::Delete, MsgData::Delete { key: "watched".to_string(), consistency: None, colocate_with: None, if_version: None }).await;
+    match subscriber.recv().await {
+        MsgData::Notify { key, item: None } => assert_eq!(key, "watched"),
+        other => panic!("expected a deletion notice, got {:?}", other),
+    }
+
+    subscriber.send(&nodes[2], MsgType::Unsubscribe, MsgData::Unsubscribe { key: "watched".to_string() }).await;
+    subscriber.recv().await;
+    insert(&nodes[1], "watched", "v2").await;
+    assert!(subscriber.try_recv(Duration::from_millis(500)).await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blank_keys_are_refused_and_never_listed() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    for (i, blank) in ["", " ", "\t \n"].iter().enumerate() {
+        let refused = insert(&nodes[i], blank, "value").await;
+        assert_eq!(refused, "Error: BadRequest: key must not be empty", "{:?}", blank);
+    }
+    // empty values are allowed
+    let inserted = insert(&nodes[0], "real", "").await;
+    assert!(inserted.starts_with("Inserted"), "{}", inserted);
+
+    for node in nodes.iter() {
+        assert!(node.records.read().await.values().all(|item| !item.title.trim().is_empty()), "{}", node.get_info());
+    }
+    let all = reply(&nodes[1], MsgType::QueryAll, MsgData::QueryAll { parallel: false, restart_on_churn: false }).await;
+    let listed: Vec<&str> = all.lines().filter(|line| line.starts_with("(🔑")).collect();
+    assert_eq!(listed, vec!["(🔑real : 🔒)"], "{}", all);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stored_bytes_track_inserts_and_deletes() {
+    for mode in [Consistency::Eventual, Consistency::Chain] {
+        let nodes = ring(2, mode, 3).await;
+        for i in 0..30 {
+            insert(&nodes[i % nodes.len()], &format!("key{}", i), &"x".repeat(i * 7 + 1)).await;
+        }
+        for i in (0..30).step_by(3) {
+            let deleted = reply(&nodes[i % nodes.len()], MsgType::Delete, MsgData::Delete { key: format!("key{}", i), consistency: None, colocate_with: None, if_version: None }).await;
+            assert!(!deleted.starts_with("Error"), "{:?}: {}", mode, deleted);
+        }
+        for i in 0..30 {
+            let key = format!("key{}", i);
+            let expected = if i % 3 == 0 { 0 } else { 2 };
+            assert_eq!(copies_when(&nodes, &key, |found| found.len() == expected).await.len(), expected, "{:?}: {}", mode, key);
+        }
+
+        let mut total = 0;
+        for node in nodes.iter() {
+            let held: u64 = node.records.read().await.values().map(|item| item.value.len() as u64).sum();
+            assert_eq!(node.stored_bytes(), held, "{:?}: {}", mode, node.get_info());
+            total += held;
+        }
+        let expected: u64 = (0..30u64).filter(|i| i % 3 != 0).map(|i| 2 * (i * 7 + 1)).sum();
+        assert_eq!(total, expected, "{:?}", mode);
+        match request(&nodes[1], MsgType::Overlay, MsgData::Overlay { structured: true }).await {
+            MsgData::Ring { bytes, .. } => assert_eq!(bytes.iter().sum::<u64>(), expected, "{:?}", mode),
+            other => panic!("expected the ring, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_only_node_serves_reads_and_refuses_its_writes() {
+    let nodes = ring(1, Consistency::Eventual, 3).await;
+    let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+    for key in keys.iter() {
+        insert(&nodes[0], key, "v1").await;
+    }
+    let quiet = &nodes[1];
+    let set = reply(quiet, MsgType::SetReadOnly, MsgData::SetReadOnly { enabled: true }).await;
+    assert!(set.ends_with("is now read-only"), "{}", set);
+
+    let (mut own, mut other) = (Vec::new(), Vec::new());
+    for key in keys.iter() {
+        if quiet.is_responsible(&HashFunc(key)).await { own.push(key) } else { other.push(key) }
+    }
+    assert!(!own.is_empty() && !other.is_empty(), "keys all on one side");
+    for key in keys.iter() {
+        assert_eq!(read(quiet, key).await.expect("key was stored").value, "v1", "{}", key);
+    }
+    for key in own.iter() {
+        let refused = insert(&nodes[2], key, "v2").await;
+        assert!(refused.starts_with("Error: ReadOnly:"), "{}", refused);
+        let refused = reply(&nodes[0], MsgType::Delete, MsgData::Delete { key: key.to_string(), consistency: None, colocate_with: None, if_version: None }).await;
+        assert!(refused.starts_with("Error: ReadOnly:"), "{}", refused);
+        assert_eq!(read(&nodes[0], key).await.expect("key was kept").value, "v1");
+    }
+    // writes the node only relays still go through
+    for key in other.iter() {
+        let relayed = insert(quiet, key, "v2").await;
+        assert!(!relayed.starts_with("Error"), "{}", relayed);
+    }
+
+    reply(quiet, MsgType::SetReadOnly, MsgData::SetReadOnly { enabled: false }).await;
+    let accepted = insert(&nodes[0], own[0], "v2").await;
+    assert!(!accepted.starts_with("Error"), "{}", accepted);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_past_the_high_mark_sheds_down_to_the_low_mark() {
+    let boot = bootstrap(2, Consistency::Eventual, NodeOptions { mem_limits: Some((8000, 4000)), ..NodeOptions::default() }).await;
+    let other = peer(&boot, NodeOptions::default()).await;
+    let joined = join(&other).await;
+    assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+    let nodes = vec![boot.clone(), other.clone()];
+    settle(&nodes).await;
+
+    // only keys the bootstrap is primary for, so all of its bytes can be shed
+    let ids = [boot.get_id(), other.get_id()];
+    let keys: Vec<String> = (0..).map(|i| format!("key{}", i))
+        .filter(|key| chord_owner(&ids, HashFunc(key)) == boot.get_id())
+        .take(30)
+        .collect();
+    let value = "v".repeat(400);
+    let (hot, cold) = keys.split_at(5);
+    for key in hot {
+        insert(&other, key, &value).await;
+        assert_eq!(read(&boot, key).await.expect("key was stored").value, value);
+    }
+    for key in cold {
+        insert(&other, key, &value).await;
+    }
+
+    let deadline = Instant::now() + SETTLE_WAIT;
+    while boot.stored_bytes() > 4000 {
+        assert!(Instant::now() < deadline, "still {} bytes stored", boot.stored_bytes());
+        sleep(Duration::from_millis(50)).await;
+    }
+    // shedding stops as soon as the low mark is reached
+    assert!(boot.stored_bytes() > 4000 - 400, "shed down to {} bytes", boot.stored_bytes());
+    for key in hot {
+        assert!(read(&boot, key).await.is_some(), "recently read {} was shed", key);
+    }
+    // the deletes reached the replicas too
+    for key in keys.iter() {
+        let found = copies_when(&nodes, key, |found| found.len() != 1).await;
+        assert!(found.is_empty() || found.len() == 2, "{} left with {:?}", key, found);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rename_moves_the_value_intact_to_a_key_on_another_node() {
+    let nodes = ring(2, Consistency::Eventual, 4).await;
+    let ids: Vec<HashType> = nodes.iter().map(|node| node.get_id()).collect();
+    let from = "old-name";
+    let to = (0..).map(|i| format!("new-name{}", i))
+        .find(|key| chord_owner(&ids, HashFunc(key)) != chord_owner(&ids, HashFunc(from)))
+        .expect("a key placed on another node");
+    let value = "a value, with ✨ and spaces";
+    insert(&nodes[0], from, value).await;
+    let original = copies_when(&nodes, from, |found| found.len() == 2).await[0].1.version();
+
+    let entry = nodes.iter().find(|node| node.get_id() != chord_owner(&ids, HashFunc(from))).expect("an entry away from the old key");
+    let renamed = reply(entry, MsgType::Rename, MsgData::Rename { from: from.to_string(), to: to.clone() }).await;
+    assert_eq!(renamed, format!("Renamed 🔑 {} to 🔑 {} (🔒{})", from, to, value));
+
+    let moved = copies_when(&nodes, &to, |found| found.len() == 2).await;
+    assert_eq!(moved.len(), 2, "{:?}", moved);
+    assert!(moved.iter().all(|(_, item)| item.value == value), "{:?}", moved);
+    // the copy is the same version, not a new write
+    assert!(moved.iter().all(|(_, item)| item.version() == original), "{:?}", moved);
+    assert_eq!(holder(&nodes, &to, 0).await.get_id(), chord_owner(&ids, HashFunc(&to)));
+    assert!(copies_when(&nodes, from, |found| found.is_empty()).await.is_empty());
+    for node in nodes.iter() {
+        assert!(read(node, from).await.is_none());
+        assert_eq!(read(node, &to).await.expect("renamed key").value, value);
+    }
+
+    // renaming onto a taken key leaves both untouched
+    insert(&nodes[1], "taken", "other").await;
+    let refused = reply(&nodes[2], MsgType::Rename, MsgData::Rename { from: to.clone(), to: "taken".to_string() }).await;
+    assert!(refused.starts_with("Error: 🔑 taken already exists"), "{}", refused);
+    assert_eq!(read(&nodes[3], &to).await.expect("renamed key").value, value);
+
+    // a delete conditional on a version the key has moved past leaves it in place
+    let stale = MsgData::Delete { key: "taken".to_string(), consistency: None, colocate_with: None, if_version: Some(original) };
+    let conflict = reply(&nodes[0], MsgType::Delete, stale).await;
+    assert!(conflict.starts_with("Error: Conflict"), "{}", conflict);
+    assert_eq!(read(&nodes[1], "taken").await.expect("kept key").value, "other");
+
+    /* An insert racing the rename is never lost: it is copied along when it landed before
+       the read, backs the rename out when it landed before the delete, or starts 'racing' afresh */
+    insert(&nodes[0], "racing", "base").await;
+    copies_when(&nodes, "racing", |found| found.len() == 2).await;
+    let renaming = nodes[1].clone();
+    let rename = tokio::spawn(async move {
+        reply(&renaming, MsgType::Rename, MsgData::Rename { from: "racing".to_string(), to: "raced".to_string() }).await
+    });
+    let appended = insert(&nodes[2], "racing", "+late").await;
+    assert!(!appended.starts_with("Error"), "{}", appended);
+    let renamed = rename.await.expect("rename task");
+    let primary = |key: &str| request(&nodes[0], MsgType::Query, query_data(key, QueryKind::Record, ReadPreference::Primary));
+    let (racing, raced) = match (primary("racing").await, primary("raced").await) {
+        (MsgData::Record { record: racing, .. }, MsgData::Record { record: raced, .. }) => (racing, raced),
+        other => panic!("expected records, got {:?}", other),
+    };
+    if renamed.starts_with("Renamed") {
+        let values = (raced.map(|item| item.value), racing.map(|item| item.value));
+        assert!(values == (Some("base+late".to_string()), None) || values == (Some("base".to_string()), Some("+late".to_string())), "{:?}", values);
+    } else {
+        assert!(renamed.starts_with("Error: Conflict"), "{}", renamed);
+        assert!(raced.is_none(), "{:?}", raced);
+        assert_eq!(racing.expect("key left in place").value, "base+late");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn case_folded_ring_resolves_differently_cased_keys_to_one_record() {
+    let folding = NodeOptions { key_norm: "trim,nfc,case".parse().expect("valid normalization"), ..NodeOptions::default() };
+    let boot = bootstrap(2, Consistency::Eventual, folding).await;
+    let mut nodes = vec![boot.clone()];
+    // the peers are started without it and take the bootstrap's normalization on joining
+    for _ in 0..2 {
+        let node = peer(&boot, NodeOptions::default()).await;
+        let joined = join(&node).await;
+        assert!(joined.contains("joined the ring"), "join failed: {}", joined);
+        nodes.push(node);
+        settle(&nodes).await;
+    }
+
+    let inserted = insert(&nodes[1], "Foo", "value").await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+    let found = copies_when(&nodes, "foo", |found| found.len() == 2).await;
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert!(found.iter().all(|(_, item)| item.title == "foo"), "{:?}", found);
+    assert!(copies(&nodes, "Foo").await.is_empty());
+    for (node, key) in nodes.iter().zip(["foo", "FOO", " fOo "]) {
+        let record = read(node, key).await.unwrap_or_else(|| panic!("{:?} did not resolve", key));
+        assert_eq!((record.title.as_str(), record.value.as_str()), ("foo", "value"));
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delete_reports_every_chain_copy_but_only_the_eventual_primary() {
+    for (mode, removed) in [(Consistency::Chain, 3), (Consistency::Eventual, 1)] {
+        let nodes = ring(3, mode, 4).await;
+        insert(&nodes[0], "key", "value").await;
+        // a chain reply leaves the tail before the acks clear the pending copies
+        copies_when(&nodes, "key", |found| found.len() == 3 && found.iter().all(|(_, item)| !item.pending)).await;
+
+        let deleted = reply(&nodes[1], MsgType::Delete, MsgData::Delete { key: "key".to_string(), consistency: None, colocate_with: None, if_version: None }).await;
+        assert!(deleted.ends_with(&format!("[replicas_removed: {}]", removed)), "{:?}: {}", mode, deleted);
+        let left = copies_when(&nodes, "key", |found| found.is_empty()).await;
+        assert!(left.is_empty(), "{:?}: {:?}", mode, left);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bounded_value_keeps_only_its_newest_bytes_on_every_copy() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let bounded = MsgData::Insert { key: "tail".to_string(), value: "0123456789".to_string(), wait_for_replication: false, consistency: None,
+                                    colocate_with: None, max_value_len: Some(10), create_only: false, json: false, copy_of: None };
+    let inserted = reply(&nodes[0], MsgType::Insert, bounded).await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+
+    // later writes carry no limit of their own, the key keeps the one it was given
+    for (i, (part, expected)) in [("abcde", "56789abcde"), ("XY", "789abcdeXY")].into_iter().enumerate() {
+        insert(&nodes[(i + 1) % nodes.len()], "tail", part).await;
+        let found = copies_when(&nodes, "tail", |found| found.len() == 2 && found.iter().all(|(_, item)| item.value == expected)).await;
+        assert!(found.len() == 2 && found.iter().all(|(_, item)| item.value == expected), "{:?}", found);
+        assert_eq!(read(&nodes[2], "tail").await.expect("key was stored").value, expected);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_create_only_inserts_admit_exactly_one() {
+    for mode in [Consistency::Eventual, Consistency::Chain] {
+        let nodes = ring(2, mode, 3).await;
+        for round in 0..5 {
+            let key = format!("fresh{}", round);
+            let create = |value: &str| MsgData::Insert { key: key.clone(), value: value.to_string(), wait_for_replication: false, consistency: None,
+                                                          colocate_with: None, max_value_len: None, create_only: true, json: false, copy_of: None };
+            let (first, second) = tokio::join!(reply(&nodes[1], MsgType::Insert, create("first")), reply(&nodes[2], MsgType::Insert, create("second")));
+
+            let replies = [&first, &second];
+            assert_eq!(replies.iter().filter(|reply| reply.starts_with("Inserted")).count(), 1, "{:?}: {:?}", mode, replies);
+            assert_eq!(replies.iter().filter(|reply| reply.starts_with("Error: AlreadyExists")).count(), 1, "{:?}: {:?}", mode, replies);
+            let winner = if first.starts_with("Inserted") { "first" } else { "second" };
+            let found = copies_when(&nodes, &key, |found| found.len() == 2 && found.iter().all(|(_, item)| !item.pending)).await;
+            assert!(found.len() == 2 && found.iter().all(|(_, item)| item.value == winner), "{:?}: {:?}", mode, found);
+        }
+        // the refused chain inserts gave their slot back
+        let primary = holder(&nodes, "fresh0", 0).await;
+        assert_eq!(primary.chain_window.available_permits(), crate::CHAIN_WINDOW, "{:?}", mode);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn value_that_is_not_json_is_refused_and_leaves_the_key_untouched() {
+    let nodes = ring(2, Consistency::Eventual, 3).await;
+    let json_insert = |value: &str| MsgData::Insert { key: "profile".to_string(), value: value.to_string(), wait_for_replication: false, consistency: None,
+                                                      colocate_with: None, max_value_len: None, create_only: false, json: true, copy_of: None };
+    let refused = reply(&nodes[0], MsgType::Insert, json_insert("{\"name\": ")).await;
+    assert!(refused.starts_with("Error: BadRequest: value is not valid JSON"), "{}", refused);
+    assert!(copies(&nodes, "profile").await.is_empty());
+
+    let stored = r#"{"name":"ada","address":{"lines":["1 Main St"]}}"#;
+    let inserted = reply(&nodes[1], MsgType::Insert, json_insert(stored)).await;
+    assert!(!inserted.starts_with("Error"), "{}", inserted);
+    let found = copies_when(&nodes, "profile", |found| found.len() == 2).await;
+    assert!(found.len() == 2 && found.iter().all(|(_, item)| item.value == stored), "{:?}", found);
+
+    // an append that is not JSON is refused before it reaches any copy
+    let refused = reply(&nodes[2], MsgType::Insert, json_insert("plain text")).await;
+    assert!(refused.starts_with("Error: BadRequest: value is not valid JSON"), "{}", refused);
+    let record = read(&nodes[2], "profile").await.expect("key was stored");
+    assert_eq!(record.value, stored);
+    assert_eq!(record.json_field("address.lines.0"), Ok(serde_json::json!("1 Main St")));
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+
+use crate::utils::{HashType, UnionRange};
+
+/* Decides which node owns a key. Node only knows its own neighbourhood,
+   so every question is asked relative to this node, its previous and its successor.
+   Chord ring arithmetic is the default, other schemes can be plugged in for experiments */
+pub trait Partitioner: fmt::Debug + Send + Sync {
+    // true if `me` is the primary for key, given its previous node
+    fn owns(&self, key: &HashType, prev: &HashType, me: &HashType) -> bool;
+
+    // true if a request for key should travel towards the successor rather than the previous
+    fn forward(&self, key: &HashType, me: &HashType, succ: &HashType) -> bool;
+
+    // replica index of key among the ranges this node replicates, -1 if none
+    fn replica_index(&self, key: &HashType, ranges: &UnionRange<HashType>) -> i16;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChordPartitioner;
+
+impl Partitioner for ChordPartitioner {
+    fn owns(&self, key: &HashType, prev: &HashType, me: &HashType) -> bool {
+        if prev < me {
+            // Normal case: key falls within (prev, self]
+            key > prev && key <= me
+        } else {
+            // Wrapped case: previous is greater due to ring wrap-around
+            key > prev || key <= me
+        }
+    }
+
+    fn forward(&self, key: &HashType, me: &HashType, succ: &HashType) -> bool {
+        if me < succ {
+            // Normal case: key falls within (self, any forward successor]
+            key > me
+        } else {
+            // Wrapped case
+            key > me || key <= succ
+        }
+    }
+
+    fn replica_index(&self, key: &HashType, ranges: &UnionRange<HashType>) -> i16 {
+        ranges.is_subset(*key)
+    }
+}
+
+// Chord placement over the bitwise complement of the key, so every key lands on a different node
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MirrorPartitioner;
+
+impl MirrorPartitioner {
+    fn mirror(key: &HashType) -> HashType {
+        HashType(key.0.map(|byte| !byte))
+    }
+}
+
+impl Partitioner for MirrorPartitioner {
+    fn owns(&self, key: &HashType, prev: &HashType, me: &HashType) -> bool {
+        ChordPartitioner.owns(&Self::mirror(key), prev, me)
+    }
+
+    fn forward(&self, key: &HashType, me: &HashType, succ: &HashType) -> bool {
+        ChordPartitioner.forward(&Self::mirror(key), me, succ)
+    }
+
+    fn replica_index(&self, key: &HashType, ranges: &UnionRange<HashType>) -> i16 {
+        ChordPartitioner.replica_index(&Self::mirror(key), ranges)
+    }
+}
@@ -8,6 +8,7 @@ use hex::{FromHex, ToHex};
 use std::cmp::Ord;
 use num_traits::Bounded;
 use chrono::{DateTime, Utc};
+use unicode_normalization::UnicodeNormalization;
 
 use crate::node::NodeInfo;
 
@@ -16,7 +17,11 @@ use crate::node::NodeInfo;
 pub trait DebugMsg {
     #[cfg(debug_assertions)]
     fn print_debug_msg(&self, msg: &str) {
-        println!("{:?}", msg);
+        // prefix with the trace id so one request can be grepped across all nodes
+        match crate::messages::current_trace() {
+            Some(trace_id) => println!("[{}] {:?}", trace_id, msg),
+            None => println!("{:?}", msg)
+        }
     }
 
     #[cfg(not(debug_assertions))]
@@ -100,6 +105,23 @@ pub fn HashFunc(input: &str) -> HashType {
     HashType(result.into()) 
 }
 
+/* Position of a key stored next to its parent. The high bytes come from the parent so the
+   key falls in the same node range, the low 4 bytes from the key itself keep it a distinct record.
+   Only a node id within 2^32 of the parent hash could split them, which SHA-1 makes negligible */
+pub fn HashColocated(key: &str, parent: &str) -> HashType {
+    let mut hash = HashFunc(parent);
+    hash.0[16..].copy_from_slice(&HashFunc(key).0[16..]);
+    hash
+}
+
+// hash of a key given by a client, colocated with its parent when one is given
+pub fn HashKey(key: &str, colocate_with: &Option<String>) -> HashType {
+    match colocate_with {
+        Some(parent) => HashColocated(key, parent),
+        None => HashFunc(key),
+    }
+}
+
 // wrap ip and port in a single string and call global hashing function
 pub fn HashIP(ip_addr: Ipv4Addr, port: u16) -> HashType { 
     // extract only numbers from ip
@@ -109,6 +131,32 @@ pub fn HashIP(ip_addr: Ipv4Addr, port: u16) -> HashType {
     HashFunc(&input)
 }
 
+/* Position of a node joining before 'succ' on a ring where nodes carry weights. The arc (prev, succ]
+   is split in proportion to the two weights, so the new node takes weight/(weight + succ_weight) of it.
+   Only the high 16 bytes are split, the low 4 bytes of the hashed position keep two nodes apart.
+   prev == succ stands for the whole ring, as for a node alone */
+pub fn HashWeighted(prev: HashType, succ: HashType, hashed: HashType, weight: u32, succ_weight: u32) -> HashType {
+    let high = |hash: HashType| u128::from_be_bytes(hash.0[..16].try_into().unwrap());
+    let arc = match high(succ).wrapping_sub(high(prev)) {
+        0 => u128::MAX,
+        arc => arc
+    };
+    let (weight, total) = (weight as u128, weight as u128 + succ_weight as u128);
+    let share = arc / total * weight + arc % total * weight / total;
+    let mut position = hashed;
+    position.0[..16].copy_from_slice(&high(prev).wrapping_add(share).to_be_bytes());
+    position
+}
+
+// true if key lies strictly inside the arc (lower, upper) walking clockwise on the ring
+pub fn in_arc(key: HashType, lower: HashType, upper: HashType) -> bool {
+    if lower < upper {
+        key > lower && key < upper
+    } else {
+        key > lower || key < upper
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     pub title : String, 
@@ -117,6 +165,30 @@ pub struct Item {
     // used for Chain replication to block dirty tail reads
     pub pending: bool,  
     pub timestamp: DateTime<Utc>,
+    // checksum of value, None for items received from peers that predate it
+    #[serde(default)]
+    pub checksum: Option<u32>,
+    // byte offsets where later Eventual appends start, the first part begins at 0
+    #[serde(default)]
+    pub appends: Vec<usize>,
+    // entries of an append-only log key, oldest first; value stays empty for such keys
+    #[serde(default)]
+    pub log: Vec<LogEntry>,
+    // node that stamped 'timestamp', breaks ties between versions written at the same instant
+    #[serde(default)]
+    pub writer: Option<HashType>,
+    // key this item was inserted colocated with, it decides the ring position
+    #[serde(default)]
+    pub parent: Option<String>,
+    // longest value kept, appends beyond it drop the oldest bytes
+    #[serde(default)]
+    pub max_value_len: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub entry: String,
 }
 
 impl Item {
@@ -127,6 +199,407 @@ impl Item {
             replica_idx, 
             pending,
             timestamp: Utc::now(), // stub when created 
+            checksum: Some(value_checksum(value)),
+            appends: Vec::new(),
+            log: Vec::new(),
+            writer: None,
+            parent: None,
+            max_value_len: None,
+        }
+    }
+
+    // ring position of the item, the one it was inserted under
+    pub fn key_hash(&self) -> HashType {
+        match &self.parent {
+            Some(parent) => HashColocated(&self.title, parent),
+            None => HashFunc(&self.title),
+        }
+    }
+
+    // versions order by timestamp, then by writer id, so every replica picks the same winner
+    pub fn version(&self) -> (DateTime<Utc>, Option<HashType>) {
+        (self.timestamp, self.writer)
+    }
+
+    pub fn newer_than(&self, other: &Item) -> bool {
+        self.version() > other.version()
+    }
+
+    // a copy of 'source' stored under another key keeps its version, append offsets and limit
+    pub fn adopt(&mut self, source: &Item) {
+        self.timestamp = source.timestamp;
+        self.writer = source.writer;
+        self.appends = source.appends.clone();
+        self.max_value_len = source.max_value_len;
+    }
+
+    // recompute the checksum after the value has been changed
+    pub fn seal(&mut self) {
+        self.checksum = Some(value_checksum(&self.value));
+    }
+
+    pub fn verify(&self) -> bool {
+        self.checksum.is_none_or(|sum| sum == value_checksum(&self.value))
+    }
+
+    /* Drops the oldest bytes of the value so that at most limit remain, keeping it a sliding
+        window over the appends. The cut moves forward to a char boundary, so the result may be
+        a few bytes shorter. Returns the number of bytes dropped, the caller reseals */
+    pub fn trim_front(&mut self, limit: usize) -> usize {
+        if self.value.len() <= limit {
+            return 0;
+        }
+        let mut cut = self.value.len() - limit;
+        while !self.value.is_char_boundary(cut) {
+            cut += 1;
+        }
+        self.value.drain(..cut);
+        self.appends = self.appends.iter().filter(|&&start| start > cut).map(|start| start - cut).collect();
+        cut
+    }
+
+    // collapses the appended parts of the value into one
+    pub fn compact(&mut self, strategy: CompactStrategy) {
+        self.value = match strategy {
+            CompactStrategy::Last => {
+                let start = self.appends.last().copied().unwrap_or(0);
+                self.value.get(start..).unwrap_or(&self.value).to_string()
+            }
+            CompactStrategy::Dedup => {
+                let mut seen = std::collections::HashSet::new();
+                self.value.lines().filter(|line| seen.insert(*line)).collect::<Vec<&str>>().join("\n")
+            }
+        };
+        self.appends.clear();
+        self.seal();
+    }
+
+    /* Parses the newest part of the value as JSON and walks a dotted path into it, numeric
+        segments index arrays. The raw text is what gets stored, so this runs on every read */
+    pub fn json_field(&self, path: &str) -> Result<serde_json::Value, String> {
+        let start = self.appends.last().copied().unwrap_or(0);
+        let raw = self.value.get(start..).unwrap_or(&self.value);
+        let mut field: serde_json::Value = serde_json::from_str(raw).map_err(|e| format!("value is not valid JSON: {}", e))?;
+        for segment in path.split('.') {
+            let next = match &mut field {
+                serde_json::Value::Object(map) => map.remove(segment),
+                serde_json::Value::Array(items) => match segment.parse::<usize>() {
+                    Ok(idx) if idx < items.len() => Some(items.swap_remove(idx)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            field = match next {
+                Some(next) => next,
+                None => return Err(format!("no field '{}'", path)),
+            };
+        }
+        Ok(field)
+    }
+
+    pub fn append_log(&mut self, entry: &str) {
+        let timestamp = Utc::now();
+        self.log.push(LogEntry { timestamp, entry: entry.to_string() });
+        self.timestamp = timestamp;
+    }
+
+    // log entries in append order, from 'since' on and at most 'limit' of them
+    pub fn read_log(&self, since: Option<DateTime<Utc>>, limit: Option<usize>) -> Vec<LogEntry> {
+        self.log.iter()
+            .filter(|entry| since.is_none_or(|ts| entry.timestamp >= ts))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+/* HyperLogLog sketch of distinct key hashes. Both the register and the rank come from the
+   low 4 bytes of the hash, the only ones that differ between keys colocated with one parent.
+   Sketches merge by keeping the larger register, so keys seen on several nodes count once */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub const PRECISION: u32 = 12;      // 4096 registers, a standard error of about 1.6%
+
+    pub fn new() -> Self {
+        HyperLogLog { registers: vec![0; 1 << Self::PRECISION] }
+    }
+
+    pub fn add(&mut self, key: &HashType) {
+        let low = u32::from_be_bytes([key.0[16], key.0[17], key.0[18], key.0[19]]);
+        let idx = (low >> (32 - Self::PRECISION)) as usize;
+        let rest_bits = 32 - Self::PRECISION;
+        let rank = ((low << Self::PRECISION).leading_zeros().min(rest_bits) + 1) as u8;
+        self.registers[idx] = self.registers[idx].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (mine, theirs) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        // linear counting is more accurate while many registers are still empty
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    pub fn std_error() -> f64 {
+        1.04 / ((1u32 << Self::PRECISION) as f64).sqrt()
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/* Keys by the timestamp of their latest version. Entries are only added: a key whose
+   timestamp changed or that was removed leaves a stale entry behind, so every hit must be
+   checked against the records. Stale entries are dropped by range() callers or a rebuild */
+#[derive(Debug, Default)]
+pub struct TimeIndex {
+    entries: std::collections::BTreeMap<DateTime<Utc>, Vec<HashType>>,
+    len: usize,
+}
+
+impl TimeIndex {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn add(&mut self, timestamp: DateTime<Utc>, key: HashType) {
+        let keys = self.entries.entry(timestamp).or_default();
+        if !keys.contains(&key) {
+            keys.push(key);
+            self.len += 1;
+        }
+    }
+
+    pub fn remove(&mut self, timestamp: &DateTime<Utc>, key: &HashType) {
+        if let Some(keys) = self.entries.get_mut(timestamp) {
+            let before = keys.len();
+            keys.retain(|indexed| indexed != key);
+            self.len -= before - keys.len();
+            if keys.is_empty() {
+                self.entries.remove(timestamp);
+            }
+        }
+    }
+
+    // candidates written in [after, before], oldest first
+    pub fn range(&self, after: DateTime<Utc>, before: DateTime<Utc>) -> Vec<(DateTime<Utc>, HashType)> {
+        if after > before {
+            return Vec::new();
+        }
+        self.entries.range(after..=before)
+            .flat_map(|(timestamp, keys)| keys.iter().map(move |key| (*timestamp, *key)))
+            .collect()
+    }
+
+    pub fn rebuild<'a>(&mut self, records: impl Iterator<Item = (&'a HashType, &'a Item)>) {
+        self.entries.clear();
+        self.len = 0;
+        for (key, item) in records {
+            self.add(item.timestamp, *key);
+        }
+    }
+}
+
+/* Token bucket per client address: each holds up to one second worth of requests
+   and refills continuously at 'rate' per second. A rate of 0 disables limiting */
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    buckets: std::collections::HashMap<std::net::IpAddr, (f64, std::time::Instant)>,
+}
+
+impl RateLimiter {
+    // buckets kept before full (idle) ones are dropped
+    const MAX_BUCKETS: usize = 10_000;
+
+    pub fn new(rate: u32) -> Self {
+        RateLimiter { rate: rate as f64, buckets: std::collections::HashMap::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    // takes a token for ip, false when its bucket is empty
+    pub fn allow(&mut self, ip: std::net::IpAddr) -> bool {
+        let now = std::time::Instant::now();
+        let rate = self.rate;
+        if self.buckets.len() >= Self::MAX_BUCKETS && !self.buckets.contains_key(&ip) {
+            self.buckets.retain(|_, (tokens, last)| *tokens + last.elapsed().as_secs_f64() * rate < rate);
+        }
+        let (tokens, last) = self.buckets.entry(ip).or_insert((rate, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/* Bounded LRU of query results for keys stored on other nodes.
+   A miss is cached too (None) so repeated reads of absent keys are answered locally */
+#[derive(Debug)]
+pub struct ReadCache {
+    capacity: usize,
+    ttl: std::time::Duration,
+    entries: std::collections::HashMap<HashType, (std::time::Instant, Option<Item>)>,
+    order: std::collections::VecDeque<HashType>,    // least recently used first
+}
+
+impl ReadCache {
+    pub fn new(capacity: usize, ttl: std::time::Duration) -> Self {
+        ReadCache { capacity, ttl, entries: std::collections::HashMap::new(), order: std::collections::VecDeque::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // Some(result) while the entry is fresh, expired entries are dropped
+    pub fn get(&mut self, key: &HashType) -> Option<Option<Item>> {
+        let fresh = match self.entries.get(key) {
+            Some((stored, _)) => stored.elapsed() < self.ttl,
+            None => return None
+        };
+        if !fresh {
+            self.invalidate(key);
+            return None;
+        }
+        self.order.retain(|cached| cached != key);
+        self.order.push_back(*key);
+        self.entries.get(key).map(|(_, item)| item.clone())
+    }
+
+    pub fn put(&mut self, key: HashType, item: Option<Item>) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.order.retain(|cached| *cached != key);
+        while self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.push_back(key);
+        self.entries.insert(key, (std::time::Instant::now(), item));
+    }
+
+    pub fn invalidate(&mut self, key: &HashType) {
+        self.order.retain(|cached| cached != key);
+        self.entries.remove(key);
+    }
+}
+
+// first 4 bytes of the SHA-1 of a value
+pub fn value_checksum(value: &str) -> u32 {
+    let digest = HashFunc(value).0;
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+// how 'compact' collapses a value built from Eventual appends
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum CompactStrategy {
+    #[default]
+    Last,       // keep only the newest append
+    Dedup       // drop repeated lines
+}
+
+// what a read does when it reaches a node while a relocation shifts its replica indices
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum RelocateReads {
+    #[default]
+    Wait,       // until the relocation is done, at most RELOCATE_WAIT_MS
+    Primary     // go on to the primary of the key, Eventual reads only
+}
+
+impl std::str::FromStr for RelocateReads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wait" => Ok(RelocateReads::Wait),
+            "primary" => Ok(RelocateReads::Primary),
+            _ => Err(format!("Invalid relocate read policy '{}': expected wait|primary", s))
+        }
+    }
+}
+
+// what a node that is offline answers to client requests still sent to it
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum OfflineRequests {
+    #[default]
+    Redirect,   // an error naming a live node to retry at, which clients follow for reads
+    Reject      // a plain "is offline" error
+}
+
+impl std::str::FromStr for OfflineRequests {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "redirect" => Ok(OfflineRequests::Redirect),
+            "reject" => Ok(OfflineRequests::Reject),
+            _ => Err(format!("Invalid offline request policy '{}': expected redirect|reject", s))
+        }
+    }
+}
+
+/* Extrapolates hits found in a uniform sample without replacement to the whole population.
+    Returns the estimate with a 95% Wilson score interval, narrowed towards the estimate by the
+    finite population correction and clamped to what the sample already rules out */
+pub fn sample_estimate(hits: usize, sampled: usize, population: usize) -> (f64, f64, f64) {
+    if sampled == 0 || population == 0 {
+        return (0.0, 0.0, population as f64);
+    }
+    let (x, m, n) = (hits as f64, sampled as f64, population as f64);
+    let z = 1.96;
+    let p = x / m;
+    let center = (p + z * z / (2.0 * m)) / (1.0 + z * z / m);
+    let half = z / (1.0 + z * z / m) * (p * (1.0 - p) / m + z * z / (4.0 * m * m)).sqrt();
+    let fpc = if population > 1 { ((n - m) / (n - 1.0)).max(0.0).sqrt() } else { 0.0 };
+    let low = p - (p - (center - half).max(0.0)) * fpc;
+    let high = p + ((center + half).min(1.0) - p) * fpc;
+    let estimate = p * n;
+    (estimate, (low * n).clamp(x.min(estimate), estimate), (high * n).clamp(estimate, (n - (m - x)).max(estimate)))
+}
+
+// what a node does when neither its entry nor any known member answers its join
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum EmptyRing {
+    #[default]
+    Fail,       // report the ring as unreachable and stay out
+    Start       // start a ring of its own, pointing to itself like a lone bootstrap
+}
+
+impl std::str::FromStr for EmptyRing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fail" => Ok(EmptyRing::Fail),
+            "start" => Ok(EmptyRing::Start),
+            _ => Err(format!("Invalid empty ring policy '{}': expected fail|start", s))
         }
     }
 }
@@ -138,6 +611,77 @@ pub enum Consistency  {
     Quorum
 }
 
+// accepts the names case-insensitively and the legacy numeric codes
+impl std::str::FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "0" | "eventual" => Ok(Consistency::Eventual),
+            "1" | "chain" => Ok(Consistency::Chain),
+            "2" | "quorum" => Ok(Consistency::Quorum),
+            _ => Err(format!("Invalid consistency '{}': expected eventual|chain|quorum (or 0|1|2)", s))
+        }
+    }
+}
+
+/* How client keys are rewritten before they are hashed or stored. Chosen at the bootstrap
+   and taken from the ring by every joining node, so all nodes place a key the same way */
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct KeyNorm {
+    pub trim: bool,         // drop leading and trailing whitespace
+    pub nfc: bool,          // Unicode normalization form C
+    pub fold_case: bool,    // lowercase
+}
+
+impl KeyNorm {
+    pub fn is_identity(&self) -> bool {
+        *self == KeyNorm::default()
+    }
+
+    // applied in this order, so normalizing an already normalized key changes nothing
+    pub fn apply(&self, key: &str) -> String {
+        let key = if self.trim { key.trim() } else { key };
+        let key: String = if self.nfc { key.nfc().collect() } else { key.to_string() };
+        if self.fold_case { key.to_lowercase() } else { key }
+    }
+}
+
+// a comma separated list of trim, nfc and case, or none
+impl std::str::FromStr for KeyNorm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut norm = KeyNorm::default();
+        for part in s.split(',').map(|part| part.trim().to_ascii_lowercase()) {
+            match part.as_str() {
+                "none" => (),
+                "trim" => norm.trim = true,
+                "nfc" => norm.nfc = true,
+                "case" => norm.fold_case = true,
+                _ => return Err(format!("Invalid key normalization '{}': expected a list of trim|nfc|case, or none", part))
+            }
+        }
+        Ok(norm)
+    }
+}
+
+impl fmt::Display for KeyNorm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<&str> = [(self.trim, "trim"), (self.nfc, "nfc"), (self.fold_case, "case")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+        if parts.is_empty() { write!(f, "none") } else { write!(f, "{}", parts.join(",")) }
+    }
+}
+
+// deadline for a reply that may be forwarded around a ring of ring_size nodes
+pub fn ring_timeout(base_ms: u64, ring_size: usize) -> std::time::Duration {
+    std::time::Duration::from_millis(base_ms + crate::HOP_TIMEOUT_MS * ring_size.max(1) as u64)
+}
+
 pub fn get_local_ip() -> Ipv4Addr {
     let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind UDP socket");
     socket.connect("8.8.8.8:80").expect("Failed to connect to external server");
@@ -149,7 +693,7 @@ pub fn get_local_ip() -> Ipv4Addr {
     Ipv4Addr::new(127, 0, 0, 1) // Fallback to loopback if something fails
 }
 
-pub fn format_overlay_msg(ring_list: &Vec<NodeInfo>) -> String {
+pub fn format_overlay_msg(ring_list: &[NodeInfo]) -> String {
     let mut result = String::from("***************\nRING OVERLAY🔗\n***************\n"); 
     // sort just to start from smallest ID 
     // -- TODO! do we need this ?
@@ -163,6 +707,14 @@ pub fn format_overlay_msg(ring_list: &Vec<NodeInfo>) -> String {
     result
 }
 
+pub fn format_members_msg(members: &[NodeInfo]) -> String {
+    let mut result = format!("***************\nMEMBERS👥 ({})\n***************\n", members.len());
+    for peer in members.iter() {
+        result.push_str(&format!("(nodeID:{}, IP:{}:{})\n", peer.get_id(), peer.get_ip(), peer.get_port()));
+    }
+    result
+}
+
 pub fn format_queryall_msg(items: &Vec<Item>) -> String {
     let mut result = String::from("****************\nALL RECORDS⭐\n****************\n"); 
     for item in items.iter() {
@@ -175,6 +727,89 @@ pub fn format_queryall_msg(items: &Vec<Item>) -> String {
     result
 }
 
+pub fn format_time_range_msg(items: &[Item], after: &DateTime<Utc>, before: &DateTime<Utc>) -> String {
+    let mut result = format!("****************\nWRITTEN 🕰️ {} .. {}\n****************\n", after, before);
+    let mut sorted: Vec<&Item> = items.iter().collect();
+    sorted.sort_by_key(|item| item.timestamp);
+    for item in sorted {
+        result.push_str(&format!("(🔑{} : 🔒{}, 🕰️ {})\n", item.title, item.value, item.timestamp));
+    }
+    result.push_str(&format!("{} record(s)\n", items.len()));
+    result
+}
+
+/* Only the latest version of a key is stored, so a key written after the snapshot
+   has no visible version and is reported as changed instead of showing a newer value */
+pub fn format_multiget_msg(found: &[(String, Option<Item>)], snapshot_ts: &DateTime<Utc>) -> String {
+    let mut result = format!("****************\nSNAPSHOT @ 🕰️ {}\n****************\n", snapshot_ts);
+    for (key, record) in found.iter() {
+        match record {
+            Some(item) if item.timestamp <= *snapshot_ts =>
+                result.push_str(&format!("(🔑{} : 🔒{}, 🕰️ {})\n", item.title, item.value, item.timestamp)),
+            Some(item) =>
+                result.push_str(&format!("(🔑{} : changed after snapshot, 🕰️ {})\n", key, item.timestamp)),
+            None =>
+                result.push_str(&format!("(🔑{} : doesn't exist)\n", key))
+        }
+    }
+    result
+}
+
+pub fn format_log_msg(key: &str, entries: &[LogEntry]) -> String {
+    let mut result = format!("****************\nLOG📜 🔑{} ({})\n****************\n", key, entries.len());
+    for entry in entries.iter() {
+        result.push_str(&format!("🕰️ {} : {}\n", entry.timestamp, entry.entry));
+    }
+    result
+}
+
+/* replicas_acked counts the copies written when the reply is sent, primary included.
+   The insert is durable once every one of the k + 1 copies holds it */
+pub fn format_insert_msg(key: &str, value: &str, timestamp: &DateTime<Utc>, replicas_acked: u8, durable: bool) -> String {
+    format!("Inserted (🔑 {} : 🔒{}) at 🕰️ {} successfully! [replicas_acked: {}, durable: {}]",
+            key, value, timestamp, replicas_acked, durable)
+}
+
+pub fn format_exists_msg(key: &str, record: Option<&Item>) -> String {
+    match record {
+        Some(found) => format!("Exists: 🔑 {} = true (🕰️ {})", found.title, found.timestamp),
+        None => format!("Exists: 🔑 {} = false", key)
+    }
+}
+
+// wall clock reading of a single node, collected while traversing the ring
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSample {
+    pub node: NodeInfo,
+    pub now: DateTime<Utc>,
+}
+
+pub fn format_clockcheck_msg(samples: &[ClockSample], finished: DateTime<Utc>) -> String {
+    let mut result = String::from("****************\nCLOCK CHECK🕰️\n****************\n");
+    /* samples are taken one hop after the other, so spread the whole round trip
+       evenly across the ring and compare each clock against its expected reading */
+    let start = samples[0].now;
+    let hops = samples.len() as i64;
+    let round_trip = (finished - start).num_milliseconds();
+    let offsets: Vec<i64> = samples.iter().enumerate()
+        .map(|(i, sample)| (sample.now - start).num_milliseconds() - round_trip * i as i64 / hops)
+        .collect();
+
+    for (sample, offset) in samples.iter().zip(offsets.iter()) {
+        result.push_str(&format!(
+            "(nodeID:{}, IP:{}:{}) 🕰️ {} offset: {:+}ms\n",
+            sample.node.get_id(), sample.node.get_ip(), sample.node.get_port(), sample.now, offset));
+    }
+
+    let max_skew = offsets.iter().max().unwrap_or(&0) - offsets.iter().min().unwrap_or(&0);
+    result.push_str(&format!("Max pairwise skew: {}ms (round trip {}ms)\n", max_skew, round_trip));
+    if max_skew > crate::MAX_CLOCK_SKEW_MS {
+        result.push_str(&format!("⚠️ Skew exceeds {}ms, timestamp based reconciliation is unreliable!\n",
+                                 crate::MAX_CLOCK_SKEW_MS));
+    }
+    result
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Range<T> {
     lower: T,
@@ -200,6 +835,15 @@ where
             || (self.lower < number && number < self.upper)
     }
 
+    // in_range for a range that may wrap around the top of the ring
+    pub fn contains(&self, number: T) -> bool {
+        if self.lower < self.upper {
+            self.in_range(number)
+        } else {
+            number > self.lower || number <= self.upper
+        }
+    }
+
     pub fn set_lower(&mut self, lower: T) {
         self.lower = lower;
     }
@@ -261,38 +905,34 @@ where
         self.replication_vector.len()
     }
 
+    // ranges run from the furthest replica at the head to the previous node's range at the tail, which is replica 1
     pub fn is_subset(&self, element: T ) -> i16 {
         let rev_idx =  self.replication_vector.len();
         for (i, set) in self.replication_vector.iter().enumerate().rev() {
-            if set.lower < set.upper { // normal case
-                if set.in_range(element) {
-                    return (rev_idx - i + 1) as i16;
-                }
-            } else { // wrap-around set
-                if element > set.lower || element <= set.upper { // wrap-around case
-                    return (rev_idx - i + 1) as i16;
-                }
+            if set.contains(element) {
+                return (rev_idx - i) as i16;
             }
         }
         -1
     }
 
+    /* The range at position idx, counted from the furthest one at the head, is joined to the
+       next nearer range. The nearest range joins the primary range, which is not kept here */
     pub fn merge_at(&mut self, idx: usize) {
-        if idx == 0 && self.replication_vector.len() > 0 {
-            self.replication_vector.remove(idx);
-            return;
-        } else if idx >= self.replication_vector.len() {
+        if idx >= self.replication_vector.len() {
             println!("Index out of bounds");
             return;
+        } else if idx == self.replication_vector.len() - 1 {
+            self.replication_vector.remove(idx);
+            return;
         }
-        let merged_range = Range::new(self.replication_vector[idx-1].lower, 
-                                          self.replication_vector[idx].upper, 
-                                          self.replication_vector[idx-1].lc, 
-                                          self.replication_vector[idx].uc);
+        let merged_range = Range::new(self.replication_vector[idx].lower, 
+                                          self.replication_vector[idx+1].upper, 
+                                          self.replication_vector[idx].lc, 
+                                          self.replication_vector[idx+1].uc);
         
-        self.replication_vector.insert(idx-1, merged_range);
-        self.replication_vector.remove(idx);
-        self.replication_vector.remove(idx);
+        self.replication_vector.remove(idx+1);
+        self.replication_vector[idx] = merged_range;
     }
 
     pub fn clear(&mut self) {
@@ -304,7 +944,7 @@ where
         let mut pos = None;
 
         for (i, range) in self.replication_vector.iter().enumerate(){
-            if range.in_range(new_key) || new_key > range.lower || new_key <= range.upper { // split here
+            if range.contains(new_key) { // split here
                 let split_left = Range::new(
                     range.lower,
                     new_key,
@@ -336,3 +976,115 @@ where
    
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consistency_parses_names_and_numeric_codes() {
+        for (names, expected) in [(["eventual", "EVENTUAL", "0"], Consistency::Eventual), (["chain", "Chain", "1"], Consistency::Chain), (["quorum", "QuOrUm", "2"], Consistency::Quorum)] {
+            for name in names {
+                let parsed: Consistency = name.parse().unwrap_or_else(|e| panic!("{}: {}", name, e));
+                assert_eq!(std::mem::discriminant(&parsed), std::mem::discriminant(&expected), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn ring_timeout_adds_a_hop_for_every_node() {
+        let hop = std::time::Duration::from_millis(crate::HOP_TIMEOUT_MS);
+        assert_eq!(ring_timeout(1000, 0), ring_timeout(1000, 1));
+        assert_eq!(ring_timeout(1000, 1), std::time::Duration::from_millis(1000) + hop);
+        assert_eq!(ring_timeout(1000, 50) - ring_timeout(1000, 10), hop * 40);
+    }
+
+    #[test]
+    fn json_field_reaches_nested_fields_of_the_newest_part() {
+        let mut item = Item::new("user", r#"{"name":"old"}"#, 0, false);
+        let newest = r#"{"name":"ada","address":{"lines":["1 Main St","Flat 2"],"zip":"10115"}}"#;
+        item.appends.push(item.value.len());
+        item.value.push_str(newest);
+
+        assert_eq!(item.json_field("name"), Ok(serde_json::json!("ada")));
+        assert_eq!(item.json_field("address.zip"), Ok(serde_json::json!("10115")));
+        assert_eq!(item.json_field("address.lines.1"), Ok(serde_json::json!("Flat 2")));
+        assert_eq!(item.json_field("address"), Ok(serde_json::json!({"lines": ["1 Main St", "Flat 2"], "zip": "10115"})));
+        for missing in ["age", "address.lines.2", "address.zip.first", "name.0"] {
+            assert_eq!(item.json_field(missing), Err(format!("no field '{}'", missing)));
+        }
+
+        let plain = Item::new("note", "not json", 0, false);
+        assert!(plain.json_field("name").is_err_and(|e| e.starts_with("value is not valid JSON")));
+    }
+
+    #[test]
+    fn sample_estimate_interval_covers_the_true_count() {
+        use rand::{seq::SliceRandom, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let (population, missing, sampled) = (1_000, 120, 100);
+        let keys: Vec<bool> = (0..population).map(|i| i < missing).collect();
+        let trials = 2_000;
+        let mut covered = 0;
+        for _ in 0..trials {
+            let hits = keys.choose_multiple(&mut rng, sampled).filter(|&&miss| miss).count();
+            let (estimate, low, high) = sample_estimate(hits, sampled, population);
+            assert!(low <= estimate && estimate <= high, "{} outside {}..{}", estimate, low, high);
+            if (low..=high).contains(&(missing as f64)) {
+                covered += 1;
+            }
+        }
+        // a 95% interval, with some slack for the trials drawn
+        assert!(covered as f64 / trials as f64 >= 0.93, "covered {} of {}", covered, trials);
+        // a full sample leaves nothing to estimate
+        assert_eq!(sample_estimate(missing, population, population), (120.0, 120.0, 120.0));
+    }
+
+    #[test]
+    fn hyperloglog_estimate_stays_within_its_error_bound() {
+        for count in [100, 5_000, 50_000] {
+            let mut sketch = HyperLogLog::new();
+            for i in 0..count {
+                let key = HashFunc(&format!("key{}", i));
+                // a key seen again, on another node or as a replica, is not counted twice
+                sketch.add(&key);
+                sketch.add(&key);
+            }
+            let error = (sketch.estimate() - count as f64).abs() / count as f64;
+            // 4 standard errors, a false failure about once in 15000 runs
+            assert!(error < 4.0 * HyperLogLog::std_error(), "{} keys estimated as {:.0}", count, sketch.estimate());
+        }
+    }
+
+    #[test]
+    fn merge_at_joins_a_departed_range_to_the_next_nearer_one() {
+        let range = |lower: u8, upper: u8| Range::new(lower, upper, false, true);
+        let bounds = |ranges: &UnionRange<u8>| ranges.iter().map(|range| range.get_bounds()).collect::<Vec<_>>();
+        let held = || {
+            let mut ranges = UnionRange::new();
+            for (lower, upper) in [(10, 20), (20, 30), (30, 40)] {
+                ranges.insert(range(lower, upper));
+            }
+            ranges
+        };
+        // furthest first, as the replica indices 3, 2 and 1
+        let mut ranges = held();
+        ranges.merge_at(0);
+        assert_eq!(bounds(&ranges), vec![(10, 30), (30, 40)]);
+        let mut ranges = held();
+        ranges.merge_at(1);
+        assert_eq!(bounds(&ranges), vec![(10, 20), (20, 40)]);
+        // the nearest one goes to the primary range
+        let mut ranges = held();
+        ranges.merge_at(2);
+        assert_eq!(bounds(&ranges), vec![(10, 20), (20, 30)]);
+    }
+
+    #[test]
+    fn consistency_rejects_garbage_listing_the_valid_options() {
+        for garbage in ["", "3", "linearizable", "eventual "] {
+            let error = garbage.parse::<Consistency>().expect_err(garbage);
+            assert!(error.contains("eventual|chain|quorum") && error.contains("0|1|2"), "{}", error);
+        }
+    }
+}